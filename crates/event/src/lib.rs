@@ -1,11 +1,16 @@
-use std::{cell::Cell, num::NonZeroUsize, ptr::null_mut};
+use std::{cell::Cell, net::SocketAddr, num::NonZeroUsize, ops::Deref, ptr::null_mut};
 
 use game_entity::EntityId;
 use nalgebra_glm::{Vec2, Vec3};
 
 #[derive(Clone, Copy)]
 pub enum FrameEvent {
-    Location(EntityId),
+    /// A static mesh was moved to the location by a remote host. Local moves are game events.
+    Location(EntityId, Vec3),
+    /// A static mesh was added to the world
+    Spawned(EntityId),
+    /// A static mesh was removed from the world
+    Despawned(EntityId),
 }
 
 /// Events which are created by game input.
@@ -21,6 +26,10 @@ pub enum InputEvent {
     ServerDisconnect,
     Spawn,
     SpawnGuest,
+    Undo,
+    Redo,
+    /// Cancels the current action, e.g. placing an object
+    Cancel,
 }
 
 /// Events which are created by the game controller and consumed by systems.
@@ -33,6 +42,8 @@ pub enum GameEvent {
     SpawnGuest {
         entity_id: EntityId,
         replicate: bool,
+        /// m/s
+        walk_speed: f32,
     },
     Despawn(EntityId),
     UpdateEntityId {
@@ -40,28 +51,77 @@ pub enum GameEvent {
         new_id: EntityId,
     },
     StaticMeshLocation(EntityId, Vec3),
+    SetVisible(EntityId, bool),
+    /// Placing the object was rejected because it would overlap another object. It is still
+    /// being placed.
+    PlacementBlocked(EntityId),
     NetworkRoleOffline,
-    NetworkRoleClient,
-    NetworkRoleServer,
+    /// Connect to the server at this address
+    NetworkRoleClient(SocketAddr),
+    /// Host a server bound to this address
+    NetworkRoleServer(SocketAddr),
     NetworkClientSpawnAck {
         spawn_id: u16,
         entity_id: EntityId,
     },
 }
 
+impl GameEvent {
+    /// Whether this changes the local network role
+    #[inline]
+    pub fn is_network_role(&self) -> bool {
+        matches!(
+            self,
+            Self::NetworkRoleOffline | Self::NetworkRoleClient(_) | Self::NetworkRoleServer(_)
+        )
+    }
+}
+
 /// Events which are created by systems and consumed by the game controller.
 #[derive(Clone, Copy)]
 pub enum SystemGameEvent {
     NetworkSpawn(EntityId),
-    NetworkSpawnGuest(EntityId),
+    NetworkSpawnGuest {
+        entity_id: EntityId,
+        walk_speed: f32,
+    },
     NetworkDespawn(EntityId),
+    /// A guest's visit has ended and it should be despawned. Server and offline only.
+    GuestLeft(EntityId),
     NetworkClientSpawn(u16),
+    /// The requested network role could not be started, so the network is offline
+    NetworkRoleFailed,
     NetworkClientSpawnAck {
         client_id: EntityId,
         replicable_id: EntityId,
     },
 }
 
+/// An input event which a handler may consume, so that handlers later in the frame skip it
+pub struct ConsumableInputEvent<'a> {
+    event: &'a InputEvent,
+    consumed: &'a mut bool,
+}
+
+impl ConsumableInputEvent<'_> {
+    #[inline]
+    pub fn consume(self) {
+        *self.consumed = true;
+    }
+}
+
+impl Deref for ConsumableInputEvent<'_> {
+    type Target = InputEvent;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        self.event
+    }
+}
+
+const UNASSIGNED_THREAD: &str =
+    "event buffer used on a thread which was not registered with assign_thread_event_buffer";
+
 thread_local! {
     static FRAME_EVENT_BUFFER: Cell<*mut [Vec<FrameEvent>; 2]> = Cell::new(null_mut())
 }
@@ -80,14 +140,58 @@ impl SyncEventDelegate<'_> {
         self.event_manager.game_event_buffer.push(event);
     }
 
+    #[inline]
+    pub fn game_event_writer(&mut self) -> SyncGameEventWriter {
+        SyncGameEventWriter(&mut self.event_manager.game_event_buffer)
+    }
+
     #[inline]
     pub fn push_input_event(&mut self, event: InputEvent) {
-        self.event_manager.input_event_buffer.push(event);
+        self.event_manager.input_event_buffer.push((event, false));
+    }
+
+    /// Game events pushed this frame which match the predicate
+    #[inline]
+    pub fn game_events_of<'a>(
+        &'a self,
+        mut predicate: impl FnMut(&GameEvent) -> bool + 'a,
+    ) -> impl Iterator<Item = &'a GameEvent> {
+        self.event_manager
+            .game_event_buffer
+            .iter()
+            .filter(move |event| predicate(event))
+    }
+
+    /// Game events pushed this frame which change the local network role, in the order they
+    /// were pushed
+    #[inline]
+    pub fn network_role_changes(&self) -> impl Iterator<Item = &GameEvent> {
+        self.game_events_of(GameEvent::is_network_role)
     }
 
+    /// Input events which have not been consumed
     #[inline]
     pub fn input_events(&self) -> impl Iterator<Item = &InputEvent> {
-        self.event_manager.input_event_buffer.iter()
+        self.event_manager.input_events()
+    }
+
+    /// Input events which have not been consumed and match the predicate
+    #[inline]
+    pub fn input_events_of<'a>(
+        &'a self,
+        mut predicate: impl FnMut(&InputEvent) -> bool + 'a,
+    ) -> impl Iterator<Item = &'a InputEvent> {
+        self.input_events().filter(move |event| predicate(event))
+    }
+
+    /// Input events which have not been consumed, which this handler may consume
+    #[inline]
+    pub fn consumable_input_events(&mut self) -> impl Iterator<Item = ConsumableInputEvent> {
+        self.event_manager
+            .input_event_buffer
+            .iter_mut()
+            .filter(|(_, consumed)| !*consumed)
+            .map(|(event, consumed)| ConsumableInputEvent { event, consumed })
     }
 
     #[inline]
@@ -106,7 +210,12 @@ impl SyncEventDelegate<'_> {
     #[inline]
     pub fn input_events_mut(&mut self) -> (SyncGameEventWriter, impl Iterator<Item = &InputEvent>) {
         let game_event_writer = SyncGameEventWriter(&mut self.event_manager.game_event_buffer);
-        let input_events = self.event_manager.input_event_buffer.iter();
+        let input_events = self
+            .event_manager
+            .input_event_buffer
+            .iter()
+            .filter(|(_, consumed)| !*consumed)
+            .map(|(event, _)| event);
         (game_event_writer, input_events)
     }
 
@@ -141,16 +250,17 @@ impl AsyncEventDelegate<'_> {
 
         // SAFETY: no other access with this swap index aliases. This is guaranteed because
         // EventManager is exclusively borrowed as long as an EventDelegate exists, preventing
-        // modification of the swap index or simultaneous access to the event buffers
+        // modification of the swap index or simultaneous access to the event buffers. The buffer
+        // is null on threads which were never assigned one, which is checked rather than assumed
         FRAME_EVENT_BUFFER.with(|queue| unsafe {
-            queue.get().as_mut().unwrap_unchecked()[swap_index].push(event)
+            queue.get().as_mut().expect(UNASSIGNED_THREAD)[swap_index].push(event)
         });
     }
 
     #[inline]
     pub fn push_system_game_event(&self, event: SystemGameEvent) {
         SYSTEM_GAME_EVENT_BUFFER
-            .with(|queue| unsafe { queue.get().as_mut().unwrap_unchecked().push(event) });
+            .with(|queue| unsafe { queue.get().as_mut().expect(UNASSIGNED_THREAD).push(event) });
     }
 
     #[inline]
@@ -158,9 +268,25 @@ impl AsyncEventDelegate<'_> {
         self.event_manager.game_event_buffer.iter()
     }
 
+    /// Game events which match the predicate
+    #[inline]
+    pub fn game_events_of<'a>(
+        &'a self,
+        mut predicate: impl FnMut(&GameEvent) -> bool + 'a,
+    ) -> impl Iterator<Item = &'a GameEvent> {
+        self.game_events().filter(move |event| predicate(event))
+    }
+
+    /// Game events which change the local network role, in the order they were pushed
+    #[inline]
+    pub fn network_role_changes(&self) -> impl Iterator<Item = &GameEvent> {
+        self.game_events_of(GameEvent::is_network_role)
+    }
+
+    /// Input events which have not been consumed
     #[inline]
     pub fn input_events(&self) -> impl Iterator<Item = &InputEvent> {
-        self.event_manager.input_event_buffer.iter()
+        self.event_manager.input_events()
     }
 
     /// Frame events which occurred in the previous frame
@@ -177,7 +303,8 @@ impl AsyncEventDelegate<'_> {
 pub struct EventManager {
     event_buffers: Vec<[Vec<FrameEvent>; 2]>,
     game_event_buffer: Vec<GameEvent>,
-    input_event_buffer: Vec<InputEvent>,
+    /// Cleared each swap, along with whether each event has been consumed
+    input_event_buffer: Vec<(InputEvent, bool)>,
     system_game_event_buffers: Vec<Vec<SystemGameEvent>>,
     swap_index: bool,
 }
@@ -230,6 +357,13 @@ impl EventManager {
         }
     }
 
+    fn input_events(&self) -> impl Iterator<Item = &InputEvent> {
+        self.input_event_buffer
+            .iter()
+            .filter(|(_, consumed)| !*consumed)
+            .map(|(event, _)| event)
+    }
+
     fn read_index(&self) -> usize {
         !self.swap_index as usize
     }
@@ -238,3 +372,123 @@ impl EventManager {
         self.swap_index as usize
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An event manager with the events pushed for this frame
+    fn event_manager(game_events: &[GameEvent]) -> EventManager {
+        let mut event_manager = EventManager::new(NonZeroUsize::new(1).unwrap());
+
+        let mut event_delegate = event_manager.sync_delegate();
+        for game_event in game_events {
+            event_delegate.push_game_event(*game_event);
+        }
+
+        event_manager
+    }
+
+    fn mixed_events() -> [GameEvent; 4] {
+        [
+            GameEvent::Despawn(EntityId::new(1)),
+            GameEvent::NetworkRoleServer("127.0.0.1:7000".parse().unwrap()),
+            GameEvent::Despawn(EntityId::new(2)),
+            GameEvent::NetworkRoleOffline,
+        ]
+    }
+
+    fn is_despawn(event: &GameEvent) -> bool {
+        matches!(event, GameEvent::Despawn(_))
+    }
+
+    #[test]
+    fn sync_network_role_changes_are_filtered_in_order() {
+        let mut event_manager = event_manager(&mixed_events());
+        let event_delegate = event_manager.sync_delegate();
+
+        let mut role_changes = event_delegate.network_role_changes();
+        assert!(matches!(
+            role_changes.next(),
+            Some(GameEvent::NetworkRoleServer(_))
+        ));
+        assert!(matches!(
+            role_changes.next(),
+            Some(GameEvent::NetworkRoleOffline)
+        ));
+        assert!(role_changes.next().is_none());
+    }
+
+    #[test]
+    fn sync_game_events_of_yields_matching_events() {
+        let mut event_manager = event_manager(&mixed_events());
+        let event_delegate = event_manager.sync_delegate();
+
+        assert_eq!(event_delegate.game_events_of(is_despawn).count(), 2);
+        assert!(event_delegate.game_events_of(is_despawn).all(is_despawn));
+    }
+
+    #[test]
+    fn async_filters_yield_matching_events() {
+        let mut event_manager = event_manager(&mixed_events());
+        let event_delegate = event_manager.async_delegate();
+
+        let despawned: Vec<_> = event_delegate
+            .game_events_of(is_despawn)
+            .map(|event| match event {
+                GameEvent::Despawn(entity_id) => entity_id.get(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(despawned, [1, 2]);
+
+        assert_eq!(event_delegate.network_role_changes().count(), 2);
+        assert!(event_delegate
+            .network_role_changes()
+            .all(GameEvent::is_network_role));
+    }
+
+    #[test]
+    fn consumed_input_event_is_skipped_by_later_handlers() {
+        let mut event_manager = event_manager(&[]);
+        let mut event_delegate = event_manager.sync_delegate();
+        event_delegate.push_input_event(InputEvent::MouseButton(true));
+        event_delegate.push_input_event(InputEvent::CursorMoved);
+
+        for event in event_delegate.consumable_input_events() {
+            if matches!(*event, InputEvent::MouseButton(_)) {
+                event.consume();
+            }
+        }
+
+        let mut remaining = event_delegate.consumable_input_events();
+        assert!(matches!(
+            remaining.next().as_deref(),
+            Some(InputEvent::CursorMoved)
+        ));
+        assert!(remaining.next().is_none());
+        drop(remaining);
+
+        assert!(!event_delegate
+            .input_events()
+            .any(|event| matches!(event, InputEvent::MouseButton(_))));
+    }
+
+    #[test]
+    #[should_panic(expected = "event buffer used on a thread which was not registered")]
+    fn push_from_unassigned_thread_panics() {
+        let mut event_manager = event_manager(&[]);
+        let event_delegate = event_manager.async_delegate();
+
+        // run on a fresh thread, so no other test can have assigned it a buffer
+        let result = std::thread::scope(|scope| {
+            scope
+                .spawn(|| event_delegate.push_system_game_event(SystemGameEvent::NetworkRoleFailed))
+                .join()
+        });
+
+        if let Err(payload) = result {
+            std::panic::resume_unwind(payload);
+        }
+    }
+}
@@ -2,7 +2,16 @@ use std::{cell::Cell, marker::PhantomData, num::NonZeroUsize, ptr, sync::Arc};
 
 use game_entity::EntityId;
 use game_resources::Resource;
-use nalgebra_glm::Vec3;
+use nalgebra_glm::{
+    look_at_lh, ortho_lh_zo, perspective_lh_zo, quat_identity, quat_to_mat4, scale, translate,
+    Mat4, Quat, Vec3,
+};
+
+pub use scene::SceneState;
+pub use snapshot::{FrameDelta, FrameSnapshot};
+
+mod scene;
+mod snapshot;
 
 thread_local! {
     static EVENT_BUFFER: Cell<*mut [Data; 2]> = Cell::new(ptr::null_mut())
@@ -33,7 +42,7 @@ pub struct FrameBufferReader<'a> {
 
 impl FrameBufferReader<'_> {
     #[inline]
-    pub fn spawned_guests(&self) -> impl Iterator<Item = &EntityId> {
+    pub fn spawned_guests(&self) -> impl Iterator<Item = &SpawnedGuest> {
         self.inner.spawned_guests.iter()
     }
 
@@ -52,20 +61,43 @@ impl FrameBufferReader<'_> {
         self.inner.updated_entity_ids.iter()
     }
 
+    #[inline]
+    pub fn visibility(&self) -> impl Iterator<Item = &(EntityId, bool)> {
+        self.inner.visibility.iter()
+    }
+
+    #[inline]
+    pub fn render_styles(&self) -> impl Iterator<Item = &(EntityId, RenderStyle)> {
+        self.inner.render_styles.iter()
+    }
+
     #[inline]
     pub fn camera_info(&self) -> &CameraInfo {
-        &self.inner.camera_info
+        &self.inner.camera_info[self.inner.read_index()]
     }
 
+    /// Transforms written in the previous frame, ordered by executor thread index and then by
+    /// insertion order within each thread. An entity may appear more than once, in which case
+    /// consumers should apply transforms in iteration order so that the last write wins.
+    ///
+    /// Writes from the sync delegate are appended to the buffer of thread 0, after any
+    /// asynchronous writes made by that thread.
     #[inline]
-    pub fn locations(&self) -> impl Iterator<Item = (EntityId, &Vec3)> {
+    pub fn transforms(&self) -> impl Iterator<Item = (EntityId, &Transform)> {
         let swap_index = self.inner.read_index();
         self.inner
             .event_buffers
             .iter()
-            .flat_map(move |buffers| &buffers[swap_index].locations)
+            .flat_map(move |buffers| &buffers[swap_index].transforms)
             .map(|entity_data| (entity_data.entity_id, &entity_data.data))
     }
+
+    /// The location of each transform, in the same order as `transforms`
+    #[inline]
+    pub fn locations(&self) -> impl Iterator<Item = (EntityId, &Vec3)> {
+        self.transforms()
+            .map(|(entity_id, transform)| (entity_id, &transform.location))
+    }
 }
 
 pub struct FrameBufferWriter<'a> {
@@ -75,13 +107,19 @@ pub struct FrameBufferWriter<'a> {
 
 impl FrameBufferWriter<'_> {
     #[inline]
-    pub fn push_location(&self, entity_id: EntityId, location: Vec3) {
+    pub fn push_transform(&self, entity_id: EntityId, transform: Transform) {
         EVENT_BUFFER.with(|queue| unsafe {
             queue.get().as_mut().unwrap_unchecked()[self.swap_index as usize]
-                .locations
-                .push(EntityData::new(entity_id, location));
+                .transforms
+                .push(EntityData::new(entity_id, transform));
         });
     }
+
+    /// Pushes a transform with no rotation and unit scale
+    #[inline]
+    pub fn push_location(&self, entity_id: EntityId, location: Vec3) {
+        self.push_transform(entity_id, Transform::from_location(location));
+    }
 }
 
 pub struct SyncFrameBufferDelegate<'a> {
@@ -90,8 +128,8 @@ pub struct SyncFrameBufferDelegate<'a> {
 
 impl SyncFrameBufferDelegate<'_> {
     #[inline]
-    pub fn spawn_guest(&mut self, entity_id: EntityId) {
-        self.inner.spawned_guests.push(entity_id);
+    pub fn spawn_guest(&mut self, guest: SpawnedGuest) {
+        self.inner.spawned_guests.push(guest);
     }
 
     #[inline]
@@ -109,17 +147,80 @@ impl SyncFrameBufferDelegate<'_> {
         self.inner.updated_entity_ids.push((old_id, new_id));
     }
 
+    #[inline]
+    pub fn set_visible(&mut self, entity_id: EntityId, visible: bool) {
+        self.inner.visibility.push((entity_id, visible));
+    }
+
+    #[inline]
+    pub fn set_render_style(&mut self, entity_id: EntityId, style: RenderStyle) {
+        self.inner.render_styles.push((entity_id, style));
+    }
+
     #[inline]
     pub fn set_camera_info(&mut self, info: CameraInfo) {
-        self.inner.camera_info = info;
+        let index = self.inner.read_index(); // post-swap
+        self.inner.camera_info[index] = info;
     }
 
     #[inline]
-    pub fn push_location(&mut self, entity_id: EntityId, location: Vec3) {
+    pub fn push_transform(&mut self, entity_id: EntityId, transform: Transform) {
         let index = self.inner.read_index(); // post-swap
         self.inner.event_buffers[0][index]
-            .locations
-            .push(EntityData::new(entity_id, location));
+            .transforms
+            .push(EntityData::new(entity_id, transform));
+    }
+
+    /// Pushes a transform with no rotation and unit scale
+    #[inline]
+    pub fn push_location(&mut self, entity_id: EntityId, location: Vec3) {
+        self.push_transform(entity_id, Transform::from_location(location));
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub location: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::from_location(Vec3::zeros())
+    }
+}
+
+impl Transform {
+    pub fn from_location(location: Vec3) -> Self {
+        Self {
+            location,
+            rotation: quat_identity(),
+            scale: Vec3::from([1.0, 1.0, 1.0]),
+        }
+    }
+
+    /// Model matrix which scales, then rotates, then translates
+    pub fn matrix(&self) -> Mat4 {
+        let translation = translate(&Mat4::identity(), &self.location);
+        scale(&(translation * quat_to_mat4(&self.rotation)), &self.scale)
+    }
+}
+
+#[derive(Clone)]
+pub struct SpawnedGuest {
+    pub entity_id: EntityId,
+    /// Mesh to draw the guest with, or None for the renderer's default guest mesh
+    pub resource: Option<Arc<Resource>>,
+}
+
+impl SpawnedGuest {
+    /// A guest drawn with the default guest mesh
+    pub fn new(entity_id: EntityId) -> Self {
+        Self {
+            entity_id,
+            resource: None,
+        }
     }
 }
 
@@ -129,11 +230,47 @@ pub struct SpawnedStaticMesh {
     pub resource: Arc<Resource>,
 }
 
+/// How a static mesh is drawn. Entities are solid unless set otherwise.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RenderStyle {
+    Solid,
+    /// Translucent preview, e.g. of an object being placed
+    Ghost,
+    /// Translucent preview tinted to show that the object can't be placed here
+    InvalidGhost,
+}
+
+impl RenderStyle {
+    /// Premultiplied color blended over the scene, or None if the mesh is drawn opaque
+    pub fn blend_color(&self) -> Option<[f32; 4]> {
+        match self {
+            Self::Solid => None,
+            Self::Ghost => Some([0.25, 0.3, 0.4, 0.4]),
+            Self::InvalidGhost => Some([0.4, 0.05, 0.05, 0.4]),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Vertical field of view in radians
+    Perspective { fov: f32 },
+    /// Height of the view volume in world units, e.g. for a top-down planning view
+    Orthographic { height: f32 },
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Self::Perspective { fov: 1.0 }
+    }
+}
+
+#[derive(Clone, PartialEq)]
 pub struct CameraInfo {
     pub focus: Vec3,
     pub location: Vec3,
     pub up: Vec3,
-    pub fov: f32,
+    pub projection: Projection,
     pub near_plane: f32,
     pub far_plane: f32,
 }
@@ -145,16 +282,43 @@ impl Default for CameraInfo {
             focus: Vec3::zeros(),
             location: Vec3::from([0.0, 0.0, 1.0]),
             up: Vec3::from([0.0, 1.0, 0.0]),
-            fov: 1.0,
+            projection: Default::default(),
             near_plane: 0.01,
             far_plane: 50.0,
         }
     }
 }
 
+impl CameraInfo {
+    /// Left-handed projection with zero-to-one depth. Renderers whose clip space y axis points
+    /// down must flip the result.
+    pub fn proj_matrix(&self, aspect: f32) -> Mat4 {
+        match self.projection {
+            Projection::Perspective { fov } => {
+                perspective_lh_zo(aspect, fov, self.near_plane, self.far_plane)
+            }
+            Projection::Orthographic { height } => {
+                let (half_width, half_height) = (0.5 * height * aspect, 0.5 * height);
+                ortho_lh_zo(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.near_plane,
+                    self.far_plane,
+                )
+            }
+        }
+    }
+
+    pub fn view_matrix(&self) -> Mat4 {
+        look_at_lh(&self.location, &self.focus, &self.up)
+    }
+}
+
 #[derive(Clone, Default)]
 struct Data {
-    locations: Vec<EntityData<Vec3>>,
+    transforms: Vec<EntityData<Transform>>,
 }
 
 #[derive(Clone, Copy)]
@@ -171,17 +335,20 @@ impl<T> EntityData<T> {
 
 impl Data {
     fn clear(&mut self) {
-        self.locations.clear();
+        self.transforms.clear();
     }
 }
 
 pub struct FrameBufferManager {
     event_buffers: Vec<[Data; 2]>,
-    spawned_guests: Vec<EntityId>,
+    spawned_guests: Vec<SpawnedGuest>,
     spawned_static_meshes: Vec<SpawnedStaticMesh>,
     despawned: Vec<EntityId>,
     updated_entity_ids: Vec<(EntityId, EntityId)>,
-    camera_info: CameraInfo,
+    visibility: Vec<(EntityId, bool)>,
+    render_styles: Vec<(EntityId, RenderStyle)>,
+    /// Indexed like `event_buffers`, so the reader sees the value written since the last swap
+    camera_info: [CameraInfo; 2],
     swap_index: bool,
 }
 
@@ -193,7 +360,9 @@ impl FrameBufferManager {
             spawned_static_meshes: Vec::new(),
             despawned: Vec::new(),
             updated_entity_ids: Vec::new(),
-            camera_info: CameraInfo::default(),
+            visibility: Vec::new(),
+            render_styles: Vec::new(),
+            camera_info: Default::default(),
             swap_index: false,
         }
     }
@@ -213,6 +382,9 @@ impl FrameBufferManager {
     pub fn swap(&mut self) {
         self.swap_index = !self.swap_index;
 
+        // carry the camera over in case it isn't written this frame
+        self.camera_info[self.read_index()] = self.camera_info[self.swap_index as usize].clone();
+
         for event_buffer in &mut self.event_buffers {
             event_buffer[self.swap_index as usize].clear();
         }
@@ -221,9 +393,101 @@ impl FrameBufferManager {
         self.spawned_static_meshes.clear();
         self.despawned.clear();
         self.updated_entity_ids.clear();
+        self.visibility.clear();
+        self.render_styles.clear();
     }
 
     fn read_index(&self) -> usize {
         !self.swap_index as usize
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn transforms_are_ordered_by_thread_index() {
+        let mut frame_buffer_manager = FrameBufferManager::new(NonZeroUsize::new(2).unwrap());
+        let entity_id = EntityId::new(1);
+
+        frame_buffer_manager.swap();
+
+        {
+            let frame_buffer_delegate = frame_buffer_manager.async_delegate();
+            let writer = frame_buffer_delegate.writer();
+
+            // thread 1 writes first, but thread 0's write is still ordered before it
+            for (thread_index, location) in [(1, Vec3::new(1.0, 0.0, 0.0)), (0, Vec3::zeros())] {
+                thread::scope(|scope| {
+                    scope.spawn(|| {
+                        frame_buffer_delegate
+                            .inner
+                            .assign_thread_frame_buffer(thread_index);
+                        writer.push_location(entity_id, location);
+                    });
+                });
+            }
+        }
+
+        frame_buffer_manager.swap();
+
+        let frame_buffer_delegate = frame_buffer_manager.async_delegate();
+        let reader = frame_buffer_delegate.reader();
+
+        let locations: Vec<_> = reader.locations().map(|(_, location)| *location).collect();
+        assert_eq!(locations, [Vec3::zeros(), Vec3::new(1.0, 0.0, 0.0)]);
+
+        let snapshot = reader.snapshot();
+        assert_eq!(snapshot.locations[&entity_id], Vec3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn reader_sees_latest_camera_info() {
+        let mut frame_buffer_manager = FrameBufferManager::new(NonZeroUsize::new(1).unwrap());
+
+        for z in [2.0, 3.0] {
+            let camera_info = CameraInfo {
+                location: Vec3::new(0.0, 0.0, z),
+                ..Default::default()
+            };
+
+            frame_buffer_manager.swap();
+            frame_buffer_manager
+                .sync_delegate()
+                .set_camera_info(camera_info.clone());
+
+            let frame_buffer_delegate = frame_buffer_manager.async_delegate();
+            assert!(*frame_buffer_delegate.reader().camera_info() == camera_info);
+        }
+
+        // carried over when not written
+        frame_buffer_manager.swap();
+        let frame_buffer_delegate = frame_buffer_manager.async_delegate();
+        assert_eq!(
+            frame_buffer_delegate.reader().camera_info().location,
+            Vec3::new(0.0, 0.0, 3.0)
+        );
+    }
+
+    #[test]
+    fn orthographic_projection_differs_from_perspective() {
+        let perspective = CameraInfo::default();
+        let orthographic = CameraInfo {
+            projection: Projection::Orthographic { height: 10.0 },
+            ..Default::default()
+        };
+
+        let perspective = perspective.proj_matrix(1.5);
+        let orthographic = orthographic.proj_matrix(1.5);
+
+        assert_ne!(perspective, orthographic);
+
+        // only perspective divides by depth
+        assert_eq!(perspective[(3, 2)], 1.0);
+        assert_eq!(orthographic[(3, 2)], 0.0);
+        assert_eq!(orthographic[(3, 3)], 1.0);
+    }
+}
@@ -0,0 +1,192 @@
+use std::sync::Arc;
+
+use game_entity::{EntityId, EntityMap};
+use game_resources::Resource;
+
+use crate::{
+    FrameBufferReader, RenderStyle, SpawnedGuest, SpawnedStaticMesh, SyncFrameBufferDelegate,
+    Transform,
+};
+
+/// Accumulates everything the renderer has been told about across frames, so that a newly
+/// created renderer can be brought up to date with the existing scene
+#[derive(Default)]
+pub struct SceneState {
+    entities: EntityMap<SceneEntity>,
+}
+
+#[derive(Clone)]
+enum SceneEntityKind {
+    Guest(Option<Arc<Resource>>),
+    StaticMesh(Arc<Resource>),
+}
+
+#[derive(Clone)]
+struct SceneEntity {
+    kind: SceneEntityKind,
+    transform: Option<Transform>,
+    visible: bool,
+    style: RenderStyle,
+}
+
+impl SceneEntity {
+    fn new(kind: SceneEntityKind) -> Self {
+        Self {
+            kind,
+            transform: None,
+            visible: true,
+            style: RenderStyle::Solid,
+        }
+    }
+}
+
+impl SceneState {
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn transform(&self, entity_id: EntityId) -> Option<&Transform> {
+        self.entities
+            .get(entity_id)
+            .and_then(|entity| entity.transform.as_ref())
+    }
+
+    /// Records the changes in the frame, in the order renderers apply them
+    pub fn apply(&mut self, frame_buffer: &FrameBufferReader) {
+        for entity_id in frame_buffer.despawned() {
+            self.entities.try_remove(*entity_id);
+        }
+
+        for (old_id, new_id) in frame_buffer.updated_entity_ids() {
+            if let Some(entity) = self.entities.try_remove(*old_id) {
+                self.entities.insert(*new_id, entity);
+            }
+        }
+
+        for static_mesh in frame_buffer.spawned_static_meshes() {
+            let kind = SceneEntityKind::StaticMesh(static_mesh.resource.clone());
+            self.entities
+                .insert(static_mesh.entity_id, SceneEntity::new(kind));
+        }
+
+        for guest in frame_buffer.spawned_guests() {
+            let kind = SceneEntityKind::Guest(guest.resource.clone());
+            self.entities
+                .insert(guest.entity_id, SceneEntity::new(kind));
+        }
+
+        for (entity_id, visible) in frame_buffer.visibility() {
+            if let Some(entity) = self.entities.get_mut(*entity_id) {
+                entity.visible = *visible;
+            }
+        }
+
+        for (entity_id, style) in frame_buffer.render_styles() {
+            if let Some(entity) = self.entities.get_mut(*entity_id) {
+                entity.style = *style;
+            }
+        }
+
+        for (entity_id, transform) in frame_buffer.transforms() {
+            if let Some(entity) = self.entities.get_mut(entity_id) {
+                entity.transform = Some(*transform);
+            }
+        }
+    }
+
+    /// Spawns every recorded entity in its current state. Must be called after the frame
+    /// buffer is swapped, so that the spawns are read by the next render.
+    pub fn replay(&self, frame_buffer: &mut SyncFrameBufferDelegate) {
+        for (entity_id, entity) in &self.entities {
+            match &entity.kind {
+                SceneEntityKind::Guest(resource) => frame_buffer.spawn_guest(SpawnedGuest {
+                    entity_id: *entity_id,
+                    resource: resource.clone(),
+                }),
+                SceneEntityKind::StaticMesh(resource) => {
+                    frame_buffer.spawn_static_mesh(SpawnedStaticMesh {
+                        entity_id: *entity_id,
+                        resource: resource.clone(),
+                    })
+                }
+            }
+
+            if !entity.visible {
+                frame_buffer.set_visible(*entity_id, false);
+            }
+
+            if entity.style != RenderStyle::Solid {
+                frame_buffer.set_render_style(*entity_id, entity.style);
+            }
+
+            if let Some(transform) = entity.transform {
+                frame_buffer.push_transform(*entity_id, transform);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use crate::FrameBufferManager;
+
+    use super::*;
+
+    /// Visibility changes which the replay of `scene` pushes to a fresh frame buffer
+    fn replayed_visibility(scene: &SceneState) -> Vec<(u32, bool)> {
+        let mut frame_buffer_manager = FrameBufferManager::new(NonZeroUsize::new(1).unwrap());
+        frame_buffer_manager.assign_thread_frame_buffer(0);
+        frame_buffer_manager.swap();
+
+        scene.replay(&mut frame_buffer_manager.sync_delegate());
+
+        frame_buffer_manager
+            .async_delegate()
+            .reader()
+            .visibility()
+            .map(|(entity_id, visible)| (entity_id.get(), *visible))
+            .collect()
+    }
+
+    /// Records a frame in which `update` writes to the frame buffer
+    fn apply_frame(
+        scene: &mut SceneState,
+        frame_buffer_manager: &mut FrameBufferManager,
+        update: impl FnOnce(&mut SyncFrameBufferDelegate),
+    ) {
+        frame_buffer_manager.swap();
+        update(&mut frame_buffer_manager.sync_delegate());
+        scene.apply(&frame_buffer_manager.async_delegate().reader());
+    }
+
+    #[test]
+    fn replays_visibility_toggles() {
+        let mut frame_buffer_manager = FrameBufferManager::new(NonZeroUsize::new(1).unwrap());
+        frame_buffer_manager.assign_thread_frame_buffer(0);
+
+        let mut scene = SceneState::default();
+        let entity_id = EntityId::new(1);
+
+        apply_frame(&mut scene, &mut frame_buffer_manager, |frame_buffer| {
+            frame_buffer.spawn_guest(SpawnedGuest::new(entity_id));
+            frame_buffer.set_visible(entity_id, false);
+        });
+        assert_eq!(replayed_visibility(&scene), [(1, false)]);
+
+        apply_frame(&mut scene, &mut frame_buffer_manager, |frame_buffer| {
+            frame_buffer.set_visible(entity_id, true)
+        });
+        assert_eq!(replayed_visibility(&scene), []);
+
+        apply_frame(&mut scene, &mut frame_buffer_manager, |frame_buffer| {
+            frame_buffer.set_visible(entity_id, false)
+        });
+        assert_eq!(replayed_visibility(&scene), [(1, false)]);
+    }
+}
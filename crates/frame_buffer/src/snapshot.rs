@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+
+use game_entity::EntityId;
+use nalgebra_glm::Vec3;
+
+use crate::{CameraInfo, FrameBufferReader};
+
+/// An owned copy of the frame buffer read state, for replay and debugging. Taking a snapshot
+/// clones every buffer, so avoid doing so every frame outside of debugging.
+#[derive(Clone, Default)]
+pub struct FrameSnapshot {
+    pub spawned_guests: Vec<EntityId>,
+    pub spawned_static_meshes: Vec<EntityId>,
+    pub despawned: Vec<EntityId>,
+    /// Final location of each entity written during the frame
+    pub locations: BTreeMap<EntityId, Vec3>,
+    pub camera_info: CameraInfo,
+}
+
+/// Differences between two frame snapshots
+#[derive(Default)]
+pub struct FrameDelta {
+    /// Entities with a location in both snapshots, whose locations differ
+    pub moved: Vec<(EntityId, Vec3, Vec3)>,
+    /// Entities with a location only in the newer snapshot
+    pub added: Vec<(EntityId, Vec3)>,
+    /// Entities with a location only in the older snapshot
+    pub removed: Vec<EntityId>,
+    pub camera_changed: bool,
+}
+
+impl FrameDelta {
+    pub fn is_empty(&self) -> bool {
+        self.moved.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && !self.camera_changed
+    }
+}
+
+impl FrameBufferReader<'_> {
+    pub fn snapshot(&self) -> FrameSnapshot {
+        FrameSnapshot {
+            spawned_guests: self.spawned_guests().map(|guest| guest.entity_id).collect(),
+            spawned_static_meshes: self
+                .spawned_static_meshes()
+                .map(|static_mesh| static_mesh.entity_id)
+                .collect(),
+            despawned: self.despawned().copied().collect(),
+            // last write wins, matching the order locations are applied in
+            locations: self
+                .locations()
+                .map(|(entity_id, location)| (entity_id, *location))
+                .collect(),
+            camera_info: self.camera_info().clone(),
+        }
+    }
+}
+
+impl FrameSnapshot {
+    /// Changes from `prev` to this snapshot
+    pub fn diff(&self, prev: &FrameSnapshot) -> FrameDelta {
+        let mut delta = FrameDelta::default();
+
+        for (entity_id, location) in &self.locations {
+            match prev.locations.get(entity_id) {
+                Some(prev_location) if prev_location != location => {
+                    delta.moved.push((*entity_id, *prev_location, *location));
+                }
+                Some(_) => {}
+                None => delta.added.push((*entity_id, *location)),
+            }
+        }
+
+        delta.removed = prev
+            .locations
+            .keys()
+            .filter(|entity_id| !self.locations.contains_key(entity_id))
+            .copied()
+            .collect();
+
+        delta.camera_changed = self.camera_info != prev.camera_info;
+
+        delta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(locations: &[(u32, Vec3)]) -> FrameSnapshot {
+        FrameSnapshot {
+            locations: locations
+                .iter()
+                .map(|(entity_id, location)| (EntityId::new(*entity_id), *location))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn diff_lists_only_moved_entity() {
+        let prev = snapshot(&[(1, Vec3::new(0.0, 0.0, 0.0)), (2, Vec3::new(1.0, 0.0, 0.0))]);
+        let next = snapshot(&[(1, Vec3::new(0.0, 0.0, 0.0)), (2, Vec3::new(1.0, 2.0, 0.0))]);
+
+        let delta = next.diff(&prev);
+
+        assert_eq!(delta.moved.len(), 1);
+        let (entity_id, from, to) = delta.moved[0];
+        assert_eq!(entity_id.get(), 2);
+        assert_eq!(from, Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(to, Vec3::new(1.0, 2.0, 0.0));
+
+        assert!(delta.added.is_empty());
+        assert!(delta.removed.is_empty());
+        assert!(!delta.camera_changed);
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::{Duration, Instant};
+
 use game_engine::GameEngine;
 use log::LevelFilter;
 use winit::{
@@ -6,6 +8,9 @@ use winit::{
     window::WindowBuilder,
 };
 
+const WINDOW_TITLE: &str = "Theme Park Sim";
+const TITLE_UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+
 fn main() {
     GameClient::start();
 }
@@ -20,10 +25,20 @@ impl GameClient {
         #[cfg(not(debug_assertions))]
         env_logger::builder().filter_level(LevelFilter::Warn).init();
 
+        #[cfg(feature = "trace")]
+        tracing_subscriber::fmt()
+            .with_max_level(tracing_subscriber::filter::LevelFilter::TRACE)
+            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+            .init();
+
         let event_loop = EventLoop::new();
-        let window = WindowBuilder::new().build(&event_loop).unwrap();
+        let window = WindowBuilder::new()
+            .with_title(WINDOW_TITLE)
+            .build(&event_loop)
+            .unwrap();
 
         let mut engine = GameEngine::new(&window);
+        let mut last_title_update_instant = Instant::now();
 
         event_loop.run(move |event, _, control_flow| {
             *control_flow = ControlFlow::Poll;
@@ -40,7 +55,16 @@ impl GameClient {
                     engine.handle_window_event(event);
                 }
                 Event::MainEventsCleared => {
-                    engine.frame();
+                    if let Err(err) = engine.frame() {
+                        log::error!("{err}");
+                        *control_flow = ControlFlow::Exit;
+                        return;
+                    }
+
+                    if last_title_update_instant.elapsed() >= TITLE_UPDATE_INTERVAL {
+                        last_title_update_instant = Instant::now();
+                        window.set_title(&engine.frame_stats().window_title(WINDOW_TITLE));
+                    }
                 }
                 _ => (),
             }
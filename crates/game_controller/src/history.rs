@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+
+use game_entity::EntityId;
+use nalgebra_glm::Vec3;
+
+/// An operation made by the local player, which may be undone
+#[derive(Clone, Copy)]
+pub enum Operation {
+    /// An object was placed at the location
+    Spawn { entity_id: EntityId, location: Vec3 },
+    Move {
+        entity_id: EntityId,
+        from: Vec3,
+        to: Vec3,
+    },
+    /// An object was despawned from the location
+    Despawn { entity_id: EntityId, location: Vec3 },
+}
+
+impl Operation {
+    fn entity_id(&self) -> EntityId {
+        match *self {
+            Self::Spawn { entity_id, .. }
+            | Self::Move { entity_id, .. }
+            | Self::Despawn { entity_id, .. } => entity_id,
+        }
+    }
+
+    fn entity_id_mut(&mut self) -> &mut EntityId {
+        match self {
+            Self::Spawn { entity_id, .. }
+            | Self::Move { entity_id, .. }
+            | Self::Despawn { entity_id, .. } => entity_id,
+        }
+    }
+}
+
+/// Bounded undo and redo stacks. Recording an operation discards any redoable operations.
+pub struct History {
+    undo: VecDeque<Operation>,
+    redo: Vec<Operation>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            undo: VecDeque::with_capacity(capacity),
+            redo: Vec::new(),
+            capacity,
+        }
+    }
+
+    /// Records an operation, forgetting the oldest if at capacity
+    pub fn record(&mut self, operation: Operation) {
+        self.redo.clear();
+
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.undo.len() == self.capacity {
+            self.undo.pop_front();
+        }
+
+        self.undo.push_back(operation);
+    }
+
+    /// Takes the latest operation to undo, which then may be redone
+    pub fn undo(&mut self) -> Option<Operation> {
+        let operation = self.undo.pop_back()?;
+        self.redo.push(operation);
+        Some(operation)
+    }
+
+    /// Takes the latest undone operation to redo, which then may be undone again
+    pub fn redo(&mut self) -> Option<Operation> {
+        let operation = self.redo.pop()?;
+        self.undo.push_back(operation);
+        Some(operation)
+    }
+
+    /// Follows an entity to a new id, e.g. when it is respawned or becomes replicable
+    pub fn update_entity_id(&mut self, old_id: EntityId, new_id: EntityId) {
+        for operation in self.undo.iter_mut().chain(&mut self.redo) {
+            let entity_id = operation.entity_id_mut();
+            if *entity_id == old_id {
+                *entity_id = new_id;
+            }
+        }
+    }
+
+    /// Forgets the operations on an entity, e.g. when it is despawned remotely
+    pub fn remove_entity(&mut self, entity_id: EntityId) {
+        self.undo
+            .retain(|operation| operation.entity_id() != entity_id);
+        self.redo
+            .retain(|operation| operation.entity_id() != entity_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::vec3;
+
+    use super::*;
+
+    fn spawn(id: u32) -> Operation {
+        Operation::Spawn {
+            entity_id: EntityId::new(id),
+            location: Vec3::zeros(),
+        }
+    }
+
+    fn undo_ids(history: &mut History) -> Vec<u32> {
+        let mut ids = Vec::new();
+        while let Some(operation) = history.undo() {
+            ids.push(operation.entity_id().get());
+        }
+        ids
+    }
+
+    #[test]
+    fn undo_then_redo() {
+        let mut history = History::new(8);
+        history.record(spawn(1));
+        history.record(Operation::Move {
+            entity_id: EntityId::new(1),
+            from: Vec3::zeros(),
+            to: vec3(1.0, 0.0, 0.0),
+        });
+
+        assert!(matches!(history.undo(), Some(Operation::Move { .. })));
+        assert!(matches!(history.undo(), Some(Operation::Spawn { .. })));
+        assert!(history.undo().is_none());
+
+        assert!(matches!(history.redo(), Some(Operation::Spawn { .. })));
+        assert!(matches!(history.redo(), Some(Operation::Move { .. })));
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn record_discards_redo() {
+        let mut history = History::new(8);
+        history.record(spawn(1));
+        history.undo();
+
+        history.record(spawn(2));
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn forgets_oldest_at_capacity() {
+        let mut history = History::new(2);
+        for id in 1..=3 {
+            history.record(spawn(id));
+        }
+
+        assert_eq!(undo_ids(&mut history), [3, 2]);
+    }
+
+    #[test]
+    fn follows_and_forgets_entities() {
+        let mut history = History::new(8);
+        history.record(spawn(1));
+        history.record(spawn(2));
+        history.record(spawn(3));
+
+        history.update_entity_id(EntityId::new(1), EntityId::new(4));
+        history.remove_entity(EntityId::new(2));
+
+        assert_eq!(undo_ids(&mut history), [3, 4]);
+    }
+}
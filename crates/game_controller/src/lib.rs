@@ -1,16 +1,43 @@
-use event::{GameEvent, InputEvent, SyncEventDelegate, SystemGameEvent};
-use frame_buffer::{SpawnedStaticMesh, SyncFrameBufferDelegate};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddr},
+    num::NonZeroUsize,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use event::{GameEvent, InputEvent, SyncEventDelegate, SyncGameEventWriter, SystemGameEvent};
+use frame_buffer::{RenderStyle, SpawnedGuest, SpawnedStaticMesh, SyncFrameBufferDelegate};
 use game_entity::EntityId;
 use game_input::GameInputInterface;
-use game_resources::ResourceManager;
+use game_resources::{Resource, ResourceManager};
 use nalgebra_glm::Vec3;
 use system_camera::CameraInterface;
 use system_interfaces::physics::Interface as PhysicsInterface;
 
-use self::world::World;
+use self::{
+    history::{History, Operation},
+    selection::Selection,
+    world::World,
+};
 
+mod history;
+mod selection;
 mod world;
 
+const DEFAULT_SPAWN_COOLDOWN: Duration = Duration::from_millis(250);
+
+/// Typical adult walking speed, in m/s
+const DEFAULT_GUEST_WALK_SPEED: f32 = 1.4;
+
+const DEFAULT_SERVER_PORT: u16 = 12351;
+
+/// Number of operations which may be undone
+const HISTORY_CAPACITY: usize = 64;
+
+/// Radius kept clear around a placed object whose mesh is not loaded
+const DEFAULT_PLACEMENT_RADIUS: f32 = 0.5;
+
 #[derive(PartialEq, Eq)]
 pub enum NetworkRole {
     Offline,
@@ -23,7 +50,19 @@ pub struct GameController {
     resource_manager: ResourceManager,
     world: World,
     placing_object: Option<EntityId>,
+    /// Resource of the object being placed, which determines the space it needs
+    placing_resource: Option<Arc<Resource>>,
+    history: History,
+    /// Locations of objects placed by the local player, whose operations may be undone
+    placed_objects: HashMap<EntityId, Vec3>,
+    selection: Selection,
     network_role: NetworkRole,
+    spawn_cooldown: Duration,
+    last_spawn_instant: Option<Instant>,
+    pending_guest_spawns: usize,
+    guest_spawn_rate: Option<NonZeroUsize>,
+    guest_walk_speed: f32,
+    server_addr: SocketAddr,
 }
 
 impl GameController {
@@ -33,10 +72,68 @@ impl GameController {
             resource_manager: Default::default(),
             world: Default::default(),
             placing_object: None,
+            placing_resource: None,
+            history: History::new(HISTORY_CAPACITY),
+            placed_objects: HashMap::new(),
+            selection: Default::default(),
             network_role: NetworkRole::Offline,
+            spawn_cooldown: DEFAULT_SPAWN_COOLDOWN,
+            last_spawn_instant: None,
+            pending_guest_spawns: 0,
+            guest_spawn_rate: None,
+            guest_walk_speed: DEFAULT_GUEST_WALK_SPEED,
+            server_addr: (Ipv4Addr::LOCALHOST, DEFAULT_SERVER_PORT).into(),
         }
     }
 
+    /// All entities currently in the world, both local and replicated
+    pub fn entities(&self) -> &[EntityId] {
+        self.world.entities()
+    }
+
+    /// Minimum interval between object spawns
+    pub fn set_spawn_cooldown(&mut self, cooldown: Duration) {
+        self.spawn_cooldown = cooldown;
+    }
+
+    /// Maximum guests spawned per frame, with further requested spawns deferred to later frames.
+    /// None spawns every requested guest immediately.
+    pub fn set_guest_spawn_rate(&mut self, guests_per_frame: Option<NonZeroUsize>) {
+        self.guest_spawn_rate = guests_per_frame;
+    }
+
+    /// Walking speed of guests spawned from now on, in m/s
+    pub fn set_guest_walk_speed(&mut self, walk_speed: f32) {
+        self.guest_walk_speed = walk_speed;
+    }
+
+    /// Address the server binds to, or the client connects to. Takes effect the next time the
+    /// network role changes.
+    pub fn set_server_addr(&mut self, addr: SocketAddr) {
+        self.server_addr = addr;
+    }
+
+    pub fn server_addr(&self) -> SocketAddr {
+        self.server_addr
+    }
+
+    /// Objects selected for editing, in the order they were selected
+    pub fn selection(&self) -> &[EntityId] {
+        self.selection.entities()
+    }
+
+    /// Guests requested but not yet spawned
+    pub fn pending_guest_spawns(&self) -> usize {
+        self.pending_guest_spawns
+    }
+
+    /// Time until another object may be spawned
+    pub fn spawn_cooldown_remaining(&self) -> Duration {
+        self.last_spawn_instant
+            .map(|instant| self.spawn_cooldown.saturating_sub(instant.elapsed()))
+            .unwrap_or_default()
+    }
+
     pub fn update(
         &mut self,
         event_delegate: &mut SyncEventDelegate,
@@ -48,15 +145,78 @@ impl GameController {
 
         self.handle_input_events(event_delegate, frame_buffer, input, camera);
 
+        self.spawn_pending_guests(event_delegate, frame_buffer);
+
         // object placement
 
         if let Some(entity_id) = &self.placing_object {
-            if let Some(hit_location) = self.location_under_cursor(input, camera) {
+            if let Some(hit_location) = self.location_under_cursor(input, camera, *entity_id) {
                 let event = GameEvent::StaticMeshLocation(*entity_id, hit_location);
                 event_delegate.push_game_event(event);
                 frame_buffer.push_location(*entity_id, hit_location);
+
+                let style = if self.is_placement_blocked(*entity_id, &hit_location) {
+                    RenderStyle::InvalidGhost
+                } else {
+                    RenderStyle::Ghost
+                };
+                frame_buffer.set_render_style(*entity_id, style);
+            } else {
+                frame_buffer.set_render_style(*entity_id, RenderStyle::InvalidGhost);
             }
         }
+
+        self.update_drag(event_delegate, frame_buffer, input, camera);
+    }
+
+    /// Shows or hides an entity without despawning it
+    pub fn set_visible(
+        &mut self,
+        event_delegate: &mut SyncEventDelegate,
+        frame_buffer: &mut SyncFrameBufferDelegate,
+        entity_id: EntityId,
+        visible: bool,
+    ) {
+        event_delegate.push_game_event(GameEvent::SetVisible(entity_id, visible));
+        frame_buffer.set_visible(entity_id, visible);
+    }
+
+    /// Moves an object. Moves of objects placed by the local player may be undone.
+    pub fn move_object(
+        &mut self,
+        event_delegate: &mut SyncEventDelegate,
+        frame_buffer: &mut SyncFrameBufferDelegate,
+        entity_id: EntityId,
+        location: Vec3,
+    ) {
+        if let Some(from) = self.placed_objects.get(&entity_id).copied() {
+            self.history.record(Operation::Move {
+                entity_id,
+                from,
+                to: location,
+            });
+        }
+
+        let mut game_event_writer = event_delegate.game_event_writer();
+        self.set_location(&mut game_event_writer, frame_buffer, entity_id, location);
+    }
+
+    /// Despawns an object. Despawns of objects placed by the local player may be undone.
+    pub fn despawn_object(
+        &mut self,
+        event_delegate: &mut SyncEventDelegate,
+        frame_buffer: &mut SyncFrameBufferDelegate,
+        entity_id: EntityId,
+    ) {
+        if let Some(location) = self.placed_objects.get(&entity_id).copied() {
+            self.history.record(Operation::Despawn {
+                entity_id,
+                location,
+            });
+        }
+
+        let mut game_event_writer = event_delegate.game_event_writer();
+        self.remove_object(&mut game_event_writer, frame_buffer, entity_id);
     }
 
     fn handle_system_game_events(
@@ -80,19 +240,30 @@ impl GameController {
                         resource: self.resource_manager.resource("sphere".to_string()),
                     });
                 }
-                NetworkSpawnGuest(entity_id) => {
+                NetworkSpawnGuest {
+                    entity_id,
+                    walk_speed,
+                } => {
                     // client-only
                     self.world.remote_spawn(*entity_id);
                     game_event_writer.push_game_event(GameEvent::SpawnGuest {
                         entity_id: *entity_id,
                         replicate: false,
+                        walk_speed: *walk_speed,
                     });
-                    frame_buffer.spawn_static_mesh(SpawnedStaticMesh {
-                        entity_id: *entity_id,
-                        resource: self.resource_manager.resource("sphere".to_string()),
-                    });
+                    frame_buffer.spawn_guest(SpawnedGuest::new(*entity_id));
                 }
-                NetworkDespawn(entity_id) => {
+                NetworkDespawn(entity_id) | GuestLeft(entity_id) => {
+                    // despawns may be resent by the server if an ack was lost
+                    if !self.world.contains(*entity_id) {
+                        continue;
+                    }
+
+                    // operations on the object can no longer be undone
+                    self.history.remove_entity(*entity_id);
+                    self.placed_objects.remove(entity_id);
+                    self.selection.remove(*entity_id);
+
                     self.world.despawn(*entity_id);
                     game_event_writer.push_game_event(GameEvent::Despawn(*entity_id));
                     frame_buffer.despawn(*entity_id);
@@ -130,6 +301,18 @@ impl GameController {
                     if self.placing_object == Some(*client_id) {
                         self.placing_object = Some(*replicable_id);
                     }
+
+                    self.history.update_entity_id(*client_id, *replicable_id);
+                    self.selection.update_entity_id(*client_id, *replicable_id);
+
+                    if let Some(location) = self.placed_objects.remove(client_id) {
+                        self.placed_objects.insert(*replicable_id, location);
+                    }
+                }
+                NetworkRoleFailed => {
+                    // the network system is already offline, other systems follow it
+                    game_event_writer.push_game_event(GameEvent::NetworkRoleOffline);
+                    self.network_role = NetworkRole::Offline;
                 }
             }
         }
@@ -145,40 +328,78 @@ impl GameController {
         let (mut game_event_writer, input_events) = event_delegate.input_events_mut();
         for input_event in input_events {
             match input_event {
-                InputEvent::Spawn if self.placing_object.is_none() => {
-                    let entity_id = if self.network_role != NetworkRole::Client {
-                        self.world.spawn_replicable()
-                    } else {
-                        self.world.spawn()
-                    };
+                InputEvent::Spawn
+                    if self.placing_object.is_none()
+                        && self.spawn_cooldown_remaining().is_zero() =>
+                {
+                    self.last_spawn_instant = Some(Instant::now());
 
-                    game_event_writer.push_game_event(GameEvent::Spawn {
-                        entity_id,
-                        replicate: true,
-                    });
+                    let (entity_id, resource) =
+                        self.spawn_object(&mut game_event_writer, frame_buffer);
 
-                    frame_buffer.spawn_static_mesh(SpawnedStaticMesh {
-                        entity_id,
-                        resource: self.resource_manager.resource("sphere".to_string()),
-                    });
+                    frame_buffer.set_render_style(entity_id, RenderStyle::Ghost);
 
                     self.placing_object = Some(entity_id);
+                    self.placing_resource = Some(resource);
                 }
                 InputEvent::MouseButton(true) => {
-                    if let Some(entity_id) = self.placing_object.take() {
-                        if let Some(location) = self.location_under_cursor(input, camera) {
-                            let event = GameEvent::StaticMeshLocation(entity_id, location);
-                            game_event_writer.push_game_event(event);
-                            frame_buffer.push_location(entity_id, location);
-                        }
+                    if let Some(entity_id) = self.placing_object {
+                        // placing continues while the location is invalid, as shown by the ghost
+                        let location = match self.location_under_cursor(input, camera, entity_id) {
+                            Some(location) if self.is_placement_blocked(entity_id, &location) => {
+                                game_event_writer
+                                    .push_game_event(GameEvent::PlacementBlocked(entity_id));
+                                continue;
+                            }
+                            Some(location) => location,
+                            None => continue,
+                        };
+
+                        self.placing_object = None;
+                        self.placing_resource = None;
+
+                        self.placed_objects.insert(entity_id, location);
+                        self.set_location(
+                            &mut game_event_writer,
+                            frame_buffer,
+                            entity_id,
+                            location,
+                        );
+                        frame_buffer.set_render_style(entity_id, RenderStyle::Solid);
+
+                        self.history.record(Operation::Spawn {
+                            entity_id,
+                            location,
+                        });
+                    } else {
+                        self.select_under_cursor(input, camera);
                     }
                 }
+                InputEvent::MouseButton(false) => {
+                    if let Some(drag) = self.selection.end_drag() {
+                        self.record_drag(drag.origins, drag.offset);
+                    }
+                }
+                InputEvent::Cancel => {
+                    // the object was never placed, so there is nothing to undo
+                    if let Some(entity_id) = self.placing_object {
+                        self.remove_object(&mut game_event_writer, frame_buffer, entity_id);
+                    }
+                }
+                InputEvent::Undo => {
+                    self.undo(&mut game_event_writer, frame_buffer);
+                }
+                InputEvent::Redo => {
+                    self.redo(&mut game_event_writer, frame_buffer);
+                }
                 InputEvent::ServerBegin => {
-                    game_event_writer.push_game_event(GameEvent::NetworkRoleServer);
+                    game_event_writer
+                        .push_game_event(GameEvent::NetworkRoleServer(self.server_addr));
                     self.network_role = NetworkRole::Server;
                 }
                 InputEvent::ServerConnect => {
-                    game_event_writer.push_game_event(GameEvent::NetworkRoleClient);
+                    game_event_writer
+                        .push_game_event(GameEvent::NetworkRoleClient(self.server_addr));
                     self.network_role = NetworkRole::Client;
                 }
                 InputEvent::ServerDisconnect => {
@@ -186,28 +407,618 @@ impl GameController {
                     self.network_role = NetworkRole::Offline;
                 }
                 InputEvent::SpawnGuest if self.network_role != NetworkRole::Client => {
-                    let entity_id = self.world.spawn_replicable();
+                    self.pending_guest_spawns += 1;
+                }
+                _ => {}
+            }
+        }
+    }
 
-                    game_event_writer.push_game_event(GameEvent::SpawnGuest {
-                        entity_id,
-                        replicate: true,
-                    });
+    fn spawn_pending_guests(
+        &mut self,
+        event_delegate: &mut SyncEventDelegate,
+        frame_buffer: &mut SyncFrameBufferDelegate,
+    ) {
+        // guests requested before becoming a client are dropped, as only the server spawns them
+        if self.network_role == NetworkRole::Client {
+            self.pending_guest_spawns = 0;
+            return;
+        }
+
+        let count = match self.guest_spawn_rate {
+            Some(rate) => self.pending_guest_spawns.min(rate.get()),
+            None => self.pending_guest_spawns,
+        };
+
+        self.pending_guest_spawns -= count;
+
+        for _ in 0..count {
+            let entity_id = self.world.spawn_replicable();
+
+            event_delegate.push_game_event(GameEvent::SpawnGuest {
+                entity_id,
+                replicate: true,
+                walk_speed: self.guest_walk_speed,
+            });
+
+            frame_buffer.spawn_guest(SpawnedGuest::new(entity_id));
+        }
+    }
+
+    /// Reverts the latest operation made by the local player
+    fn undo(
+        &mut self,
+        game_event_writer: &mut SyncGameEventWriter,
+        frame_buffer: &mut SyncFrameBufferDelegate,
+    ) {
+        match self.history.undo() {
+            Some(Operation::Spawn { entity_id, .. }) => {
+                self.remove_object(game_event_writer, frame_buffer, entity_id);
+            }
+            Some(Operation::Move {
+                entity_id, from, ..
+            }) => {
+                self.set_location(game_event_writer, frame_buffer, entity_id, from);
+            }
+            Some(Operation::Despawn {
+                entity_id,
+                location,
+            }) => {
+                self.respawn_object(game_event_writer, frame_buffer, entity_id, location);
+            }
+            None => {}
+        }
+    }
+
+    /// Reapplies the latest undone operation
+    fn redo(
+        &mut self,
+        game_event_writer: &mut SyncGameEventWriter,
+        frame_buffer: &mut SyncFrameBufferDelegate,
+    ) {
+        match self.history.redo() {
+            Some(Operation::Spawn {
+                entity_id,
+                location,
+            }) => {
+                self.respawn_object(game_event_writer, frame_buffer, entity_id, location);
+            }
+            Some(Operation::Move { entity_id, to, .. }) => {
+                self.set_location(game_event_writer, frame_buffer, entity_id, to);
+            }
+            Some(Operation::Despawn { entity_id, .. }) => {
+                self.remove_object(game_event_writer, frame_buffer, entity_id);
+            }
+            None => {}
+        }
+    }
+
+    /// Spawns a replicated static mesh object at the origin
+    fn spawn_object(
+        &mut self,
+        game_event_writer: &mut SyncGameEventWriter,
+        frame_buffer: &mut SyncFrameBufferDelegate,
+    ) -> (EntityId, Arc<Resource>) {
+        let entity_id = if self.network_role != NetworkRole::Client {
+            self.world.spawn_replicable()
+        } else {
+            self.world.spawn()
+        };
+
+        game_event_writer.push_game_event(GameEvent::Spawn {
+            entity_id,
+            replicate: true,
+        });
+
+        let resource = self.resource_manager.resource("sphere".to_string());
+
+        frame_buffer.spawn_static_mesh(SpawnedStaticMesh {
+            entity_id,
+            resource: resource.clone(),
+        });
+
+        (entity_id, resource)
+    }
+
+    /// Spawns a previously placed object again. It has a new id, which its recorded operations
+    /// follow.
+    fn respawn_object(
+        &mut self,
+        game_event_writer: &mut SyncGameEventWriter,
+        frame_buffer: &mut SyncFrameBufferDelegate,
+        old_id: EntityId,
+        location: Vec3,
+    ) {
+        let (entity_id, _) = self.spawn_object(game_event_writer, frame_buffer);
+
+        self.placed_objects.insert(entity_id, location);
+        self.set_location(game_event_writer, frame_buffer, entity_id, location);
 
-                    frame_buffer.spawn_guest(entity_id);
+        self.history.update_entity_id(old_id, entity_id);
+    }
+
+    fn set_location(
+        &mut self,
+        game_event_writer: &mut SyncGameEventWriter,
+        frame_buffer: &mut SyncFrameBufferDelegate,
+        entity_id: EntityId,
+        location: Vec3,
+    ) {
+        game_event_writer.push_game_event(GameEvent::StaticMeshLocation(entity_id, location));
+        frame_buffer.push_location(entity_id, location);
+
+        if let Some(placed_location) = self.placed_objects.get_mut(&entity_id) {
+            *placed_location = location;
+        }
+    }
+
+    fn remove_object(
+        &mut self,
+        game_event_writer: &mut SyncGameEventWriter,
+        frame_buffer: &mut SyncFrameBufferDelegate,
+        entity_id: EntityId,
+    ) {
+        if !self.world.contains(entity_id) {
+            return;
+        }
+
+        if self.placing_object == Some(entity_id) {
+            self.placing_object = None;
+            self.placing_resource = None;
+        }
+
+        self.world.despawn(entity_id);
+        self.placed_objects.remove(&entity_id);
+        self.selection.remove(entity_id);
+
+        game_event_writer.push_game_event(GameEvent::Despawn(entity_id));
+        frame_buffer.despawn(entity_id);
+    }
+
+    /// Selects the object under the cursor, and begins dragging the selection. Clicking a
+    /// selected object keeps the selection, so that every selected object is dragged.
+    fn select_under_cursor(&mut self, input: GameInputInterface, camera: CameraInterface) {
+        let (origin, direction) = camera.deproject(&input.cursor_position_ndc());
+
+        let hit = self
+            .physics
+            .raycast_all(&origin, &direction)
+            .first()
+            .copied();
+
+        let entity_id = match hit {
+            Some((entity_id, _)) => entity_id,
+            None => {
+                // clicking empty space deselects
+                if !input.is_adding_to_selection() {
+                    self.selection.clear();
                 }
-                _ => {}
+                return;
             }
+        };
+
+        if input.is_adding_to_selection() {
+            self.selection.add(entity_id);
+        } else if !self.selection.contains(entity_id) {
+            self.selection.select(entity_id);
+        }
+
+        if let Some(start) = self.physics.raycast_ground(&origin, &direction) {
+            let origins = self
+                .selection
+                .entities()
+                .iter()
+                .filter_map(|entity_id| {
+                    let location = self.physics.collider_center(*entity_id)?;
+                    Some((*entity_id, location))
+                })
+                .collect();
+
+            self.selection.begin_drag(start, origins);
         }
     }
 
+    /// Moves the dragged objects by the cursor's movement over the ground since the drag began
+    fn update_drag(
+        &mut self,
+        event_delegate: &mut SyncEventDelegate,
+        frame_buffer: &mut SyncFrameBufferDelegate,
+        input: GameInputInterface,
+        camera: CameraInterface,
+    ) {
+        let drag = match self.selection.drag_mut() {
+            Some(drag) => drag,
+            None => return,
+        };
+
+        let (origin, direction) = camera.deproject(&input.cursor_position_ndc());
+
+        let offset = match self.physics.raycast_ground(&origin, &direction) {
+            Some(location) => location - drag.start,
+            None => return,
+        };
+
+        if offset == drag.offset {
+            return;
+        }
+
+        drag.offset = offset;
+
+        for (entity_id, drag_origin) in &drag.origins {
+            let location = drag_origin + offset;
+            event_delegate.push_game_event(GameEvent::StaticMeshLocation(*entity_id, location));
+            frame_buffer.push_location(*entity_id, location);
+        }
+    }
+
+    /// Records the moves of a finished drag, so that each may be undone
+    fn record_drag(&mut self, origins: Vec<(EntityId, Vec3)>, offset: Vec3) {
+        if offset == Vec3::zeros() {
+            return;
+        }
+
+        for (entity_id, from) in origins {
+            if let Some(location) = self.placed_objects.get_mut(&entity_id) {
+                let to = from + offset;
+                *location = to;
+
+                self.history.record(Operation::Move {
+                    entity_id,
+                    from,
+                    to,
+                });
+            }
+        }
+    }
+
+    /// Whether the object being placed would overlap another object at the location
+    fn is_placement_blocked(&self, placing: EntityId, location: &Vec3) -> bool {
+        // the mesh is loaded by the renderer once the object spawns, so may not be loaded yet.
+        // Loads aren't requested here, as the controller doesn't run on an executor thread.
+        let radius = match self
+            .placing_resource
+            .as_ref()
+            .and_then(|resource| resource.loaded_mesh())
+        {
+            Some(Ok(mesh)) => mesh.bounding_radius(),
+            _ => DEFAULT_PLACEMENT_RADIUS,
+        };
+
+        self.physics
+            .overlap_sphere(location, radius)
+            .into_iter()
+            .any(|entity_id| entity_id != placing)
+    }
+
+    /// Location to place `placing` at, on top of whatever else is under the cursor
     fn location_under_cursor(
         &self,
         input: GameInputInterface,
         camera: CameraInterface,
+        placing: EntityId,
     ) -> Option<Vec3> {
-        let origin = camera.location();
-        let orientation = camera.deproject(&input.cursor_position_ndc());
+        let (origin, direction) = camera.deproject(&input.cursor_position_ndc());
+
+        self.physics.raycast_ignoring(&origin, &direction, placing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use event::EventManager;
+    use frame_buffer::FrameBufferManager;
+    use game_input::GameInput;
+    use nalgebra_glm::vec3;
+    use system_camera::FrameData as Camera;
+    use system_interfaces::physics::{Collider, Data as PhysicsData};
+    use winit::{
+        dpi::{PhysicalPosition, PhysicalSize},
+        event::{DeviceId, WindowEvent},
+    };
+
+    use super::*;
+
+    /// Drives a controller on the test thread, with the camera looking straight down at the
+    /// origin so that the cursor is always over the ground
+    struct Harness {
+        controller: GameController,
+        event_manager: EventManager,
+        frame_buffer_manager: FrameBufferManager,
+        input: GameInput,
+        camera: Camera,
+        physics: PhysicsData,
+    }
+
+    /// Events produced by a frame
+    struct Frame {
+        game_events: Vec<GameEvent>,
+        despawned: Vec<EntityId>,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let thread_count = NonZeroUsize::new(1).unwrap();
+
+            let event_manager = EventManager::new(thread_count);
+            event_manager.assign_thread_event_buffer(0);
+
+            let mut frame_buffer_manager = FrameBufferManager::new(thread_count);
+            frame_buffer_manager.assign_thread_frame_buffer(0);
+
+            let physics = PhysicsData::default();
+            let mut camera = Camera::new(800, 600, physics.clone().into());
+            camera.set_pose(
+                &mut frame_buffer_manager.sync_delegate(),
+                Vec3::zeros(),
+                0.0,
+                FRAC_PI_2,
+                5.0,
+            );
+
+            Self {
+                controller: GameController::new(physics.clone().into()),
+                event_manager,
+                frame_buffer_manager,
+                input: GameInput::new(PhysicalSize::new(800, 600), 1.0),
+                camera,
+                physics,
+            }
+        }
+
+        fn frame(&mut self, input_events: &[InputEvent]) -> Frame {
+            self.event_manager.swap();
+            self.frame_buffer_manager.swap();
+
+            let mut event_delegate = self.event_manager.sync_delegate();
+            for input_event in input_events {
+                event_delegate.push_input_event(*input_event);
+            }
+
+            self.controller.update(
+                &mut event_delegate,
+                &mut self.frame_buffer_manager.sync_delegate(),
+                self.input.interface(),
+                self.camera.interface(),
+            );
+
+            Frame {
+                game_events: self
+                    .event_manager
+                    .async_delegate()
+                    .game_events()
+                    .copied()
+                    .collect(),
+                despawned: self
+                    .frame_buffer_manager
+                    .async_delegate()
+                    .reader()
+                    .despawned()
+                    .copied()
+                    .collect(),
+            }
+        }
+
+        /// Spawns and places an object, returning its id and location
+        fn place_object(&mut self) -> (EntityId, Vec3) {
+            self.frame(&[InputEvent::Spawn]);
+            let entity_id = self.controller.placing_object.unwrap();
+
+            self.frame(&[
+                InputEvent::MouseButton(true),
+                InputEvent::MouseButton(false),
+            ]);
+            assert!(self.controller.placing_object.is_none());
+
+            (entity_id, self.controller.placed_objects[&entity_id])
+        }
+
+        /// Moves the cursor to a position in physical pixels
+        fn move_cursor(&mut self, x: f64, y: f64) {
+            #[allow(deprecated)]
+            self.input.handle_input(WindowEvent::CursorMoved {
+                // SAFETY: the id is only compared, never used to access a device
+                device_id: unsafe { DeviceId::dummy() },
+                position: PhysicalPosition::new(x, y),
+                modifiers: Default::default(),
+            });
+        }
+
+        /// Adds the collider the physics system would give a placed object
+        fn add_collider(&mut self, entity_id: EntityId, location: Vec3) {
+            let collider = Collider::sphere(location, DEFAULT_PLACEMENT_RADIUS);
+            let mut physics = self.physics.try_write_single().unwrap();
+            physics.colliders.insert(entity_id, collider);
+        }
+    }
+
+    fn despawns(frame: &Frame) -> Vec<EntityId> {
+        frame
+            .game_events
+            .iter()
+            .filter_map(|event| match event {
+                GameEvent::Despawn(entity_id) => Some(*entity_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn locations(frame: &Frame) -> Vec<(EntityId, Vec3)> {
+        frame
+            .game_events
+            .iter()
+            .filter_map(|event| match event {
+                GameEvent::StaticMeshLocation(entity_id, location) => Some((*entity_id, *location)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn undo_spawn_despawns() {
+        let mut harness = Harness::new();
+        let (entity_id, _) = harness.place_object();
+
+        let frame = harness.frame(&[InputEvent::Undo]);
+        assert!(despawns(&frame) == [entity_id]);
+        assert!(frame.despawned == [entity_id]);
+        assert!(!harness.controller.entities().contains(&entity_id));
+    }
+
+    #[test]
+    fn undo_move_restores_location() {
+        let mut harness = Harness::new();
+        let (entity_id, from) = harness.place_object();
+
+        let mut event_delegate = harness.event_manager.sync_delegate();
+        harness.controller.move_object(
+            &mut event_delegate,
+            &mut harness.frame_buffer_manager.sync_delegate(),
+            entity_id,
+            vec3(2.0, 0.0, 2.0),
+        );
+
+        let frame = harness.frame(&[InputEvent::Undo]);
+        assert!(locations(&frame) == [(entity_id, from)]);
+        assert!(despawns(&frame).is_empty());
+
+        let frame = harness.frame(&[InputEvent::Redo]);
+        assert!(locations(&frame) == [(entity_id, vec3(2.0, 0.0, 2.0))]);
+    }
+
+    #[test]
+    fn cancel_despawns_placing_object() {
+        let mut harness = Harness::new();
+
+        harness.frame(&[InputEvent::Spawn]);
+        let entity_id = harness.controller.placing_object.unwrap();
+
+        let frame = harness.frame(&[InputEvent::Cancel]);
+        assert!(despawns(&frame) == [entity_id]);
+        assert!(harness.controller.placing_object.is_none());
+
+        // the placement was never recorded, so there is nothing to undo
+        let frame = harness.frame(&[InputEvent::Undo]);
+        assert!(frame.game_events.is_empty());
+    }
+
+    #[test]
+    fn cancel_without_placing_object_does_nothing() {
+        let mut harness = Harness::new();
+        let (entity_id, _) = harness.place_object();
+
+        let frame = harness.frame(&[InputEvent::Cancel]);
+        assert!(frame.game_events.is_empty());
+        assert!(frame.despawned.is_empty());
+        assert!(harness.controller.entities().contains(&entity_id));
+    }
+
+    #[test]
+    fn overlapping_placement_is_rejected() {
+        let mut harness = Harness::new();
+        harness.controller.set_spawn_cooldown(Duration::ZERO);
+
+        let (first_id, location) = harness.place_object();
+        harness.add_collider(first_id, location);
+
+        harness.frame(&[InputEvent::Spawn]);
+        let second_id = harness.controller.placing_object.unwrap();
+
+        // the cursor hasn't moved, so the second object is over the first
+        let frame = harness.frame(&[InputEvent::MouseButton(true)]);
+        assert!(frame
+            .game_events
+            .iter()
+            .any(|event| matches!(event, GameEvent::PlacementBlocked(id) if *id == second_id)));
+
+        assert!(harness.controller.placing_object == Some(second_id));
+        assert!(!harness.controller.placed_objects.contains_key(&second_id));
+    }
+
+    #[test]
+    fn spawns_within_cooldown_are_ignored() {
+        let mut harness = Harness::new();
+        harness
+            .controller
+            .set_spawn_cooldown(Duration::from_secs(60));
+
+        // cancelling each spawn means only the cooldown prevents the next
+        let spawns: usize = (0..3)
+            .map(|_| {
+                let frame = harness.frame(&[InputEvent::Spawn, InputEvent::Cancel]);
+                frame
+                    .game_events
+                    .iter()
+                    .filter(|event| matches!(event, GameEvent::Spawn { .. }))
+                    .count()
+            })
+            .sum();
+
+        assert_eq!(spawns, 1);
+        assert!(!harness.controller.spawn_cooldown_remaining().is_zero());
+    }
+
+    #[test]
+    fn guest_spawns_are_spread_over_frames() {
+        let mut harness = Harness::new();
+        harness
+            .controller
+            .set_guest_spawn_rate(NonZeroUsize::new(10));
+
+        let guest_spawns = |frame: &Frame| {
+            frame
+                .game_events
+                .iter()
+                .filter(|event| matches!(event, GameEvent::SpawnGuest { .. }))
+                .count()
+        };
+
+        let frame = harness.frame(&[InputEvent::SpawnGuest; 100]);
+        assert_eq!(guest_spawns(&frame), 10);
+
+        for remaining in (0..9).rev() {
+            let frame = harness.frame(&[]);
+            assert_eq!(guest_spawns(&frame), 10);
+            assert_eq!(harness.controller.pending_guest_spawns(), remaining * 10);
+        }
+
+        let frame = harness.frame(&[]);
+        assert_eq!(guest_spawns(&frame), 0);
+    }
+
+    #[test]
+    fn dragging_selection_moves_each_object_by_same_offset() {
+        let mut harness = Harness::new();
+        harness.controller.set_spawn_cooldown(Duration::ZERO);
+
+        harness.move_cursor(400.0, 300.0);
+        let (first_id, first_location) = harness.place_object();
+        harness.add_collider(first_id, first_location);
+
+        harness.move_cursor(700.0, 300.0);
+        let (second_id, second_location) = harness.place_object();
+        harness.add_collider(second_id, second_location);
+
+        harness.controller.selection.select(first_id);
+        harness.controller.selection.add(second_id);
+
+        // clicking a selected object drags the whole selection
+        harness.frame(&[InputEvent::MouseButton(true)]);
+
+        harness.move_cursor(500.0, 450.0);
+        let frame = harness.frame(&[InputEvent::CursorMoved]);
+
+        let moves = locations(&frame);
+        assert_eq!(moves.len(), 2);
+
+        let offset = |entity_id: EntityId, from: Vec3| {
+            let (_, to) = moves.iter().find(|(id, _)| *id == entity_id).unwrap();
+            to - from
+        };
 
-        self.physics.raycast(origin, &orientation)
+        let first_offset = offset(first_id, first_location);
+        assert!(first_offset.norm() > 0.1);
+        assert!((first_offset - offset(second_id, second_location)).norm() < 1e-4);
     }
 }
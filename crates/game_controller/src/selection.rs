@@ -0,0 +1,86 @@
+use game_entity::EntityId;
+use nalgebra_glm::Vec3;
+
+/// Entities selected for editing, and the drag moving them
+#[derive(Default)]
+pub struct Selection {
+    entities: Vec<EntityId>,
+    drag: Option<Drag>,
+}
+
+/// Moves every selected entity by the cursor's movement over the ground
+pub struct Drag {
+    /// Where the cursor met the ground when the drag began
+    pub start: Vec3,
+    /// Location of each dragged entity when the drag began
+    pub origins: Vec<(EntityId, Vec3)>,
+    /// Offset from the origins which was last applied
+    pub offset: Vec3,
+}
+
+impl Selection {
+    pub fn entities(&self) -> &[EntityId] {
+        &self.entities
+    }
+
+    pub fn contains(&self, entity_id: EntityId) -> bool {
+        self.entities.contains(&entity_id)
+    }
+
+    /// Replaces the selection with the entity
+    pub fn select(&mut self, entity_id: EntityId) {
+        self.clear();
+        self.entities.push(entity_id);
+    }
+
+    /// Adds the entity, unless already selected
+    pub fn add(&mut self, entity_id: EntityId) {
+        if !self.contains(entity_id) {
+            self.entities.push(entity_id);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entities.clear();
+        self.drag = None;
+    }
+
+    pub fn begin_drag(&mut self, start: Vec3, origins: Vec<(EntityId, Vec3)>) {
+        self.drag = Some(Drag {
+            start,
+            origins,
+            offset: Vec3::zeros(),
+        });
+    }
+
+    pub fn drag_mut(&mut self) -> Option<&mut Drag> {
+        self.drag.as_mut()
+    }
+
+    pub fn end_drag(&mut self) -> Option<Drag> {
+        self.drag.take()
+    }
+
+    /// Deselects the entity, e.g. when it is despawned
+    pub fn remove(&mut self, entity_id: EntityId) {
+        self.entities.retain(|selected| *selected != entity_id);
+
+        if let Some(drag) = &mut self.drag {
+            drag.origins.retain(|(dragged, _)| *dragged != entity_id);
+        }
+    }
+
+    /// Follows an entity to a new id, e.g. when it becomes replicable
+    pub fn update_entity_id(&mut self, old_id: EntityId, new_id: EntityId) {
+        let dragged = self
+            .drag
+            .iter_mut()
+            .flat_map(|drag| drag.origins.iter_mut().map(|(entity_id, _)| entity_id));
+
+        for entity_id in self.entities.iter_mut().chain(dragged) {
+            if *entity_id == old_id {
+                *entity_id = new_id;
+            }
+        }
+    }
+}
@@ -98,21 +98,58 @@ impl<T0, T1, const T1_LEN: usize> SharedData<T0, T1, T1_LEN> {
         self.inner.single_data.read().await
     }
 
+    /// Reads without waiting, e.g. from synchronous code. Returns None while locked for writing.
+    pub fn try_read_single(&self) -> Option<RwLockReadGuard<'_, T0>> {
+        self.inner.single_data.try_read()
+    }
+
     pub async fn write_single(&mut self) -> RwLockWriteGuard<'_, T0> {
         self.inner.single_data.write().await
     }
 
+    /// Writes without waiting, e.g. from synchronous code. Returns None while locked.
+    pub fn try_write_single(&mut self) -> Option<RwLockWriteGuard<'_, T0>> {
+        self.inner.single_data.try_write()
+    }
+
+    /// Reads the buffer `offset` swaps behind the write buffer. The buffer must not be locked for
+    /// writing, which the swap scheme guarantees as long as `offset` is less than `T1_LEN`.
     #[inline]
     pub fn read_multiple(&self, offset: usize) -> RwLockReadGuard<'_, T1> {
-        debug_assert!(offset > 0);
-        let index = (self.inner.swap_index.load(Ordering::Acquire) + offset) % T1_LEN;
-        self.inner.multiple_data[index].try_read().unwrap()
+        self.inner.multiple_data[self.read_index(offset)]
+            .try_read()
+            .expect("read_multiple buffer is locked for writing, read_multiple_await may be used")
     }
 
+    /// Reads back the current write buffer. Unlike other buffers, this is locked by
+    /// `write_multiple`, so the write guard must be dropped first: through this handle the
+    /// borrow checker enforces this, but a write guard held through a clone causes a panic.
+    #[inline]
+    pub fn read_current(&self) -> RwLockReadGuard<'_, T1> {
+        self.inner.multiple_data[self.write_index()]
+            .try_read()
+            .expect("read_current buffer is locked for writing")
+    }
+
+    /// Writes the current buffer. The buffer must not be locked, which the swap scheme guarantees
+    /// as long as readers use an `offset` less than `T1_LEN` and guards do not outlive a swap.
     #[inline]
     pub fn write_multiple(&mut self) -> RwLockWriteGuard<'_, T1> {
-        let index = self.inner.swap_index.load(Ordering::Acquire);
-        self.inner.multiple_data[index].try_write().unwrap()
+        self.inner.multiple_data[self.write_index()]
+            .try_write()
+            .expect("write_multiple buffer is locked, write_multiple_await may be used")
+    }
+
+    /// Like `read_multiple`, but waits for a writer to release the buffer
+    pub async fn read_multiple_await(&self, offset: usize) -> RwLockReadGuard<'_, T1> {
+        self.inner.multiple_data[self.read_index(offset)]
+            .read()
+            .await
+    }
+
+    /// Like `write_multiple`, but waits for other guards to release the buffer
+    pub async fn write_multiple_await(&mut self) -> RwLockWriteGuard<'_, T1> {
+        self.inner.multiple_data[self.write_index()].write().await
     }
 
     pub fn swap_multiple(&mut self) {
@@ -129,4 +166,88 @@ impl<T0, T1, const T1_LEN: usize> SharedData<T0, T1, T1_LEN> {
                 .unwrap();
         }
     }
+
+    fn read_index(&self, offset: usize) -> usize {
+        debug_assert!(offset > 0);
+        (self.inner.swap_index.load(Ordering::Acquire) + offset) % T1_LEN
+    }
+
+    fn write_index(&self) -> usize {
+        self.inner.swap_index.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        pin::pin,
+        ptr,
+        task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    };
+
+    use super::*;
+
+    type Data = SharedData<(), u32, 2>;
+
+    /// Polls the future once, without being woken
+    fn poll_once<F: Future>(future: std::pin::Pin<&mut F>) -> Poll<F::Output> {
+        static VTABLE: RawWakerVTable =
+            RawWakerVTable::new(|data| RawWaker::new(data, &VTABLE), |_| {}, |_| {}, |_| {});
+
+        let waker = unsafe { Waker::from_raw(RawWaker::new(ptr::null(), &VTABLE)) };
+        future.poll(&mut Context::from_waker(&waker))
+    }
+
+    #[test]
+    fn read_current_sees_latest_write() {
+        let mut data = Data::default();
+
+        *data.write_multiple() = 7;
+        assert_eq!(*data.read_current(), 7);
+
+        data.swap_multiple();
+        *data.write_multiple() = 9;
+        assert_eq!(*data.read_current(), 9);
+        assert_eq!(*data.read_multiple(1), 7);
+    }
+
+    #[test]
+    fn write_await_proceeds_once_write_guard_drops() {
+        let mut data = Data::default();
+        let mut other = data.clone();
+
+        let write_guard = other.write_multiple();
+
+        let mut write = pin!(data.write_multiple_await());
+        assert!(poll_once(write.as_mut()).is_pending());
+
+        drop(write_guard);
+
+        match poll_once(write.as_mut()) {
+            Poll::Ready(mut guard) => *guard = 3,
+            Poll::Pending => panic!("write did not proceed once the guard dropped"),
+        };
+    }
+
+    #[test]
+    fn read_await_proceeds_once_write_guard_drops() {
+        let mut data = Data::default();
+        let mut other = data.clone();
+
+        // a writer still holding the buffer when it is swapped to be read
+        let mut write_guard = other.write_multiple();
+        *write_guard = 5;
+        data.swap_multiple();
+
+        let mut read = pin!(data.read_multiple_await(1));
+        assert!(poll_once(read.as_mut()).is_pending());
+
+        drop(write_guard);
+
+        match poll_once(read.as_mut()) {
+            Poll::Ready(guard) => assert_eq!(*guard, 5),
+            Poll::Pending => panic!("read did not proceed once the guard dropped"),
+        };
+    }
 }
@@ -0,0 +1,153 @@
+use std::net::SocketAddr;
+
+use anyhow::{bail, Context, Error, Result};
+
+/// A runtime tweak, parsed from a debug console command such as `camera.speed 3.0`
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    CameraSpeed(f32),
+    SpawnGuests(usize),
+    /// Optionally overrides the address to bind to
+    NetworkServer(Option<SocketAddr>),
+    /// Optionally overrides the address to connect to
+    NetworkClient(Option<SocketAddr>),
+    NetworkOffline,
+    RenderWireframe(bool),
+}
+
+impl TryFrom<&str> for Command {
+    type Error = Error;
+
+    fn try_from(command: &str) -> Result<Self> {
+        let mut args = command.split_whitespace();
+
+        let name = args.next().context("empty command")?;
+
+        let command = match name {
+            "camera.speed" => {
+                let speed: f32 = parse_arg(args.next(), "speed")?;
+                if !speed.is_finite() || speed < 0.0 {
+                    bail!("camera speed must be a non-negative number");
+                }
+                Command::CameraSpeed(speed)
+            }
+            "spawn" => match args.next() {
+                Some("guest") => {
+                    let count = match args.next() {
+                        Some(count) => parse_arg(Some(count), "count")?,
+                        None => 1,
+                    };
+                    Command::SpawnGuests(count)
+                }
+                Some(other) => bail!("unknown spawn type: {other}"),
+                None => bail!("missing argument: type"),
+            },
+            "net" => match args.next() {
+                Some("server") => Command::NetworkServer(parse_addr(args.next())?),
+                Some("client") => Command::NetworkClient(parse_addr(args.next())?),
+                Some("offline") => Command::NetworkOffline,
+                Some(other) => bail!("unknown network role: {other}"),
+                None => bail!("missing argument: role"),
+            },
+            "render" => match args.next() {
+                Some("wireframe") => Command::RenderWireframe(parse_toggle(args.next())?),
+                Some(other) => bail!("unknown render setting: {other}"),
+                None => bail!("missing argument: setting"),
+            },
+            _ => bail!("unknown command: {name}"),
+        };
+
+        if let Some(arg) = args.next() {
+            bail!("unexpected argument: {arg}");
+        }
+
+        Ok(command)
+    }
+}
+
+fn parse_arg<T>(arg: Option<&str>, name: &str) -> Result<T>
+where
+    T: std::str::FromStr,
+{
+    let arg = arg.with_context(|| format!("missing argument: {name}"))?;
+    arg.parse()
+        .map_err(|_| Error::msg(format!("invalid {name}: {arg}")))
+}
+
+fn parse_addr(arg: Option<&str>) -> Result<Option<SocketAddr>> {
+    arg.map(|addr| parse_arg(Some(addr), "address")).transpose()
+}
+
+fn parse_toggle(arg: Option<&str>) -> Result<bool> {
+    match arg {
+        Some("on") | Some("true") | Some("1") => Ok(true),
+        Some("off") | Some("false") | Some("0") => Ok(false),
+        Some(other) => bail!("expected on or off, found: {other}"),
+        None => bail!("missing argument: on/off"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(command: &str) -> Result<Command> {
+        Command::try_from(command)
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            parse("camera.speed 3.0").unwrap(),
+            Command::CameraSpeed(3.0)
+        );
+        assert_eq!(parse("spawn guest 10").unwrap(), Command::SpawnGuests(10));
+        assert_eq!(parse("spawn guest").unwrap(), Command::SpawnGuests(1));
+        assert_eq!(parse("net server").unwrap(), Command::NetworkServer(None));
+        assert_eq!(parse("net offline").unwrap(), Command::NetworkOffline);
+        assert_eq!(
+            parse("render wireframe on").unwrap(),
+            Command::RenderWireframe(true)
+        );
+        assert_eq!(
+            parse("render wireframe off").unwrap(),
+            Command::RenderWireframe(false)
+        );
+    }
+
+    #[test]
+    fn parses_addresses() {
+        let addr: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        assert_eq!(
+            parse("net server 127.0.0.1:4000").unwrap(),
+            Command::NetworkServer(Some(addr))
+        );
+        assert_eq!(
+            parse("  net   client 127.0.0.1:4000 ").unwrap(),
+            Command::NetworkClient(Some(addr))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_commands() {
+        let error = |command| parse(command).unwrap_err().to_string();
+
+        assert_eq!(error(""), "empty command");
+        assert_eq!(error("fly"), "unknown command: fly");
+        assert_eq!(error("camera.speed"), "missing argument: speed");
+        assert_eq!(error("camera.speed fast"), "invalid speed: fast");
+        assert_eq!(
+            error("camera.speed -1"),
+            "camera speed must be a non-negative number"
+        );
+        assert_eq!(error("spawn guest -3"), "invalid count: -3");
+        assert_eq!(error("spawn ride"), "unknown spawn type: ride");
+        assert_eq!(error("net server localhost"), "invalid address: localhost");
+        assert_eq!(error("net"), "missing argument: role");
+        assert_eq!(
+            error("render wireframe maybe"),
+            "expected on or off, found: maybe"
+        );
+        assert_eq!(error("net offline now"), "unexpected argument: now");
+    }
+}
@@ -18,8 +18,13 @@ impl FixedUpdate {
     }
 
     pub async fn await_prev_update(&mut self) {
-        if let Some(task_handle) = self.task_handle.take() {
-            self.systems = Some(task_handle.await);
+        if let Some(mut task_handle) = self.task_handle.take() {
+            let systems = match task_handle.try_result() {
+                Some(systems) => systems,
+                None => task_handle.await,
+            };
+
+            self.systems = Some(systems);
         }
     }
 
@@ -0,0 +1,85 @@
+use std::fmt::Display;
+
+/// Smoothing applied to the frame time average, per frame
+const FRAME_TIME_SMOOTHING: f32 = 0.05;
+
+#[derive(Clone, Copy, Default)]
+pub struct FrameStats {
+    /// Exponentially smoothed frame time, in seconds
+    pub frame_time: f32,
+    pub frame_count: u64,
+}
+
+impl FrameStats {
+    pub(crate) fn update(&mut self, delta_time: f32) {
+        if self.frame_count == 0 {
+            self.frame_time = delta_time;
+        } else {
+            self.frame_time += (delta_time - self.frame_time) * FRAME_TIME_SMOOTHING;
+        }
+
+        self.frame_count += 1;
+    }
+
+    pub fn fps(&self) -> f32 {
+        if self.frame_time > 0.0 {
+            1.0 / self.frame_time
+        } else {
+            0.0
+        }
+    }
+
+    /// Formats a window title, e.g. "Theme Park | 60 fps (16.67 ms)"
+    pub fn window_title(&self, name: &str) -> String {
+        format!("{name} | {self}")
+    }
+}
+
+impl Display for FrameStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.0} fps ({:.2} ms)",
+            self.fps(),
+            self.frame_time * 1000.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_title_shows_fps_and_frame_time() {
+        let stats = FrameStats {
+            frame_time: 0.016,
+            frame_count: 100,
+        };
+
+        assert_eq!(
+            stats.window_title("Theme Park"),
+            "Theme Park | 62 fps (16.00 ms)"
+        );
+    }
+
+    #[test]
+    fn window_title_before_first_frame() {
+        let stats = FrameStats::default();
+
+        assert_eq!(
+            stats.window_title("Theme Park"),
+            "Theme Park | 0 fps (0.00 ms)"
+        );
+    }
+
+    #[test]
+    fn first_frame_sets_frame_time() {
+        let mut stats = FrameStats::default();
+        stats.update(0.02);
+        stats.update(0.04);
+
+        assert_eq!(stats.frame_count, 2);
+        assert!((stats.frame_time - 0.021).abs() < 1e-6);
+    }
+}
@@ -3,7 +3,7 @@ use frame_buffer::{AsyncFrameBufferDelegate, SyncFrameBufferDelegate};
 use futures::pin_mut;
 use system_interfaces::SystemData;
 use task_executor::task::parallel;
-use winit::window::Window;
+use winit::dpi::PhysicalSize;
 
 pub struct FrameUpdate {
     pub audio: system_audio::FrameData,
@@ -11,30 +11,36 @@ pub struct FrameUpdate {
     pub guest: system_guest::FrameData,
     pub navigation: system_navigation::FrameData,
     pub network: system_network::FrameData,
+    pub physics: system_physics::FrameData,
     pub static_mesh: system_static_mesh::FrameData,
 }
 
 impl FrameUpdate {
-    pub fn new(system_data: &SystemData, window: &Window) -> Self {
-        let size = window.inner_size();
-
+    pub fn new(system_data: &SystemData, window_size: PhysicalSize<u32>) -> Self {
         let camera = system_camera::FrameData::new(
-            size.width,
-            size.height,
+            window_size.width,
+            window_size.height,
             system_data.physics.clone().into(),
         );
         let navigation = system_navigation::FrameData::new(
             system_data.navigation.clone(),
             system_data.static_mesh.clone().into(),
         );
+        let physics = system_physics::FrameData::new(system_data.physics.clone());
         let static_mesh = system_static_mesh::FrameData::new(system_data.static_mesh.clone());
 
+        let audio = system_audio::FrameData::try_new().unwrap_or_else(|err| {
+            log::error!("audio disabled: {err}");
+            system_audio::FrameData::disabled()
+        });
+
         Self {
-            audio: Default::default(),
+            audio,
             camera,
-            guest: Default::default(),
+            guest: system_guest::FrameData::new(system_data.navigation.clone().into()),
             navigation,
             network: Default::default(),
+            physics,
             static_mesh,
         }
     }
@@ -60,14 +66,16 @@ impl FrameUpdate {
         let guest = self.guest.update(event_delegate, frame_buffer, delta_time);
         let navigation = self.navigation.update(event_delegate);
         let network = self.network.update(event_delegate);
+        let physics = self.physics.update(event_delegate, frame_buffer);
         let static_mesh = self.static_mesh.update(event_delegate, frame_buffer);
 
         pin_mut!(audio);
         pin_mut!(guest);
         pin_mut!(navigation);
         pin_mut!(network);
+        pin_mut!(physics);
         pin_mut!(static_mesh);
 
-        parallel([audio, guest, navigation, network, static_mesh]).await;
+        parallel([audio, guest, navigation, network, physics, static_mesh]).await;
     }
 }
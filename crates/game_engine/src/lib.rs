@@ -1,23 +1,44 @@
-use std::time::Instant;
+use std::{
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
 
-use event::EventManager;
-use frame_buffer::FrameBufferManager;
+use anyhow::{bail, Result};
+use event::{EventManager, InputEvent};
+use frame_buffer::{FrameBufferManager, SceneState};
 use futures::pin_mut;
 use game_controller::GameController;
 use game_input::GameInput;
 use game_system::FIXED_TIMESTEP;
 use system_interfaces::SystemData;
-use task_executor::{task::parallel, TaskExecutor};
+use task_executor::{task::parallel, ExecutorOptions, TaskExecutor};
+use tracing::trace_span;
 use update_buffer::UpdateBuffer;
 use winit::{
+    dpi::PhysicalSize,
     event::{DeviceEvent, WindowEvent},
     window::Window,
 };
 
-use crate::{fixed_update::FixedUpdate, frame_update::FrameUpdate};
+use crate::{
+    command::Command, fixed_update::FixedUpdate, frame_update::FrameUpdate, phase::Phase,
+    resize_throttle::ResizeThrottle, script::ScriptContext,
+};
+
+pub use frame_stats::FrameStats;
+pub use script::Script;
+pub use system_network::NetworkStats;
 
+#[cfg(feature = "rhai")]
+pub use script::RhaiScript;
+
+mod command;
 mod fixed_update;
+mod frame_stats;
 mod frame_update;
+mod phase;
+mod resize_throttle;
+mod script;
 
 #[cfg(target_vendor = "apple")]
 use metal::Metal;
@@ -25,50 +46,116 @@ use metal::Metal;
 #[cfg(not(target_vendor = "apple"))]
 use vulkan::Vulkan;
 
+/// Default limit on async tasks running at once, which bounds the memory held by their futures
+const DEFAULT_MAX_IN_FLIGHT_TASKS: usize = 64;
+
 pub struct GameEngine {
-    task_executor: TaskExecutor,
     event_manager: EventManager,
     frame_update: FrameUpdate,
     fixed_update: FixedUpdate,
     frame_buffer_manager: FrameBufferManager,
+    scene_state: SceneState,
+    replay_scene: bool,
     game_controller: GameController,
     input: GameInput,
+    scripts: Vec<Box<dyn Script>>,
+    resize_throttle: ResizeThrottle,
+    frame_stats: FrameStats,
     last_fixed_update_instant: Instant,
     last_frame_update_instant: Instant,
+    instance_capacity: usize,
 
+    /// None if reinitialization failed, in which case nothing is rendered until it succeeds
     #[cfg(target_vendor = "apple")]
-    graphics: Metal,
+    graphics: Option<Metal>,
 
+    /// None if reinitialization failed, in which case nothing is rendered until it succeeds
     #[cfg(not(target_vendor = "apple"))]
-    graphics: std::mem::ManuallyDrop<Vulkan>,
+    graphics: Option<Vulkan>,
+
+    // dropped last, as dropping an in-flight fixed update waits on the executor threads
+    task_executor: TaskExecutor,
+}
+
+#[derive(Clone, Copy)]
+pub struct EngineOptions {
+    /// Pin executor threads to cores. Disable when profiling with tools that dislike pinning.
+    pub pin_threads: bool,
+    /// Number of static meshes the renderer's buffers are initially sized for
+    pub instance_capacity: usize,
+    /// Exit the process immediately when an executor task panics, instead of returning the
+    /// panic from `GameEngine::frame`
+    pub exit_on_panic: bool,
+    /// Maximum number of async tasks, such as navmesh rebuilds, which may run at once. Systems
+    /// defer further tasks until one completes.
+    pub max_in_flight_tasks: Option<NonZeroUsize>,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        #[cfg(target_vendor = "apple")]
+        let instance_capacity = metal::DEFAULT_INSTANCE_CAPACITY;
+
+        #[cfg(not(target_vendor = "apple"))]
+        let instance_capacity = vulkan::DEFAULT_INSTANCE_CAPACITY;
+
+        Self {
+            pin_threads: true,
+            instance_capacity,
+            exit_on_panic: false,
+            max_in_flight_tasks: NonZeroUsize::new(DEFAULT_MAX_IN_FLIGHT_TASKS),
+        }
+    }
 }
 
 impl GameEngine {
     pub fn new(window: &Window) -> Self {
+        Self::with_options(window, Default::default())
+    }
+
+    pub fn with_options(window: &Window, options: EngineOptions) -> Self {
+        let mut engine = Self::headless(window.inner_size(), window.scale_factor(), options);
+
+        #[cfg(target_vendor = "apple")]
+        let graphics = Metal::with_instance_capacity(window, options.instance_capacity).unwrap();
+
+        #[cfg(not(target_vendor = "apple"))]
+        let graphics = Vulkan::with_instance_capacity(window, options.instance_capacity).unwrap();
+
+        engine.graphics = Some(graphics);
+        engine
+    }
+
+    /// An engine which updates the game without rendering it
+    fn headless(window_size: PhysicalSize<u32>, scale_factor: f64, options: EngineOptions) -> Self {
         let thread_count = TaskExecutor::available_parallelism();
 
         let event_manager = EventManager::new(thread_count);
         let update_buffer = UpdateBuffer::new(thread_count);
         let frame_buffer_manager = FrameBufferManager::new(thread_count);
 
-        let task_executor = TaskExecutor::new(thread_count, &|thread_index| {
-            event_manager.assign_thread_event_buffer(thread_index);
-            update_buffer.assign_thread_event_buffer(thread_index);
-            frame_buffer_manager.assign_thread_frame_buffer(thread_index);
-        });
+        let executor_options = ExecutorOptions {
+            pin_threads: options.pin_threads,
+            exit_on_panic: options.exit_on_panic,
+            max_in_flight: options.max_in_flight_tasks,
+            ..Default::default()
+        };
+
+        // every executor thread runs this before with_options returns, so tasks never run on a
+        // thread without event and frame buffers
+        let task_executor =
+            TaskExecutor::with_options(executor_options, thread_count, &|thread_index| {
+                event_manager.assign_thread_event_buffer(thread_index);
+                update_buffer.assign_thread_event_buffer(thread_index);
+                frame_buffer_manager.assign_thread_frame_buffer(thread_index);
+            });
 
         let system_data = system_data();
-        let frame_update = FrameUpdate::new(&system_data, window);
+        let frame_update = FrameUpdate::new(&system_data, window_size);
         let fixed_update = FixedUpdate::new(update_buffer);
         let game_controller = GameController::new(system_data.physics.into());
 
-        let input = GameInput::new(window.inner_size());
-
-        #[cfg(target_vendor = "apple")]
-        let graphics = Metal::new(window).unwrap();
-
-        #[cfg(not(target_vendor = "apple"))]
-        let graphics = std::mem::ManuallyDrop::new(Vulkan::new(window).unwrap());
+        let input = GameInput::new(window_size, scale_factor);
 
         Self {
             task_executor,
@@ -76,11 +163,17 @@ impl GameEngine {
             frame_update,
             fixed_update,
             frame_buffer_manager,
+            scene_state: Default::default(),
+            replay_scene: false,
             game_controller,
             input,
+            scripts: Vec::new(),
+            resize_throttle: Default::default(),
+            frame_stats: Default::default(),
             last_fixed_update_instant: Instant::now(),
             last_frame_update_instant: Instant::now(),
-            graphics,
+            instance_capacity: options.instance_capacity,
+            graphics: None,
         }
     }
 }
@@ -96,51 +189,189 @@ fn system_data() -> SystemData {
 #[cfg(not(target_vendor = "apple"))]
 impl Drop for GameEngine {
     fn drop(&mut self) {
-        let graphics = unsafe { std::mem::ManuallyDrop::take(&mut self.graphics) };
-        graphics.destroy();
+        if let Some(graphics) = self.graphics.take() {
+            graphics.destroy();
+        }
     }
 }
 
 impl GameEngine {
+    /// Minimum interval between renderer resizes while the window is being continuously resized
+    pub fn set_resize_throttle(&mut self, interval: Duration) {
+        self.resize_throttle.set_interval(interval);
+    }
+
+    /// Spreads large guest spawn requests across frames, see `GameController::set_guest_spawn_rate`
+    pub fn set_guest_spawn_rate(&mut self, guests_per_frame: Option<NonZeroUsize>) {
+        self.game_controller.set_guest_spawn_rate(guests_per_frame);
+    }
+
+    /// How far behind the latest network update remote entity locations are rendered, see
+    /// `system_static_mesh::FrameData::set_interpolation_delay`
+    pub fn set_network_interpolation_delay(&mut self, delay: Duration) {
+        self.frame_update.static_mesh.set_interpolation_delay(delay);
+    }
+
+    pub fn frame_stats(&self) -> &FrameStats {
+        &self.frame_stats
+    }
+
+    pub fn network_stats(&self) -> &NetworkStats {
+        self.frame_update.network.stats()
+    }
+
+    /// Executes a debug console command, e.g. `camera.speed 3.0` or `spawn guest 10`
+    pub fn execute_command(&mut self, command: &str) -> Result<String> {
+        let response = match Command::try_from(command)? {
+            Command::CameraSpeed(speed) => {
+                self.frame_update.camera.set_move_speed(speed);
+                format!("camera speed set to {speed}")
+            }
+            Command::SpawnGuests(count) => {
+                for _ in 0..count {
+                    self.input.queue_event(InputEvent::SpawnGuest);
+                }
+                format!("spawning {count} guests")
+            }
+            Command::NetworkServer(addr) => {
+                if let Some(addr) = addr {
+                    self.game_controller.set_server_addr(addr);
+                }
+                self.input.queue_event(InputEvent::ServerBegin);
+                format!("starting server on {}", self.game_controller.server_addr())
+            }
+            Command::NetworkClient(addr) => {
+                if let Some(addr) = addr {
+                    self.game_controller.set_server_addr(addr);
+                }
+                self.input.queue_event(InputEvent::ServerConnect);
+                format!(
+                    "connecting to server at {}",
+                    self.game_controller.server_addr()
+                )
+            }
+            Command::NetworkOffline => {
+                self.input.queue_event(InputEvent::ServerDisconnect);
+                "going offline".to_string()
+            }
+            Command::RenderWireframe(enabled) => {
+                match &mut self.graphics {
+                    Some(graphics) => graphics.set_wireframe(enabled)?,
+                    None => bail!("no renderer"),
+                }
+                format!("wireframe {}", if enabled { "on" } else { "off" })
+            }
+        };
+
+        Ok(response)
+    }
+
+    /// Adds a script which is updated every frame until the engine is dropped
+    pub fn add_script(&mut self, script: Box<dyn Script>) {
+        self.scripts.push(script);
+    }
+
+    /// Destroys and recreates the renderer, e.g. after the device was lost. The scene is
+    /// re-uploaded to the new renderer during the next frame. On failure the engine continues
+    /// without a renderer, and reinitialization may be retried.
+    pub fn reinitialize_graphics(&mut self, window: &Window) -> Result<()> {
+        #[cfg(target_vendor = "apple")]
+        {
+            self.graphics = None;
+            self.graphics = Some(Metal::with_instance_capacity(
+                window,
+                self.instance_capacity,
+            )?);
+        }
+
+        #[cfg(not(target_vendor = "apple"))]
+        {
+            // waits for all pending GPU work before destroying resources
+            if let Some(graphics) = self.graphics.take() {
+                graphics.destroy();
+            }
+
+            self.graphics = Some(Vulkan::with_instance_capacity(
+                window,
+                self.instance_capacity,
+            )?);
+        }
+
+        self.replay_scene = true;
+
+        Ok(())
+    }
+
     pub fn handle_device_event(&mut self, event: DeviceEvent) {
         self.input.handle_raw_input(event);
     }
 
     pub fn handle_window_event(&mut self, event: WindowEvent) {
-        if let WindowEvent::Resized(size) = event {
+        let size = match &event {
+            WindowEvent::Resized(size) => Some(*size),
+            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => Some(**new_inner_size),
+            _ => None,
+        };
+
+        if let Some(size) = size {
             self.frame_update
                 .camera
                 .window_resized(size.width, size.height);
-            self.graphics.window_resized(size);
+            self.resize_throttle.resized(size);
         }
 
         self.input.handle_input(event);
     }
 
-    pub fn frame(&mut self) {
+    /// Updates and renders a frame. Returns an error if a system task panicked, in which case
+    /// the engine state is unreliable and the engine should be dropped.
+    pub fn frame(&mut self) -> Result<()> {
+        let _phase = Phase::enter(trace_span!("frame"));
+
         let now = Instant::now();
         let delta_time = now
             .duration_since(self.last_frame_update_instant)
             .as_secs_f32();
         self.last_frame_update_instant = now;
 
+        self.frame_stats.update(delta_time);
+
+        if let Some(size) = self.resize_throttle.poll(now) {
+            if let Some(graphics) = &mut self.graphics {
+                graphics.window_resized(size);
+            }
+        }
+
         self.event_manager.swap();
         self.frame_buffer_manager.swap();
 
-        self.update_fixed();
+        if self.replay_scene {
+            self.replay_scene = false;
+            self.scene_state
+                .replay(&mut self.frame_buffer_manager.sync_delegate());
+        }
+
+        self.update_fixed()?;
+
+        #[cfg(feature = "gamepad")]
+        self.input.handle_gamepad();
 
         self.input.update(&mut self.event_manager.sync_delegate());
 
+        self.update_scripts(delta_time);
+
         self.update_sync_systems(delta_time);
 
         self.update_game_state();
 
         self.event_manager.clear_system_game_events();
 
-        self.update_and_render_frame(delta_time);
+        self.update_and_render_frame(delta_time)
     }
 
-    fn update_fixed(&mut self) {
+    fn update_fixed(&mut self) -> Result<()> {
+        let _phase = Phase::enter(trace_span!("fixed_update"));
+
         let now = Instant::now();
 
         while now.duration_since(self.last_fixed_update_instant) >= FIXED_TIMESTEP {
@@ -150,18 +381,36 @@ impl GameEngine {
             {
                 let await_task = self.fixed_update.await_prev_update();
                 pin_mut!(await_task);
-                self.task_executor.execute_blocking(await_task);
+                self.task_executor.execute_blocking(await_task)?;
             }
 
             // if last iteration, swap with frame updates
             if now.duration_since(self.last_fixed_update_instant) < FIXED_TIMESTEP {
                 let swap_task = self.fixed_update.swap(&mut self.frame_update);
                 pin_mut!(swap_task);
-                self.task_executor.execute_blocking(swap_task);
+                self.task_executor.execute_blocking(swap_task)?;
             }
 
             self.fixed_update.execute(&mut self.task_executor);
         }
+
+        Ok(())
+    }
+
+    fn update_scripts(&mut self, delta_time: f32) {
+        let mut event_delegate = self.event_manager.sync_delegate();
+
+        let mut context = ScriptContext {
+            event_delegate: &mut event_delegate,
+            entities: self.game_controller.entities(),
+            camera: self.frame_update.camera.interface(),
+        };
+
+        for script in &mut self.scripts {
+            if let Err(err) = script.update(&mut context, delta_time) {
+                log::error!("{err}");
+            }
+        }
     }
 
     fn update_sync_systems(&mut self, delta_time: f32) {
@@ -173,6 +422,8 @@ impl GameEngine {
     }
 
     fn update_game_state(&mut self) {
+        let _phase = Phase::enter(trace_span!("game_state"));
+
         let mut event_delegate = self.event_manager.sync_delegate();
         let mut frame_buffer = self.frame_buffer_manager.sync_delegate();
 
@@ -184,7 +435,9 @@ impl GameEngine {
         );
     }
 
-    fn update_and_render_frame(&mut self, delta_time: f32) {
+    fn update_and_render_frame(&mut self, delta_time: f32) -> Result<()> {
+        let _phase = Phase::enter(trace_span!("render"));
+
         let frame_buffer_delegate = self.frame_buffer_manager.async_delegate();
         let frame_buffer_reader = frame_buffer_delegate.reader();
         let event_delegate = self.event_manager.async_delegate();
@@ -194,15 +447,150 @@ impl GameEngine {
                 self.frame_update
                     .update_async(&event_delegate, &frame_buffer_delegate, delta_time);
 
-            let graphics_task = self.graphics.frame(&frame_buffer_reader);
-
             pin_mut!(frame_update_task);
-            pin_mut!(graphics_task);
 
-            parallel([frame_update_task, graphics_task]).await;
+            match &mut self.graphics {
+                Some(graphics) => {
+                    let graphics_task = graphics.frame(&frame_buffer_reader);
+                    pin_mut!(graphics_task);
+
+                    parallel([frame_update_task, graphics_task]).await;
+                }
+                None => frame_update_task.await,
+            }
         };
 
         pin_mut!(frame_task);
-        self.task_executor.execute_blocking(frame_task);
+        self.task_executor.execute_blocking(frame_task)?;
+
+        self.scene_state.apply(&frame_buffer_reader);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing::{
+        span::{Attributes, Id, Record},
+        Event, Metadata, Subscriber,
+    };
+
+    use super::*;
+
+    fn engine() -> GameEngine {
+        let options = EngineOptions {
+            pin_threads: false,
+            ..Default::default()
+        };
+
+        GameEngine::headless(PhysicalSize::new(800, 600), 1.0, options)
+    }
+
+    #[derive(Default)]
+    struct Capture {
+        /// Name and parent name of each span, in the order they were created
+        spans: Vec<(&'static str, Option<&'static str>)>,
+        /// Name of the span each phase duration was emitted in
+        durations: Vec<&'static str>,
+        names: Vec<&'static str>,
+        stack: Vec<Id>,
+    }
+
+    impl Capture {
+        fn name(&self, id: &Id) -> &'static str {
+            self.names[id.into_u64() as usize - 1]
+        }
+    }
+
+    /// Records spans and events on the thread it is the default subscriber of
+    #[derive(Clone, Default)]
+    struct CaptureSubscriber(Arc<Mutex<Capture>>);
+
+    impl Subscriber for CaptureSubscriber {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut capture = self.0.lock().unwrap();
+            let parent = span
+                .parent()
+                .or(capture.stack.last())
+                .map(|id| capture.name(id));
+            capture.spans.push((span.metadata().name(), parent));
+            capture.names.push(span.metadata().name());
+            Id::from_u64(capture.names.len() as u64)
+        }
+
+        fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut capture = self.0.lock().unwrap();
+            if event.metadata().fields().field("duration_us").is_some() {
+                let span = capture.stack.last().map(|id| capture.name(id)).unwrap();
+                capture.durations.push(span);
+            }
+        }
+
+        fn enter(&self, span: &Id) {
+            self.0.lock().unwrap().stack.push(span.clone());
+        }
+
+        fn exit(&self, _span: &Id) {
+            self.0.lock().unwrap().stack.pop();
+        }
+    }
+
+    #[test]
+    fn headless_engine_runs_frames() {
+        let mut engine = engine();
+
+        for _ in 0..3 {
+            engine.frame().unwrap();
+        }
+
+        assert_eq!(engine.frame_stats().frame_count, 3);
+    }
+
+    #[test]
+    fn frame_phases_are_traced_in_order() {
+        let mut engine = engine();
+        let subscriber = CaptureSubscriber::default();
+
+        tracing::subscriber::with_default(subscriber.clone(), || engine.frame()).unwrap();
+
+        let capture = subscriber.0.lock().unwrap();
+        assert_eq!(
+            capture.spans,
+            [
+                ("frame", None),
+                ("fixed_update", Some("frame")),
+                ("game_state", Some("frame")),
+                ("render", Some("frame")),
+            ]
+        );
+        assert_eq!(
+            capture.durations,
+            ["fixed_update", "game_state", "render", "frame"]
+        );
+    }
+
+    #[cfg(feature = "rhai")]
+    #[test]
+    fn script_spawn_adds_entity_after_frame() {
+        let mut engine = engine();
+        let script = RhaiScript::new("fn update(delta_time) { spawn(); }").unwrap();
+        engine.add_script(Box::new(script));
+
+        assert!(engine.game_controller.entities().is_empty());
+
+        engine.frame().unwrap();
+
+        assert_eq!(engine.game_controller.entities().len(), 1);
     }
 }
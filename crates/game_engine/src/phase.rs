@@ -0,0 +1,31 @@
+use std::time::Instant;
+
+use tracing::{span::EnteredSpan, trace, Span};
+
+/// Enters a span for a phase of the frame, and emits the phase duration within it when dropped.
+/// Timing is skipped entirely when no subscriber is interested in the span.
+pub struct Phase {
+    start: Option<Instant>,
+    _span: EnteredSpan,
+}
+
+impl Phase {
+    pub fn enter(span: Span) -> Self {
+        Self {
+            start: (!span.is_disabled()).then(Instant::now),
+            _span: span.entered(),
+        }
+    }
+}
+
+impl Drop for Phase {
+    fn drop(&mut self) {
+        // the span is exited after this, as fields are dropped after the struct
+        if let Some(start) = self.start {
+            trace!(
+                duration_us = start.elapsed().as_micros() as u64,
+                "phase complete"
+            );
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::time::{Duration, Instant};
+
+use winit::dpi::PhysicalSize;
+
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Coalesces bursts of window resize events so the renderer is resized at most once per
+/// interval, or as soon as the size has stopped changing for a frame
+pub struct ResizeThrottle {
+    interval: Duration,
+    pending_size: Option<PhysicalSize<u32>>,
+    settled: bool,
+    last_applied_instant: Instant,
+}
+
+impl Default for ResizeThrottle {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_INTERVAL,
+            pending_size: None,
+            settled: false,
+            last_applied_instant: Instant::now(),
+        }
+    }
+}
+
+impl ResizeThrottle {
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    pub fn resized(&mut self, size: PhysicalSize<u32>) {
+        self.pending_size = Some(size);
+        self.settled = false;
+    }
+
+    /// Called once per frame. Returns the latest size if the renderer should be resized now.
+    pub fn poll(&mut self, now: Instant) -> Option<PhysicalSize<u32>> {
+        let size = self.pending_size?;
+
+        if self.settled || now.duration_since(self.last_applied_instant) >= self.interval {
+            self.pending_size = None;
+            self.last_applied_instant = now;
+            Some(size)
+        } else {
+            // apply next frame unless another resize event arrives in the meantime
+            self.settled = true;
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_is_applied_once_at_final_size() {
+        let mut throttle = ResizeThrottle::default();
+        let start = Instant::now();
+
+        // one resize event per frame, faster than the interval
+        let mut applied = Vec::new();
+        for frame in 0..10 {
+            throttle.resized(PhysicalSize::new(800 + frame * 10, 600));
+            let now = start + Duration::from_millis(frame as u64);
+            applied.extend(throttle.poll(now));
+        }
+
+        // the size stopped changing, so it is applied on the following frame
+        for frame in 10..20 {
+            let now = start + Duration::from_millis(frame);
+            applied.extend(throttle.poll(now));
+        }
+
+        assert_eq!(applied, [PhysicalSize::new(890, 600)]);
+    }
+
+    #[test]
+    fn continuous_resizing_is_applied_once_per_interval() {
+        let mut throttle = ResizeThrottle::default();
+        throttle.set_interval(Duration::from_millis(10));
+        let start = Instant::now();
+
+        let applied = (1..=25)
+            .filter_map(|frame| {
+                throttle.resized(PhysicalSize::new(frame, frame));
+                throttle.poll(start + Duration::from_millis(frame as u64))
+            })
+            .count();
+
+        assert_eq!(applied, 2);
+    }
+}
@@ -0,0 +1,42 @@
+use anyhow::Result;
+use event::{GameEvent, InputEvent, SyncEventDelegate};
+use game_entity::EntityId;
+use nalgebra_glm::Vec3;
+use system_camera::CameraInterface;
+
+#[cfg(feature = "rhai")]
+pub use self::rhai_script::RhaiScript;
+
+#[cfg(feature = "rhai")]
+mod rhai_script;
+
+/// Game logic driven by a scripting language. Updated once per frame, after input is processed
+/// and before systems are updated, so events pushed by a script are handled in the same frame.
+pub trait Script {
+    fn update(&mut self, context: &mut ScriptContext, delta_time: f32) -> Result<()>;
+}
+
+/// The engine state exposed to scripts
+pub struct ScriptContext<'a, 'b> {
+    pub(crate) event_delegate: &'a mut SyncEventDelegate<'b>,
+    pub(crate) entities: &'a [EntityId],
+    pub(crate) camera: CameraInterface<'a>,
+}
+
+impl ScriptContext<'_, '_> {
+    pub fn push_input_event(&mut self, event: InputEvent) {
+        self.event_delegate.push_input_event(event);
+    }
+
+    pub fn push_game_event(&mut self, event: GameEvent) {
+        self.event_delegate.push_game_event(event);
+    }
+
+    pub fn entities(&self) -> &[EntityId] {
+        self.entities
+    }
+
+    pub fn camera_location(&self) -> &Vec3 {
+        self.camera.location()
+    }
+}
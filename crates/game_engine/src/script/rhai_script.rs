@@ -0,0 +1,118 @@
+use std::{cell::RefCell, rc::Rc};
+
+use anyhow::{Error, Result};
+use event::InputEvent;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Scope, AST};
+
+use super::{Script, ScriptContext};
+
+/// Engine state shared with the functions registered on the rhai engine
+#[derive(Default)]
+struct SharedState {
+    input_events: Vec<InputEvent>,
+    entity_count: usize,
+    camera_location: [f32; 3],
+}
+
+/// Runs a rhai script which may define `fn update(delta_time)`. Top-level statements are run
+/// once when the script is loaded. Available functions:
+///
+/// - `spawn()`: spawns a guest
+/// - `entity_count()`: number of entities in the world
+/// - `camera_location()`: camera location as `[x, y, z]`
+pub struct RhaiScript {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    state: Rc<RefCell<SharedState>>,
+    has_update: bool,
+}
+
+impl RhaiScript {
+    pub fn new(source: &str) -> Result<Self> {
+        let state = Rc::new(RefCell::new(SharedState::default()));
+
+        let mut engine = Engine::new();
+
+        // spawn is a reserved keyword in rhai, so it cannot be registered as a function. The
+        // parentheses are a single token, the unit value
+        let spawn_state = state.clone();
+        engine
+            .register_custom_syntax(["spawn", "()"], false, move |_, _| {
+                spawn_state
+                    .borrow_mut()
+                    .input_events
+                    .push(InputEvent::SpawnGuest);
+                Ok(Dynamic::UNIT)
+            })
+            .expect("spawn syntax is valid");
+
+        let entity_count_state = state.clone();
+        engine.register_fn("entity_count", move || {
+            entity_count_state.borrow().entity_count as i64
+        });
+
+        let camera_state = state.clone();
+        engine.register_fn("camera_location", move || {
+            camera_state
+                .borrow()
+                .camera_location
+                .iter()
+                .map(|val| Dynamic::from(*val as f64))
+                .collect::<Array>()
+        });
+
+        let ast = engine
+            .compile(source)
+            .map_err(|err| Error::msg(format!("script compile error: {err}")))?;
+
+        let has_update = ast.iter_functions().any(|f| f.name == "update");
+
+        let mut script = Self {
+            engine,
+            ast,
+            scope: Scope::new(),
+            state,
+            has_update,
+        };
+
+        script
+            .engine
+            .run_ast_with_scope(&mut script.scope, &script.ast)
+            .map_err(|err| Error::msg(format!("script error: {err}")))?;
+
+        Ok(script)
+    }
+}
+
+impl Script for RhaiScript {
+    fn update(&mut self, context: &mut ScriptContext, delta_time: f32) -> Result<()> {
+        {
+            let mut state = self.state.borrow_mut();
+            state.entity_count = context.entities().len();
+            state.camera_location = (*context.camera_location()).into();
+        }
+
+        let result = if self.has_update {
+            self.engine
+                .call_fn_with_options::<Dynamic>(
+                    CallFnOptions::new().eval_ast(false),
+                    &mut self.scope,
+                    &self.ast,
+                    "update",
+                    (delta_time as f64,),
+                )
+                .map(|_| ())
+        } else {
+            Ok(())
+        };
+
+        // forward events even if the script failed partway through, including events pushed by
+        // top-level statements when the script was loaded
+        for event in self.state.borrow_mut().input_events.drain(..) {
+            context.push_input_event(event);
+        }
+
+        result.map_err(|err| Error::msg(format!("script error: {err}")))
+    }
+}
@@ -0,0 +1,122 @@
+use crate::EntityId;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Ascending,
+    Descending,
+}
+
+/// Hands out unique EntityIds, reusing freed ids before advancing a counter
+pub struct EntityAllocator {
+    free_list: Vec<EntityId>,
+    next: EntityId,
+    direction: Direction,
+}
+
+impl EntityAllocator {
+    /// Allocates ids starting from EntityId::min() and growing
+    pub fn ascending() -> Self {
+        Self {
+            free_list: Vec::new(),
+            next: EntityId::min(),
+            direction: Direction::Ascending,
+        }
+    }
+
+    /// Allocates ids starting from EntityId::max() and shrinking
+    pub fn descending() -> Self {
+        Self {
+            free_list: Vec::new(),
+            next: EntityId::max(),
+            direction: Direction::Descending,
+        }
+    }
+
+    /// The id the counter will hand out once the free list is empty
+    pub fn next(&self) -> EntityId {
+        self.next
+    }
+
+    pub fn allocate(&mut self) -> EntityId {
+        self.free_list.pop().unwrap_or_else(|| {
+            let entity_id = self.next;
+            self.advance();
+            entity_id
+        })
+    }
+
+    /// Returns an allocated id for reuse
+    pub fn free(&mut self, entity_id: EntityId) {
+        debug_assert!(self.was_allocated(entity_id), "freed unallocated id");
+        debug_assert!(!self.free_list.contains(&entity_id), "double free");
+        self.free_list.push(entity_id);
+    }
+
+    /// Marks an id allocated elsewhere, e.g. by a remote host, as live so it won't be handed out
+    pub fn claim(&mut self, entity_id: EntityId) {
+        self.free_list.retain(|id| *id != entity_id);
+
+        if !self.was_allocated(entity_id) {
+            self.next = entity_id;
+            self.advance();
+        }
+    }
+
+    fn was_allocated(&self, entity_id: EntityId) -> bool {
+        match self.direction {
+            Direction::Ascending => entity_id < self.next,
+            Direction::Descending => entity_id > self.next,
+        }
+    }
+
+    fn advance(&mut self) {
+        match self.direction {
+            Direction::Ascending => self.next.increment(),
+            Direction::Descending => self.next.decrement(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_freed_ids_before_advancing() {
+        let mut allocator = EntityAllocator::ascending();
+
+        let ids: Vec<_> = (0..4).map(|_| allocator.allocate()).collect();
+        assert!(ids.iter().map(|id| id.get()).eq(1..=4));
+
+        allocator.free(ids[2]);
+        allocator.free(ids[0]);
+        allocator.free(ids[3]);
+
+        let mut reused: Vec<_> = (0..3).map(|_| allocator.allocate().get()).collect();
+        reused.sort_unstable();
+        assert_eq!(reused, [1, 3, 4]);
+
+        assert_eq!(allocator.allocate().get(), 5);
+    }
+
+    #[test]
+    fn descending_starts_at_max() {
+        let mut allocator = EntityAllocator::descending();
+
+        assert!(allocator.allocate() == EntityId::max());
+        assert_eq!(allocator.allocate().get(), EntityId::max().get() - 1);
+    }
+
+    #[test]
+    fn claimed_ids_are_not_handed_out() {
+        let mut allocator = EntityAllocator::ascending();
+
+        allocator.claim(EntityId::new(3));
+        assert_eq!(allocator.allocate().get(), 4);
+
+        let freed = allocator.allocate();
+        allocator.free(freed);
+        allocator.claim(freed);
+        assert_eq!(allocator.allocate().get(), 6);
+    }
+}
@@ -1,11 +1,17 @@
 use std::{
+    collections::HashMap,
     fmt::Display,
     iter::zip,
+    mem,
     num::NonZeroU32,
     ops::{Deref, Index, IndexMut, RangeBounds},
     slice,
 };
 
+pub use allocator::EntityAllocator;
+
+mod allocator;
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct EntityId(NonZeroU32);
 
@@ -65,9 +71,16 @@ impl Display for EntityId {
     }
 }
 
+/// Capacity below which `EntityMap::shrink_if_sparse` keeps its allocations
+const MIN_SHRINK_CAPACITY: usize = 64;
+
+/// Densely packed entity data. Iteration order is insertion order, except that removal moves
+/// the last entry into the removed slot.
 pub struct EntityMap<T> {
     entity_ids: Vec<EntityId>,
     data: Vec<T>,
+    /// Maps each entity to its slot in `entity_ids` and `data`
+    indices: HashMap<EntityId, usize>,
 }
 
 impl<T> Default for EntityMap<T> {
@@ -75,6 +88,7 @@ impl<T> Default for EntityMap<T> {
         Self {
             entity_ids: Vec::new(),
             data: Vec::new(),
+            indices: HashMap::new(),
         }
     }
 }
@@ -84,32 +98,93 @@ impl<T> EntityMap<T> {
         Default::default()
     }
 
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut entity_map = Self::default();
+        entity_map.reserve(capacity);
+        entity_map
+    }
+
+    /// Reserves capacity for at least `additional` more entities
+    #[inline]
+    pub fn reserve(&mut self, additional: usize) {
+        self.entity_ids.reserve(additional);
+        self.data.reserve(additional);
+        self.indices.reserve(additional);
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entity_ids.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entity_ids.is_empty()
+    }
+
     #[inline]
     fn index(&self, entity_id: EntityId) -> Option<usize> {
-        self.entity_ids
-            .iter()
-            .enumerate()
-            .find(|(_, id)| **id == entity_id)
-            .map(|a| a.0)
+        self.indices.get(&entity_id).copied()
     }
 
     #[inline]
     pub fn insert(&mut self, entity_id: EntityId, data: T) {
+        self.indices.insert(entity_id, self.entity_ids.len());
         self.entity_ids.push(entity_id);
         self.data.push(data);
     }
 
+    /// Panics if the entity is missing
     #[inline]
     pub fn remove(&mut self, entity_id: EntityId) -> T {
-        let index = self.index(entity_id).unwrap();
+        self.try_remove(entity_id).unwrap()
+    }
+
+    /// Removes the entity's data, or returns None if the entity is missing
+    #[inline]
+    pub fn try_remove(&mut self, entity_id: EntityId) -> Option<T> {
+        let index = self.indices.remove(&entity_id)?;
         self.entity_ids.swap_remove(index);
-        self.data.swap_remove(index)
+
+        // the last entry was moved into the removed slot
+        if let Some(moved_entity_id) = self.entity_ids.get(index) {
+            self.indices.insert(*moved_entity_id, index);
+        }
+
+        Some(self.data.swap_remove(index))
     }
 
     #[inline]
     pub fn clear(&mut self) {
         self.entity_ids.clear();
         self.data.clear();
+        self.indices.clear();
+    }
+
+    /// Releases unused capacity, e.g. after despawning a large number of entities
+    #[inline]
+    pub fn shrink_to_fit(&mut self) {
+        self.entity_ids.shrink_to_fit();
+        self.data.shrink_to_fit();
+        self.indices.shrink_to_fit();
+    }
+
+    /// Releases unused capacity if less than a quarter of it is in use, e.g. after clearing the
+    /// park. Cheap enough to call after every batch of removals.
+    #[inline]
+    pub fn shrink_if_sparse(&mut self) {
+        let capacity = self.entity_ids.capacity();
+        if capacity > MIN_SHRINK_CAPACITY && self.len() < capacity / 4 {
+            self.shrink_to_fit();
+        }
+    }
+
+    /// Estimated heap memory in bytes, including unused capacity
+    #[inline]
+    pub fn memory_footprint(&self) -> usize {
+        self.entity_ids.capacity() * mem::size_of::<EntityId>()
+            + self.data.capacity() * mem::size_of::<T>()
+            + self.indices.capacity() * mem::size_of::<(EntityId, usize)>()
     }
 
     #[inline]
@@ -123,6 +198,27 @@ impl<T> EntityMap<T> {
             .and_then(|index| self.data.get_mut(index))
     }
 
+    /// Mutable references to two different entities, or None if the ids are equal or either
+    /// entity is missing
+    #[inline]
+    pub fn get_disjoint_mut(&mut self, a: EntityId, b: EntityId) -> Option<(&mut T, &mut T)> {
+        let index_a = self.index(a)?;
+        let index_b = self.index(b)?;
+
+        if index_a == index_b {
+            return None;
+        }
+
+        let (low, high) = self.data.split_at_mut(index_a.max(index_b));
+        let (low, high) = (&mut low[index_a.min(index_b)], &mut high[0]);
+
+        if index_a < index_b {
+            Some((low, high))
+        } else {
+            Some((high, low))
+        }
+    }
+
     #[inline]
     pub fn values(&self) -> impl Iterator<Item = &T> {
         self.data.iter()
@@ -156,22 +252,32 @@ impl<T> EntityMap<T> {
     {
         let iter = iter.into_iter();
 
-        let min_bound = iter.size_hint().0;
-        self.entity_ids.reserve(min_bound);
-        self.data.reserve(min_bound);
+        self.reserve(iter.size_hint().0);
 
-        for entry in iter {
-            self.entity_ids.push(entry.0);
-            self.data.push(entry.1);
+        for (entity_id, data) in iter {
+            self.insert(entity_id, data);
         }
     }
 
+    /// Removes the entries in the range, which are dropped if not consumed. Unlike
+    /// `Vec::drain`, entries are removed eagerly.
     #[inline]
     pub fn drain<R>(&mut self, range: R) -> impl Iterator<Item = (EntityId, T)> + '_
     where
         R: RangeBounds<usize> + Copy,
     {
-        zip(self.entity_ids.drain(range), self.data.drain(range))
+        let drained: Vec<_> = zip(self.entity_ids.drain(range), self.data.drain(range)).collect();
+
+        // entries after the range have shifted
+        self.indices.clear();
+        self.indices.extend(
+            self.entity_ids
+                .iter()
+                .enumerate()
+                .map(|(index, entity_id)| (*entity_id, index)),
+        );
+
+        drained.into_iter()
     }
 }
 
@@ -279,3 +385,99 @@ impl<'a, T> IntoIterator for &'a mut EntityMap<T> {
         self.iter_mut()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity_map(len: u32) -> EntityMap<u32> {
+        let mut entity_map = EntityMap::new();
+        for i in 1..=len {
+            entity_map.insert(EntityId::new(i), i * 10);
+        }
+        entity_map
+    }
+
+    #[test]
+    fn try_remove_updates_moved_index() {
+        let mut entity_map = entity_map(3);
+
+        assert_eq!(entity_map.try_remove(EntityId::new(1)), Some(10));
+        assert_eq!(entity_map.try_remove(EntityId::new(1)), None);
+
+        // the last entry was moved into the removed slot
+        assert_eq!(entity_map.values().copied().collect::<Vec<_>>(), [30, 20]);
+        assert_eq!(entity_map.get(EntityId::new(2)), Some(&20));
+        assert_eq!(entity_map.get(EntityId::new(3)), Some(&30));
+
+        assert_eq!(entity_map.try_remove(EntityId::new(3)), Some(30));
+        assert_eq!(entity_map.get(EntityId::new(2)), Some(&20));
+        assert_eq!(entity_map.len(), 1);
+    }
+
+    #[test]
+    fn get_disjoint_mut() {
+        let mut entity_map = entity_map(2);
+
+        let (a, b) = entity_map
+            .get_disjoint_mut(EntityId::new(2), EntityId::new(1))
+            .unwrap();
+        assert_eq!((*a, *b), (20, 10));
+
+        assert!(entity_map
+            .get_disjoint_mut(EntityId::new(1), EntityId::new(1))
+            .is_none());
+        assert!(entity_map
+            .get_disjoint_mut(EntityId::new(1), EntityId::new(3))
+            .is_none());
+    }
+
+    #[test]
+    fn shrink_releases_capacity() {
+        let mut entity_map = entity_map(10_000);
+        let full_footprint = entity_map.memory_footprint();
+
+        for i in 1..=10_000 {
+            entity_map.remove(EntityId::new(i));
+        }
+
+        // removal alone keeps the capacity
+        assert!(entity_map.entity_ids.capacity() >= 10_000);
+
+        entity_map.shrink_to_fit();
+        assert!(entity_map.memory_footprint() < full_footprint / 100);
+    }
+
+    #[test]
+    fn shrink_if_sparse() {
+        let mut entity_map = entity_map(1000);
+        let capacity = entity_map.entity_ids.capacity();
+
+        for i in 1..=700 {
+            entity_map.remove(EntityId::new(i));
+        }
+
+        entity_map.shrink_if_sparse();
+        assert_eq!(entity_map.entity_ids.capacity(), capacity);
+
+        for i in 701..=900 {
+            entity_map.remove(EntityId::new(i));
+        }
+
+        entity_map.shrink_if_sparse();
+        assert_eq!(entity_map.entity_ids.capacity(), 100);
+        assert_eq!(entity_map.get(EntityId::new(1000)), Some(&10_000));
+    }
+
+    #[test]
+    fn drain_reindexes_remaining_entries() {
+        let mut entity_map = entity_map(4);
+
+        let drained: Vec<_> = entity_map.drain(..2).map(|(_, data)| data).collect();
+        assert_eq!(drained, [10, 20]);
+
+        assert_eq!(entity_map.get(EntityId::new(1)), None);
+        assert_eq!(entity_map.get(EntityId::new(3)), Some(&30));
+        assert_eq!(entity_map.get(EntityId::new(4)), Some(&40));
+    }
+}
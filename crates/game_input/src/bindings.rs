@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use winit::event::{MouseButton, VirtualKeyCode};
+
+/// Logical actions which may be bound to a key or mouse button
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum InputAction {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    /// Rotates the camera with mouse motion while held
+    RotateHold,
+    Select,
+    /// Selecting adds to the selection while held, rather than replacing it
+    AddToSelection,
+    Spawn,
+    SpawnGuest,
+    Undo,
+    Redo,
+    Cancel,
+    ServerBegin,
+    ServerConnect,
+    ServerDisconnect,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+impl From<VirtualKeyCode> for Binding {
+    fn from(keycode: VirtualKeyCode) -> Self {
+        Self::Key(keycode)
+    }
+}
+
+impl From<MouseButton> for Binding {
+    fn from(button: MouseButton) -> Self {
+        Self::Mouse(button)
+    }
+}
+
+/// Maps keys and mouse buttons to actions. Each input triggers at most one action, while an
+/// action may be bound to several inputs.
+#[derive(Clone)]
+pub struct InputBindings {
+    actions: HashMap<Binding, InputAction>,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        let mut bindings = Self::empty();
+
+        bindings.add_binding(InputAction::MoveForward, VirtualKeyCode::W);
+        bindings.add_binding(InputAction::MoveLeft, VirtualKeyCode::A);
+        bindings.add_binding(InputAction::MoveBackward, VirtualKeyCode::S);
+        bindings.add_binding(InputAction::MoveRight, VirtualKeyCode::D);
+        bindings.add_binding(InputAction::RotateHold, VirtualKeyCode::Space);
+        bindings.add_binding(InputAction::RotateHold, MouseButton::Middle);
+        bindings.add_binding(InputAction::Select, MouseButton::Left);
+        bindings.add_binding(InputAction::AddToSelection, VirtualKeyCode::LShift);
+        bindings.add_binding(InputAction::AddToSelection, VirtualKeyCode::RShift);
+        bindings.add_binding(InputAction::Spawn, VirtualKeyCode::N);
+        bindings.add_binding(InputAction::SpawnGuest, VirtualKeyCode::G);
+        bindings.add_binding(InputAction::Undo, VirtualKeyCode::Z);
+        bindings.add_binding(InputAction::Redo, VirtualKeyCode::Y);
+        bindings.add_binding(InputAction::Cancel, VirtualKeyCode::Escape);
+        bindings.add_binding(InputAction::ServerBegin, VirtualKeyCode::Key1);
+        bindings.add_binding(InputAction::ServerConnect, VirtualKeyCode::Key2);
+        bindings.add_binding(InputAction::ServerDisconnect, VirtualKeyCode::Key3);
+
+        bindings
+    }
+}
+
+impl InputBindings {
+    /// Bindings with no inputs assigned to any action
+    pub fn empty() -> Self {
+        Self {
+            actions: HashMap::new(),
+        }
+    }
+
+    pub fn action(&self, input: Binding) -> Option<InputAction> {
+        self.actions.get(&input).copied()
+    }
+
+    pub fn inputs(&self, action: InputAction) -> impl Iterator<Item = Binding> + '_ {
+        self.actions
+            .iter()
+            .filter(move |(_, bound_action)| **bound_action == action)
+            .map(|(input, _)| *input)
+    }
+
+    /// Replaces all inputs bound to the action. The input is unbound from any other action.
+    pub fn set_binding(&mut self, action: InputAction, input: impl Into<Binding>) {
+        self.actions
+            .retain(|_, bound_action| *bound_action != action);
+        self.add_binding(action, input);
+    }
+
+    /// Binds an additional input to the action. The input is unbound from any other action.
+    pub fn add_binding(&mut self, action: InputAction, input: impl Into<Binding>) {
+        self.actions.insert(input.into(), action);
+    }
+
+    pub fn remove_binding(&mut self, input: impl Into<Binding>) {
+        self.actions.remove(&input.into());
+    }
+}
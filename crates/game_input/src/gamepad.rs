@@ -0,0 +1,168 @@
+use gilrs::{Axis, Button, EventType, Gilrs, GilrsBuilder};
+use nalgebra_glm::{vec2, Vec2};
+
+#[derive(Clone, Copy)]
+pub struct GamepadConfig {
+    /// Stick deflection below which input is ignored, in the range [0, 1)
+    pub deadzone: f32,
+    /// Camera rotation per update at full right stick deflection, in mouse motion units
+    pub rotate_speed: f32,
+    /// Camera zoom per update at full trigger or while a bumper is held, in mouse wheel units
+    pub zoom_speed: f32,
+}
+
+impl Default for GamepadConfig {
+    fn default() -> Self {
+        Self {
+            deadzone: 0.15,
+            rotate_speed: 10.0,
+            zoom_speed: 10.0,
+        }
+    }
+}
+
+/// Raw state of the gamepad inputs used by the camera, combined across connected gamepads
+#[derive(Clone, Copy, Default)]
+pub(crate) struct GamepadState {
+    pub left_stick: Vec2,
+    pub right_stick: Vec2,
+    pub zoom_in: f32,
+    pub zoom_out: f32,
+}
+
+impl GamepadState {
+    fn apply(&mut self, event: EventType) {
+        match event {
+            EventType::AxisChanged(axis, value, _) => match axis {
+                Axis::LeftStickX => self.left_stick.x = value,
+                Axis::LeftStickY => self.left_stick.y = value,
+                Axis::RightStickX => self.right_stick.x = value,
+                Axis::RightStickY => self.right_stick.y = value,
+                _ => {}
+            },
+            EventType::ButtonChanged(button, value, _) => match button {
+                Button::RightTrigger | Button::RightTrigger2 => self.zoom_in = value,
+                Button::LeftTrigger | Button::LeftTrigger2 => self.zoom_out = value,
+                _ => {}
+            },
+            EventType::Disconnected => *self = Default::default(),
+            _ => {}
+        }
+    }
+
+    pub fn move_axis(&self, config: &GamepadConfig) -> Vec2 {
+        apply_deadzone(self.left_stick, config.deadzone)
+    }
+
+    /// Stick up tilts the camera up, matching mouse motion towards the top of the window
+    pub fn rotate_axis(&self, config: &GamepadConfig) -> Vec2 {
+        let stick = apply_deadzone(self.right_stick, config.deadzone);
+        vec2(stick.x, -stick.y) * config.rotate_speed
+    }
+
+    pub fn zoom(&self, config: &GamepadConfig) -> f32 {
+        (self.zoom_in - self.zoom_out) * config.zoom_speed
+    }
+}
+
+/// Radial deadzone, rescaled so that output ramps up from zero at the deadzone edge
+fn apply_deadzone(stick: Vec2, deadzone: f32) -> Vec2 {
+    let len = stick.norm();
+    if len <= deadzone {
+        Vec2::zeros()
+    } else {
+        let scaled_len = (len.min(1.0) - deadzone) / (1.0 - deadzone);
+        stick * (scaled_len / len)
+    }
+}
+
+pub(crate) struct Gamepads {
+    /// None if gamepad support is unavailable on this platform
+    gilrs: Option<Gilrs>,
+    pub state: GamepadState,
+    pub config: GamepadConfig,
+}
+
+impl Default for Gamepads {
+    fn default() -> Self {
+        // deadzones are applied by GamepadState so that they are configurable
+        let gilrs = match GilrsBuilder::new().with_default_filters(false).build() {
+            Ok(gilrs) => Some(gilrs),
+            Err(err) => {
+                log::warn!("gamepad support unavailable: {err}");
+                None
+            }
+        };
+
+        Self {
+            gilrs,
+            state: Default::default(),
+            config: Default::default(),
+        }
+    }
+}
+
+impl Gamepads {
+    pub fn poll(&mut self) {
+        if let Some(gilrs) = &mut self.gilrs {
+            while let Some(event) = gilrs.next_event() {
+                self.state.apply(event.event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> GamepadConfig {
+        GamepadConfig {
+            deadzone: 0.2,
+            rotate_speed: 10.0,
+            zoom_speed: 5.0,
+        }
+    }
+
+    #[test]
+    fn stick_inside_deadzone_is_ignored() {
+        let state = GamepadState {
+            left_stick: vec2(0.1, -0.1),
+            right_stick: vec2(0.0, 0.2),
+            ..Default::default()
+        };
+
+        assert_eq!(state.move_axis(&config()), Vec2::zeros());
+        assert_eq!(state.rotate_axis(&config()), Vec2::zeros());
+    }
+
+    #[test]
+    fn stick_ramps_up_from_deadzone_edge() {
+        let state = GamepadState {
+            left_stick: vec2(0.6, 0.0),
+            right_stick: vec2(0.0, 1.0),
+            ..Default::default()
+        };
+
+        // halfway between the deadzone and full deflection
+        assert!((state.move_axis(&config()) - vec2(0.5, 0.0)).norm() < 1e-6);
+
+        // stick up rotates towards the top of the window
+        assert!((state.rotate_axis(&config()) - vec2(0.0, -10.0)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn triggers_zoom_in_opposite_directions() {
+        let mut state = GamepadState {
+            zoom_in: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(state.zoom(&config()), 5.0);
+
+        state.zoom_out = 1.0;
+        assert_eq!(state.zoom(&config()), 0.0);
+
+        state.zoom_in = 0.0;
+        assert_eq!(state.zoom(&config()), -5.0);
+    }
+}
@@ -4,11 +4,18 @@ use event::{InputEvent, SyncEventDelegate};
 use nalgebra_glm::{vec2, Vec2};
 use winit::{
     dpi::PhysicalSize,
-    event::{
-        DeviceEvent, ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
-    },
+    event::{DeviceEvent, ElementState, MouseScrollDelta, WindowEvent},
 };
 
+pub use bindings::{Binding, InputAction, InputBindings};
+
+#[cfg(feature = "gamepad")]
+pub use gamepad::GamepadConfig;
+
+mod bindings;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+
 #[derive(Clone, Copy)]
 pub struct GameInputInterface<'a> {
     inner: &'a GameInput,
@@ -20,13 +27,30 @@ impl<'a> GameInputInterface<'a> {
         &*self.inner.cursor_position
     }
 
+    /// Ratio of physical to logical pixels for the monitor the window is on
+    #[inline]
+    pub fn scale_factor(&self) -> f64 {
+        self.inner.scale_factor
+    }
+
+    /// Cursor position in normalized device coordinates, with x pointing right and y pointing
+    /// up, in the range [-1, 1]. The window's top-left corner maps to (-1, 1).
+    ///
+    /// Cursor position and window size are both tracked in physical pixels, so this remains
+    /// correct across scale factor changes
     #[inline]
     pub fn cursor_position_ndc(&self) -> Vec2 {
         Vec2::from([
             self.inner.cursor_position.x * 2.0 / self.inner.window_size.x - 1.0,
-            self.inner.cursor_position.y * 2.0 / self.inner.window_size.y - 1.0,
+            1.0 - self.inner.cursor_position.y * 2.0 / self.inner.window_size.y,
         ])
     }
+
+    /// Whether selecting adds to the selection, rather than replacing it
+    #[inline]
+    pub fn is_adding_to_selection(&self) -> bool {
+        self.inner.adding_to_selection
+    }
 }
 
 struct InputState<T> {
@@ -72,32 +96,79 @@ impl<T> DerefMut for InputState<T> {
     }
 }
 
+/// Movement actions currently held down, so that releasing one leaves the others in effect
+#[derive(Clone, Copy, Default)]
+struct HeldMovement(u8);
+
+impl HeldMovement {
+    const FORWARD: u8 = 1 << 0;
+    const BACKWARD: u8 = 1 << 1;
+    const LEFT: u8 = 1 << 2;
+    const RIGHT: u8 = 1 << 3;
+
+    fn set(&mut self, flag: u8, held: bool) {
+        if held {
+            self.0 |= flag;
+        } else {
+            self.0 &= !flag;
+        }
+    }
+
+    fn is_held(&self, flag: u8) -> bool {
+        self.0 & flag != 0
+    }
+
+    /// Opposing directions cancel out
+    fn axis(&self) -> Vec2 {
+        let direction = |positive, negative| {
+            self.is_held(positive) as i8 as f32 - self.is_held(negative) as i8 as f32
+        };
+
+        vec2(
+            direction(Self::RIGHT, Self::LEFT),
+            direction(Self::FORWARD, Self::BACKWARD),
+        )
+    }
+}
+
 pub struct GameInput {
+    bindings: InputBindings,
     window_size: Vec2,
+    scale_factor: f64,
     cursor_position: InputState<Vec2>,
     left_mouse_button: InputState<bool>,
-    camera_movement: Vec2,
+    adding_to_selection: bool,
+    held_movement: HeldMovement,
     camera_rotating: bool,
     camera_rotation: Vec2,
     camera_zoom: f32,
     server_state: Option<InputEvent>,
     spawn: InputState<bool>,
     spawn_guest: InputState<bool>,
+    queued_events: Vec<InputEvent>,
+    #[cfg(feature = "gamepad")]
+    gamepads: gamepad::Gamepads,
 }
 
 impl GameInput {
-    pub fn new(window_size: PhysicalSize<u32>) -> Self {
+    pub fn new(window_size: PhysicalSize<u32>, scale_factor: f64) -> Self {
         Self {
+            bindings: Default::default(),
             window_size: vec2(window_size.width as f32, window_size.height as f32),
+            scale_factor,
             cursor_position: Default::default(),
             left_mouse_button: Default::default(),
-            camera_movement: Default::default(),
+            adding_to_selection: false,
+            held_movement: Default::default(),
             camera_rotating: false,
             camera_rotation: Default::default(),
             camera_zoom: Default::default(),
             server_state: None,
             spawn: Default::default(),
             spawn_guest: Default::default(),
+            queued_events: Vec::new(),
+            #[cfg(feature = "gamepad")]
+            gamepads: Default::default(),
         }
     }
 
@@ -105,6 +176,35 @@ impl GameInput {
         GameInputInterface { inner: self }
     }
 
+    pub fn bindings(&self) -> &InputBindings {
+        &self.bindings
+    }
+
+    pub fn set_bindings(&mut self, bindings: InputBindings) {
+        self.bindings = bindings;
+    }
+
+    /// Replaces all inputs bound to the action, see `InputBindings::set_binding`
+    pub fn set_binding(&mut self, action: InputAction, input: impl Into<Binding>) {
+        self.bindings.set_binding(action, input);
+    }
+
+    #[cfg(feature = "gamepad")]
+    pub fn set_gamepad_config(&mut self, config: GamepadConfig) {
+        self.gamepads.config = config;
+    }
+
+    /// Polls connected gamepads. Must be called before `update` each frame.
+    #[cfg(feature = "gamepad")]
+    pub fn handle_gamepad(&mut self) {
+        self.gamepads.poll();
+    }
+
+    /// Queues an event to be pushed on the next update, e.g. from the debug console
+    pub fn queue_event(&mut self, event: InputEvent) {
+        self.queued_events.push(event);
+    }
+
     pub fn handle_raw_input(&mut self, event: DeviceEvent) {
         if let DeviceEvent::MouseMotion { delta } = event {
             if self.camera_rotating {
@@ -121,54 +221,14 @@ impl GameInput {
                 self.cursor_position.y = position.y as f32;
             }
             WindowEvent::KeyboardInput { input, .. } => {
-                let pressed = input.state == ElementState::Pressed;
                 if let Some(keycode) = input.virtual_keycode {
-                    match keycode {
-                        VirtualKeyCode::W => {
-                            self.camera_movement.y = if pressed { 1.0 } else { 0.0 };
-                        }
-                        VirtualKeyCode::A => {
-                            self.camera_movement.x = if pressed { -1.0 } else { 0.0 };
-                        }
-                        VirtualKeyCode::S => {
-                            self.camera_movement.y = if pressed { -1.0 } else { 0.0 };
-                        }
-                        VirtualKeyCode::D => {
-                            self.camera_movement.x = if pressed { 1.0 } else { 0.0 };
-                        }
-                        VirtualKeyCode::Space => {
-                            self.camera_rotating = pressed;
-                        }
-                        VirtualKeyCode::Key1 => {
-                            self.server_state = Some(InputEvent::ServerBegin);
-                        }
-                        VirtualKeyCode::Key2 => {
-                            self.server_state = Some(InputEvent::ServerConnect);
-                        }
-                        VirtualKeyCode::Key3 => {
-                            self.server_state = Some(InputEvent::ServerDisconnect);
-                        }
-                        VirtualKeyCode::N if pressed => {
-                            *self.spawn = true;
-                        }
-                        VirtualKeyCode::G if pressed => {
-                            *self.spawn_guest = true;
-                        }
-                        _ => {}
-                    }
+                    let pressed = input.state == ElementState::Pressed;
+                    self.handle_binding(Binding::Key(keycode), pressed);
                 }
             }
             WindowEvent::MouseInput { button, state, .. } => {
                 let pressed = state == ElementState::Pressed;
-                match button {
-                    MouseButton::Left => {
-                        *self.left_mouse_button = pressed;
-                    }
-                    MouseButton::Middle => {
-                        self.camera_rotating = pressed;
-                    }
-                    _ => {}
-                }
+                self.handle_binding(Binding::Mouse(button), pressed);
             }
             WindowEvent::MouseWheel { delta, .. } => {
                 self.camera_zoom += match delta {
@@ -180,10 +240,70 @@ impl GameInput {
                 self.window_size.x = size.width as f32;
                 self.window_size.y = size.height as f32;
             }
+            WindowEvent::ScaleFactorChanged {
+                scale_factor,
+                new_inner_size,
+            } => {
+                self.scale_factor = scale_factor;
+                self.window_size.x = new_inner_size.width as f32;
+                self.window_size.y = new_inner_size.height as f32;
+            }
             _ => {}
         }
     }
 
+    fn handle_binding(&mut self, input: Binding, pressed: bool) {
+        let action = match self.bindings.action(input) {
+            Some(action) => action,
+            None => return,
+        };
+
+        match action {
+            InputAction::MoveForward => self.held_movement.set(HeldMovement::FORWARD, pressed),
+            InputAction::MoveLeft => self.held_movement.set(HeldMovement::LEFT, pressed),
+            InputAction::MoveBackward => self.held_movement.set(HeldMovement::BACKWARD, pressed),
+            InputAction::MoveRight => self.held_movement.set(HeldMovement::RIGHT, pressed),
+            InputAction::RotateHold => {
+                self.camera_rotating = pressed;
+            }
+            InputAction::Select => {
+                *self.left_mouse_button = pressed;
+            }
+            InputAction::AddToSelection => {
+                self.adding_to_selection = pressed;
+            }
+            InputAction::ServerBegin => {
+                self.server_state = Some(InputEvent::ServerBegin);
+            }
+            InputAction::ServerConnect => {
+                self.server_state = Some(InputEvent::ServerConnect);
+            }
+            InputAction::ServerDisconnect => {
+                self.server_state = Some(InputEvent::ServerDisconnect);
+            }
+            InputAction::Spawn if pressed => {
+                *self.spawn = true;
+            }
+            InputAction::SpawnGuest if pressed => {
+                *self.spawn_guest = true;
+            }
+            InputAction::Undo if pressed => {
+                self.queued_events.push(InputEvent::Undo);
+            }
+            InputAction::Redo if pressed => {
+                self.queued_events.push(InputEvent::Redo);
+            }
+            InputAction::Cancel if pressed => {
+                self.queued_events.push(InputEvent::Cancel);
+            }
+            InputAction::Spawn
+            | InputAction::SpawnGuest
+            | InputAction::Undo
+            | InputAction::Redo
+            | InputAction::Cancel => {}
+        }
+    }
+
     pub fn update(&mut self, event_delegate: &mut SyncEventDelegate) {
         if self.cursor_position.updated().is_some() {
             event_delegate.push_input_event(InputEvent::CursorMoved);
@@ -205,9 +325,25 @@ impl GameInput {
             event_delegate.push_input_event(InputEvent::SpawnGuest);
         }
 
+        for event in self.queued_events.drain(..) {
+            event_delegate.push_input_event(event);
+        }
+
         // axis events are updated every frame
 
-        event_delegate.push_input_event(InputEvent::CameraMoveAxis(self.camera_movement));
+        #[allow(unused_mut)]
+        let mut camera_movement = self.held_movement.axis();
+
+        #[cfg(feature = "gamepad")]
+        {
+            let state = &self.gamepads.state;
+            let config = &self.gamepads.config;
+            camera_movement += state.move_axis(config);
+            self.camera_rotation += state.rotate_axis(config);
+            self.camera_zoom += state.zoom(config);
+        }
+
+        event_delegate.push_input_event(InputEvent::CameraMoveAxis(camera_movement));
         event_delegate.push_input_event(InputEvent::CameraRotateAxis(self.camera_rotation));
         event_delegate.push_input_event(InputEvent::CameraZoom(self.camera_zoom));
 
@@ -215,3 +351,106 @@ impl GameInput {
         self.camera_zoom = 0.0;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use event::EventManager;
+    use winit::{
+        dpi::PhysicalPosition,
+        event::{DeviceId, VirtualKeyCode},
+    };
+
+    use super::*;
+
+    fn game_input() -> GameInput {
+        GameInput::new(PhysicalSize::new(800, 600), 1.0)
+    }
+
+    #[test]
+    fn held_movement_combines_keys() {
+        let mut input = game_input();
+
+        input.handle_binding(VirtualKeyCode::W.into(), true);
+        input.handle_binding(VirtualKeyCode::D.into(), true);
+        assert_eq!(input.held_movement.axis(), vec2(1.0, 1.0));
+
+        input.handle_binding(VirtualKeyCode::W.into(), false);
+        assert_eq!(input.held_movement.axis(), vec2(1.0, 0.0));
+
+        input.handle_binding(VirtualKeyCode::D.into(), false);
+        assert_eq!(input.held_movement.axis(), vec2(0.0, 0.0));
+    }
+
+    #[test]
+    fn held_movement_opposing_keys_cancel() {
+        let mut input = game_input();
+
+        input.handle_binding(VirtualKeyCode::A.into(), true);
+        input.handle_binding(VirtualKeyCode::D.into(), true);
+        assert_eq!(input.held_movement.axis(), vec2(0.0, 0.0));
+
+        input.handle_binding(VirtualKeyCode::A.into(), false);
+        assert_eq!(input.held_movement.axis(), vec2(1.0, 0.0));
+    }
+
+    #[test]
+    fn window_corners_map_to_ndc_corners() {
+        let mut input = game_input();
+
+        let corners = [
+            (vec2(0.0, 0.0), vec2(-1.0, 1.0)),
+            (vec2(800.0, 0.0), vec2(1.0, 1.0)),
+            (vec2(0.0, 600.0), vec2(-1.0, -1.0)),
+            (vec2(800.0, 600.0), vec2(1.0, -1.0)),
+        ];
+
+        for (cursor_position, ndc) in corners {
+            *input.cursor_position = cursor_position;
+            assert_eq!(input.interface().cursor_position_ndc(), ndc);
+        }
+    }
+
+    #[test]
+    fn rebound_forward_moves_camera() {
+        let mut input = game_input();
+        input.set_binding(InputAction::MoveForward, VirtualKeyCode::Up);
+
+        // the previous binding no longer moves the camera
+        input.handle_binding(VirtualKeyCode::W.into(), true);
+        input.handle_binding(VirtualKeyCode::Up.into(), true);
+
+        let mut event_manager = EventManager::new(NonZeroUsize::new(1).unwrap());
+        let mut event_delegate = event_manager.sync_delegate();
+        input.update(&mut event_delegate);
+
+        let move_axis = event_delegate.input_events().find_map(|event| match event {
+            InputEvent::CameraMoveAxis(axis) => Some(*axis),
+            _ => None,
+        });
+        assert_eq!(move_axis, Some(vec2(0.0, 1.0)));
+    }
+
+    #[test]
+    fn cursor_ndc_follows_scale_factor_change() {
+        let mut input = game_input();
+
+        let mut new_inner_size = PhysicalSize::new(1600, 1200);
+        input.handle_input(WindowEvent::ScaleFactorChanged {
+            scale_factor: 2.0,
+            new_inner_size: &mut new_inner_size,
+        });
+
+        #[allow(deprecated)]
+        input.handle_input(WindowEvent::CursorMoved {
+            // SAFETY: the id is only compared, never used to access a device
+            device_id: unsafe { DeviceId::dummy() },
+            position: PhysicalPosition::new(400.0, 900.0),
+            modifiers: Default::default(),
+        });
+
+        assert_eq!(input.interface().scale_factor(), 2.0);
+        assert_eq!(input.interface().cursor_position_ndc(), vec2(-0.5, -0.5));
+    }
+}
@@ -1,31 +1,255 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Error, Result};
+use task_executor::async_task::{execute_async, AsyncTaskHandle};
 
 pub use mesh::Mesh;
+pub use mesh_cache::MeshCache;
+pub use mesh_layout::{pack_mesh, MeshLayout, VERTEX_STRIDE};
 
 mod mesh;
+mod mesh_cache;
+mod mesh_layout;
 
+/// Caches resources by name, so that each is loaded at most once while cached
 #[derive(Default)]
-pub struct ResourceManager;
+pub struct ResourceManager {
+    resources: HashMap<String, Arc<Resource>>,
+}
 
 impl ResourceManager {
     pub fn resource(&mut self, name: String) -> Arc<Resource> {
-        Arc::new(Resource::new(name))
+        self.resources
+            .entry(name)
+            .or_insert_with_key(|name| Arc::new(Resource::new(name.clone())))
+            .clone()
+    }
+
+    /// Removes the resource from the cache, so that it is reloaded when next requested. Existing
+    /// references keep their loaded data. Returns false if the resource was not cached.
+    pub fn unload(&mut self, name: &str) -> bool {
+        self.resources.remove(name).is_some()
     }
 }
 
+enum MeshState {
+    Unloaded,
+    Loading {
+        /// Set by whichever of the task and `Resource::mesh` begins parsing first, so that the
+        /// mesh is only parsed once
+        claimed: Arc<AtomicBool>,
+        /// Returns None if the mesh was claimed before the task began
+        task_handle: AsyncTaskHandle<Option<Result<Mesh>>>,
+    },
+    Loaded(Arc<Mesh>),
+    /// The error message, including its causes
+    Failed(String),
+}
+
 pub struct Resource {
     name: String,
+    mesh: Mutex<MeshState>,
 }
 
 impl Resource {
     fn new(name: String) -> Self {
-        Self { name }
+        Self {
+            name,
+            mesh: Mutex::new(MeshState::Unloaded),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
-    pub fn mesh(&self) -> Result<Mesh> {
-        let path = PathBuf::from(&self.name);
-        mesh::load(&path).with_context(|| format!("could not load mesh {}", self.name))
+    /// Begins loading the mesh on the task executor, if not already loading or loaded. Must be
+    /// called from an executor thread.
+    pub fn request_mesh(&self) {
+        let mut state = self.mesh.lock().unwrap();
+        if let MeshState::Unloaded = *state {
+            let path = self.mesh_path();
+            let claimed = Arc::new(AtomicBool::new(false));
+
+            let task_handle = {
+                let claimed = claimed.clone();
+                execute_async(async move {
+                    (!claimed.swap(true, Ordering::AcqRel)).then(|| load_mesh(path))
+                })
+            };
+
+            *state = MeshState::Loading {
+                claimed,
+                task_handle,
+            };
+        }
+    }
+
+    /// Returns the mesh once loaded, requesting it if necessary. Returns None while loading. Must
+    /// be called from an executor thread.
+    pub fn try_mesh(&self) -> Option<Result<Arc<Mesh>>> {
+        self.request_mesh();
+        self.loaded_mesh()
+    }
+
+    /// Returns the mesh if it has finished loading, without requesting it. May be called from any
+    /// thread.
+    pub fn loaded_mesh(&self) -> Option<Result<Arc<Mesh>>> {
+        let mut state = self.mesh.lock().unwrap();
+        Self::complete_loading(&mut state);
+
+        match &*state {
+            MeshState::Loaded(mesh) => Some(Ok(mesh.clone())),
+            MeshState::Failed(message) => Some(Err(Error::msg(message.clone()))),
+            _ => None,
+        }
+    }
+
+    /// Returns the mesh, loading it on this thread if it has not been loaded. If a load task has
+    /// not yet begun parsing, the mesh is parsed here instead, as the task may be unable to
+    /// progress if this is called from another task. Otherwise this waits for the task.
+    pub fn mesh(&self) -> Result<Arc<Mesh>> {
+        let mut state = self.mesh.lock().unwrap();
+        Self::complete_loading(&mut state);
+
+        if let MeshState::Loading {
+            claimed,
+            task_handle,
+        } = &*state
+        {
+            if claimed.swap(true, Ordering::AcqRel) {
+                // the task is parsing on another thread, so finishes without this one
+                while !task_handle.is_finished() {
+                    thread::yield_now();
+                }
+
+                Self::complete_loading(&mut state);
+            } else {
+                // the task will find the mesh claimed and return without parsing
+                *state = MeshState::Unloaded;
+            }
+        }
+
+        if let MeshState::Unloaded = *state {
+            *state = mesh_state(load_mesh(self.mesh_path()));
+        }
+
+        match &*state {
+            MeshState::Loading { .. } => unreachable!(),
+            MeshState::Loaded(mesh) => Ok(mesh.clone()),
+            MeshState::Failed(message) => Err(Error::msg(message.clone())),
+            MeshState::Unloaded => unreachable!(),
+        }
+    }
+
+    fn mesh_path(&self) -> PathBuf {
+        PathBuf::from(&self.name)
+    }
+
+    fn complete_loading(state: &mut MeshState) {
+        if let MeshState::Loading { task_handle, .. } = state {
+            // the task only finds the mesh claimed once the state has left Loading
+            match task_handle.try_take_result() {
+                Some(Ok(Some(result))) => *state = mesh_state(result),
+                Some(Err(_)) => *state = MeshState::Failed("mesh load task panicked".to_string()),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn load_mesh(path: PathBuf) -> Result<Mesh> {
+    mesh::load(&path).with_context(|| format!("could not load mesh {}", path.display()))
+}
+
+fn mesh_state(result: Result<Mesh>) -> MeshState {
+    match result {
+        Ok(mesh) => MeshState::Loaded(Arc::new(mesh)),
+        Err(err) => MeshState::Failed(format!("{err:#}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, future::Future, num::NonZeroUsize, process};
+
+    use task_executor::{async_task::yield_now, ExecutorOptions, TaskExecutor};
+
+    use super::*;
+
+    const TRIANGLE_OBJ: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1\n";
+
+    /// Writes a triangle mesh to a file unique to the test, returning its path
+    fn triangle_mesh_path(test_name: &str) -> String {
+        let path = env::temp_dir().join(format!("{test_name}-{}.obj", process::id()));
+        fs::write(&path, TRIANGLE_OBJ).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    /// Runs the future on a single executor thread, so that async tasks it starts can't
+    /// progress until it yields
+    fn execute_blocking(future: impl Future<Output = ()> + Send) {
+        let options = ExecutorOptions {
+            pin_threads: false,
+            ..Default::default()
+        };
+
+        let mut executor =
+            TaskExecutor::with_options(options, NonZeroUsize::new(1).unwrap(), &|_| {});
+
+        let mut future = Box::pin(future);
+        executor.execute_blocking(future.as_mut()).unwrap();
+    }
+
+    #[test]
+    fn mesh_parses_in_place_of_pending_task() {
+        let path = triangle_mesh_path("mesh_parses_in_place_of_pending_task");
+        let resource = ResourceManager::default().resource(path.clone());
+
+        execute_blocking(async {
+            assert!(resource.try_mesh().is_none());
+
+            let mesh = resource.mesh().unwrap();
+            assert_eq!(mesh.vertex_indices, [0, 1, 2]);
+
+            // the task finds the mesh claimed, so it isn't parsed again
+            for _ in 0..8 {
+                yield_now().await;
+            }
+
+            let loaded_mesh = resource.try_mesh().unwrap().unwrap();
+            assert!(Arc::ptr_eq(&mesh, &loaded_mesh));
+        });
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn mesh_waits_for_finished_task() {
+        let path = triangle_mesh_path("mesh_waits_for_finished_task");
+        let resource = ResourceManager::default().resource(path.clone());
+
+        execute_blocking(async {
+            resource.request_mesh();
+
+            let loaded_mesh = loop {
+                match resource.loaded_mesh() {
+                    Some(mesh) => break mesh.unwrap(),
+                    None => yield_now().await,
+                }
+            };
+
+            assert!(Arc::ptr_eq(&resource.mesh().unwrap(), &loaded_mesh));
+        });
+
+        fs::remove_file(path).unwrap();
     }
 }
@@ -1,7 +1,8 @@
 use std::path::Path;
 
-use anyhow::{Error, Result};
-use nalgebra_glm::Vec3;
+use anyhow::{bail, Error, Result};
+use gltf::{buffer, Document};
+use nalgebra_glm::{vec3, Vec3};
 
 #[derive(Default)]
 pub struct Mesh {
@@ -10,9 +11,124 @@ pub struct Mesh {
     pub vertex_normals: Vec<Vec3>,
 }
 
+impl Mesh {
+    /// Placeholder triangle for entities without a mesh resource
+    pub fn placeholder() -> Self {
+        Self {
+            vertex_indices: vec![0, 1, 2],
+            vertex_positions: vec![
+                vec3(0.0, 0.0, 1.0),
+                vec3(-1.0, 0.0, -1.0),
+                vec3(1.0, 0.0, -1.0),
+            ],
+            vertex_normals: Vec::new(),
+        }
+    }
+
+    /// Loads a Wavefront OBJ mesh. Materials are ignored, and faces are triangulated.
+    pub fn from_obj_bytes(bytes: &[u8]) -> Result<Self> {
+        let (models, _) = tobj::load_obj_buf(&mut &bytes[..], &obj_load_options(), |_| {
+            Err(tobj::LoadError::OpenFileFailed)
+        })?;
+
+        from_obj(models)
+    }
+
+    /// Distance from the origin to the furthest vertex
+    pub fn bounding_radius(&self) -> f32 {
+        self.vertex_positions
+            .iter()
+            .map(|position| position.norm())
+            .fold(0.0, f32::max)
+    }
+
+    /// Minimum and maximum corners of the axis-aligned box containing every vertex, or None if
+    /// the mesh has no vertices
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        let first = self.vertex_positions.first()?;
+
+        Some(
+            self.vertex_positions
+                .iter()
+                .fold((*first, *first), |(min, max), position| {
+                    (min.inf(position), max.sup(position))
+                }),
+        )
+    }
+
+    /// Loads a binary glTF (.glb) mesh, or a .gltf mesh with embedded buffers
+    pub fn from_gltf_bytes(bytes: &[u8]) -> Result<Self> {
+        let (document, buffers, _) = gltf::import_slice(bytes)?;
+        from_gltf(&document, &buffers)
+    }
+}
+
+/// Loads a mesh in the format given by the file extension: .obj, .gltf or .glb
 pub fn load(path: &Path) -> Result<Mesh> {
-    let (document, buffers, _) = gltf::import(path)?;
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("obj") => {
+            let (models, _) = tobj::load_obj(path, &obj_load_options())?;
+            from_obj(models)
+        }
+        Some("gltf" | "glb") => {
+            let (document, buffers, _) = gltf::import(path)?;
+            from_gltf(&document, &buffers)
+        }
+        Some(extension) => bail!("unsupported mesh format: .{extension}"),
+        None => bail!("mesh path has no file extension: {}", path.display()),
+    }
+}
+
+fn obj_load_options() -> tobj::LoadOptions {
+    tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ignore_points: true,
+        ignore_lines: true,
+    }
+}
+
+/// Models are merged into a single mesh
+fn from_obj(models: Vec<tobj::Model>) -> Result<Mesh> {
+    let mut mesh = Mesh::default();
 
+    for model in models {
+        let positions = &model.mesh.positions;
+        let normals = &model.mesh.normals;
+
+        if normals.len() != positions.len() {
+            bail!("model {} contains no vertex normals", model.name);
+        }
+
+        let index_offset = mesh.vertex_positions.len() as u32;
+
+        for index in &model.mesh.indices {
+            mesh.vertex_indices
+                .push(u16::try_from(index + index_offset)?);
+        }
+
+        mesh.vertex_positions.extend(
+            positions
+                .chunks_exact(3)
+                .map(|position| vec3(position[0], position[1], position[2])),
+        );
+
+        mesh.vertex_normals.extend(
+            normals
+                .chunks_exact(3)
+                .map(|normal| vec3(normal[0], normal[1], normal[2])),
+        );
+    }
+
+    Ok(mesh)
+}
+
+fn from_gltf(document: &Document, buffers: &[buffer::Data]) -> Result<Mesh> {
     let mut mesh = Mesh::default();
 
     for primitive in document.meshes().flat_map(|mesh| mesh.primitives()) {
@@ -40,3 +156,45 @@ pub fn load(path: &Path) -> Result<Mesh> {
 
     Ok(mesh)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE_OBJ: &str = "\
+v 0 0 1
+v -1 0 -1
+v 1 0 -1
+vn 0 1 0
+f 1//1 2//1 3//1
+";
+
+    /// Triangle with positions, normals and u16 indices in a single embedded buffer
+    const TRIANGLE_GLTF: &str = include_str!("../fixtures/triangle.gltf");
+
+    #[test]
+    fn obj_triangle_is_loaded() {
+        let mesh = Mesh::from_obj_bytes(TRIANGLE_OBJ.as_bytes()).unwrap();
+        assert_eq!(mesh.vertex_positions.len(), 3);
+        assert_eq!(mesh.vertex_normals.len(), 3);
+        assert_eq!(mesh.vertex_indices.len(), 3);
+    }
+
+    #[test]
+    fn gltf_triangle_is_loaded() {
+        let mesh = Mesh::from_gltf_bytes(TRIANGLE_GLTF.as_bytes()).unwrap();
+        assert_eq!(mesh.vertex_positions.len(), 3);
+        assert_eq!(mesh.vertex_normals.len(), 3);
+        assert_eq!(mesh.vertex_indices, [0, 1, 2]);
+        assert_eq!(mesh.vertex_positions[0], vec3(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn unknown_extension_is_an_error() {
+        let error = load(Path::new("mesh.fbx")).err().unwrap();
+        assert!(error.to_string().contains("unsupported mesh format: .fbx"));
+
+        let error = load(Path::new("mesh")).err().unwrap();
+        assert!(error.to_string().contains("no file extension"));
+    }
+}
@@ -0,0 +1,144 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::Result;
+
+use crate::{Mesh, Resource};
+
+/// Cache key of the mesh drawn for entities without a mesh resource
+const DEFAULT_MESH_NAME: &str = "<default>";
+
+/// Uploaded meshes by resource name, shared by every entity using the resource. Renderers provide
+/// the upload, and meshes which fail to load or upload are cached as None, so that they aren't
+/// loaded again.
+pub struct MeshCache<T> {
+    meshes: HashMap<String, Option<T>>,
+}
+
+impl<T> Default for MeshCache<T> {
+    fn default() -> Self {
+        Self {
+            meshes: HashMap::new(),
+        }
+    }
+}
+
+impl<T> MeshCache<T> {
+    /// Uploads the resource's mesh on first use, or the placeholder mesh if there is no resource.
+    /// Returns None if the mesh could not be loaded or uploaded.
+    pub fn get_or_upload(
+        &mut self,
+        resource: Option<&Arc<Resource>>,
+        upload: impl FnOnce(&Mesh) -> Result<T>,
+    ) -> Option<&T> {
+        let name = resource.map_or(DEFAULT_MESH_NAME, |resource| resource.name());
+
+        if !self.meshes.contains_key(name) {
+            let mesh = match resource {
+                Some(resource) => resource.mesh(),
+                None => Ok(Arc::new(Mesh::placeholder())),
+            }
+            .and_then(|mesh| upload(&mesh));
+
+            let mesh = match mesh {
+                Ok(mesh) => Some(mesh),
+                Err(err) => {
+                    log::error!("skipping mesh {name}: {err:#}");
+                    None
+                }
+            };
+
+            self.meshes.insert(name.to_owned(), mesh);
+        }
+
+        self.meshes[name].as_ref()
+    }
+
+    /// Number of cached meshes, including those which failed to load
+    pub fn len(&self) -> usize {
+        self.meshes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.meshes.is_empty()
+    }
+
+    /// Consumes the cache, returning the uploaded meshes so that they may be freed
+    pub fn into_meshes(self) -> impl Iterator<Item = T> {
+        self.meshes.into_values().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::{pack_mesh, MeshLayout, ResourceManager};
+
+    fn fixture(name: &str) -> Arc<Resource> {
+        let path = format!("{}/fixtures/{name}", env!("CARGO_MANIFEST_DIR"));
+        ResourceManager::default().resource(path)
+    }
+
+    /// Lays out meshes as the renderers do, without a device
+    fn upload(mesh: &Mesh) -> Result<MeshLayout> {
+        pack_mesh(mesh, 16).map(|(_, layout)| layout)
+    }
+
+    #[test]
+    fn fixture_meshes_are_uploaded_with_their_index_counts() {
+        let mut cache = MeshCache::default();
+
+        let quad = cache.get_or_upload(Some(&fixture("quad.obj")), upload);
+        assert_eq!(quad.unwrap().index_count, 6);
+
+        let triangle = cache.get_or_upload(Some(&fixture("triangle.gltf")), upload);
+        assert_eq!(triangle.unwrap().index_count, 3);
+
+        let placeholder = cache.get_or_upload(None, upload);
+        assert_eq!(placeholder.unwrap().index_count, 3);
+
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn each_resource_is_uploaded_once() {
+        let mut cache = MeshCache::default();
+        let uploads = Cell::new(0);
+
+        for resource in [fixture("quad.obj"), fixture("quad.obj")] {
+            cache.get_or_upload(Some(&resource), |mesh| {
+                uploads.set(uploads.get() + 1);
+                upload(mesh)
+            });
+        }
+
+        assert_eq!(uploads.get(), 1);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn failed_mesh_is_not_uploaded_again() {
+        let mut cache = MeshCache::<MeshLayout>::default();
+        let uploads = Cell::new(0);
+
+        for resource in [fixture("quad.obj"), fixture("quad.obj")] {
+            let mesh = cache.get_or_upload(Some(&resource), |_| {
+                uploads.set(uploads.get() + 1);
+                Err(anyhow::Error::msg("out of memory"))
+            });
+            assert!(mesh.is_none());
+        }
+
+        assert_eq!(uploads.get(), 1);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn missing_mesh_is_skipped() {
+        let mut cache = MeshCache::default();
+        assert!(cache
+            .get_or_upload(Some(&fixture("missing.obj")), upload)
+            .is_none());
+    }
+}
@@ -0,0 +1,170 @@
+use std::{mem::size_of, slice};
+
+use anyhow::{bail, Result};
+use nalgebra_glm::Vec3;
+
+use crate::Mesh;
+
+/// Size of each vertex position in a packed mesh buffer
+pub const VERTEX_STRIDE: usize = size_of::<Vec3>();
+
+/// Layout of a mesh in a combined index and vertex buffer. Indices are u16 and start at offset 0,
+/// followed by the vertex positions.
+#[derive(Clone, Copy)]
+pub struct MeshLayout {
+    pub index_count: u32,
+    pub vertex_offset: usize,
+    pub vertex_count: u32,
+}
+
+impl MeshLayout {
+    /// Lays out the mesh with its vertex positions aligned to `vertex_alignment` bytes, which
+    /// must be a power of two
+    pub fn new(mesh: &Mesh, vertex_alignment: usize) -> Self {
+        let index_size = mesh.vertex_indices.len() * size_of::<u16>();
+
+        Self {
+            index_count: mesh.vertex_indices.len() as u32,
+            vertex_offset: (index_size + vertex_alignment - 1) & !(vertex_alignment - 1),
+            vertex_count: mesh.vertex_positions.len() as u32,
+        }
+    }
+
+    /// Size of the buffer holding the mesh, in bytes
+    pub fn size(&self) -> usize {
+        self.vertex_offset + self.vertex_count as usize * VERTEX_STRIDE
+    }
+
+    /// Checks that drawing `indices` with this layout stays within a buffer of `buffer_size`
+    /// bytes, and that every index refers to a valid vertex. An inconsistent layout would read
+    /// out of bounds on the GPU.
+    pub fn validate(&self, indices: &[u16], buffer_size: usize) -> Result<()> {
+        let index_end = self.index_count as usize * size_of::<u16>();
+        let vertex_end = self.size();
+
+        if indices.len() != self.index_count as usize {
+            bail!(
+                "mesh declares {} indices but provides {}",
+                self.index_count,
+                indices.len()
+            );
+        }
+
+        if index_end > self.vertex_offset {
+            bail!(
+                "mesh indices end at byte {index_end}, overlapping vertices at byte {}",
+                self.vertex_offset
+            );
+        }
+
+        if vertex_end > buffer_size {
+            bail!("mesh vertices end at byte {vertex_end}, past buffer size {buffer_size}");
+        }
+
+        if let Some(index) = indices
+            .iter()
+            .find(|index| **index as u32 >= self.vertex_count)
+        {
+            bail!(
+                "mesh index {index} out of range for {} vertices",
+                self.vertex_count
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Writes the mesh into `data`, after validating the layout against its length
+    pub fn write(&self, mesh: &Mesh, data: &mut [u8]) -> Result<()> {
+        self.validate(&mesh.vertex_indices, data.len())?;
+
+        let index_size = self.index_count as usize * size_of::<u16>();
+        let vertex_size = self.vertex_count as usize * VERTEX_STRIDE;
+
+        // SAFETY: u16 and Vec3 are plain data, and the sizes match the mesh's vectors
+        unsafe {
+            let indices =
+                slice::from_raw_parts(mesh.vertex_indices.as_ptr() as *const u8, index_size);
+            data[..index_size].copy_from_slice(indices);
+
+            let vertices =
+                slice::from_raw_parts(mesh.vertex_positions.as_ptr() as *const u8, vertex_size);
+            data[self.vertex_offset..self.vertex_offset + vertex_size].copy_from_slice(vertices);
+        }
+
+        Ok(())
+    }
+}
+
+/// Packs the mesh indices and vertices into a single buffer, returning the buffer and its layout
+pub fn pack_mesh(mesh: &Mesh, vertex_alignment: usize) -> Result<(Vec<u8>, MeshLayout)> {
+    let layout = MeshLayout::new(mesh, vertex_alignment);
+
+    let mut data = vec![0; layout.size()];
+    layout.write(mesh, &mut data)?;
+
+    Ok((data, layout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle() -> Mesh {
+        Mesh::placeholder()
+    }
+
+    #[test]
+    fn packed_mesh_round_trips() {
+        let mesh = triangle();
+        let (data, layout) = pack_mesh(&mesh, 16).unwrap();
+
+        assert_eq!(layout.index_count, 3);
+        assert_eq!(layout.vertex_offset, 16);
+        assert_eq!(data.len(), 16 + 3 * VERTEX_STRIDE);
+        assert_eq!(&data[..2], &mesh.vertex_indices[0].to_le_bytes());
+    }
+
+    #[test]
+    fn out_of_range_index_is_rejected() {
+        let mut mesh = triangle();
+        mesh.vertex_indices[2] = 3;
+
+        assert!(pack_mesh(&mesh, 4).is_err());
+    }
+
+    #[test]
+    fn inconsistent_counts_are_rejected() {
+        let mesh = triangle();
+        let layout = MeshLayout::new(&mesh, 4);
+
+        // more indices declared than the mesh provides
+        let declared_indices = MeshLayout {
+            index_count: 6,
+            ..layout
+        };
+        assert!(declared_indices
+            .validate(&mesh.vertex_indices, layout.size())
+            .is_err());
+
+        // more vertices declared than the buffer holds
+        let declared_vertices = MeshLayout {
+            vertex_count: 4,
+            ..layout
+        };
+        assert!(declared_vertices
+            .validate(&mesh.vertex_indices, layout.size())
+            .is_err());
+
+        // indices overlapping the vertices
+        let overlapping = MeshLayout {
+            vertex_offset: 4,
+            ..layout
+        };
+        assert!(overlapping
+            .validate(&mesh.vertex_indices, layout.size())
+            .is_err());
+
+        assert!(layout.validate(&mesh.vertex_indices, layout.size()).is_ok());
+    }
+}
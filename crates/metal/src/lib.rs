@@ -1,17 +1,19 @@
 #![cfg(target_vendor = "apple")]
 
-use std::{collections::HashMap, mem, slice};
+use std::{collections::HashMap, mem, slice, sync::Arc};
 
 use anyhow::{Context, Error, Result};
 use cocoa::{appkit::NSView, base::id as cocoa_id};
 use core_graphics_types::geometry::CGSize;
-use frame_buffer::FrameBufferReader;
+use frame_buffer::{FrameBufferReader, RenderStyle, Transform};
 use game_entity::EntityId;
+use game_resources::{Mesh, MeshCache, MeshLayout, Resource};
 use metal::{
-    Buffer, CommandQueue, Device, MTLClearColor, MTLIndexType, MTLLoadAction, MTLPixelFormat,
-    MTLPrimitiveType, MTLResourceOptions, MetalLayer, NSRange, NSUInteger, RenderPassDescriptor,
+    Buffer, CommandBuffer, CommandQueue, Device, MTLClearColor, MTLIndexType, MTLLoadAction,
+    MTLPixelFormat, MTLPrimitiveType, MTLResourceOptions, MTLTriangleFillMode, MetalLayer, NSRange,
+    NSUInteger, RenderPassDescriptor,
 };
-use nalgebra_glm::{look_at_lh, perspective_lh_zo, translate, Mat4, Vec3};
+use nalgebra_glm::Mat4;
 use objc::{rc::autoreleasepool, runtime::YES};
 use winit::{dpi::PhysicalSize, platform::macos::WindowExtMacOS, window::Window};
 
@@ -19,10 +21,26 @@ use crate::pipeline::Pipeline;
 
 mod pipeline;
 
-struct StaticMesh {
+/// Combined index and vertex buffer, shared by every static mesh using the same resource
+#[derive(Clone)]
+struct MeshBuffer {
     buffer: Buffer,
     locations_offset: NSUInteger,
-    location: Vec3,
+    index_count: NSUInteger,
+}
+
+/// Model matrices of the static meshes drawn in one frame
+struct InstanceBuffer {
+    buffer: Buffer,
+    /// The last command buffer reading from the buffer, which must complete before it is written
+    command_buffer: Option<CommandBuffer>,
+}
+
+struct StaticMesh {
+    mesh: MeshBuffer,
+    transform: Transform,
+    visible: bool,
+    style: RenderStyle,
 }
 
 pub struct Metal {
@@ -30,14 +48,43 @@ pub struct Metal {
     layer: MetalLayer,
     queue: CommandQueue,
     pipeline: Pipeline,
+    ghost_pipeline: Pipeline,
     aspect: f32,
+    wireframe: bool,
     static_meshes: HashMap<EntityId, StaticMesh>,
+    /// Alternated between frames, so that one may be written while the other is drawn
+    instance_buffers: [InstanceBuffer; 2],
+    current_frame_index: bool,
+    instance_capacity: usize,
+    meshes: MeshCache<MeshBuffer>,
 }
 
 unsafe impl Send for Metal {}
 
+/// Number of static meshes to preallocate for, unless set otherwise
+pub const DEFAULT_INSTANCE_CAPACITY: usize = 32;
+
+/// Offset alignment of buffers in the constant address space
+const CONSTANT_BUFFER_ALIGNMENT: usize = 256;
+
+/// Stride of model matrices within an instance buffer
+const INSTANCE_STRIDE: usize = (mem::size_of::<Mat4>() + CONSTANT_BUFFER_ALIGNMENT - 1)
+    / CONSTANT_BUFFER_ALIGNMENT
+    * CONSTANT_BUFFER_ALIGNMENT;
+
+/// Length of an instance buffer holding `instance_capacity` model matrices. Buffers may not be
+/// empty, so room for at least one is allocated.
+fn instance_buffer_length(instance_capacity: usize) -> NSUInteger {
+    (INSTANCE_STRIDE * instance_capacity.max(1)) as NSUInteger
+}
+
 impl Metal {
     pub fn new(window: &Window) -> Result<Self> {
+        Self::with_instance_capacity(window, DEFAULT_INSTANCE_CAPACITY)
+    }
+
+    /// Sizes per-instance buffers for up to `instance_capacity` static meshes
+    pub fn with_instance_capacity(window: &Window, instance_capacity: usize) -> Result<Self> {
         autoreleasepool(|| {
             let device = Device::system_default().ok_or_else(|| Error::msg("no device found"))?;
             log::info!("Metal device: {}", device.name());
@@ -58,18 +105,35 @@ impl Metal {
 
             let queue = device.new_command_queue();
 
-            let pipeline = Pipeline::new("default", &device)
+            let pipeline = Pipeline::new("default", &device, false)
                 .context("pipeline creation failed for: default")?;
 
+            let ghost_pipeline = Pipeline::new("default", &device, true)
+                .context("pipeline creation failed for: default (translucent)")?;
+
             let aspect = size.width as f32 / size.height as f32;
 
+            let instance_buffers = [(); 2].map(|_| InstanceBuffer {
+                buffer: device.new_buffer(
+                    instance_buffer_length(instance_capacity),
+                    MTLResourceOptions::StorageModeManaged,
+                ),
+                command_buffer: None,
+            });
+
             Ok(Self {
                 device,
                 layer,
                 queue,
                 pipeline,
+                ghost_pipeline,
                 aspect,
-                static_meshes: HashMap::new(),
+                wireframe: false,
+                static_meshes: HashMap::with_capacity(instance_capacity),
+                instance_buffers,
+                current_frame_index: false,
+                instance_capacity,
+                meshes: MeshCache::default(),
             })
         })
     }
@@ -83,27 +147,46 @@ impl Metal {
         self.aspect = size.width as f32 / size.height as f32;
     }
 
+    pub fn set_wireframe(&mut self, wireframe: bool) -> Result<()> {
+        self.wireframe = wireframe;
+        Ok(())
+    }
+
     pub async fn frame(&mut self, frame_buffer: &FrameBufferReader<'_>) {
         for (old_id, new_id) in frame_buffer.updated_entity_ids() {
-            let static_mesh = self.static_meshes.remove(old_id).unwrap();
-            self.static_meshes.insert(*new_id, static_mesh);
+            // entities may have no mesh if their upload was rejected
+            if let Some(static_mesh) = self.static_meshes.remove(old_id) {
+                self.static_meshes.insert(*new_id, static_mesh);
+            }
         }
 
         for entity_id in frame_buffer.despawned() {
             self.static_meshes.remove(entity_id);
         }
 
-        for entity_id in frame_buffer.spawned_guests() {
-            self.spawn_static_mesh(*entity_id);
+        for guest in frame_buffer.spawned_guests() {
+            self.spawn_static_mesh(guest.entity_id, guest.resource.as_ref());
         }
 
         for static_mesh in frame_buffer.spawned_static_meshes() {
-            self.spawn_static_mesh(static_mesh.entity_id);
+            self.spawn_static_mesh(static_mesh.entity_id, Some(&static_mesh.resource));
+        }
+
+        for (entity_id, visible) in frame_buffer.visibility() {
+            if let Some(static_mesh) = self.static_meshes.get_mut(entity_id) {
+                static_mesh.visible = *visible;
+            }
+        }
+
+        for (entity_id, style) in frame_buffer.render_styles() {
+            if let Some(static_mesh) = self.static_meshes.get_mut(entity_id) {
+                static_mesh.style = *style;
+            }
         }
 
-        for (entity_id, location) in frame_buffer.locations() {
+        for (entity_id, transform) in frame_buffer.transforms() {
             if let Some(static_mesh) = self.static_meshes.get_mut(&entity_id) {
-                static_mesh.location = *location;
+                static_mesh.transform = *transform;
             }
         }
 
@@ -117,18 +200,17 @@ impl Metal {
         let proj_view = {
             let camera_info = frame_buffer.camera_info();
 
-            let proj = perspective_lh_zo(
-                self.aspect,
-                camera_info.fov,
-                camera_info.near_plane,
-                camera_info.far_plane,
-            );
-
-            let view = look_at_lh(&camera_info.location, &camera_info.focus, &camera_info.up);
+            let proj = camera_info.proj_matrix(self.aspect);
+            let view = camera_info.view_matrix();
 
             ProjView { proj, view }
         };
 
+        self.current_frame_index = !self.current_frame_index;
+        self.update_instances();
+
+        let instance_buffer = &mut self.instance_buffers[self.current_frame_index as usize];
+
         autoreleasepool(|| {
             let drawable = self.layer.next_drawable().unwrap();
 
@@ -140,33 +222,55 @@ impl Metal {
             color_attachment.set_clear_color(MTLClearColor::new(0.0, 0.0, 0.0, 1.0));
 
             let cmd_buf = self.queue.new_command_buffer();
+            instance_buffer.command_buffer = Some(cmd_buf.to_owned());
             let encoder = cmd_buf.new_render_command_encoder(descriptor);
 
-            if !self.static_meshes.is_empty() {
-                encoder.set_render_pipeline_state(&self.pipeline.state);
+            // translucent meshes last so they blend over the scene
+            for (pipeline, translucent) in [(&self.pipeline, false), (&self.ghost_pipeline, true)] {
+                let mut static_meshes = self
+                    .static_meshes
+                    .values()
+                    .enumerate()
+                    .take(self.instance_capacity)
+                    .filter(|(_, mesh)| mesh.visible)
+                    .filter(|(_, mesh)| mesh.style.blend_color().is_some() == translucent)
+                    .peekable();
+
+                if static_meshes.peek().is_none() {
+                    continue;
+                }
+
+                encoder.set_render_pipeline_state(&pipeline.state);
+
+                encoder.set_triangle_fill_mode(if self.wireframe && !translucent {
+                    MTLTriangleFillMode::Lines
+                } else {
+                    MTLTriangleFillMode::Fill
+                });
+
                 encoder.set_vertex_bytes(
                     1,
                     mem::size_of_val(&proj_view) as u64,
                     &proj_view as *const _ as *const _,
                 );
 
-                for static_mesh in self.static_meshes.values() {
-                    let model = translate(&Mat4::identity(), &static_mesh.location);
-                    encoder.set_vertex_bytes(
-                        2,
-                        mem::size_of_val(&model) as u64,
-                        &model as *const _ as *const _,
-                    );
+                for (i, static_mesh) in static_meshes {
+                    if let Some([r, g, b, a]) = static_mesh.style.blend_color() {
+                        encoder.set_blend_color(r, g, b, a);
+                    }
+
                     encoder.set_vertex_buffer(
-                        0,
-                        Some(&static_mesh.buffer),
-                        static_mesh.locations_offset,
+                        2,
+                        Some(&instance_buffer.buffer),
+                        (i * INSTANCE_STRIDE) as u64,
                     );
+                    let mesh = &static_mesh.mesh;
+                    encoder.set_vertex_buffer(0, Some(&mesh.buffer), mesh.locations_offset);
                     encoder.draw_indexed_primitives(
                         MTLPrimitiveType::Triangle,
-                        3,
+                        mesh.index_count,
                         MTLIndexType::UInt16,
-                        &static_mesh.buffer,
+                        &mesh.buffer,
                         0,
                     );
                 }
@@ -178,40 +282,124 @@ impl Metal {
         });
     }
 
-    fn spawn_static_mesh(&mut self, entity_id: EntityId) {
-        let indices = [0_u16, 1, 2];
-        let vertex_data = [0.0_f32, 0.0, 1.0, -1.0, 0.0, -1.0, 1.0, 0.0, -1.0];
+    /// Writes the model matrices for the current frame, once the GPU is no longer reading them
+    fn update_instances(&mut self) {
+        let instance_buffer = &mut self.instance_buffers[self.current_frame_index as usize];
 
-        // align vertex locations to 16 bytes
-        let locations_offset = (mem::size_of_val(&indices) + 15) & !15;
-        let size = (locations_offset + mem::size_of_val(&vertex_data)) as u64;
-
-        let buffer = self
-            .device
-            .new_buffer(size, MTLResourceOptions::StorageModeManaged);
+        if let Some(command_buffer) = instance_buffer.command_buffer.take() {
+            command_buffer.wait_until_completed();
+        }
 
-        unsafe {
-            let data = buffer.contents();
+        let buffer = &instance_buffer.buffer;
+        let data = unsafe {
+            slice::from_raw_parts_mut(buffer.contents() as *mut u8, buffer.length() as usize)
+        };
 
-            let indices_slice = slice::from_raw_parts_mut(data as *mut u16, indices.len());
-            indices_slice.copy_from_slice(&indices);
+        let static_meshes = self.static_meshes.values().take(self.instance_capacity);
+        let instance_count = static_meshes.len();
 
-            let data = (data as *mut u8).add(locations_offset);
-            let vertex_slice = slice::from_raw_parts_mut(data as *mut f32, vertex_data.len());
-            vertex_slice.copy_from_slice(&vertex_data);
+        for (static_mesh, instance) in static_meshes.zip(data.chunks_exact_mut(INSTANCE_STRIDE)) {
+            let model = static_mesh.transform.matrix();
+            instance[..mem::size_of_val(&model)].copy_from_slice(unsafe {
+                slice::from_raw_parts(&model as *const _ as *const u8, mem::size_of_val(&model))
+            });
         }
 
         buffer.did_modify_range(NSRange {
             location: 0,
-            length: size,
+            length: (instance_count * INSTANCE_STRIDE) as u64,
         });
+    }
+
+    fn spawn_static_mesh(&mut self, entity_id: EntityId, resource: Option<&Arc<Resource>>) {
+        let mesh = match self.upload_mesh(resource) {
+            Some(mesh) => mesh,
+            None => return,
+        };
 
         let static_mesh = StaticMesh {
-            buffer,
-            locations_offset: locations_offset as u64,
-            location: Vec3::zeros(),
+            mesh,
+            transform: Default::default(),
+            visible: true,
+            style: RenderStyle::Solid,
         };
 
         self.static_meshes.insert(entity_id, static_mesh);
+
+        if self.static_meshes.len() > self.instance_capacity {
+            log::warn!(
+                "static mesh count exceeds instance capacity ({}), entity {entity_id} will not be \
+                drawn",
+                self.instance_capacity
+            );
+        }
+    }
+
+    /// Returns the buffer of the resource's mesh, creating it on first use
+    fn upload_mesh(&mut self, resource: Option<&Arc<Resource>>) -> Option<MeshBuffer> {
+        let device = &self.device;
+
+        self.meshes
+            .get_or_upload(resource, |mesh| new_mesh_buffer(device, mesh))
+            .cloned()
+    }
+}
+
+fn new_mesh_buffer(device: &Device, mesh: &Mesh) -> Result<MeshBuffer> {
+    // align vertex locations to 16 bytes
+    let layout = MeshLayout::new(mesh, 16);
+
+    let buffer = device.new_buffer(layout.size() as u64, MTLResourceOptions::StorageModeManaged);
+
+    // an inconsistent layout would read out of bounds on the GPU, so skip the mesh
+    let data = unsafe {
+        slice::from_raw_parts_mut(buffer.contents() as *mut u8, buffer.length() as usize)
+    };
+    layout.write(mesh, data)?;
+
+    buffer.did_modify_range(NSRange {
+        location: 0,
+        length: layout.size() as u64,
+    });
+
+    Ok(MeshBuffer {
+        buffer,
+        locations_offset: layout.vertex_offset as u64,
+        index_count: layout.index_count as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_buffer_is_sized_by_capacity() {
+        assert_eq!(instance_buffer_length(128), 4 * instance_buffer_length(32));
+        assert_eq!(INSTANCE_STRIDE % CONSTANT_BUFFER_ALIGNMENT, 0);
+        assert!(INSTANCE_STRIDE >= mem::size_of::<Mat4>());
+    }
+
+    #[test]
+    fn fixture_mesh_is_drawn_with_its_index_count() {
+        let device = Device::system_default().unwrap();
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../game_resources/fixtures/quad.obj"
+        );
+        let resource = game_resources::ResourceManager::default().resource(path.to_string());
+
+        let mut meshes = MeshCache::default();
+        let mesh = meshes
+            .get_or_upload(Some(&resource), |mesh| new_mesh_buffer(&device, mesh))
+            .unwrap();
+
+        assert_eq!(mesh.index_count, 6);
+        assert_eq!(mesh.locations_offset, 16);
+    }
+
+    #[test]
+    fn empty_instance_buffer_has_room_for_one() {
+        assert_eq!(instance_buffer_length(0), INSTANCE_STRIDE as NSUInteger);
     }
 }
@@ -5,8 +5,9 @@ use std::{
 
 use anyhow::{Context, Error, Result};
 use metal::{
-    CompileOptions, Device, Function, MTLLanguageVersion, MTLPixelFormat, MTLVertexFormat,
-    MTLVertexStepFunction, RenderPipelineDescriptor, RenderPipelineState, VertexDescriptor,
+    CompileOptions, Device, Function, MTLBlendFactor, MTLLanguageVersion, MTLPixelFormat,
+    MTLVertexFormat, MTLVertexStepFunction, RenderPipelineDescriptor, RenderPipelineState,
+    VertexDescriptor,
 };
 use naga::{
     back::msl::{self, BindTarget, PerStageMap, PerStageResources},
@@ -20,7 +21,8 @@ pub struct Pipeline {
 }
 
 impl Pipeline {
-    pub fn new(name: &str, device: &Device) -> Result<Self> {
+    /// Translucent pipelines blend the encoder's blend color over the scene
+    pub fn new(name: &str, device: &Device, translucent: bool) -> Result<Self> {
         let vertex_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
             .join("../../res/shaders")
             .join(name)
@@ -58,6 +60,14 @@ impl Pipeline {
             .unwrap();
         attachment.set_pixel_format(MTLPixelFormat::BGRA8Unorm);
 
+        if translucent {
+            attachment.set_blending_enabled(true);
+            attachment.set_source_rgb_blend_factor(MTLBlendFactor::BlendColor);
+            attachment.set_destination_rgb_blend_factor(MTLBlendFactor::OneMinusBlendAlpha);
+            attachment.set_source_alpha_blend_factor(MTLBlendFactor::One);
+            attachment.set_destination_alpha_blend_factor(MTLBlendFactor::Zero);
+        }
+
         let state = device
             .new_render_pipeline_state(&pipeline_descriptor)
             .map_err(Error::msg)?;
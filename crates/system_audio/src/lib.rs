@@ -3,19 +3,33 @@ use std::{
     f32::consts::TAU,
     iter::zip,
     slice,
-    sync::{atomic::Ordering, Arc},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 
+use anyhow::{anyhow, Result};
 use atomic_float::AtomicF32;
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
-    BufferSize, FrameCount, SampleFormat, SampleRate, Stream, SupportedBufferSize,
+    BufferSize, Device, FrameCount, Sample, SampleFormat, SampleRate, Stream, StreamConfig,
+    SupportedBufferSize,
 };
-use frame_buffer::AsyncFrameBufferDelegate;
+use frame_buffer::{AsyncFrameBufferDelegate, CameraInfo};
+use game_entity::{EntityId, EntityMap};
+use nalgebra_glm::Vec3;
+use rtrb::{Consumer, Producer, RingBuffer};
 
 const PREFERRED_SAMPLE_RATE: u32 = 48000;
 const PREFERRED_BUFFER_LEN: FrameCount = 512;
 
+/// Combined channel gain below which the mixer outputs silence without processing voices
+const DEFAULT_SILENCE_THRESHOLD: f32 = 1e-4;
+
+/// Maximum sounds playing at once. Further sounds are dropped until others finish.
+const MAX_VOICES: usize = 32;
+
 /// Wrapper to allow cpal::Stream to be Send
 struct SendStream(Stream);
 
@@ -23,29 +37,104 @@ struct SendStream(Stream);
 #[cfg(not(target_os = "android"))]
 unsafe impl Send for SendStream {}
 
-#[derive(Default)]
 struct SharedAudioData {
     channel_gains: [AtomicF32; 2],
+    master_volume: AtomicF32,
+    silence_threshold: AtomicF32,
+}
+
+impl Default for SharedAudioData {
+    fn default() -> Self {
+        Self {
+            channel_gains: Default::default(),
+            master_volume: AtomicF32::new(1.0),
+            silence_threshold: AtomicF32::new(DEFAULT_SILENCE_THRESHOLD),
+        }
+    }
+}
+
+/// State of a voice shared between the audio thread and the frame update
+struct VoiceState {
+    /// Updated every frame for sounds emitted by entities
+    channel_gains: [AtomicF32; 2],
+    finished: AtomicBool,
+}
+
+impl VoiceState {
+    fn new(channel_gains: [f32; 2]) -> Self {
+        Self {
+            channel_gains: channel_gains.map(AtomicF32::new),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    fn set_channel_gains(&self, channel_gains: [f32; 2]) {
+        for (atomic_gain, gain) in zip(&self.channel_gains, channel_gains) {
+            atomic_gain.store(gain, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A one-shot sound passed to the audio thread
+struct Voice {
+    /// Mono samples at the output sample rate
+    samples: Arc<[f32]>,
+    position: usize,
+    state: Arc<VoiceState>,
+}
+
+/// Tracks a sound started by `FrameData::play_sound` or `FrameData::emit_at`
+#[derive(Clone)]
+pub struct SoundHandle {
+    state: Arc<VoiceState>,
+}
+
+impl SoundHandle {
+    /// True once every sample has been played, or if the sound was dropped by the mixer
+    pub fn is_finished(&self) -> bool {
+        self.state.finished.load(Ordering::Acquire)
+    }
 }
 
 pub struct FrameData {
     audio_data: Arc<SharedAudioData>,
-    _stream: SendStream,
+    voice_producer: Producer<Voice>,
+    /// Finished voices returned by the audio thread, so that their samples are freed here
+    retired_voice_consumer: Consumer<Voice>,
+    camera_info: CameraInfo,
+    /// Last known location of every entity, for positioning emitted sounds
+    entity_locations: EntityMap<Vec3>,
+    /// Sounds which follow an entity, removed once finished
+    emitters: Vec<(EntityId, Arc<VoiceState>)>,
+    sample_rate: u32,
+    /// None if audio output is disabled
+    stream: Option<SendStream>,
 }
 
-impl Default for FrameData {
-    fn default() -> Self {
+impl FrameData {
+    /// Opens the default output device, preferring a stereo F32 stream and otherwise converting
+    /// to the closest supported channel count and sample format
+    pub fn try_new() -> Result<Self> {
         let host = cpal::default_host();
-        let device = host.default_output_device().expect("no audio device");
+        let device = host
+            .default_output_device()
+            .ok_or_else(|| anyhow!("no audio output device"))?;
+
         let config = device
-            .supported_output_configs()
-            .expect("no audio configs")
-            .find(|config| {
-                matches!(config.sample_format(), SampleFormat::F32) && config.channels() >= 2
+            .supported_output_configs()?
+            .max_by_key(|config| {
+                let channel_score = match config.channels() {
+                    2 => 2,
+                    0 | 1 => 0,
+                    _ => 1,
+                };
+                let format_score = matches!(config.sample_format(), SampleFormat::F32);
+                (channel_score, format_score)
             })
-            .expect("no supported audio device");
+            .ok_or_else(|| anyhow!("no supported audio output configs"))?;
 
-        let sample_rate = min(config.max_sample_rate().0, PREFERRED_SAMPLE_RATE);
+        let sample_rate =
+            PREFERRED_SAMPLE_RATE.clamp(config.min_sample_rate().0, config.max_sample_rate().0);
 
         let buffer_size = match config.buffer_size() {
             SupportedBufferSize::Range { min, max } => {
@@ -54,60 +143,276 @@ impl Default for FrameData {
             SupportedBufferSize::Unknown => BufferSize::Default,
         };
 
+        let sample_format = config.sample_format();
         let mut config = config.with_sample_rate(SampleRate(sample_rate)).config();
-        config.channels = 2;
         config.buffer_size = buffer_size;
 
+        if config.channels != 2 || sample_format != SampleFormat::F32 {
+            log::info!(
+                "audio output is {} channel {sample_format:?}, converting from stereo F32",
+                config.channels,
+            );
+        }
+
+        let (mut frame_data, audio_player) = Self::with_player(sample_rate);
+
+        let stream = match sample_format {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, audio_player)?,
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, audio_player)?,
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, audio_player)?,
+        };
+
+        stream.play()?;
+
+        frame_data.stream = Some(SendStream(stream));
+
+        Ok(frame_data)
+    }
+
+    /// Audio system without an output stream yet, and the player which mixes its sounds
+    fn with_player(sample_rate: u32) -> (Self, AudioPlayer) {
         let audio_data = Arc::new(SharedAudioData::default());
-        let mut audio_player = AudioPlayer::new(audio_data.clone(), sample_rate as f32);
+        let (voice_producer, voice_consumer) = RingBuffer::new(MAX_VOICES);
+        let (retired_voice_producer, retired_voice_consumer) = RingBuffer::new(MAX_VOICES);
+        let audio_player = AudioPlayer::new(
+            audio_data.clone(),
+            voice_consumer,
+            retired_voice_producer,
+            sample_rate as f32,
+        );
 
-        let stream = device
-            .build_output_stream(
-                &config,
-                move |data, _| audio_player.data_callback(data),
-                |err| eprintln!("an error occurred on the output audio stream: {err}"),
-            )
-            .unwrap();
+        let frame_data = Self {
+            audio_data,
+            voice_producer,
+            retired_voice_consumer,
+            camera_info: Default::default(),
+            entity_locations: Default::default(),
+            emitters: Vec::new(),
+            sample_rate,
+            stream: None,
+        };
 
-        stream.play().unwrap();
+        (frame_data, audio_player)
+    }
+
+    /// Audio system without an output stream, for when no device is available. Sounds finish
+    /// immediately.
+    pub fn disabled() -> Self {
+        let (voice_producer, _) = RingBuffer::new(1);
+        let (_, retired_voice_consumer) = RingBuffer::new(1);
 
         Self {
-            audio_data,
-            _stream: SendStream(stream),
+            audio_data: Default::default(),
+            voice_producer,
+            retired_voice_consumer,
+            camera_info: Default::default(),
+            entity_locations: Default::default(),
+            emitters: Vec::new(),
+            sample_rate: PREFERRED_SAMPLE_RATE,
+            stream: None,
+        }
+    }
+}
+
+/// Builds a stream which mixes in stereo F32 and converts to the output format
+fn build_stream<T>(
+    device: &Device,
+    config: &StreamConfig,
+    mut audio_player: AudioPlayer,
+) -> Result<Stream>
+where
+    T: Sample,
+{
+    let channels = config.channels as usize;
+    let mut stereo_buffer = Vec::new();
+
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            // only allocates if the device requests a larger buffer than any before
+            stereo_buffer.resize(data.len() / channels * 2, 0.0);
+            audio_player.data_callback(&mut stereo_buffer);
+            write_output(&stereo_buffer, data, channels);
+        },
+        |err| eprintln!("an error occurred on the output audio stream: {err}"),
+    )?;
+
+    Ok(stream)
+}
+
+/// Writes interleaved stereo samples to the output. Mono outputs are downmixed, and channels
+/// beyond the first two are silent.
+fn write_output<T>(stereo: &[f32], output: &mut [T], channels: usize)
+where
+    T: Sample,
+{
+    for (frame, output_frame) in zip(stereo.chunks_exact(2), output.chunks_exact_mut(channels)) {
+        match output_frame {
+            [mono] => *mono = T::from(&((frame[0] + frame[1]) * 0.5)),
+            [left, right, rest @ ..] => {
+                *left = T::from(&frame[0]);
+                *right = T::from(&frame[1]);
+                rest.fill(T::from(&0.0f32));
+            }
+            [] => {}
         }
     }
 }
 
 impl FrameData {
+    /// Combined channel gain below which the mixer skips processing and outputs silence
+    pub fn set_silence_threshold(&mut self, threshold: f32) {
+        self.audio_data
+            .silence_threshold
+            .store(threshold, Ordering::Relaxed);
+    }
+
+    /// Scales all audio output, clamped to [0, 1]. Changes are smoothed to avoid clicks.
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.audio_data
+            .master_volume
+            .store(volume.clamp(0.0, 1.0), Ordering::Relaxed);
+    }
+
+    /// Sample rate of the output stream. Sounds must be resampled to this rate before playing.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Plays mono samples once, panned and attenuated by the position relative to the camera
+    /// at the time the sound starts. The samples are never freed by the audio thread.
+    pub fn play_sound(&mut self, samples: Arc<[f32]>, position: &Vec3) -> SoundHandle {
+        let channel_gains = channel_gains(&self.camera_info, position);
+        self.start_voice(samples, channel_gains)
+    }
+
+    /// Plays mono samples once from the entity's location, following it as it and the camera
+    /// move. Silent until the entity's location is known.
+    pub fn emit_at(&mut self, entity_id: EntityId, samples: Arc<[f32]>) -> SoundHandle {
+        let channel_gains = self
+            .entity_locations
+            .get(entity_id)
+            .map(|location| channel_gains(&self.camera_info, location))
+            .unwrap_or_default();
+
+        let handle = self.start_voice(samples, channel_gains);
+
+        if !handle.is_finished() {
+            self.emitters.push((entity_id, handle.state.clone()));
+        }
+
+        handle
+    }
+
+    fn start_voice(&mut self, samples: Arc<[f32]>, channel_gains: [f32; 2]) -> SoundHandle {
+        let state = Arc::new(VoiceState::new(channel_gains));
+
+        let voice = Voice {
+            samples,
+            position: 0,
+            state: state.clone(),
+        };
+
+        // the player is gone if audio is disabled
+        if self.voice_producer.is_abandoned() || self.voice_producer.push(voice).is_err() {
+            state.finished.store(true, Ordering::Release);
+        }
+
+        SoundHandle { state }
+    }
+
     pub async fn update(&mut self, frame_buffer: &AsyncFrameBufferDelegate<'_>) {
         let frame_buffer = frame_buffer.reader();
-        let camera_info = frame_buffer.camera_info();
-        let camera_orientation = (camera_info.focus - camera_info.location).normalize();
+        self.camera_info = frame_buffer.camera_info().clone();
+
+        // frees the samples of voices no longer referenced elsewhere
+        while self.retired_voice_consumer.pop().is_ok() {}
+
+        for entity_id in frame_buffer.despawned() {
+            self.entity_locations.try_remove(*entity_id);
+        }
+
+        for (old_id, new_id) in frame_buffer.updated_entity_ids() {
+            if let Some(location) = self.entity_locations.try_remove(*old_id) {
+                self.entity_locations.insert(*new_id, location);
+            }
+
+            for (entity_id, _) in &mut self.emitters {
+                if entity_id == old_id {
+                    *entity_id = *new_id;
+                }
+            }
+        }
+
+        for (entity_id, transform) in frame_buffer.transforms() {
+            match self.entity_locations.get_mut(entity_id) {
+                Some(location) => *location = transform.location,
+                None => self.entity_locations.insert(entity_id, transform.location),
+            }
+        }
+
+        // the ambient tone is positioned at the world origin
+        let gains = channel_gains(&self.camera_info, &Vec3::zeros());
 
-        let dist = 0.5_f32.powf(camera_info.location.norm());
-        let pan = camera_orientation
-            .cross(&camera_info.location.normalize())
-            .y;
+        self.audio_data.channel_gains[0].store(gains[0], Ordering::Relaxed);
+        self.audio_data.channel_gains[1].store(gains[1], Ordering::Relaxed);
 
-        self.audio_data.channel_gains[0].store(dist * (pan + 1.0) * 0.5, Ordering::Relaxed);
-        self.audio_data.channel_gains[1].store(dist * (-pan + 1.0) * 0.5, Ordering::Relaxed);
+        // emitters of despawned entities keep playing from the last known location
+        self.emitters
+            .retain(|(_, state)| !state.finished.load(Ordering::Acquire));
+
+        for (entity_id, state) in &self.emitters {
+            if let Some(location) = self.entity_locations.get(*entity_id) {
+                state.set_channel_gains(channel_gains(&self.camera_info, location));
+            }
+        }
     }
 }
 
+/// Left and right gains for a sound at the position, halving with each meter from the camera
+fn channel_gains(camera_info: &CameraInfo, position: &Vec3) -> [f32; 2] {
+    let camera_orientation = (camera_info.focus - camera_info.location).try_normalize(f32::EPSILON);
+    let relative = camera_info.location - position;
+
+    let dist = 0.5_f32.powf(relative.norm());
+
+    // sounds at the camera have no direction, so are centered
+    let pan = match (camera_orientation, relative.try_normalize(f32::EPSILON)) {
+        (Some(camera_orientation), Some(direction)) => camera_orientation.cross(&direction).y,
+        _ => 0.0,
+    };
+
+    [dist * (pan + 1.0) * 0.5, dist * (-pan + 1.0) * 0.5]
+}
+
 struct AudioPlayer {
     audio_data: Arc<SharedAudioData>,
+    voice_consumer: Consumer<Voice>,
+    /// Finished voices are returned to the frame update, as dropping one may free its samples
+    retired_voice_producer: Producer<Voice>,
+    voices: Vec<Voice>,
     channel_gains: [f32; 2],
     target_channel_gains: [f32; 2],
+    master_volume: f32,
     sample_rate: f32,
     phase: f32,
 }
 
 impl AudioPlayer {
-    fn new(audio_data: Arc<SharedAudioData>, sample_rate: f32) -> Self {
+    fn new(
+        audio_data: Arc<SharedAudioData>,
+        voice_consumer: Consumer<Voice>,
+        retired_voice_producer: Producer<Voice>,
+        sample_rate: f32,
+    ) -> Self {
         Self {
             audio_data,
+            voice_consumer,
+            retired_voice_producer,
+            voices: Vec::with_capacity(MAX_VOICES),
             channel_gains: Default::default(),
             target_channel_gains: Default::default(),
+            master_volume: 1.0,
             sample_rate,
             phase: 0.0,
         }
@@ -121,11 +426,44 @@ impl AudioPlayer {
             *local_target = atomic_target.load(Ordering::Relaxed);
         }
 
+        // capacity is reserved up front, so this never allocates on the audio thread
+        while self.voices.len() < MAX_VOICES {
+            match self.voice_consumer.pop() {
+                Ok(voice) => self.voices.push(voice),
+                Err(_) => break,
+            }
+        }
+
+        let target_master_volume = self.audio_data.master_volume.load(Ordering::Relaxed);
+
+        // skip processing while silent. Gains resume ramping from their current values once the
+        // target rises above the threshold, so there is no click.
+        let silence_threshold = self.audio_data.silence_threshold.load(Ordering::Relaxed);
+        let current_gain: f32 = self.channel_gains.iter().sum();
+        let target_gain: f32 = self.target_channel_gains.iter().sum();
+        let muted =
+            self.master_volume < silence_threshold && target_master_volume < silence_threshold;
+        if muted
+            || (current_gain < silence_threshold
+                && target_gain < silence_threshold
+                && self.voices.is_empty())
+        {
+            buffer.fill(0.0);
+
+            // muted voices still play through, so that they finish on time
+            for voice in &mut self.voices {
+                voice.position += buffer.len() / 2;
+            }
+            self.retire_finished_voices();
+
+            return;
+        }
+
         let buffer = as_stereo_mut(buffer);
 
         let phase_delta = 880.0 * TAU / self.sample_rate;
 
-        for frame in buffer {
+        for frame in buffer.iter_mut() {
             for (current, target) in zip(&mut self.channel_gains, &self.target_channel_gains) {
                 *current += (*target - *current) * 0.001;
             }
@@ -136,6 +474,54 @@ impl AudioPlayer {
             frame[0] = val * self.channel_gains[0];
             frame[1] = val * self.channel_gains[1];
         }
+
+        for voice in &mut self.voices {
+            let [left_gain, right_gain] = &voice.state.channel_gains;
+            let channel_gains = [
+                left_gain.load(Ordering::Relaxed),
+                right_gain.load(Ordering::Relaxed),
+            ];
+
+            let remaining = &voice.samples[voice.position..];
+            for (frame, sample) in zip(buffer.iter_mut(), remaining) {
+                frame[0] += sample * channel_gains[0];
+                frame[1] += sample * channel_gains[1];
+            }
+
+            voice.position += min(buffer.len(), remaining.len());
+        }
+
+        for frame in buffer.iter_mut() {
+            self.master_volume += (target_master_volume - self.master_volume) * 0.001;
+
+            frame[0] *= self.master_volume;
+            frame[1] *= self.master_volume;
+        }
+
+        self.retire_finished_voices();
+    }
+
+    /// Returns finished voices to the frame update. If the queue is full, finished voices are
+    /// kept silently until the frame update catches up, rather than dropped on this thread.
+    fn retire_finished_voices(&mut self) {
+        let mut index = 0;
+        while index < self.voices.len() {
+            let voice = &self.voices[index];
+            if voice.position < voice.samples.len() {
+                index += 1;
+                continue;
+            }
+
+            voice.state.finished.store(true, Ordering::Release);
+
+            if self.retired_voice_producer.is_full() {
+                index += 1;
+            } else {
+                let voice = self.voices.swap_remove(index);
+                // cannot fail, as the queue is not full and this is its only producer
+                let _ = self.retired_voice_producer.push(voice);
+            }
+        }
     }
 }
 
@@ -145,3 +531,118 @@ fn as_stereo_mut(slice: &mut [f32]) -> &mut [[f32; 2]] {
     // SAFETY: stereo_len * 2 is guaranteed to not exceed original slice len
     unsafe { slice::from_raw_parts_mut(slice.as_mut_ptr().cast(), stereo_len) }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_output_downmixes_mono() {
+        let mut output = [0.0f32; 2];
+        write_output(&[1.0, 0.0, -0.5, 0.25], &mut output, 1);
+        assert_eq!(output, [0.5, -0.125]);
+    }
+
+    #[test]
+    fn write_output_silences_extra_channels() {
+        let mut output = [1.0f32; 6];
+        write_output(&[0.25, -0.25, 0.5, -0.5], &mut output, 3);
+        assert_eq!(output, [0.25, -0.25, 0.0, 0.5, -0.5, 0.0]);
+    }
+
+    #[test]
+    fn write_output_converts_to_i16() {
+        let mut output = [1i16; 4];
+        write_output(&[1.0, -1.0, 0.0, 0.0], &mut output, 2);
+        assert_eq!(output, [i16::MAX, i16::MIN, 0, 0]);
+    }
+
+    /// Audio system and player at the preferred sample rate, without a device
+    fn frame_data() -> (FrameData, AudioPlayer) {
+        FrameData::with_player(PREFERRED_SAMPLE_RATE)
+    }
+
+    #[test]
+    fn gains_below_threshold_output_silence() {
+        let (frame_data, mut audio_player) = frame_data();
+        for channel_gain in &frame_data.audio_data.channel_gains {
+            channel_gain.store(DEFAULT_SILENCE_THRESHOLD * 0.25, Ordering::Relaxed);
+        }
+
+        let mut buffer = [1.0; 64];
+        audio_player.data_callback(&mut buffer);
+        assert_eq!(buffer, [0.0; 64]);
+
+        // the ambient tone fades in once the gains rise above the threshold
+        for channel_gain in &frame_data.audio_data.channel_gains {
+            channel_gain.store(0.5, Ordering::Relaxed);
+        }
+
+        audio_player.data_callback(&mut buffer);
+        assert!(buffer.iter().any(|sample| *sample != 0.0));
+    }
+
+    #[test]
+    fn played_sound_is_output_scaled_by_gains() {
+        let (mut frame_data, mut audio_player) = frame_data();
+        let samples: Arc<[f32]> = Arc::new([0.5, -0.25, 1.0, 0.75]);
+        let position = Vec3::new(1.0, 0.0, 0.0);
+
+        let handle = frame_data.play_sound(samples.clone(), &position);
+        assert!(!handle.is_finished());
+
+        let [left, right] = channel_gains(&frame_data.camera_info, &position);
+        assert!(left != right);
+
+        let mut buffer = [0.0; 8];
+        audio_player.data_callback(&mut buffer);
+
+        let expected: Vec<_> = samples
+            .iter()
+            .flat_map(|sample| [sample * left, sample * right])
+            .collect();
+        assert_eq!(buffer.as_slice(), expected);
+        assert!(handle.is_finished());
+    }
+
+    #[test]
+    fn mirrored_positions_swap_channel_gains() {
+        let camera_info = CameraInfo::default();
+
+        let [left, right] = channel_gains(&camera_info, &Vec3::new(1.0, 0.0, 0.0));
+        assert!(left != right);
+
+        let mirrored = channel_gains(&camera_info, &Vec3::new(-1.0, 0.0, 0.0));
+        assert_eq!(mirrored, [right, left]);
+    }
+
+    #[test]
+    fn sound_at_camera_is_centered() {
+        let camera_info = CameraInfo::default();
+
+        let gains = channel_gains(&camera_info, &camera_info.location);
+        assert_eq!(gains, [0.5, 0.5]);
+    }
+
+    #[test]
+    fn zero_master_volume_settles_to_silence() {
+        let (mut frame_data, mut audio_player) = frame_data();
+        for channel_gain in &frame_data.audio_data.channel_gains {
+            channel_gain.store(0.5, Ordering::Relaxed);
+        }
+
+        let mut buffer = vec![0.0; 2048];
+        audio_player.data_callback(&mut buffer);
+        assert!(buffer.iter().any(|sample| *sample != 0.0));
+
+        frame_data.set_master_volume(0.0);
+
+        // smoothed over about ten thousand frames
+        for _ in 0..20 {
+            audio_player.data_callback(&mut buffer);
+        }
+
+        audio_player.data_callback(&mut buffer);
+        assert!(buffer.iter().all(|sample| *sample == 0.0));
+    }
+}
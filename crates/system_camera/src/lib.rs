@@ -1,13 +1,15 @@
 use std::f32::consts::FRAC_PI_2;
 
 use event::{InputEvent, SyncEventDelegate};
-use frame_buffer::{CameraInfo, SyncFrameBufferDelegate};
-use nalgebra_glm::{inverse, look_at, perspective, rotate_vec3, vec3, vec4, Vec2, Vec3};
+use frame_buffer::{CameraInfo, Projection, SyncFrameBufferDelegate};
+use nalgebra_glm::{inverse, rotate_vec3, vec3, vec4, Vec2, Vec3};
 use system_interfaces::physics::Interface as PhysicsInterface;
 
 const NEAR_PLANE: f32 = 0.01;
 const FAR_PLANE: f32 = 50.0;
 
+const DEPROJECT_EPSILON: f32 = 1e-6;
+
 #[derive(Clone, Copy)]
 pub struct CameraInterface<'a> {
     inner: &'a FrameData,
@@ -18,22 +20,66 @@ impl<'a> CameraInterface<'a> {
         &self.inner.location
     }
 
-    /// Returns orientation
-    pub fn deproject(&self, ndc: &Vec2) -> Vec3 {
-        let orientation = (self.inner.origin - self.inner.location).normalize();
-        let proj = perspective(self.inner.aspect, 1.0, NEAR_PLANE, FAR_PLANE);
-        let view = look_at(&Vec3::zeros(), &orientation, &vec3(0.0, 1.0, 0.0));
+    /// Returns the ray through a point in normalized device coordinates (x right, y up, see
+    /// `GameInputInterface::cursor_position_ndc`), using the same projection as the renderers.
+    /// The ray is an origin on the near plane and a direction, which is parallel to the view
+    /// direction for orthographic projections. Always finite, with a unit length direction, even
+    /// if the camera is degenerate.
+    pub fn deproject(&self, ndc: &Vec2) -> (Vec3, Vec3) {
+        let camera_info = self.inner.camera_info();
+
+        let orientation = (camera_info.focus - camera_info.location)
+            .try_normalize(DEPROJECT_EPSILON)
+            .unwrap_or_else(|| vec3(0.0, 0.0, 1.0));
+
+        let proj = camera_info.proj_matrix(self.inner.aspect);
+        let view = camera_info.view_matrix();
         let vp_inv = inverse(&(proj * view));
-        let screen = vec4(-ndc.x, -ndc.y, 1.0, 1.0);
 
-        (vp_inv * screen).xyz().normalize()
+        let unproject = |depth: f32| {
+            let point = vp_inv * vec4(ndc.x, ndc.y, depth, 1.0);
+            point.xyz() / point.w
+        };
+
+        let near = unproject(0.0);
+
+        let direction = (unproject(1.0) - near)
+            .try_normalize(DEPROJECT_EPSILON)
+            .filter(|direction| direction.iter().all(|val| val.is_finite()));
+
+        match direction {
+            Some(direction) if near.iter().all(|val| val.is_finite()) => (near, direction),
+            _ => (camera_info.location, orientation),
+        }
+    }
+}
+
+/// Limits applied to user camera control
+#[derive(Clone, Copy)]
+pub struct CameraConfig {
+    /// Polar angle range in radians, where 0 looks horizontally and FRAC_PI_2 looks straight down
+    pub polar_angle_range: (f32, f32),
+    pub boom_len_range: (f32, f32),
+    /// Minimum and maximum origin location, where x and y are the world x and z coordinates
+    pub origin_bounds: (Vec2, Vec2),
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            polar_angle_range: (0.05, FRAC_PI_2 - 0.05),
+            boom_len_range: (1.0, 15.0),
+            origin_bounds: (Vec2::repeat(f32::NEG_INFINITY), Vec2::repeat(f32::INFINITY)),
+        }
     }
 }
 
 pub struct FrameData {
     physics: PhysicsInterface,
+    config: CameraConfig,
     aspect: f32,
-    fov: f32,
+    projection: Projection,
+    move_speed: f32,
     location: Vec3,
     origin: Vec3,
     origin_vel: Vec3,
@@ -58,8 +104,10 @@ impl FrameData {
 
         Self {
             physics,
+            config: Default::default(),
             aspect,
-            fov: 1.0,
+            projection: Default::default(),
+            move_speed: MOVE_SPEED,
             location: Default::default(),
             origin: Default::default(),
             origin_vel: Default::default(),
@@ -76,6 +124,32 @@ impl FrameData {
         CameraInterface { inner: self }
     }
 
+    pub fn set_polar_angle_range(&mut self, min: f32, max: f32) {
+        self.config.polar_angle_range = (min, max);
+        self.polar_angle_target = self.polar_angle_target.clamp(min, max);
+    }
+
+    pub fn set_boom_len_range(&mut self, min: f32, max: f32) {
+        self.config.boom_len_range = (min, max);
+        self.boom_len_target = self.boom_len_target.clamp(min, max);
+    }
+
+    /// Perspective by default. Orthographic projection suits a top-down view, e.g. with the
+    /// polar angle range limited to straight down.
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    /// Bounds of the origin in the xz plane, e.g. the edges of the map. Unbounded by default.
+    pub fn set_origin_bounds(&mut self, min: Vec2, max: Vec2) {
+        self.config.origin_bounds = (min, max);
+        self.clamp_origin();
+    }
+
+    pub fn set_move_speed(&mut self, move_speed: f32) {
+        self.move_speed = move_speed;
+    }
+
     pub fn window_resized(&mut self, width: u32, height: u32) {
         self.aspect = width as f32 / height as f32;
     }
@@ -104,12 +178,13 @@ impl FrameData {
                     self.azimuth_angle_target += axis.x * ROTATE_SPEED;
                     self.polar_angle_target += axis.y * ROTATE_SPEED;
 
-                    self.polar_angle_target =
-                        self.polar_angle_target.max(0.05).min(FRAC_PI_2 - 0.05);
+                    let (min, max) = self.config.polar_angle_range;
+                    self.polar_angle_target = self.polar_angle_target.max(min).min(max);
                 }
                 InputEvent::CameraZoom(delta) => {
                     self.boom_len_target -= delta * 0.01;
-                    self.boom_len_target = self.boom_len_target.max(1.0).min(15.0);
+                    let (min, max) = self.config.boom_len_range;
+                    self.boom_len_target = self.boom_len_target.max(min).min(max);
                 }
                 _ => {}
             }
@@ -122,36 +197,337 @@ impl FrameData {
         let boom_len_alpha = 1.0 - ZOOM_DAMPING_FACTOR.powf(delta_time);
         self.boom_len += (self.boom_len_target - self.boom_len) * boom_len_alpha;
 
-        let location = rotate_vec3(
+        let boom = self.boom();
+
+        let y_scaling = 1.0 + MOVE_SPEED_Y_SCALING * boom.y;
+        self.origin += self.origin_vel * self.move_speed * y_scaling * delta_time;
+        self.origin_vel *= MOVE_DAMPING_FACTOR.powf(delta_time);
+        self.clamp_origin();
+
+        let camera_info = self.update_location();
+
+        frame_buffer.set_camera_info(camera_info);
+    }
+
+    /// Instantly moves the camera, bypassing damping. Angles are in radians. The pose is clamped
+    /// to the configured limits.
+    pub fn set_pose(
+        &mut self,
+        frame_buffer: &mut SyncFrameBufferDelegate,
+        origin: Vec3,
+        azimuth_angle: f32,
+        polar_angle: f32,
+        boom_len: f32,
+    ) {
+        let (min, max) = self.config.polar_angle_range;
+        let polar_angle = polar_angle.max(min).min(max);
+
+        let (min, max) = self.config.boom_len_range;
+        let boom_len = boom_len.max(min).min(max);
+
+        self.origin = origin;
+        self.origin_vel = Vec3::zeros();
+        self.clamp_origin();
+
+        self.azimuth_angle = azimuth_angle;
+        self.azimuth_angle_target = azimuth_angle;
+        self.polar_angle = polar_angle;
+        self.polar_angle_target = polar_angle;
+        self.boom_len = boom_len;
+        self.boom_len_target = boom_len;
+
+        let camera_info = self.update_location();
+
+        frame_buffer.set_camera_info(camera_info);
+    }
+
+    /// Clamps the origin to the configured bounds. Velocity out of the bounds is discarded, so
+    /// that it doesn't accumulate against the edge.
+    fn clamp_origin(&mut self) {
+        let (min, max) = self.config.origin_bounds;
+
+        for (axis, min, max) in [(0, min.x, max.x), (2, min.y, max.y)] {
+            if self.origin[axis] < min {
+                self.origin[axis] = min;
+                self.origin_vel[axis] = self.origin_vel[axis].max(0.0);
+            } else if self.origin[axis] > max {
+                self.origin[axis] = max;
+                self.origin_vel[axis] = self.origin_vel[axis].min(0.0);
+            }
+        }
+    }
+
+    /// Camera location relative to the origin
+    fn boom(&self) -> Vec3 {
+        let boom = rotate_vec3(
             &vec3(0.0, 0.0, -self.boom_len),
             self.polar_angle,
             &vec3(1.0, 0.0, 0.0),
         );
-        let location = rotate_vec3(&location, self.azimuth_angle, &vec3(0.0, 1.0, 0.0));
+        rotate_vec3(&boom, self.azimuth_angle, &vec3(0.0, 1.0, 0.0))
+    }
 
-        let y_scaling = 1.0 + MOVE_SPEED_Y_SCALING * location.y;
-        self.origin += self.origin_vel * MOVE_SPEED * y_scaling * delta_time;
-        self.origin_vel *= MOVE_DAMPING_FACTOR.powf(delta_time);
+    /// Camera up direction, perpendicular to the boom so that it is valid when looking straight
+    /// down
+    fn up(&self) -> Vec3 {
+        let up = rotate_vec3(&vec3(0.0, 1.0, 0.0), self.polar_angle, &vec3(1.0, 0.0, 0.0));
+        rotate_vec3(&up, self.azimuth_angle, &vec3(0.0, 1.0, 0.0))
+    }
 
-        let mut location = self.origin + location;
-        let orientation = (self.origin - location).normalize();
+    fn update_location(&mut self) -> CameraInfo {
+        let boom = self.boom();
+        let boom_len = boom.norm();
+        let mut location = self.origin + boom;
 
-        // camera collision
-        if let Some(hit_location) = self.physics.raycast(&self.origin, &-orientation) {
-            location = hit_location;
+        // camera collision, only pulling the camera in front of geometry along the boom. The
+        // origin may be inside a prop, which is not between the origin and the camera.
+        if let Some(direction) = boom.try_normalize(f32::EPSILON) {
+            if let Some(hit_location) = self.physics.raycast_outward(&self.origin, &direction) {
+                if (hit_location - self.origin).norm() < boom_len {
+                    location = hit_location;
+                }
+            }
         }
 
-        let camera_info = CameraInfo {
+        self.location = location;
+
+        self.camera_info()
+    }
+
+    fn camera_info(&self) -> CameraInfo {
+        CameraInfo {
             focus: self.origin,
-            location,
-            up: vec3(0.0, 1.0, 0.0),
-            fov: self.fov,
+            location: self.location,
+            up: self.up(),
+            projection: self.projection,
             near_plane: NEAR_PLANE,
             far_plane: FAR_PLANE,
-        };
+        }
+    }
+}
 
-        frame_buffer.set_camera_info(camera_info);
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
 
-        self.location = location;
+    use event::EventManager;
+    use frame_buffer::FrameBufferManager;
+    use game_entity::EntityId;
+    use system_interfaces::physics::{Collider, Data as PhysicsData, DataSingle};
+
+    use super::*;
+
+    fn assert_near(a: &Vec3, b: &Vec3) {
+        assert!((a - b).norm() < 1e-4, "{a:?} != {b:?}");
+    }
+
+    /// A camera looking at the origin from above and behind
+    fn camera(frame_buffer_manager: &mut FrameBufferManager, projection: Projection) -> FrameData {
+        let mut camera = FrameData::new(800, 600, PhysicsData::default().into());
+        camera.set_projection(projection);
+        camera.set_pose(
+            &mut frame_buffer_manager.sync_delegate(),
+            Vec3::zeros(),
+            0.3,
+            0.8,
+            10.0,
+        );
+        camera
+    }
+
+    fn frame_buffer_manager() -> FrameBufferManager {
+        let frame_buffer_manager = FrameBufferManager::new(NonZeroUsize::new(1).unwrap());
+        frame_buffer_manager.assign_thread_frame_buffer(0);
+        frame_buffer_manager
+    }
+
+    /// Updates the camera for a frame with the input events
+    fn update(
+        camera: &mut FrameData,
+        frame_buffer_manager: &mut FrameBufferManager,
+        input_events: &[InputEvent],
+        delta_time: f32,
+    ) {
+        let mut event_manager = EventManager::new(NonZeroUsize::new(1).unwrap());
+        let mut event_delegate = event_manager.sync_delegate();
+        for input_event in input_events {
+            event_delegate.push_input_event(*input_event);
+        }
+
+        frame_buffer_manager.swap();
+        camera.update(
+            &event_delegate,
+            &mut frame_buffer_manager.sync_delegate(),
+            delta_time,
+        );
+    }
+
+    #[test]
+    fn deproject_center_matches_view_direction() {
+        let mut frame_buffer_manager = frame_buffer_manager();
+
+        for projection in [
+            Projection::Perspective { fov: 1.0 },
+            Projection::Orthographic { height: 10.0 },
+        ] {
+            let camera = camera(&mut frame_buffer_manager, projection);
+            let location = *camera.interface().location();
+            let forward = (-location).normalize();
+
+            let (origin, direction) = camera.interface().deproject(&Vec2::zeros());
+            assert_near(&direction, &forward);
+            assert_near(&origin, &(location + forward * NEAR_PLANE));
+        }
+    }
+
+    #[test]
+    fn deproject_orthographic_rays_are_parallel() {
+        let mut frame_buffer_manager = frame_buffer_manager();
+        let camera = camera(
+            &mut frame_buffer_manager,
+            Projection::Orthographic { height: 10.0 },
+        );
+
+        let (center, forward) = camera.interface().deproject(&Vec2::zeros());
+        let (corner, direction) = camera.interface().deproject(&Vec2::new(1.0, 1.0));
+
+        assert_near(&direction, &forward);
+
+        // the top right corner is offset by half the view volume's height and width
+        let half_extents = Vec2::new(5.0 * 800.0 / 600.0, 5.0);
+        let offset = corner - center;
+        assert!(offset.dot(&forward).abs() < 1e-4);
+        assert!((offset.norm() - half_extents.norm()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn set_pose_clamps_to_limits() {
+        let mut frame_buffer_manager = frame_buffer_manager();
+        let mut camera = FrameData::new(800, 600, PhysicsData::default().into());
+        camera.set_polar_angle_range(0.2, 1.0);
+        camera.set_boom_len_range(2.0, 8.0);
+        camera.set_origin_bounds(Vec2::new(-1.0, -1.0), Vec2::new(1.0, 1.0));
+
+        camera.set_pose(
+            &mut frame_buffer_manager.sync_delegate(),
+            vec3(5.0, 0.0, -5.0),
+            0.0,
+            FRAC_PI_2,
+            20.0,
+        );
+
+        let origin = vec3(1.0, 0.0, -1.0);
+        let boom = *camera.interface().location() - origin;
+        assert!((boom.norm() - 8.0).abs() < 1e-4);
+        assert!((boom.y / boom.norm() - 1.0f32.sin()).abs() < 1e-4);
+
+        // the pose is published to the renderers
+        frame_buffer_manager.swap();
+        let camera_info = frame_buffer_manager
+            .async_delegate()
+            .reader()
+            .camera_info()
+            .clone();
+        assert_near(&camera_info.focus, &origin);
+        assert_near(&camera_info.location, camera.interface().location());
+    }
+
+    #[test]
+    fn set_pose_is_kept_without_input() {
+        let mut frame_buffer_manager = frame_buffer_manager();
+        let mut camera = FrameData::new(800, 600, PhysicsData::default().into());
+
+        let origin = vec3(2.0, 0.0, 3.0);
+        camera.set_pose(
+            &mut frame_buffer_manager.sync_delegate(),
+            origin,
+            0.7,
+            0.6,
+            6.0,
+        );
+        let location = *camera.interface().location();
+
+        for _ in 0..10 {
+            update(&mut camera, &mut frame_buffer_manager, &[], 0.1);
+        }
+
+        assert_near(camera.interface().location(), &location);
+
+        let camera_info = frame_buffer_manager
+            .async_delegate()
+            .reader()
+            .camera_info()
+            .clone();
+        assert_near(&camera_info.focus, &origin);
+        assert_near(&camera_info.location, &location);
+    }
+
+    /// Location of a camera posed with its boom against the colliders
+    fn collided_location(colliders: impl IntoIterator<Item = Collider>) -> Vec3 {
+        let mut data = DataSingle::default();
+        for (index, collider) in colliders.into_iter().enumerate() {
+            data.colliders
+                .insert(EntityId::new(index as u32 + 1), collider);
+        }
+
+        let mut frame_buffer_manager = frame_buffer_manager();
+        let mut camera = FrameData::new(800, 600, PhysicsData::new_single(data).into());
+        camera.set_pose(
+            &mut frame_buffer_manager.sync_delegate(),
+            Vec3::zeros(),
+            0.0,
+            0.8,
+            10.0,
+        );
+
+        *camera.interface().location()
+    }
+
+    #[test]
+    fn collision_pulls_camera_in_front_of_hit() {
+        // the origin is inside a prop, which doesn't block the boom
+        let prop = Collider::sphere(Vec3::zeros(), 0.5);
+
+        let unblocked = collided_location([prop]);
+        assert!((unblocked.norm() - 10.0).abs() < 1e-4);
+
+        let direction = unblocked.normalize();
+        let obstacle = Collider::sphere(direction * 5.0, 1.0);
+        assert_near(&collided_location([prop, obstacle]), &(direction * 4.0));
+    }
+
+    #[test]
+    fn origin_is_clamped_to_bounds() {
+        let mut frame_buffer_manager = frame_buffer_manager();
+        let mut camera = FrameData::new(800, 600, PhysicsData::default().into());
+        camera.set_origin_bounds(Vec2::new(-1.0, -1.0), Vec2::new(1.0, 1.0));
+
+        let move_x = InputEvent::CameraMoveAxis(Vec2::new(1.0, 0.0));
+        for _ in 0..5 {
+            update(&mut camera, &mut frame_buffer_manager, &[move_x], 0.5);
+        }
+
+        assert_eq!(camera.origin.x, 1.0);
+        assert_eq!(camera.origin_vel.x, 0.0);
+    }
+
+    #[test]
+    fn wider_polar_range_looks_straight_down() {
+        let mut frame_buffer_manager = frame_buffer_manager();
+        let mut camera = FrameData::new(800, 600, PhysicsData::default().into());
+        let rotate_down = InputEvent::CameraRotateAxis(Vec2::new(0.0, 1000.0));
+
+        update(&mut camera, &mut frame_buffer_manager, &[rotate_down], 10.0);
+        let location = *camera.interface().location();
+        assert!(location.xz().norm() > 0.1);
+
+        camera.set_polar_angle_range(0.0, FRAC_PI_2);
+        update(&mut camera, &mut frame_buffer_manager, &[rotate_down], 10.0);
+        let location = *camera.interface().location();
+        assert!(location.xz().norm() < 1e-4);
+        assert!(location.y > 0.0);
+
+        let view = camera.camera_info().view_matrix();
+        assert!(view.iter().all(|val| val.is_finite()));
     }
 }
@@ -1,12 +1,51 @@
-use event::{AsyncEventDelegate, GameEvent};
+use std::{f32::consts::TAU, ops::Range};
+
+use event::{AsyncEventDelegate, GameEvent, SystemGameEvent};
 use frame_buffer::AsyncFrameBufferDelegate;
 use game_data::system_swap_data::SystemSwapData;
 use game_entity::EntityId;
 use game_entity::EntityMap;
-use nalgebra_glm::Vec3;
+use nalgebra_glm::{vec3, Vec3};
 use rand::prelude::*;
+use system_interfaces::navigation::{Interface as NavigationInterface, Navmesh};
 use update_buffer::GuestUpdateBufferRef;
 
+pub use region::WanderRegion;
+
+mod region;
+
+/// Walking bob height, in meters
+const BOB_AMPLITUDE: f32 = 0.05;
+
+/// Walking bob frequency, in radians per second
+const BOB_FREQUENCY: f32 = 4.0 * TAU;
+
+#[derive(Clone)]
+pub struct GuestConfig {
+    pub wander_region: WanderRegion,
+    /// Range from which each guest's visit duration is picked, in seconds
+    pub visit_duration: Range<f32>,
+}
+
+impl Default for GuestConfig {
+    fn default() -> Self {
+        Self {
+            wander_region: Default::default(),
+            visit_duration: 120.0..300.0,
+        }
+    }
+}
+
+impl GuestConfig {
+    fn sample_visit_duration(&self, rng: &mut impl Rng) -> f32 {
+        if self.visit_duration.is_empty() {
+            self.visit_duration.start
+        } else {
+            rng.gen_range(self.visit_duration.clone())
+        }
+    }
+}
+
 #[derive(Default)]
 struct SwapData {
     guest_goals: Vec<(EntityId, Vec3)>,
@@ -15,77 +54,158 @@ struct SwapData {
 struct Guest {
     location: Vec3,
     goal: Vec3,
-    /// m/s
+    /// m/s, zero while standing still
     speed: f32,
+    /// m/s
+    walk_speed: f32,
+    /// offsets the walking animation so that guests don't move in lockstep
+    animation_phase: f32,
+    /// seconds until the guest leaves the park. Only counted down on the server, clients wait
+    /// for the server's despawn.
+    visit_remaining: f32,
+}
+
+impl Guest {
+    /// Stops walking, settling the guest at its location so it doesn't freeze mid-bob
+    fn stop(&mut self, entity_id: EntityId, frame_buffer: &AsyncFrameBufferDelegate) {
+        if self.speed != 0.0 {
+            self.speed = 0.0;
+            frame_buffer
+                .writer()
+                .push_location(entity_id, self.location);
+        }
+    }
 }
 
-#[derive(Default)]
 pub struct FrameData {
     swap_data: SystemSwapData<SwapData>,
+    navigation: NavigationInterface,
     guests: EntityMap<Guest>,
+    config: GuestConfig,
     client: bool,
+    /// seconds since the system was created, used for animation
+    time: f32,
 }
 
 impl FrameData {
+    pub fn new(navigation: NavigationInterface) -> Self {
+        Self {
+            swap_data: Default::default(),
+            navigation,
+            guests: Default::default(),
+            config: Default::default(),
+            client: false,
+            time: 0.0,
+        }
+    }
+
+    pub fn set_config(&mut self, config: GuestConfig) {
+        self.config = config;
+    }
+
     pub async fn update(
         &mut self,
         event_delegate: &AsyncEventDelegate<'_>,
         frame_buffer: &AsyncFrameBufferDelegate<'_>,
         delta_time: f32,
     ) {
+        self.time += delta_time;
+
+        // must be awaited before the rng is created, which is not Send
+        let navmesh = self.navigation.navmesh().await;
+
+        let mut rng = thread_rng();
+
         if let Some(swap_data) = self.swap_data.swapped() {
             for (entity_id, goal) in &swap_data.guest_goals {
-                let guest = &mut self.guests[*entity_id];
-                guest.goal = *goal;
-                guest.speed = 1.4;
+                // goals are unreliable, so may arrive after the guest's despawn
+                if let Some(guest) = self.guests.get_mut(*entity_id) {
+                    guest.goal = *goal;
+                    guest.speed = guest.walk_speed;
+                }
             }
 
             swap_data.guest_goals.clear();
         }
 
+        // preallocate for bulk spawns
+        let spawn_count = event_delegate
+            .game_events_of(|event| matches!(event, GameEvent::SpawnGuest { .. }))
+            .count();
+        self.guests.reserve(spawn_count);
+
         for game_event in event_delegate.game_events() {
             match game_event {
-                GameEvent::SpawnGuest { entity_id, .. } => {
+                GameEvent::SpawnGuest {
+                    entity_id,
+                    walk_speed,
+                    ..
+                } => {
                     let guest = Guest {
                         location: Vec3::zeros(),
                         goal: Vec3::zeros(),
                         speed: 0.0,
+                        walk_speed: *walk_speed,
+                        animation_phase: rng.gen_range(0.0..TAU),
+                        visit_remaining: self.config.sample_visit_duration(&mut rng),
                     };
 
                     self.guests.insert(*entity_id, guest);
                 }
                 GameEvent::Despawn(entity_id) => {
-                    self.guests.remove(*entity_id);
+                    // static meshes are despawned with the same event
+                    self.guests.try_remove(*entity_id);
                 }
-                GameEvent::NetworkRoleOffline | GameEvent::NetworkRoleServer => {
+                GameEvent::NetworkRoleOffline | GameEvent::NetworkRoleServer(_) => {
                     self.client = false;
                 }
-                GameEvent::NetworkRoleClient => {
+                GameEvent::NetworkRoleClient(_) => {
                     self.client = true;
                 }
                 _ => {}
             }
         }
 
-        // check if guest has reached goal
+        self.guests.shrink_if_sparse();
+
+        // guests leave once their visit is over, and are despawned by the game controller
         if !self.client {
-            let mut rng = thread_rng();
+            for (entity_id, guest) in &mut self.guests {
+                let was_visiting = guest.visit_remaining > 0.0;
+                guest.visit_remaining -= delta_time;
 
+                if was_visiting && guest.visit_remaining <= 0.0 {
+                    event_delegate.push_system_game_event(SystemGameEvent::GuestLeft(*entity_id));
+                }
+            }
+        }
+
+        // check if guest has reached goal
+        if !self.client {
             for (entity_id, guest) in &mut self.guests {
                 if (guest.location - guest.goal).norm() < 0.5 {
-                    let x = rng.gen_range(-25.0..25.0);
-                    let z = rng.gen_range(-25.0..25.0);
-                    guest.goal = Vec3::from([x, 0.0, z]);
-                    guest.speed = 1.4;
+                    // guests remain still if there is nowhere to go
+                    let goal = if navmesh.is_empty() {
+                        self.config.wander_region.sample(&mut rng)
+                    } else {
+                        sample_reachable_goal(&navmesh, &guest.location, &mut rng)
+                    };
 
-                    self.swap_data.guest_goals.push((*entity_id, guest.goal));
+                    if let Some(goal) = goal {
+                        guest.goal = goal;
+                        guest.speed = guest.walk_speed;
+
+                        self.swap_data.guest_goals.push((*entity_id, guest.goal));
+                    } else {
+                        guest.stop(*entity_id, frame_buffer);
+                    }
                 }
             }
         } else {
             self.guests
-                .values_mut()
-                .filter(|guest| (guest.location - guest.goal).norm() < 0.5)
-                .for_each(|guest| guest.speed = 0.0);
+                .iter_mut()
+                .filter(|(_, guest)| (guest.location - guest.goal).norm() < 0.5)
+                .for_each(|(entity_id, guest)| guest.stop(*entity_id, frame_buffer));
         }
 
         // update guest positions
@@ -95,13 +215,29 @@ impl FrameData {
             .filter(|(_, guest)| guest.speed != 0.0)
         {
             guest.location += (guest.goal - guest.location).normalize() * guest.speed * delta_time;
-            frame_buffer
-                .writer()
-                .push_location(*entity_id, guest.location);
+
+            let bob = (self.time * BOB_FREQUENCY + guest.animation_phase)
+                .sin()
+                .abs();
+            let location = guest.location + vec3(0.0, bob * BOB_AMPLITUDE, 0.0);
+
+            frame_buffer.writer().push_location(*entity_id, location);
         }
     }
 }
 
+/// Attempts to sample a goal reachable from the location. Guests not yet on the navmesh may head
+/// anywhere on it.
+fn sample_reachable_goal(navmesh: &Navmesh, location: &Vec3, rng: &mut impl Rng) -> Option<Vec3> {
+    const MAX_ATTEMPTS: usize = 8;
+
+    let on_navmesh = navmesh.contains(location);
+
+    (0..MAX_ATTEMPTS)
+        .filter_map(|_| navmesh.random_point(rng))
+        .find(|goal| !on_navmesh || navmesh.is_reachable(location, goal))
+}
+
 #[derive(Default)]
 pub struct FixedData {
     swap_data: SystemSwapData<SwapData>,
@@ -125,3 +261,215 @@ impl FixedData {
         self.swap_data.guest_goals.extend(update_buffer.goals());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use event::EventManager;
+    use frame_buffer::FrameBufferManager;
+    use nalgebra_glm::Vec2;
+    use system_interfaces::navigation::Data as NavigationData;
+    use task_executor::{ExecutorOptions, TaskExecutor};
+
+    use super::*;
+
+    struct Harness {
+        executor: TaskExecutor,
+        event_manager: EventManager,
+        frame_buffer_manager: FrameBufferManager,
+        frame_data: FrameData,
+    }
+
+    /// Events and locations produced by a frame
+    struct Frame {
+        system_game_events: Vec<SystemGameEvent>,
+        locations: Vec<(u32, Vec3)>,
+    }
+
+    impl Harness {
+        fn new(config: GuestConfig) -> Self {
+            let options = ExecutorOptions {
+                pin_threads: false,
+                ..Default::default()
+            };
+
+            let thread_count = NonZeroUsize::new(1).unwrap();
+            let mut frame_data = FrameData::new(NavigationData::default().into());
+            frame_data.set_config(config);
+
+            Self {
+                executor: TaskExecutor::with_options(options, thread_count, &|_| {}),
+                event_manager: EventManager::new(thread_count),
+                frame_buffer_manager: FrameBufferManager::new(thread_count),
+                frame_data,
+            }
+        }
+
+        fn frame(&mut self, game_events: &[GameEvent], delta_time: f32) -> Frame {
+            let mut event_delegate = self.event_manager.sync_delegate();
+            for game_event in game_events {
+                event_delegate.push_game_event(*game_event);
+            }
+
+            {
+                let event_manager = &mut self.event_manager;
+                let frame_buffer_manager = &mut self.frame_buffer_manager;
+                let frame_data = &mut self.frame_data;
+
+                let mut update = Box::pin(async move {
+                    event_manager.assign_thread_event_buffer(0);
+                    frame_buffer_manager.assign_thread_frame_buffer(0);
+
+                    frame_data
+                        .update(
+                            &event_manager.async_delegate(),
+                            &frame_buffer_manager.async_delegate(),
+                            delta_time,
+                        )
+                        .await;
+                });
+
+                self.executor.execute_blocking(update.as_mut()).unwrap();
+            }
+
+            let mut event_delegate = self.event_manager.sync_delegate();
+            let (_, system_game_events) = event_delegate.system_game_events_mut();
+            let system_game_events = system_game_events.copied().collect();
+            self.event_manager.clear_system_game_events();
+            self.event_manager.swap();
+            self.frame_buffer_manager.swap();
+
+            let locations = self
+                .frame_buffer_manager
+                .async_delegate()
+                .reader()
+                .locations()
+                .map(|(entity_id, location)| (entity_id.get(), *location))
+                .collect();
+
+            Frame {
+                system_game_events,
+                locations,
+            }
+        }
+    }
+
+    fn spawn_guest(entity_id: EntityId, walk_speed: f32) -> GameEvent {
+        GameEvent::SpawnGuest {
+            entity_id,
+            replicate: false,
+            walk_speed,
+        }
+    }
+
+    fn guests_left(frame: &Frame) -> Vec<u32> {
+        frame
+            .system_game_events
+            .iter()
+            .filter_map(|event| match event {
+                SystemGameEvent::GuestLeft(entity_id) => Some(entity_id.get()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn visit_config(visit_duration: f32) -> GuestConfig {
+        GuestConfig {
+            visit_duration: visit_duration..visit_duration,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn guest_leaves_once_visit_is_over() {
+        let mut harness = Harness::new(visit_config(1.0));
+        let entity_id = EntityId::new(1);
+
+        let frame = harness.frame(&[spawn_guest(entity_id, 1.0)], 0.5);
+        assert!(guests_left(&frame).is_empty());
+
+        let frame = harness.frame(&[], 0.5);
+        assert_eq!(guests_left(&frame), [1]);
+
+        // the guest leaves once, while its despawn is pending
+        let frame = harness.frame(&[], 0.5);
+        assert!(guests_left(&frame).is_empty());
+    }
+
+    #[test]
+    fn client_waits_for_server_despawn() {
+        let mut harness = Harness::new(visit_config(1.0));
+        let server_address = "127.0.0.1:7000".parse().unwrap();
+
+        harness.frame(&[GameEvent::NetworkRoleClient(server_address)], 0.0);
+        harness.frame(&[spawn_guest(EntityId::new(1), 1.0)], 0.5);
+
+        let frame = harness.frame(&[], 1.0);
+        assert!(guests_left(&frame).is_empty());
+    }
+
+    #[test]
+    fn guests_get_different_animation_phases() {
+        let mut harness = Harness::new(Default::default());
+        let (a, b) = (EntityId::new(1), EntityId::new(2));
+
+        harness.frame(&[spawn_guest(a, 1.0), spawn_guest(b, 1.0)], 0.0);
+
+        let guests = &harness.frame_data.guests;
+        assert_ne!(guests[a].animation_phase, guests[b].animation_phase);
+    }
+
+    #[test]
+    fn stopping_guest_settles_at_base_location() {
+        let wander_region = |min: Vec2, max: Vec2| GuestConfig {
+            wander_region: WanderRegion::Rect { min, max },
+            ..Default::default()
+        };
+
+        let mut harness = Harness::new(wander_region(Vec2::new(1.0, 0.0), Vec2::new(1.1, 0.1)));
+        let entity_id = EntityId::new(1);
+
+        // walks towards the goal, then has nowhere to go once it arrives
+        harness.frame(&[spawn_guest(entity_id, 1.0)], 0.1);
+        harness
+            .frame_data
+            .set_config(wander_region(Vec2::zeros(), Vec2::zeros()));
+
+        let frame = harness.frame(&[], 1.0);
+        assert_eq!(frame.locations.len(), 1);
+
+        let frame = harness.frame(&[], 0.1);
+        assert_eq!(frame.locations.len(), 1);
+        let (_, location) = frame.locations[0];
+        assert_eq!(location.y, 0.0);
+        assert_eq!(location, harness.frame_data.guests[entity_id].location);
+
+        let frame = harness.frame(&[], 0.1);
+        assert!(frame.locations.is_empty());
+    }
+
+    #[test]
+    fn guests_walk_at_their_own_speed() {
+        // goals far from the origin, so neither guest arrives
+        let mut harness = Harness::new(GuestConfig {
+            wander_region: WanderRegion::Rect {
+                min: Vec2::new(10.0, 10.0),
+                max: Vec2::new(11.0, 11.0),
+            },
+            ..Default::default()
+        });
+        let (slow, fast) = (EntityId::new(1), EntityId::new(2));
+
+        harness.frame(&[spawn_guest(slow, 1.0), spawn_guest(fast, 2.5)], 0.0);
+        harness.frame(&[], 0.1);
+
+        let guests = &harness.frame_data.guests;
+        assert_eq!(guests[slow].speed, 1.0);
+        assert_eq!(guests[fast].speed, 2.5);
+
+        // both started at the origin
+        assert!((guests[slow].location.norm() - 0.1).abs() < 1e-5);
+        assert!((guests[fast].location.norm() - 0.25).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,100 @@
+use nalgebra_glm::{vec3, Vec2, Vec3};
+use rand::Rng;
+use system_interfaces::navigation::sample_triangles;
+
+/// Area of the ground plane from which guests pick random goals
+#[derive(Clone)]
+pub enum WanderRegion {
+    /// Axis-aligned rectangle on the xz plane
+    Rect { min: Vec2, max: Vec2 },
+    /// Triangles on the xz plane, e.g. the walkable polygons of a navmesh
+    Triangles(Vec<[Vec2; 3]>),
+}
+
+impl Default for WanderRegion {
+    fn default() -> Self {
+        Self::Rect {
+            min: Vec2::from([-25.0, -25.0]),
+            max: Vec2::from([25.0, 25.0]),
+        }
+    }
+}
+
+impl WanderRegion {
+    /// Returns a uniformly distributed point within the region, or None if the region has no area
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<Vec3> {
+        match self {
+            Self::Rect { min, max } => {
+                if min.x >= max.x || min.y >= max.y {
+                    return None;
+                }
+
+                let x = rng.gen_range(min.x..max.x);
+                let z = rng.gen_range(min.y..max.y);
+                Some(vec3(x, 0.0, z))
+            }
+            Self::Triangles(triangles) => sample_triangles(triangles, rng),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn samples_fall_inside_custom_rect() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let region = WanderRegion::Rect {
+            min: Vec2::new(2.0, -3.0),
+            max: Vec2::new(4.0, 7.0),
+        };
+
+        for _ in 0..100 {
+            let point = region.sample(&mut rng).unwrap();
+            assert!((2.0..4.0).contains(&point.x));
+            assert!((-3.0..7.0).contains(&point.z));
+            assert_eq!(point.y, 0.0);
+        }
+    }
+
+    #[test]
+    fn samples_fall_inside_triangles() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let region = WanderRegion::Triangles(vec![[
+            Vec2::new(0.0, 0.0),
+            Vec2::new(2.0, 0.0),
+            Vec2::new(0.0, 2.0),
+        ]]);
+
+        for _ in 0..100 {
+            let point = region.sample(&mut rng).unwrap();
+            assert!(point.x >= 0.0 && point.z >= 0.0);
+            assert!(point.x + point.z <= 2.0 + 1e-5);
+        }
+    }
+
+    #[test]
+    fn degenerate_region_has_no_samples() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let (a, b) = (Vec2::new(1.0, 1.0), Vec2::new(3.0, 3.0));
+
+        let regions = [
+            WanderRegion::Rect { min: a, max: a },
+            WanderRegion::Rect {
+                min: a,
+                max: Vec2::new(3.0, 1.0),
+            },
+            WanderRegion::Triangles(Vec::new()),
+            // collinear vertices
+            WanderRegion::Triangles(vec![[a, b, (a + b) * 0.5]]),
+        ];
+
+        for region in regions {
+            assert!(region.sample(&mut rng).is_none());
+        }
+    }
+}
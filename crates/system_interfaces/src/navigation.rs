@@ -1,10 +1,133 @@
+use std::{collections::VecDeque, ops::Deref};
+
 use game_data::SharedData;
-use nalgebra_glm::Vec3;
+use nalgebra_glm::{vec2, vec3, Vec2, Vec3};
+use rand::Rng;
 
 pub type Data = SharedData<DataSingle>;
 
 #[derive(Default)]
-pub struct DataSingle {}
+pub struct DataSingle {
+    pub navmesh: Navmesh,
+}
+
+/// Walkable area, as triangles on the xz plane. Triangles sharing an edge are connected.
+#[derive(Clone, Default)]
+pub struct Navmesh {
+    triangles: Vec<[Vec2; 3]>,
+}
+
+impl Navmesh {
+    pub fn new(triangles: Vec<[Vec2; 3]>) -> Self {
+        Self { triangles }
+    }
+
+    pub fn triangles(&self) -> &[[Vec2; 3]] {
+        &self.triangles
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+
+    /// Returns true if the point lies on the navmesh, ignoring its height
+    pub fn contains(&self, point: &Vec3) -> bool {
+        self.triangle_at(point).is_some()
+    }
+
+    /// Returns a uniformly distributed point on the navmesh, or None if it has no area
+    pub fn random_point(&self, rng: &mut impl Rng) -> Option<Vec3> {
+        sample_triangles(&self.triangles, rng)
+    }
+
+    /// Returns true if both points lie on the navmesh and are connected by triangles sharing
+    /// edges
+    pub fn is_reachable(&self, from: &Vec3, to: &Vec3) -> bool {
+        let (start, goal) = match (self.triangle_at(from), self.triangle_at(to)) {
+            (Some(start), Some(goal)) => (start, goal),
+            _ => return false,
+        };
+
+        let mut visited = vec![false; self.triangles.len()];
+        let mut queue = VecDeque::from([start]);
+        visited[start] = true;
+
+        while let Some(index) = queue.pop_front() {
+            if index == goal {
+                return true;
+            }
+
+            for (neighbor, triangle) in self.triangles.iter().enumerate() {
+                if !visited[neighbor] && shares_edge(&self.triangles[index], triangle) {
+                    visited[neighbor] = true;
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+
+        false
+    }
+
+    fn triangle_at(&self, point: &Vec3) -> Option<usize> {
+        let point = vec2(point.x, point.z);
+        self.triangles
+            .iter()
+            .position(|triangle| triangle_contains(triangle, &point))
+    }
+}
+
+/// Returns a uniformly distributed point within the triangles on the xz plane, or None if they
+/// have no area
+pub fn sample_triangles(triangles: &[[Vec2; 3]], rng: &mut impl Rng) -> Option<Vec3> {
+    let total_area: f32 = triangles.iter().map(triangle_area).sum();
+    if total_area <= 0.0 || !total_area.is_finite() {
+        return None;
+    }
+
+    // select a triangle weighted by area
+    let mut remaining = rng.gen_range(0.0..total_area);
+    let triangle = triangles
+        .iter()
+        .find(|triangle| {
+            remaining -= triangle_area(triangle);
+            remaining < 0.0
+        })
+        .or_else(|| triangles.last())?;
+
+    // uniform barycentric sample
+    let mut u = rng.gen_range(0.0..1.0);
+    let mut v = rng.gen_range(0.0..1.0);
+    if u + v > 1.0 {
+        u = 1.0 - u;
+        v = 1.0 - v;
+    }
+
+    let [a, b, c] = triangle;
+    let point = a + (b - a) * u + (c - a) * v;
+    Some(vec3(point.x, 0.0, point.y))
+}
+
+fn triangle_area([a, b, c]: &[Vec2; 3]) -> f32 {
+    cross(&(b - a), &(c - a)).abs() * 0.5
+}
+
+fn triangle_contains([a, b, c]: &[Vec2; 3], point: &Vec2) -> bool {
+    let d0 = cross(&(b - a), &(point - a));
+    let d1 = cross(&(c - b), &(point - b));
+    let d2 = cross(&(a - c), &(point - c));
+
+    let has_negative = d0 < 0.0 || d1 < 0.0 || d2 < 0.0;
+    let has_positive = d0 > 0.0 || d1 > 0.0 || d2 > 0.0;
+    !(has_negative && has_positive)
+}
+
+fn shares_edge(a: &[Vec2; 3], b: &[Vec2; 3]) -> bool {
+    a.iter().filter(|vertex| b.contains(vertex)).count() >= 2
+}
+
+fn cross(a: &Vec2, b: &Vec2) -> f32 {
+    a.x * b.y - a.y * b.x
+}
 
 pub struct Interface {
     data: Data,
@@ -21,4 +144,64 @@ impl Interface {
         let _ = self.data.read_single().await;
         None
     }
+
+    /// Locks the navmesh for reading. Systems rebuilding the navmesh wait until this is dropped.
+    pub async fn navmesh(&self) -> impl Deref<Target = Navmesh> + '_ {
+        struct NavmeshGuard<G>(G);
+
+        impl<G> Deref for NavmeshGuard<G>
+        where
+            G: Deref<Target = DataSingle>,
+        {
+            type Target = Navmesh;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0.navmesh
+            }
+        }
+
+        NavmeshGuard(self.data.read_single().await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    /// A 4x2 rectangle split into two triangles
+    fn rectangle() -> Vec<[Vec2; 3]> {
+        vec![
+            [vec2(0.0, 0.0), vec2(4.0, 0.0), vec2(4.0, 2.0)],
+            [vec2(0.0, 0.0), vec2(4.0, 2.0), vec2(0.0, 2.0)],
+        ]
+    }
+
+    #[test]
+    fn random_points_fall_inside_rectangle() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let navmesh = Navmesh::new(rectangle());
+
+        for _ in 0..100 {
+            let point = navmesh.random_point(&mut rng).unwrap();
+            assert!((0.0..=4.0).contains(&point.x));
+            assert!((0.0..=2.0).contains(&point.z));
+            assert!(navmesh.contains(&point));
+        }
+    }
+
+    #[test]
+    fn reachable_only_through_shared_edges() {
+        let mut triangles = rectangle();
+        triangles.push([vec2(5.0, 0.0), vec2(6.0, 0.0), vec2(6.0, 1.0)]);
+        let navmesh = Navmesh::new(triangles);
+
+        let (a, b) = (vec3(3.5, 0.0, 0.5), vec3(0.5, 0.0, 1.5));
+        assert!(navmesh.is_reachable(&a, &b));
+
+        // an unconnected triangle, and a point off the navmesh
+        assert!(!navmesh.is_reachable(&a, &vec3(5.9, 0.0, 0.5)));
+        assert!(!navmesh.is_reachable(&a, &vec3(2.0, 0.0, 3.0)));
+    }
 }
@@ -1,31 +1,410 @@
 use game_data::SharedData;
+use game_entity::{EntityId, EntityMap};
 use nalgebra_glm::{vec3, Vec3};
 
-pub type Data = SharedData;
+/// Hits nearer than this along a ray are ignored, so that rays may start on a surface
+const RAY_EPSILON: f32 = 1e-6;
 
-pub struct Interface {}
+pub type Data = SharedData<DataSingle>;
+
+#[derive(Default)]
+pub struct DataSingle {
+    pub colliders: EntityMap<Collider>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Shape {
+    Sphere {
+        radius: f32,
+    },
+    /// Axis-aligned box
+    Box {
+        half_extents: Vec3,
+    },
+}
+
+#[derive(Clone, Copy)]
+pub struct Collider {
+    pub center: Vec3,
+    pub shape: Shape,
+    /// Hidden colliders are ignored by queries
+    pub visible: bool,
+}
+
+impl Collider {
+    pub fn sphere(center: Vec3, radius: f32) -> Self {
+        Self {
+            center,
+            shape: Shape::Sphere { radius },
+            visible: true,
+        }
+    }
+
+    pub fn aabb(center: Vec3, half_extents: Vec3) -> Self {
+        Self {
+            center,
+            shape: Shape::Box { half_extents },
+            visible: true,
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        let half_extents = match self.shape {
+            Shape::Sphere { radius } => Vec3::repeat(radius),
+            Shape::Box { half_extents } => half_extents,
+        };
+
+        Aabb {
+            min: self.center - half_extents,
+            max: self.center + half_extents,
+        }
+    }
+
+    /// Distance along the ray to where it enters the collider, or leaves it if the ray starts
+    /// inside, in multiples of `direction`
+    fn ray_intersection(&self, origin: &Vec3, direction: &Vec3) -> Option<f32> {
+        // the bounds are tested first, so that most colliders are culled cheaply
+        let (near, far) = self.bounds().ray_intersection(origin, direction)?;
+
+        match self.shape {
+            Shape::Sphere { radius } => {
+                let offset = origin - self.center;
+
+                let a = direction.dot(direction);
+                let b = 2.0 * direction.dot(&offset);
+                let c = offset.dot(&offset) - radius * radius;
+
+                let discriminant = b * b - 4.0 * a * c;
+                if discriminant < 0.0 {
+                    return None;
+                }
+
+                let sqrt_discriminant = discriminant.sqrt();
+                [-b - sqrt_discriminant, -b + sqrt_discriminant]
+                    .into_iter()
+                    .map(|t| t / (2.0 * a))
+                    .find(|t| *t > RAY_EPSILON)
+            }
+            Shape::Box { .. } => [near, far].into_iter().find(|t| *t > RAY_EPSILON),
+        }
+    }
+
+    fn contains(&self, point: &Vec3) -> bool {
+        self.overlaps_sphere(point, 0.0)
+    }
+
+    fn overlaps_sphere(&self, center: &Vec3, radius: f32) -> bool {
+        match self.shape {
+            Shape::Sphere {
+                radius: collider_radius,
+            } => (self.center - center).norm() <= collider_radius + radius,
+            Shape::Box { .. } => {
+                let bounds = self.bounds();
+                let closest = center.sup(&bounds.min).inf(&bounds.max);
+                (closest - center).norm() <= radius
+            }
+        }
+    }
+}
+
+/// Axis-aligned bounding box
+struct Aabb {
+    min: Vec3,
+    max: Vec3,
+}
+
+impl Aabb {
+    /// Distances along the ray to where it enters and leaves the box, in multiples of
+    /// `direction`. The entry distance is negative if the ray starts inside.
+    fn ray_intersection(&self, origin: &Vec3, direction: &Vec3) -> Option<(f32, f32)> {
+        let mut near = f32::NEG_INFINITY;
+        let mut far = f32::INFINITY;
+
+        for axis in 0..3 {
+            if direction[axis].abs() < RAY_EPSILON {
+                // parallel to the slab, so the ray must start within it
+                if origin[axis] < self.min[axis] || origin[axis] > self.max[axis] {
+                    return None;
+                }
+            } else {
+                let t0 = (self.min[axis] - origin[axis]) / direction[axis];
+                let t1 = (self.max[axis] - origin[axis]) / direction[axis];
+                near = near.max(t0.min(t1));
+                far = far.min(t0.max(t1));
+            }
+        }
+
+        if near <= far && far > RAY_EPSILON {
+            Some((near, far))
+        } else {
+            None
+        }
+    }
+}
+
+/// Distance along the ray to the ground plane, in multiples of `direction`
+fn ground_intersection(origin: &Vec3, direction: &Vec3) -> Option<f32> {
+    let normal = vec3(0.0, 1.0, 0.0);
+    let denom = normal.dot(direction);
+
+    if denom.abs() < RAY_EPSILON {
+        return None;
+    }
+
+    let t = -(normal.dot(origin) / denom);
+
+    if t > RAY_EPSILON {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+/// Queries read the colliders without waiting, so while the physics system is writing them
+/// only the ground is hit. Hidden colliders are never hit.
+pub struct Interface {
+    data: Data,
+}
 
 impl From<Data> for Interface {
-    fn from(_data: Data) -> Self {
-        Self {}
+    fn from(data: Data) -> Self {
+        Self { data }
     }
 }
 
 impl Interface {
+    /// Nearest hit of either the ground or a collider
     pub fn raycast(&self, origin: &Vec3, direction: &Vec3) -> Option<Vec3> {
-        let normal = vec3(0.0, 1.0, 0.0);
-        let denom = normal.dot(direction);
+        self.raycast_filtered(origin, direction, |_, _| true)
+    }
 
-        if denom.abs() < 1e-6 {
-            return None;
-        }
+    /// Like `raycast`, but passing through the collider of `ignored`, e.g. an object being placed
+    pub fn raycast_ignoring(
+        &self,
+        origin: &Vec3,
+        direction: &Vec3,
+        ignored: EntityId,
+    ) -> Option<Vec3> {
+        self.raycast_filtered(origin, direction, |entity_id, _| entity_id != ignored)
+    }
 
-        let t = -(normal.dot(origin) / denom);
+    /// Like `raycast`, but passing through colliders containing the origin, e.g. to find what
+    /// lies between a point on a prop and the camera
+    pub fn raycast_outward(&self, origin: &Vec3, direction: &Vec3) -> Option<Vec3> {
+        self.raycast_filtered(origin, direction, |_, collider| !collider.contains(origin))
+    }
 
-        if t > 1e-6 {
-            Some(origin + direction * t)
-        } else {
-            None
+    /// Hit of the ground, ignoring colliders
+    pub fn raycast_ground(&self, origin: &Vec3, direction: &Vec3) -> Option<Vec3> {
+        ground_intersection(origin, direction).map(|t| origin + direction * t)
+    }
+
+    /// Every collider hit by the ray, nearest first. The ground is not included.
+    pub fn raycast_all(&self, origin: &Vec3, direction: &Vec3) -> Vec<(EntityId, Vec3)> {
+        let data = match self.data.try_read_single() {
+            Some(data) => data,
+            None => return Vec::new(),
+        };
+
+        let mut hits: Vec<_> = data
+            .colliders
+            .iter()
+            .filter(|(_, collider)| collider.visible)
+            .filter_map(|(entity_id, collider)| {
+                collider
+                    .ray_intersection(origin, direction)
+                    .map(|t| (*entity_id, t))
+            })
+            .collect();
+
+        hits.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        hits.into_iter()
+            .map(|(entity_id, t)| (entity_id, origin + direction * t))
+            .collect()
+    }
+
+    /// Center of the entity's collider
+    pub fn collider_center(&self, entity_id: EntityId) -> Option<Vec3> {
+        let data = self.data.try_read_single()?;
+        data.colliders
+            .get(entity_id)
+            .map(|collider| collider.center)
+    }
+
+    /// Every collider intersecting the sphere
+    pub fn overlap_sphere(&self, center: &Vec3, radius: f32) -> Vec<EntityId> {
+        let data = match self.data.try_read_single() {
+            Some(data) => data,
+            None => return Vec::new(),
+        };
+
+        data.colliders
+            .iter()
+            .filter(|(_, collider)| collider.visible && collider.overlaps_sphere(center, radius))
+            .map(|(entity_id, _)| *entity_id)
+            .collect()
+    }
+
+    fn raycast_filtered(
+        &self,
+        origin: &Vec3,
+        direction: &Vec3,
+        filter: impl Fn(EntityId, &Collider) -> bool,
+    ) -> Option<Vec3> {
+        let collider_hit = self.data.try_read_single().and_then(|data| {
+            data.colliders
+                .iter()
+                .filter(|(entity_id, collider)| collider.visible && filter(**entity_id, collider))
+                .filter_map(|(_, collider)| collider.ray_intersection(origin, direction))
+                .min_by(f32::total_cmp)
+        });
+
+        let t = ground_intersection(origin, direction)
+            .into_iter()
+            .chain(collider_hit)
+            .min_by(f32::total_cmp)?;
+
+        Some(origin + direction * t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interface(colliders: impl IntoIterator<Item = (u32, Collider)>) -> Interface {
+        let mut data = DataSingle::default();
+        for (entity_id, collider) in colliders {
+            data.colliders.insert(EntityId::new(entity_id), collider);
         }
+
+        Data::new_single(data).into()
+    }
+
+    #[test]
+    fn raycast_hits_ground() {
+        let physics = interface([]);
+        let origin = vec3(1.0, 2.0, 3.0);
+        let down = vec3(0.0, -1.0, 0.0);
+
+        assert_eq!(physics.raycast(&origin, &down), Some(vec3(1.0, 0.0, 3.0)));
+        assert_eq!(physics.raycast(&origin, &-down), None);
+    }
+
+    #[test]
+    fn raycast_hits_nearest_sphere() {
+        let physics = interface([
+            (1, Collider::sphere(vec3(0.0, 1.0, 0.0), 0.5)),
+            (2, Collider::sphere(vec3(0.0, 4.0, 0.0), 0.5)),
+        ]);
+        let origin = vec3(0.0, 10.0, 0.0);
+        let down = vec3(0.0, -1.0, 0.0);
+
+        assert_eq!(physics.raycast(&origin, &down), Some(vec3(0.0, 4.5, 0.0)));
+        assert_eq!(
+            physics.raycast_ignoring(&origin, &down, EntityId::new(2)),
+            Some(vec3(0.0, 1.5, 0.0))
+        );
+        assert_eq!(physics.raycast_ground(&origin, &down), Some(Vec3::zeros()));
+    }
+
+    #[test]
+    fn raycast_all_orders_hits_by_distance() {
+        let physics = interface([
+            (1, Collider::sphere(vec3(0.0, 1.0, 0.0), 0.5)),
+            (2, Collider::sphere(vec3(0.0, 4.0, 0.0), 0.5)),
+            (3, Collider::sphere(vec3(5.0, 4.0, 0.0), 0.5)),
+        ]);
+
+        let hits = physics.raycast_all(&vec3(0.0, 10.0, 0.0), &vec3(0.0, -1.0, 0.0));
+        let hit_ids: Vec<_> = hits.iter().map(|(entity_id, _)| entity_id.get()).collect();
+
+        assert_eq!(hit_ids, [2, 1]);
+        assert_eq!(hits[0].1, vec3(0.0, 4.5, 0.0));
+        assert_eq!(hits[1].1, vec3(0.0, 1.5, 0.0));
+    }
+
+    #[test]
+    fn overlap_sphere_finds_touching_spheres() {
+        let physics = interface([
+            (1, Collider::sphere(vec3(0.0, 0.0, 0.0), 1.0)),
+            (2, Collider::sphere(vec3(2.5, 0.0, 0.0), 1.0)),
+        ]);
+
+        let overlaps = |center: Vec3, radius| -> Vec<u32> {
+            let mut entity_ids: Vec<_> = physics
+                .overlap_sphere(&center, radius)
+                .iter()
+                .map(|entity_id| entity_id.get())
+                .collect();
+            entity_ids.sort_unstable();
+            entity_ids
+        };
+
+        assert_eq!(overlaps(vec3(-1.5, 0.0, 0.0), 0.25), Vec::<u32>::new());
+        assert_eq!(overlaps(vec3(-1.5, 0.0, 0.0), 0.5), [1]);
+        assert_eq!(overlaps(vec3(1.25, 0.0, 0.0), 0.5), [1, 2]);
+    }
+
+    #[test]
+    fn raycast_hits_box_faces() {
+        let physics = interface([(1, Collider::aabb(vec3(0.0, 1.0, 0.0), vec3(1.0, 1.0, 1.0)))]);
+
+        assert_eq!(
+            physics.raycast(&vec3(0.0, 1.0, -5.0), &vec3(0.0, 0.0, 1.0)),
+            Some(vec3(0.0, 1.0, -1.0))
+        );
+
+        // starting inside, the ray hits where it leaves the box
+        assert_eq!(
+            physics.raycast_all(&vec3(0.0, 1.0, 0.0), &vec3(1.0, 0.0, 0.0))[0].1,
+            vec3(1.0, 1.0, 0.0)
+        );
+
+        // parallel to a face, outside of the box
+        assert!(physics
+            .raycast_all(&vec3(0.0, 3.0, -5.0), &vec3(0.0, 0.0, 1.0))
+            .is_empty());
+    }
+
+    #[test]
+    fn overlap_sphere_finds_touching_boxes() {
+        let physics = interface([(1, Collider::aabb(Vec3::zeros(), vec3(1.0, 1.0, 1.0)))]);
+
+        assert_eq!(physics.overlap_sphere(&vec3(2.0, 0.0, 0.0), 1.0).len(), 1);
+        assert!(physics.overlap_sphere(&vec3(2.0, 2.0, 0.0), 1.0).is_empty());
+    }
+
+    #[test]
+    fn queries_ignore_hidden_colliders() {
+        let mut hidden = Collider::sphere(vec3(0.0, 1.0, 0.0), 0.5);
+        hidden.visible = false;
+        let physics = interface([(1, hidden)]);
+
+        let origin = vec3(0.0, 10.0, 0.0);
+        let down = vec3(0.0, -1.0, 0.0);
+
+        assert_eq!(physics.raycast(&origin, &down), Some(Vec3::zeros()));
+        assert!(physics.raycast_all(&origin, &down).is_empty());
+        assert!(physics.overlap_sphere(&vec3(0.0, 1.0, 0.0), 1.0).is_empty());
+    }
+
+    #[test]
+    fn raycast_outward_passes_through_containing_collider() {
+        let physics = interface([
+            (1, Collider::sphere(Vec3::zeros(), 0.5)),
+            (2, Collider::sphere(vec3(0.0, 4.0, 0.0), 0.5)),
+        ]);
+        let up = vec3(0.0, 1.0, 0.0);
+
+        assert_eq!(
+            physics.raycast(&Vec3::zeros(), &up),
+            Some(vec3(0.0, 0.5, 0.0))
+        );
+        assert_eq!(
+            physics.raycast_outward(&Vec3::zeros(), &up),
+            Some(vec3(0.0, 3.5, 0.0))
+        );
     }
 }
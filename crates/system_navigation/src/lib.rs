@@ -1,8 +1,10 @@
+use std::{future::Future, panic, pin::Pin};
+
 use event::{AsyncEventDelegate, FrameEvent};
 use system_interfaces::{
     navigation::Data as SharedData, static_mesh::Interface as StaticMeshInterface,
 };
-use task_executor::async_task::{execute_async, AsyncTaskHandle};
+use task_executor::async_task::{try_execute_async, AsyncTaskHandle};
 
 pub fn shared_data() -> SharedData {
     Default::default()
@@ -11,8 +13,14 @@ pub fn shared_data() -> SharedData {
 pub struct FrameData {
     task_data: Option<TaskData>,
     task_handle: Option<AsyncTaskHandle<TaskData>>,
+    /// Rebuild which couldn't start as the executor had too many async tasks in flight
+    deferred_task: Option<RebuildTask>,
+    /// Static geometry changed since the last rebuild began
+    rebuild_pending: bool,
 }
 
+type RebuildTask = Pin<Box<dyn Future<Output = TaskData> + Send>>;
+
 struct TaskData {
     _shared_data: SharedData,
     _static_mesh_interface: StaticMeshInterface,
@@ -28,6 +36,8 @@ impl FrameData {
         Self {
             task_data: Some(task_data),
             task_handle: None,
+            deferred_task: None,
+            rebuild_pending: false,
         }
     }
 
@@ -35,27 +45,135 @@ impl FrameData {
         // TODO: do as part of fixed update
 
         // check completion of rebuild task
-        if let Some(task_handle) = self.task_handle.take() {
-            match task_handle.result() {
-                Ok(task_data) => self.task_data = Some(task_data),
-                Err(task_handle) => self.task_handle = Some(task_handle),
+        if let Some(task_handle) = &mut self.task_handle {
+            match task_handle.try_take_result() {
+                Some(Ok(task_data)) => {
+                    self.task_data = Some(task_data);
+                    self.task_handle = None;
+                }
+                // the task data was lost with the task, so navigation can't continue
+                Some(Err(payload)) => panic::resume_unwind(payload),
+                None => {}
             }
         }
 
-        if event_delegate
-            .frame_events()
-            .any(|event| matches!(event, FrameEvent::Location(_)))
-        {
-            let mut task_data = self.task_data.take().unwrap();
+        // rebuild when static geometry moves, is added or is removed
+        if event_delegate.frame_events().any(|event| {
+            matches!(
+                event,
+                FrameEvent::Location(..) | FrameEvent::Spawned(_) | FrameEvent::Despawned(_)
+            )
+        }) {
+            self.rebuild_pending = true;
+        }
+
+        // a deferred rebuild hasn't begun, so it includes any changes since it was deferred
+        let task = match self.deferred_task.take() {
+            Some(task) => {
+                self.rebuild_pending = false;
+                task
+            }
+            None => {
+                // changes made during a rebuild are picked up once it completes
+                if !self.rebuild_pending {
+                    return;
+                }
+
+                let mut task_data = match self.task_data.take() {
+                    Some(task_data) => task_data,
+                    None => return,
+                };
 
-            let task = async move {
-                rebuild_navmesh(&mut task_data);
-                task_data
-            };
+                self.rebuild_pending = false;
 
-            self.task_handle = Some(execute_async(task));
+                Box::pin(async move {
+                    rebuild_navmesh(&mut task_data);
+                    task_data
+                })
+            }
+        };
+
+        match try_execute_async(task) {
+            Ok(task_handle) => self.task_handle = Some(task_handle),
+            Err(task) => self.deferred_task = Some(task),
         }
     }
 }
 
 fn rebuild_navmesh(_task_data: &mut TaskData) {}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        num::NonZeroUsize,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    use event::EventManager;
+    use game_entity::EntityId;
+    use system_interfaces::static_mesh::Data as StaticMeshData;
+    use task_executor::{async_task::yield_now, ExecutorOptions, TaskExecutor};
+
+    use super::*;
+
+    #[test]
+    fn rebuild_past_task_cap_is_deferred() {
+        let options = ExecutorOptions {
+            pin_threads: false,
+            max_in_flight: NonZeroUsize::new(1),
+            ..Default::default()
+        };
+
+        let thread_count = NonZeroUsize::new(1).unwrap();
+        let mut executor = TaskExecutor::with_options(options, thread_count, &|_| {});
+
+        let mut task = Box::pin(async move {
+            let mut event_manager = EventManager::new(thread_count);
+            event_manager.assign_thread_event_buffer(0);
+
+            let mut frame_data = FrameData::new(shared_data(), StaticMeshData::default().into());
+
+            // occupies the only in-flight slot
+            let release = Arc::new(AtomicBool::new(false));
+            let blocking_handle = {
+                let release = release.clone();
+                try_execute_async(async move {
+                    while !release.load(Ordering::Acquire) {
+                        yield_now().await;
+                    }
+                })
+            }
+            .ok()
+            .unwrap();
+
+            event_manager
+                .async_delegate()
+                .push_frame_event(FrameEvent::Spawned(EntityId::new(1)));
+            event_manager.swap();
+
+            frame_data.update(&event_manager.async_delegate()).await;
+            assert!(frame_data.deferred_task.is_some());
+            assert!(frame_data.task_handle.is_none());
+
+            release.store(true, Ordering::Release);
+            while !blocking_handle.is_finished() {
+                yield_now().await;
+            }
+
+            event_manager.swap();
+            frame_data.update(&event_manager.async_delegate()).await;
+            assert!(frame_data.deferred_task.is_none());
+            assert!(!frame_data.rebuild_pending);
+
+            while frame_data.task_data.is_none() {
+                yield_now().await;
+                frame_data.update(&event_manager.async_delegate()).await;
+            }
+        });
+
+        assert!(executor.execute_blocking(task.as_mut()).is_ok());
+    }
+}
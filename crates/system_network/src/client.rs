@@ -1,35 +1,29 @@
-use std::{
-    net::SocketAddr,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    },
-    thread::{self, sleep},
-    time::Instant,
-};
+use std::{collections::HashSet, net::SocketAddr};
 
-use crossbeam_channel::{Receiver, Sender};
 use event::{AsyncEventDelegate, GameEvent, SystemGameEvent};
 use game_data::system_swap_data::SystemSwapData;
 use game_entity::EntityId;
-use laminar::{Packet, Socket, SocketEvent};
+use laminar::{ErrorKind, Packet, SocketEvent};
 use update_buffer::NetworkUpdateBufferRef;
 
 use crate::{
     broadcast_reliable_ordered, broadcast_unreliable_sequenced,
     packet::{
-        ClientSpawn, ClientSpawnAckRef, GuestGoalRef, Heartbeat, Location, LocationRef, PacketRef,
-        SpawnGuestRef, SpawnRef,
+        ClientSpawn, ClientSpawnAckRef, DespawnAck, DespawnRef, GuestGoalRef, Heartbeat, Location,
+        LocationRef, PacketRef, SpawnGuestRef, SpawnRef,
     },
-    POLL_INTERVAL, SERVER_ADDR,
+    stats::NetworkStats,
+    transport::{SocketTransport, Transport},
 };
 
 #[derive(Default)]
 struct SwapData {
     server_spawned: Vec<EntityId>,
     client_spawned: Vec<EntityId>,
+    client_despawned: Vec<EntityId>,
     client_spawned_ack: Vec<(EntityId, EntityId)>,
-    spawned_guests: Vec<EntityId>,
+    spawned_guests: Vec<(EntityId, f32)>,
+    server_despawned: Vec<EntityId>,
 }
 
 #[derive(Default)]
@@ -56,12 +50,20 @@ impl ClientFrameData {
 
             swap_data.client_spawned_ack.clear();
 
-            for entity_id in &swap_data.spawned_guests {
-                event_delegate
-                    .push_system_game_event(SystemGameEvent::NetworkSpawnGuest(*entity_id));
+            for (entity_id, walk_speed) in &swap_data.spawned_guests {
+                event_delegate.push_system_game_event(SystemGameEvent::NetworkSpawnGuest {
+                    entity_id: *entity_id,
+                    walk_speed: *walk_speed,
+                });
             }
 
             swap_data.spawned_guests.clear();
+
+            for entity_id in &swap_data.server_despawned {
+                event_delegate.push_system_game_event(SystemGameEvent::NetworkDespawn(*entity_id));
+            }
+
+            swap_data.server_despawned.clear();
         }
 
         // queue spawn events from event_delegate
@@ -73,7 +75,11 @@ impl ClientFrameData {
                 } => {
                     self.swap_data.client_spawned.push(*entity_id);
                 }
-                GameEvent::Despawn(_) => todo!(),
+                GameEvent::Despawn(entity_id) => {
+                    // despawns are currently only replicated from the server, but the entity's
+                    // location must no longer be sent
+                    self.swap_data.client_despawned.push(*entity_id);
+                }
                 _ => {}
             }
         }
@@ -81,49 +87,37 @@ impl ClientFrameData {
 }
 
 pub struct Client {
-    socket_thread_join: Arc<AtomicBool>,
-    sender: Sender<Packet>,
-    receiver: Receiver<SocketEvent>,
+    transport: Box<dyn Transport>,
     server_addr: SocketAddr,
     swap_data: SystemSwapData<SwapData>,
+    /// Entities known to the server. Locally spawned entities are excluded until acked, as their
+    /// ids are only meaningful to this client.
+    replicated_entities: HashSet<EntityId>,
+    pub(crate) stats: NetworkStats,
 }
 
-impl Default for Client {
-    fn default() -> Self {
-        let mut socket = Socket::bind_any().unwrap();
-
-        let sender = socket.get_packet_sender();
-        let receiver = socket.get_event_receiver();
-
-        let socket_thread_join = Arc::new(AtomicBool::new(false));
-
-        let quit = socket_thread_join.clone();
-        thread::spawn(move || {
-            while !quit.load(Ordering::Relaxed) {
-                socket.manual_poll(Instant::now());
-                sleep(POLL_INTERVAL);
-            }
-        });
-
-        let server_addr = SERVER_ADDR.parse().unwrap();
+impl Client {
+    pub fn new(server_addr: SocketAddr) -> Result<Self, ErrorKind> {
+        let transport = SocketTransport::bind_any()?;
+        Ok(Self::with_transport(Box::new(transport), server_addr))
+    }
 
+    pub fn with_transport(transport: Box<dyn Transport>, server_addr: SocketAddr) -> Self {
         Self {
-            socket_thread_join,
-            sender,
-            receiver,
+            transport,
             server_addr,
             swap_data: Default::default(),
+            replicated_entities: HashSet::new(),
+            stats: Default::default(),
         }
     }
 }
 
-impl Drop for Client {
-    fn drop(&mut self) {
-        self.socket_thread_join.store(true, Ordering::Relaxed);
+impl Client {
+    pub fn server_addr(&self) -> SocketAddr {
+        self.server_addr
     }
-}
 
-impl Client {
     pub fn swap(&mut self, frame_data: &mut ClientFrameData) {
         self.swap_data.swap(&mut frame_data.swap_data);
     }
@@ -135,11 +129,14 @@ impl Client {
 
         // recv
 
-        while let Ok(msg) = self.receiver.try_recv() {
+        while let Some(msg) = self.transport.try_recv() {
             match &msg {
                 SocketEvent::Packet(packet) => self.recv(packet, update_buffer),
                 SocketEvent::Connect(_) => log::info!("connect"),
-                SocketEvent::Timeout(_) => log::info!("timeout"),
+                SocketEvent::Timeout(_) => {
+                    log::info!("timeout");
+                    self.stats.timeouts += 1;
+                }
                 SocketEvent::Disconnect(_) => log::info!("disconnect"),
             }
         }
@@ -147,12 +144,10 @@ impl Client {
         // send
 
         // heartbeat packet
-        self.sender
-            .send(Packet::reliable_unordered(
-                self.server_addr,
-                Heartbeat.serialize().to_vec(),
-            ))
-            .unwrap();
+        self.transport.send(Packet::reliable_unordered(
+            self.server_addr,
+            Heartbeat.serialize().to_vec(),
+        ));
     }
 
     fn update_swap(&mut self) {
@@ -164,36 +159,56 @@ impl Client {
 
             broadcast_reliable_ordered(
                 &[self.server_addr],
-                &self.sender,
+                self.transport.as_ref(),
                 &spawn_packet.serialize(),
             );
         }
 
         self.swap_data.client_spawned.clear();
+
+        for entity_id in &self.swap_data.client_despawned {
+            self.replicated_entities.remove(entity_id);
+        }
+
+        self.swap_data.client_despawned.clear();
     }
 
     fn update_state(&mut self, update_buffer: NetworkUpdateBufferRef) {
         update_buffer
             .locations()
-            .filter(|(entity_id, _)| entity_id.get() <= u16::MAX.into()) // TEMP
+            .filter(|(entity_id, _)| self.replicated_entities.contains(entity_id))
             .map(|(entity_id, location)| Location {
                 entity_id,
-                location: location.into(),
+                location: *location,
             })
             .for_each(|packet| {
                 broadcast_unreliable_sequenced(
                     &[self.server_addr],
-                    &self.sender,
+                    self.transport.as_ref(),
                     &packet.serialize(),
                 )
             });
     }
 
     fn recv(&mut self, packet: &Packet, update_buffer: NetworkUpdateBufferRef) {
-        match PacketRef::from(packet.payload()) {
+        self.stats.packets_received += 1;
+
+        let packet_ref = match PacketRef::try_from(packet.payload()) {
+            Ok(packet_ref) => packet_ref,
+            Err(err) => {
+                log::debug!("dropped packet from {}: {err}", packet.addr());
+                self.stats.packets_malformed += 1;
+                return;
+            }
+        };
+
+        match packet_ref {
             PacketRef::ClientSpawnAck(packet) => {
                 self.handle_client_spawn_ack(packet);
             }
+            PacketRef::Despawn(packet) => {
+                self.handle_despawn(packet);
+            }
             PacketRef::GuestGoal(packet) => {
                 self.handle_guest_goal(packet, update_buffer);
             }
@@ -206,33 +221,119 @@ impl Client {
             PacketRef::SpawnGuest(packet) => {
                 self.handle_spawn_guest(packet);
             }
-            _ => {}
+            PacketRef::Connect(_) | PacketRef::Heartbeat(_) => {}
+            _ => self.stats.packets_unexpected += 1,
         }
     }
 
     fn handle_client_spawn_ack(&mut self, client_spawn_ack: ClientSpawnAckRef) {
+        self.replicated_entities
+            .insert(client_spawn_ack.server_id());
+
         self.swap_data
             .client_spawned_ack
             .push((client_spawn_ack.client_id(), client_spawn_ack.server_id()));
     }
 
+    fn handle_despawn(&mut self, despawn: DespawnRef) {
+        // always ack, as a resent despawn implies that the previous ack was lost
+        let despawn_ack = DespawnAck {
+            entity_id: despawn.entity_id(),
+        };
+
+        broadcast_reliable_ordered(
+            &[self.server_addr],
+            self.transport.as_ref(),
+            &despawn_ack.serialize(),
+        );
+
+        self.replicated_entities.remove(&despawn.entity_id());
+        self.swap_data.server_despawned.push(despawn.entity_id());
+    }
+
     fn handle_guest_goal(
         &mut self,
         guest_goal: GuestGoalRef,
         update_buffer: NetworkUpdateBufferRef,
     ) {
-        update_buffer.push_guest_goal(guest_goal.entity_id(), guest_goal.location().into());
+        update_buffer.push_guest_goal(guest_goal.entity_id(), guest_goal.location());
     }
 
     fn handle_location(&mut self, location: LocationRef, update_buffer: NetworkUpdateBufferRef) {
-        update_buffer.push_location(location.entity_id(), location.location().into());
+        update_buffer.push_location(location.entity_id(), location.location());
     }
 
     fn handle_spawn(&mut self, spawn: SpawnRef) {
+        self.replicated_entities.insert(spawn.entity_id());
         self.swap_data.server_spawned.push(spawn.entity_id());
     }
 
     fn handle_spawn_guest(&mut self, spawn: SpawnGuestRef) {
-        self.swap_data.spawned_guests.push(spawn.entity_id());
+        self.replicated_entities.insert(spawn.entity_id());
+        self.swap_data
+            .spawned_guests
+            .push((spawn.entity_id(), spawn.walk_speed()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use nalgebra_glm::vec3;
+    use update_buffer::UpdateBuffer;
+
+    use super::*;
+    use crate::{packet::Spawn, transport::LoopbackTransport};
+
+    #[test]
+    fn replicates_entity_ids_above_u16_max() {
+        let client_addr = "127.0.0.1:1".parse().unwrap();
+        let server_addr = "127.0.0.1:2".parse().unwrap();
+        let (client_transport, server_transport) =
+            LoopbackTransport::pair(client_addr, server_addr);
+
+        let mut client = Client::with_transport(Box::new(client_transport), server_addr);
+
+        let mut update_buffer = UpdateBuffer::new(NonZeroUsize::new(1).unwrap());
+        update_buffer.assign_thread_event_buffer(0);
+
+        let entity_id = EntityId::new(u32::from(u16::MAX) + 1);
+        let location = vec3(1.0, 2.0, 3.0);
+
+        server_transport.send(Packet::reliable_ordered(
+            client_addr,
+            Spawn { entity_id }.serialize().to_vec(),
+            None,
+        ));
+
+        update_buffer
+            .borrow()
+            .static_mesh()
+            .push_location(entity_id, location);
+        update_buffer.swap_buffers();
+
+        // the spawn is received after sending locations, so nothing is sent yet
+        client.update(update_buffer.borrow().network());
+        assert!(client.replicated_entities.contains(&entity_id));
+
+        update_buffer
+            .borrow()
+            .static_mesh()
+            .push_location(entity_id, location);
+        update_buffer.swap_buffers();
+
+        client.update(update_buffer.borrow().network());
+
+        let mut sent = Vec::new();
+        while let Some(event) = server_transport.try_recv() {
+            if let SocketEvent::Packet(packet) = event {
+                if let Ok(PacketRef::Location(packet)) = PacketRef::try_from(packet.payload()) {
+                    sent.push((packet.entity_id(), packet.location()));
+                }
+            }
+        }
+
+        assert!(sent == [(entity_id, location)]);
     }
 }
@@ -1,24 +1,29 @@
 use std::{net::SocketAddr, time::Duration};
 
 use client::ClientFrameData;
-use crossbeam_channel::Sender;
-use event::{AsyncEventDelegate, GameEvent};
+use event::{AsyncEventDelegate, GameEvent, SystemGameEvent};
 use laminar::Packet;
 use server::ServerFrameData;
 use update_buffer::NetworkUpdateBufferRef;
 
-use self::{client::Client, server::Server};
+pub use self::{
+    client::Client,
+    server::Server,
+    stats::NetworkStats,
+    transport::{LoopbackTransport, SocketTransport, Transport},
+};
 
 mod client;
 mod packet;
 mod server;
+mod stats;
+mod transport;
 
-const SERVER_ADDR: &str = "127.0.0.1:12351";
 const POLL_INTERVAL: Duration = Duration::from_millis(50);
 
 enum FrameUpdateImpl {
-    Server(ServerFrameData),
-    Client(ClientFrameData),
+    Server(ServerFrameData, SocketAddr),
+    Client(ClientFrameData, SocketAddr),
     Offline,
 }
 
@@ -31,20 +36,28 @@ impl Default for FrameUpdateImpl {
 #[derive(Default)]
 pub struct FrameData {
     update_impl: FrameUpdateImpl,
+    stats: NetworkStats,
+    /// The requested role could not be started, and the network has fallen back to offline
+    role_failed: bool,
 }
 
 impl FrameData {
+    /// Stats as of the most recent fixed update
+    pub fn stats(&self) -> &NetworkStats {
+        &self.stats
+    }
+
     pub async fn update(&mut self, event_delegate: &AsyncEventDelegate<'_>) {
         use FrameUpdateImpl::*;
 
-        for event in event_delegate.game_events() {
+        for event in event_delegate.network_role_changes() {
             use GameEvent::*;
             match event {
-                NetworkRoleServer => {
-                    self.update_impl = Server(Default::default());
+                NetworkRoleServer(addr) => {
+                    self.update_impl = Server(Default::default(), *addr);
                 }
-                NetworkRoleClient => {
-                    self.update_impl = Client(Default::default());
+                NetworkRoleClient(server_addr) => {
+                    self.update_impl = Client(Default::default(), *server_addr);
                 }
                 NetworkRoleOffline => {
                     self.update_impl = Offline;
@@ -53,11 +66,16 @@ impl FrameData {
             }
         }
 
+        if self.role_failed {
+            self.role_failed = false;
+            event_delegate.push_system_game_event(SystemGameEvent::NetworkRoleFailed);
+        }
+
         match &mut self.update_impl {
-            Server(frame_data) => {
+            Server(frame_data, _) => {
                 frame_data.update(event_delegate);
             }
-            Client(frame_data) => {
+            Client(frame_data, _) => {
                 frame_data.update(event_delegate);
             }
             Offline => {}
@@ -79,12 +97,14 @@ impl Default for FixedUpdateImpl {
 
 impl PartialEq<FrameUpdateImpl> for FixedUpdateImpl {
     fn eq(&self, other: &FrameUpdateImpl) -> bool {
-        matches!(
-            (self, other),
-            (Self::Server(_), FrameUpdateImpl::Server(_))
-                | (Self::Client(_), FrameUpdateImpl::Client(_))
-                | (Self::Offline, FrameUpdateImpl::Offline)
-        )
+        match (self, other) {
+            (Self::Server(server), FrameUpdateImpl::Server(_, addr)) => server.addr() == *addr,
+            (Self::Client(client), FrameUpdateImpl::Client(_, server_addr)) => {
+                client.server_addr() == *server_addr
+            }
+            (Self::Offline, FrameUpdateImpl::Offline) => true,
+            _ => false,
+        }
     }
 }
 
@@ -96,24 +116,43 @@ pub struct FixedData {
 impl FixedData {
     pub async fn swap(&mut self, frame_data: &mut FrameData) {
         if self.update_impl != frame_data.update_impl {
-            self.update_impl = match &frame_data.update_impl {
-                FrameUpdateImpl::Server(_) => FixedUpdateImpl::Server(Server::default().into()),
-                FrameUpdateImpl::Client(_) => FixedUpdateImpl::Client(Client::default().into()),
-                FrameUpdateImpl::Offline => FixedUpdateImpl::Offline,
+            let update_impl = match &frame_data.update_impl {
+                FrameUpdateImpl::Server(_, addr) => Server::new(*addr)
+                    .map(|server| FixedUpdateImpl::Server(server.into()))
+                    .map_err(|err| log::error!("failed to host server on {addr}: {err}")),
+                FrameUpdateImpl::Client(_, server_addr) => Client::new(*server_addr)
+                    .map(|client| FixedUpdateImpl::Client(client.into()))
+                    .map_err(|err| log::error!("failed to connect to {server_addr}: {err}")),
+                FrameUpdateImpl::Offline => Ok(FixedUpdateImpl::Offline),
+            };
+
+            self.update_impl = match update_impl {
+                Ok(update_impl) => update_impl,
+                Err(()) => {
+                    frame_data.update_impl = FrameUpdateImpl::Offline;
+                    frame_data.role_failed = true;
+                    FixedUpdateImpl::Offline
+                }
             };
         }
 
+        frame_data.stats = match &self.update_impl {
+            FixedUpdateImpl::Server(server) => server.stats,
+            FixedUpdateImpl::Client(client) => client.stats,
+            FixedUpdateImpl::Offline => Default::default(),
+        };
+
         match &mut self.update_impl {
             FixedUpdateImpl::Server(server) => {
                 let frame_data = match &mut frame_data.update_impl {
-                    FrameUpdateImpl::Server(frame_data) => frame_data,
+                    FrameUpdateImpl::Server(frame_data, _) => frame_data,
                     _ => unreachable!(),
                 };
                 server.swap(frame_data);
             }
             FixedUpdateImpl::Client(client) => {
                 let frame_data = match &mut frame_data.update_impl {
-                    FrameUpdateImpl::Client(frame_data) => frame_data,
+                    FrameUpdateImpl::Client(frame_data, _) => frame_data,
                     _ => unreachable!(),
                 };
                 client.swap(frame_data);
@@ -136,22 +175,22 @@ impl FixedData {
     }
 }
 
-fn broadcast_reliable_ordered<'a, I>(clients: I, sender: &Sender<Packet>, data: &[u8])
+fn broadcast_reliable_ordered<'a, I>(clients: I, transport: &dyn Transport, data: &[u8])
 where
     I: IntoIterator<Item = &'a SocketAddr>,
 {
     clients
         .into_iter()
         .map(|client| Packet::reliable_ordered(*client, data.to_vec(), None))
-        .for_each(|packet| sender.send(packet).unwrap());
+        .for_each(|packet| transport.send(packet));
 }
 
-fn broadcast_unreliable_sequenced<'a, I>(clients: I, sender: &Sender<Packet>, data: &[u8])
+fn broadcast_unreliable_sequenced<'a, I>(clients: I, transport: &dyn Transport, data: &[u8])
 where
     I: IntoIterator<Item = &'a SocketAddr>,
 {
     clients
         .into_iter()
         .map(|client| Packet::unreliable_sequenced(*client, data.to_vec(), None))
-        .for_each(|packet| sender.send(packet).unwrap());
+        .for_each(|packet| transport.send(packet));
 }
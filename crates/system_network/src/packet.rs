@@ -1,44 +1,105 @@
+use std::{error::Error, fmt};
+
 use game_entity::EntityId;
 use nalgebra_glm::Vec3;
 use system_network_packet_macro::{NetworkPacket, NetworkPacketTypes};
 
-pub struct Vec3_32 {
-    pub x: f32,
-    pub y: f32,
-    pub z: f32,
+/// A value with a fixed-size little-endian encoding, which may be used as a NetworkPacket field
+pub trait PacketField: Sized {
+    const SIZE: usize;
+
+    /// `data` is exactly `SIZE` bytes
+    fn serialize_into(&self, data: &mut [u8]);
+
+    /// `data` is exactly `SIZE` bytes
+    fn deserialize(data: &[u8]) -> Self;
+
+    /// Whether `data`, exactly `SIZE` bytes, may be deserialized. Checked when a packet is
+    /// received, so that `deserialize` need not fail.
+    fn is_valid(_data: &[u8]) -> bool {
+        true
+    }
+}
+
+#[derive(Debug)]
+pub enum PacketError {
+    Empty,
+    UnknownType(u8),
+    /// Lengths include the packet type byte
+    InvalidLength {
+        expected: usize,
+        actual: usize,
+    },
+    /// Only constructed by packets with a trailing String field
+    #[allow(dead_code)]
+    InvalidUtf8,
+    /// The named field has an invalid value, e.g. a zero entity id
+    InvalidField(&'static str),
 }
 
-impl Vec3_32 {
-    fn from_le_bytes(bytes: [u8; 12]) -> Self {
-        Self {
-            x: f32::from_le_bytes(bytes[0..4].try_into().unwrap()),
-            y: f32::from_le_bytes(bytes[4..8].try_into().unwrap()),
-            z: f32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty packet"),
+            Self::UnknownType(packet_type) => write!(f, "unknown packet type {packet_type}"),
+            Self::InvalidLength { expected, actual } => {
+                write!(f, "invalid packet length {actual}, expected {expected}")
+            }
+            Self::InvalidUtf8 => write!(f, "invalid UTF-8 in packet string"),
+            Self::InvalidField(field) => write!(f, "invalid value for packet field {field}"),
         }
     }
+}
+
+impl Error for PacketError {}
+
+macro_rules! impl_packet_field {
+    ($($t:ty),*) => {
+        $(
+            impl PacketField for $t {
+                const SIZE: usize = std::mem::size_of::<$t>();
+
+                fn serialize_into(&self, data: &mut [u8]) {
+                    data.copy_from_slice(&self.to_le_bytes());
+                }
+
+                fn deserialize(data: &[u8]) -> Self {
+                    Self::from_le_bytes(data.try_into().unwrap())
+                }
+            }
+        )*
+    };
+}
+
+impl_packet_field!(u8, u16, u32, u64, i8, i16, i32, i64, f32);
+
+impl PacketField for EntityId {
+    const SIZE: usize = u32::SIZE;
+
+    fn serialize_into(&self, data: &mut [u8]) {
+        data.copy_from_slice(&self.to_le_bytes());
+    }
 
-    fn to_le_bytes(&self) -> [u8; 12] {
-        let mut data = [0; 12];
-        data[0..4].copy_from_slice(&self.x.to_le_bytes());
-        data[4..8].copy_from_slice(&self.y.to_le_bytes());
-        data[8..12].copy_from_slice(&self.z.to_le_bytes());
-        data
+    fn deserialize(data: &[u8]) -> Self {
+        Self::from_le_bytes(data.try_into().unwrap())
+    }
+
+    fn is_valid(data: &[u8]) -> bool {
+        u32::deserialize(data) != 0
     }
 }
 
-impl From<&Vec3> for Vec3_32 {
-    fn from(vec: &Vec3) -> Self {
-        Self {
-            x: vec.x,
-            y: vec.y,
-            z: vec.z,
+impl PacketField for Vec3 {
+    const SIZE: usize = 3 * f32::SIZE;
+
+    fn serialize_into(&self, data: &mut [u8]) {
+        for (i, val) in self.iter().enumerate() {
+            val.serialize_into(&mut data[i * f32::SIZE..(i + 1) * f32::SIZE]);
         }
     }
-}
 
-impl From<Vec3_32> for Vec3 {
-    fn from(vec: Vec3_32) -> Self {
-        Self::from([vec.x, vec.y, vec.z])
+    fn deserialize(data: &[u8]) -> Self {
+        Self::from_fn(|i, _| f32::deserialize(&data[i * f32::SIZE..(i + 1) * f32::SIZE]))
     }
 }
 
@@ -48,6 +109,8 @@ pub enum PacketType {
     ClientSpawn,
     ClientSpawnAck,
     Connect,
+    Despawn,
+    DespawnAck,
     GuestGoal,
     Heartbeat,
     Location,
@@ -69,10 +132,20 @@ pub struct ClientSpawnAck {
 #[derive(NetworkPacket)]
 pub struct Connect;
 
+#[derive(NetworkPacket)]
+pub struct Despawn {
+    pub entity_id: EntityId,
+}
+
+#[derive(NetworkPacket)]
+pub struct DespawnAck {
+    pub entity_id: EntityId,
+}
+
 #[derive(NetworkPacket)]
 pub struct GuestGoal {
     pub entity_id: EntityId,
-    pub location: Vec3_32,
+    pub location: Vec3,
 }
 
 #[derive(NetworkPacket)]
@@ -81,7 +154,7 @@ pub struct Heartbeat;
 #[derive(NetworkPacket)]
 pub struct Location {
     pub entity_id: EntityId,
-    pub location: Vec3_32,
+    pub location: Vec3,
 }
 
 #[derive(NetworkPacket)]
@@ -92,4 +165,189 @@ pub struct Spawn {
 #[derive(NetworkPacket)]
 pub struct SpawnGuest {
     pub entity_id: EntityId,
+    pub walk_speed: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::vec3;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let packet = Location {
+            entity_id: EntityId::new(7),
+            location: vec3(1.0, 2.0, 3.0),
+        };
+
+        match PacketRef::try_from(&packet.serialize()[..]) {
+            Ok(PacketRef::Location(location)) => {
+                assert!(location.entity_id() == EntityId::new(7));
+                assert_eq!(location.location(), vec3(1.0, 2.0, 3.0));
+            }
+            _ => panic!("expected a location packet"),
+        }
+    }
+
+    #[test]
+    fn empty() {
+        assert!(matches!(
+            PacketRef::try_from(&[][..]),
+            Err(PacketError::Empty)
+        ));
+    }
+
+    #[test]
+    fn unknown_type() {
+        assert!(matches!(
+            PacketRef::try_from(&[u8::MAX][..]),
+            Err(PacketError::UnknownType(u8::MAX))
+        ));
+    }
+
+    #[test]
+    fn truncated() {
+        let data = Spawn {
+            entity_id: EntityId::new(1),
+        }
+        .serialize();
+
+        assert!(matches!(
+            PacketRef::try_from(&data[..data.len() - 1]),
+            Err(PacketError::InvalidLength {
+                expected: 5,
+                actual: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn zero_entity_id() {
+        let mut data = Spawn {
+            entity_id: EntityId::new(1),
+        }
+        .serialize();
+        data[1..].fill(0);
+
+        assert!(matches!(
+            PacketRef::try_from(&data[..]),
+            Err(PacketError::InvalidField("entity_id"))
+        ));
+    }
+
+    /// Packets with trailing fields, under their own packet types
+    mod trailing {
+        use super::super::{PacketError, PacketField};
+        use super::*;
+
+        #[repr(u8)]
+        #[derive(NetworkPacketTypes)]
+        pub enum PacketType {
+            Blob,
+            Name,
+        }
+
+        #[derive(NetworkPacket)]
+        pub struct Blob {
+            pub entity_id: EntityId,
+            pub bytes: Vec<u8>,
+        }
+
+        #[derive(NetworkPacket)]
+        pub struct Name {
+            pub entity_id: EntityId,
+            pub name: String,
+        }
+
+        fn bytes_round_trip(bytes: Vec<u8>) {
+            let data = Blob {
+                entity_id: EntityId::new(3),
+                bytes: bytes.clone(),
+            }
+            .serialize();
+
+            match PacketRef::try_from(&data[..]) {
+                Ok(PacketRef::Blob(blob)) => {
+                    assert!(blob.entity_id() == EntityId::new(3));
+                    assert_eq!(blob.bytes(), &bytes[..]);
+                }
+                _ => panic!("expected a blob packet"),
+            }
+        }
+
+        fn string_round_trip(name: String) {
+            let data = Name {
+                entity_id: EntityId::new(3),
+                name: name.clone(),
+            }
+            .serialize();
+
+            match PacketRef::try_from(&data[..]) {
+                Ok(PacketRef::Name(packet)) => {
+                    assert!(packet.entity_id() == EntityId::new(3));
+                    assert_eq!(packet.name(), name);
+                }
+                _ => panic!("expected a name packet"),
+            }
+        }
+
+        #[test]
+        fn empty_bytes() {
+            bytes_round_trip(Vec::new());
+        }
+
+        #[test]
+        fn max_length_bytes() {
+            bytes_round_trip((0..u16::MAX).map(|i| i as u8).collect());
+        }
+
+        #[test]
+        fn non_utf8_bytes() {
+            bytes_round_trip(vec![0xff, 0xfe]);
+        }
+
+        #[test]
+        fn empty_string() {
+            string_round_trip(String::new());
+        }
+
+        #[test]
+        fn max_length_string() {
+            string_round_trip("a".repeat(u16::MAX as usize));
+        }
+
+        #[test]
+        fn invalid_utf8() {
+            let mut data = Name {
+                entity_id: EntityId::new(3),
+                name: "ab".to_string(),
+            }
+            .serialize();
+            let len = data.len();
+            data[len - 1] = 0xff;
+
+            assert!(matches!(
+                PacketRef::try_from(&data[..]),
+                Err(PacketError::InvalidUtf8)
+            ));
+        }
+
+        #[test]
+        fn trailing_length_mismatch() {
+            let data = Name {
+                entity_id: EntityId::new(3),
+                name: "ab".to_string(),
+            }
+            .serialize();
+
+            assert!(matches!(
+                PacketRef::try_from(&data[..data.len() - 1]),
+                Err(PacketError::InvalidLength {
+                    expected: 9,
+                    actual: 8
+                })
+            ));
+        }
+    }
 }
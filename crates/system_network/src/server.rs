@@ -1,35 +1,38 @@
 use std::{
+    collections::{BTreeSet, HashMap},
     net::SocketAddr,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    },
-    thread::{self, sleep},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
-use crossbeam_channel::{Receiver, Sender};
 use event::{AsyncEventDelegate, GameEvent, SystemGameEvent};
 use game_data::system_swap_data::SystemSwapData;
 use game_entity::EntityId;
-use laminar::{Packet, Socket, SocketEvent};
+use laminar::{ErrorKind, Packet, SocketEvent};
 use update_buffer::NetworkUpdateBufferRef;
 
 use crate::{
     broadcast_reliable_ordered, broadcast_unreliable_sequenced,
     packet::{
-        ClientSpawnAck, ClientSpawnRef, Connect, GuestGoal, Heartbeat, Location, LocationRef,
-        PacketRef, Spawn, SpawnGuest,
+        ClientSpawnAck, ClientSpawnRef, Connect, Despawn, DespawnAckRef, GuestGoal, Heartbeat,
+        Location, LocationRef, PacketRef, Spawn, SpawnGuest,
     },
-    POLL_INTERVAL, SERVER_ADDR,
+    stats::NetworkStats,
+    transport::{SocketTransport, Transport},
 };
 
+/// Despawns which have not been acked by a client within this interval are resent
+const DESPAWN_RESEND_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Unconnected addresses are sent at most one handshake per interval
+const HANDSHAKE_RESEND_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Default)]
 struct SwapData {
     server_spawned: Vec<EntityId>,
     client_spawned: Vec<u16>,
     client_spawned_acks: Vec<(u16, EntityId)>,
-    spawned_guests: Vec<EntityId>,
+    spawned_guests: Vec<(EntityId, f32)>,
+    despawned: Vec<EntityId>,
 }
 
 #[derive(Default)]
@@ -61,8 +64,11 @@ impl ServerFrameData {
                 GameEvent::SpawnGuest {
                     entity_id,
                     replicate: true,
+                    walk_speed,
                 } => {
-                    self.swap_data.spawned_guests.push(*entity_id);
+                    self.swap_data
+                        .spawned_guests
+                        .push((*entity_id, *walk_speed));
                 }
                 GameEvent::NetworkClientSpawnAck {
                     spawn_id,
@@ -72,7 +78,9 @@ impl ServerFrameData {
                         .client_spawned_acks
                         .push((*spawn_id, *entity_id));
                 }
-                GameEvent::Despawn(_) => todo!(),
+                GameEvent::Despawn(entity_id) => {
+                    self.swap_data.despawned.push(*entity_id);
+                }
                 _ => {}
             }
         }
@@ -83,6 +91,8 @@ struct ConnectedClient {
     addr: SocketAddr,
     /// entities spawned by the client which are awaiting ack, locally identified by a u16
     spawned_entities: Vec<(u16, EntityId)>,
+    /// despawns sent to the client which are awaiting ack, and when they were last sent
+    pending_despawns: Vec<(EntityId, Instant)>,
 }
 
 impl ConnectedClient {
@@ -90,56 +100,56 @@ impl ConnectedClient {
         Self {
             addr,
             spawned_entities: Vec::new(),
+            pending_despawns: Vec::new(),
         }
     }
 }
 
 pub struct Server {
-    socket_thread_join: Arc<AtomicBool>,
-    sender: Sender<Packet>,
-    receiver: Receiver<SocketEvent>,
+    addr: SocketAddr,
+    transport: Box<dyn Transport>,
     connected_clients: Vec<ConnectedClient>,
+    /// Unconnected addresses which have been sent a handshake, and when it was sent
+    pending_handshakes: HashMap<SocketAddr, Instant>,
     swap_data: SystemSwapData<SwapData>,
-    spawn_id_free_list: Vec<u16>,
+    /// Released spawn ids, reused lowest first
+    spawn_id_free_set: BTreeSet<u16>,
     next_spawn_id: u16,
+    pub(crate) stats: NetworkStats,
 }
 
-impl Default for Server {
-    fn default() -> Self {
-        let mut socket = Socket::bind(SERVER_ADDR).unwrap();
-
-        let sender = socket.get_packet_sender();
-        let receiver = socket.get_event_receiver();
-
-        let socket_thread_join = Arc::new(AtomicBool::new(false));
-
-        let quit = socket_thread_join.clone();
-        thread::spawn(move || {
-            while !quit.load(Ordering::Relaxed) {
-                socket.manual_poll(Instant::now());
-                sleep(POLL_INTERVAL);
-            }
-        });
+impl Server {
+    pub fn new(addr: SocketAddr) -> Result<Self, ErrorKind> {
+        let transport = SocketTransport::bind(addr)?;
+        Ok(Self::with_transport(Box::new(transport), addr))
+    }
 
+    /// `addr` is the address the transport receives on
+    pub fn with_transport(transport: Box<dyn Transport>, addr: SocketAddr) -> Self {
         Self {
-            socket_thread_join,
-            sender,
-            receiver,
+            addr,
+            transport,
             connected_clients: Vec::new(),
+            pending_handshakes: HashMap::new(),
             swap_data: Default::default(),
-            spawn_id_free_list: Vec::new(),
+            spawn_id_free_set: BTreeSet::new(),
             next_spawn_id: 0,
+            stats: Default::default(),
         }
     }
 }
 
-impl Drop for Server {
-    fn drop(&mut self) {
-        self.socket_thread_join.store(true, Ordering::Relaxed);
+impl Server {
+    /// Address the server was asked to bind to, whose port may be 0. See `local_addr`.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Address the server socket is bound to, with the port chosen when binding to port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.transport.local_addr()
     }
-}
 
-impl Server {
     pub fn swap(&mut self, frame_data: &mut ServerFrameData) {
         self.swap_data.swap(&mut frame_data.swap_data);
     }
@@ -155,20 +165,34 @@ impl Server {
 
         // recv
 
-        while let Ok(msg) = self.receiver.try_recv() {
+        while let Some(msg) = self.transport.try_recv() {
             match &msg {
                 SocketEvent::Packet(packet) => self.recv(packet, update_buffer),
                 SocketEvent::Connect(addr) => self.connect(addr),
-                SocketEvent::Timeout(_) => log::info!("timeout"),
+                SocketEvent::Timeout(_) => {
+                    log::info!("timeout");
+                    self.stats.timeouts += 1;
+                }
                 SocketEvent::Disconnect(addr) => self.disconnect(addr),
             }
         }
 
+        // expire handshakes, so that addresses which are still unconnected are sent another
+
+        let now = Instant::now();
+        self.pending_handshakes.retain(|_, sent_instant| {
+            now.duration_since(*sent_instant) < HANDSHAKE_RESEND_INTERVAL
+        });
+
+        // resend unacknowledged despawns
+
+        self.resend_despawns();
+
         // heartbeat
 
         broadcast_reliable_ordered(
             self.connected_clients.iter().map(|client| &client.addr),
-            &self.sender,
+            self.transport.as_ref(),
             &Heartbeat.serialize(),
         );
     }
@@ -176,6 +200,8 @@ impl Server {
     fn connect(&mut self, addr: &SocketAddr) {
         log::info!("connected client {addr}");
 
+        self.pending_handshakes.remove(addr);
+
         self.connected_clients.push(ConnectedClient::new(*addr));
     }
 
@@ -195,21 +221,22 @@ impl Server {
 
             broadcast_reliable_ordered(
                 self.connected_clients.iter().map(|client| &client.addr),
-                &self.sender,
+                self.transport.as_ref(),
                 &spawn_packet.serialize(),
             );
         }
 
         self.swap_data.server_spawned.clear();
 
-        for entity_id in &self.swap_data.spawned_guests {
+        for (entity_id, walk_speed) in &self.swap_data.spawned_guests {
             let spawn_packet = SpawnGuest {
                 entity_id: *entity_id,
+                walk_speed: *walk_speed,
             };
 
             broadcast_reliable_ordered(
                 self.connected_clients.iter().map(|client| &client.addr),
-                &self.sender,
+                self.transport.as_ref(),
                 &spawn_packet.serialize(),
             );
         }
@@ -219,6 +246,10 @@ impl Server {
         // broadcast client spawn acks by server
 
         for (spawn_id, entity_id) in &self.swap_data.client_spawned_acks {
+            // the spawn id may be reused as soon as the spawn is acked, even if the spawning
+            // client has since disconnected
+            self.spawn_id_free_set.insert(*spawn_id);
+
             let mut spawning_client_addr = None;
 
             if let Some((i, client)) = self.connected_clients.iter_mut().find_map(|client| {
@@ -239,7 +270,7 @@ impl Server {
 
                 broadcast_reliable_ordered(
                     &[client.addr],
-                    &self.sender,
+                    self.transport.as_ref(),
                     &spawn_ack_packet.serialize(),
                 );
 
@@ -256,10 +287,60 @@ impl Server {
                 entity_id: *entity_id,
             };
 
-            broadcast_reliable_ordered(other_clients, &self.sender, &spawn_packet.serialize());
+            broadcast_reliable_ordered(
+                other_clients,
+                self.transport.as_ref(),
+                &spawn_packet.serialize(),
+            );
         }
 
         self.swap_data.client_spawned_acks.clear();
+
+        // broadcast despawns, which must be acked by every client
+
+        let now = Instant::now();
+
+        for entity_id in &self.swap_data.despawned {
+            let despawn_packet = Despawn {
+                entity_id: *entity_id,
+            };
+
+            broadcast_reliable_ordered(
+                self.connected_clients.iter().map(|client| &client.addr),
+                self.transport.as_ref(),
+                &despawn_packet.serialize(),
+            );
+
+            for client in &mut self.connected_clients {
+                client.pending_despawns.push((*entity_id, now));
+            }
+        }
+
+        self.swap_data.despawned.clear();
+    }
+
+    fn resend_despawns(&mut self) {
+        let now = Instant::now();
+
+        for client in &mut self.connected_clients {
+            for (entity_id, sent_instant) in &mut client.pending_despawns {
+                if now.duration_since(*sent_instant) < DESPAWN_RESEND_INTERVAL {
+                    continue;
+                }
+
+                let despawn_packet = Despawn {
+                    entity_id: *entity_id,
+                };
+
+                broadcast_reliable_ordered(
+                    &[client.addr],
+                    self.transport.as_ref(),
+                    &despawn_packet.serialize(),
+                );
+
+                *sent_instant = now;
+            }
+        }
     }
 
     fn update_state(&mut self, update_buffer: NetworkUpdateBufferRef) {
@@ -267,12 +348,12 @@ impl Server {
             .guest_goals()
             .map(|(entity_id, location)| GuestGoal {
                 entity_id: *entity_id,
-                location: location.into(),
+                location: *location,
             })
             .for_each(|packet| {
                 broadcast_unreliable_sequenced(
                     self.connected_clients.iter().map(|client| &client.addr),
-                    &self.sender,
+                    self.transport.as_ref(),
                     &packet.serialize(),
                 )
             });
@@ -281,67 +362,115 @@ impl Server {
             .locations()
             .map(|(entity_id, location)| Location {
                 entity_id,
-                location: location.into(),
+                location: *location,
             })
             .for_each(|packet| {
                 broadcast_unreliable_sequenced(
                     self.connected_clients.iter().map(|client| &client.addr),
-                    &self.sender,
+                    self.transport.as_ref(),
                     &packet.serialize(),
                 )
             });
     }
 
     fn recv(&mut self, packet: &Packet, update_buffer: NetworkUpdateBufferRef) {
+        self.stats.packets_received += 1;
+
+        let packet_ref = match PacketRef::try_from(packet.payload()) {
+            Ok(packet_ref) => packet_ref,
+            Err(err) => {
+                log::debug!("dropped packet from {}: {err}", packet.addr());
+                self.stats.packets_malformed += 1;
+                return;
+            }
+        };
+
+        // clients are only tracked once the socket reports the connection, which requires a
+        // reply from the server
         if !self
             .connected_clients
             .iter()
             .any(|client| client.addr == packet.addr())
         {
-            // send handshake packet
-            self.sender
-                .send(Packet::reliable_unordered(
+            self.stats.packets_unconnected += 1;
+
+            if !self.pending_handshakes.contains_key(&packet.addr()) {
+                log::debug!("sending handshake to unconnected address {}", packet.addr());
+
+                self.transport.send(Packet::reliable_unordered(
                     packet.addr(),
                     Connect.serialize().to_vec(),
-                ))
-                .unwrap();
+                ));
+
+                self.pending_handshakes
+                    .insert(packet.addr(), Instant::now());
+            }
+
+            return;
         }
 
-        match PacketRef::from(packet.payload()) {
+        match packet_ref {
             PacketRef::ClientSpawn(spawn) => {
                 self.handle_client_spawn(spawn, &packet.addr());
             }
+            PacketRef::DespawnAck(despawn_ack) => {
+                self.handle_despawn_ack(despawn_ack, &packet.addr());
+            }
             PacketRef::Location(location) => {
                 self.handle_location(location, &packet.addr(), update_buffer);
             }
-            _ => {}
+            PacketRef::Heartbeat(_) => {}
+            _ => self.stats.packets_unexpected += 1,
         }
     }
 
+    /// Spawn ids are assigned in packet arrival order, using the lowest released id if any. The
+    /// game controller then assigns replicable EntityIds in the same order, so for a given
+    /// packet arrival order the resulting EntityIds are deterministic regardless of which
+    /// clients the spawns came from.
     fn handle_client_spawn(&mut self, spawn: ClientSpawnRef, addr: &SocketAddr) {
         if let Some(client) = self
             .connected_clients
             .iter_mut()
             .find(|client| client.addr == *addr)
         {
-            let spawn_id = self.spawn_id_free_list.pop().unwrap_or_else(|| {
-                let spawn_id = self.next_spawn_id;
-                self.next_spawn_id += 1;
-                spawn_id
-            });
+            let spawn_id = match self.spawn_id_free_set.iter().next().copied() {
+                Some(spawn_id) => {
+                    self.spawn_id_free_set.remove(&spawn_id);
+                    spawn_id
+                }
+                None => {
+                    let spawn_id = self.next_spawn_id;
+                    self.next_spawn_id += 1;
+                    spawn_id
+                }
+            };
 
             client.spawned_entities.push((spawn_id, spawn.entity_id()));
             self.swap_data.client_spawned.push(spawn_id);
         }
     }
 
+    fn handle_despawn_ack(&mut self, despawn_ack: DespawnAckRef, addr: &SocketAddr) {
+        if let Some(client) = self
+            .connected_clients
+            .iter_mut()
+            .find(|client| client.addr == *addr)
+        {
+            let entity_id = despawn_ack.entity_id();
+            client
+                .pending_despawns
+                .retain(|(pending_id, _)| *pending_id != entity_id);
+        }
+    }
+
     fn handle_location(
         &mut self,
         location: LocationRef,
         addr: &SocketAddr,
         update_buffer: NetworkUpdateBufferRef,
     ) {
-        update_buffer.push_location(location.entity_id(), location.location().into());
+        update_buffer.push_location(location.entity_id(), location.location());
 
         let location_packet = Location {
             entity_id: location.entity_id(),
@@ -354,6 +483,381 @@ impl Server {
             .map(|client| &client.addr)
             .filter(|client_addr| *client_addr != addr);
 
-        broadcast_unreliable_sequenced(other_clients, &self.sender, &location_packet.serialize());
+        broadcast_unreliable_sequenced(
+            other_clients,
+            self.transport.as_ref(),
+            &location_packet.serialize(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        num::NonZeroUsize,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use event::EventManager;
+    use nalgebra_glm::vec3;
+    use update_buffer::UpdateBuffer;
+
+    use super::*;
+    use crate::{
+        packet::{ClientSpawn, DespawnAck},
+        transport::LoopbackTransport,
+        Client, POLL_INTERVAL,
+    };
+
+    fn server_addr() -> SocketAddr {
+        "127.0.0.1:1".parse().unwrap()
+    }
+
+    fn client_addr(client: usize) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 10 + client as u16))
+    }
+
+    type SocketEvents = Arc<Mutex<VecDeque<SocketEvent>>>;
+
+    /// Server end of a loopback transport to each client, which first delivers any scripted
+    /// socket events
+    struct MultiTransport {
+        transports: Vec<LoopbackTransport>,
+        socket_events: SocketEvents,
+    }
+
+    impl Transport for MultiTransport {
+        fn send(&self, packet: Packet) {
+            // each transport drops packets addressed to other clients
+            for transport in &self.transports {
+                transport.send(packet.clone());
+            }
+        }
+
+        fn try_recv(&self) -> Option<SocketEvent> {
+            let socket_event = self.socket_events.lock().unwrap().pop_front();
+            socket_event.or_else(|| self.transports.iter().find_map(|t| t.try_recv()))
+        }
+
+        fn local_addr(&self) -> SocketAddr {
+            server_addr()
+        }
+    }
+
+    /// Drives a server on the test thread, connected to clients over loopback transports
+    struct Harness {
+        server: Server,
+        frame_data: ServerFrameData,
+        clients: Vec<LoopbackTransport>,
+        socket_events: SocketEvents,
+        event_manager: EventManager,
+        update_buffer: UpdateBuffer,
+    }
+
+    impl Harness {
+        /// A server whose socket has not yet reported a connection from any of the clients
+        fn unconnected(client_count: usize) -> Self {
+            let (clients, transports): (Vec<_>, Vec<_>) = (0..client_count)
+                .map(|client| LoopbackTransport::pair(client_addr(client), server_addr()))
+                .unzip();
+
+            for transport in &transports {
+                assert!(matches!(
+                    transport.try_recv(),
+                    Some(SocketEvent::Connect(_))
+                ));
+            }
+
+            let socket_events = SocketEvents::default();
+            let transport = MultiTransport {
+                transports,
+                socket_events: socket_events.clone(),
+            };
+
+            let thread_count = NonZeroUsize::new(1).unwrap();
+
+            let event_manager = EventManager::new(thread_count);
+            event_manager.assign_thread_event_buffer(0);
+
+            let update_buffer = UpdateBuffer::new(thread_count);
+            update_buffer.assign_thread_event_buffer(0);
+
+            Self {
+                server: Server::with_transport(Box::new(transport), server_addr()),
+                frame_data: Default::default(),
+                clients,
+                socket_events,
+                event_manager,
+                update_buffer,
+            }
+        }
+
+        /// A server connected to each of the clients
+        fn new(client_count: usize) -> Self {
+            let mut harness = Self::unconnected(client_count);
+
+            for client in 0..client_count {
+                harness.push_socket_event(SocketEvent::Connect(client_addr(client)));
+            }
+
+            harness.update(&[]);
+            harness
+        }
+
+        fn push_socket_event(&self, socket_event: SocketEvent) {
+            self.socket_events.lock().unwrap().push_back(socket_event);
+        }
+
+        /// Runs a frame update with the game events, followed by a fixed update. Returns the
+        /// system game events pushed by the frame update.
+        fn update(&mut self, game_events: &[GameEvent]) -> Vec<SystemGameEvent> {
+            self.event_manager.swap();
+            self.event_manager.clear_system_game_events();
+
+            let mut event_delegate = self.event_manager.sync_delegate();
+            for game_event in game_events {
+                event_delegate.push_game_event(*game_event);
+            }
+
+            self.frame_data.update(&self.event_manager.async_delegate());
+            self.server.swap(&mut self.frame_data);
+
+            self.update_buffer.swap_buffers();
+            self.server.update(self.update_buffer.borrow().network());
+
+            let mut event_delegate = self.event_manager.sync_delegate();
+            let (_, system_game_events) = event_delegate.system_game_events_mut();
+            system_game_events.copied().collect()
+        }
+
+        fn send(&self, client: usize, data: &[u8]) {
+            let packet = Packet::reliable_ordered(server_addr(), data.to_vec(), None);
+            self.clients[client].send(packet);
+        }
+
+        /// Packets received by the client since last called, filtered and mapped by `f`
+        fn received<T>(&self, client: usize, f: impl Fn(PacketRef) -> Option<T>) -> Vec<T> {
+            let mut received = Vec::new();
+            while let Some(socket_event) = self.clients[client].try_recv() {
+                if let SocketEvent::Packet(packet) = socket_event {
+                    if let Ok(packet_ref) = PacketRef::try_from(packet.payload()) {
+                        received.extend(f(packet_ref));
+                    }
+                }
+            }
+            received
+        }
+    }
+
+    fn despawns(harness: &Harness, client: usize) -> Vec<EntityId> {
+        harness.received(client, |packet| match packet {
+            PacketRef::Despawn(despawn) => Some(despawn.entity_id()),
+            _ => None,
+        })
+    }
+
+    /// Spawn ids assigned to client spawns, in the order the game controller receives them
+    fn client_spawns(system_game_events: &[SystemGameEvent]) -> Vec<u16> {
+        system_game_events
+            .iter()
+            .filter_map(|event| match event {
+                SystemGameEvent::NetworkClientSpawn(spawn_id) => Some(*spawn_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn location(entity_id: u32) -> Location {
+        Location {
+            entity_id: EntityId::new(entity_id),
+            location: vec3(1.0, 0.0, 1.0),
+        }
+    }
+
+    #[test]
+    fn unconnected_address_is_sent_one_handshake() {
+        let mut harness = Harness::unconnected(1);
+
+        for _ in 0..3 {
+            harness.send(0, &location(1).serialize());
+        }
+        harness.update(&[]);
+
+        let handshakes = |harness: &Harness| {
+            harness
+                .received(0, |packet| match packet {
+                    PacketRef::Connect(_) => Some(()),
+                    _ => None,
+                })
+                .len()
+        };
+
+        assert_eq!(handshakes(&harness), 1);
+        assert_eq!(harness.server.stats.packets_unconnected, 3);
+        assert!(harness.server.connected_clients.is_empty());
+
+        // packets within the resend interval are not sent another handshake
+        harness.send(0, &location(1).serialize());
+        harness.update(&[]);
+        assert_eq!(handshakes(&harness), 0);
+
+        // the client is only tracked once the socket reports the connection
+        harness.push_socket_event(SocketEvent::Connect(client_addr(0)));
+        harness.update(&[]);
+        assert_eq!(harness.server.connected_clients.len(), 1);
+        assert!(harness.server.pending_handshakes.is_empty());
+    }
+
+    #[test]
+    fn despawn_is_resent_until_acked() {
+        let mut harness = Harness::new(1);
+        let entity_id = EntityId::new(5);
+
+        // as if the resend interval had passed since the despawn was last sent
+        let elapse_resend_interval = |harness: &mut Harness| {
+            for (_, sent_instant) in &mut harness.server.connected_clients[0].pending_despawns {
+                *sent_instant -= DESPAWN_RESEND_INTERVAL;
+            }
+        };
+
+        harness.update(&[GameEvent::Despawn(entity_id)]);
+        assert!(despawns(&harness, 0) == [entity_id]);
+
+        harness.update(&[]);
+        assert!(despawns(&harness, 0).is_empty());
+
+        // the despawn is dropped, so isn't acked
+        for _ in 0..2 {
+            elapse_resend_interval(&mut harness);
+            harness.update(&[]);
+            assert!(despawns(&harness, 0) == [entity_id]);
+        }
+
+        harness.send(0, &DespawnAck { entity_id }.serialize());
+        elapse_resend_interval(&mut harness);
+        harness.update(&[]);
+
+        assert!(despawns(&harness, 0).is_empty());
+        assert!(harness.server.connected_clients[0]
+            .pending_despawns
+            .is_empty());
+    }
+
+    #[test]
+    fn stats_count_dropped_packets() {
+        let mut harness = Harness::new(1);
+
+        // unknown packet type, and a truncated location
+        harness.send(0, &[u8::MAX]);
+        harness.send(0, &location(1).serialize()[..8]);
+
+        // handled by clients only
+        harness.send(
+            0,
+            &Spawn {
+                entity_id: EntityId::new(1),
+            }
+            .serialize(),
+        );
+
+        harness.update(&[]);
+
+        let stats = harness.server.stats;
+        assert_eq!(stats.packets_received, 3);
+        assert_eq!(stats.packets_malformed, 2);
+        assert_eq!(stats.packets_unexpected, 1);
+        assert_eq!(stats.packets_unconnected, 0);
+
+        // packets still arriving over a connection the socket has timed out are stale
+        harness.push_socket_event(SocketEvent::Timeout(client_addr(0)));
+        harness.push_socket_event(SocketEvent::Disconnect(client_addr(0)));
+        harness.update(&[]);
+
+        harness.send(0, &location(1).serialize());
+        harness.update(&[]);
+
+        let stats = harness.server.stats;
+        assert_eq!(stats.timeouts, 1);
+        assert_eq!(stats.packets_received, 4);
+        assert_eq!(stats.packets_unconnected, 1);
+        assert_eq!(stats.packets_malformed, 2);
+    }
+
+    #[test]
+    fn interleaved_client_spawns_get_ids_in_arrival_order() {
+        let mut harness = Harness::new(2);
+
+        // client-local ids are only unique per client
+        let spawn = |harness: &mut Harness, client, local_id| {
+            let packet = ClientSpawn {
+                entity_id: EntityId::new(local_id),
+            };
+            harness.send(client, &packet.serialize());
+            client_spawns(&harness.update(&[]))
+        };
+
+        // spawns reach the game controller two updates after they are received
+        let flush = |harness: &mut Harness| {
+            let mut spawn_ids = client_spawns(&harness.update(&[]));
+            spawn_ids.extend(client_spawns(&harness.update(&[])));
+            spawn_ids
+        };
+
+        let mut spawn_ids = Vec::new();
+        spawn_ids.extend(spawn(&mut harness, 0, 1));
+        spawn_ids.extend(spawn(&mut harness, 1, 1));
+        spawn_ids.extend(spawn(&mut harness, 0, 2));
+        spawn_ids.extend(flush(&mut harness));
+        assert_eq!(spawn_ids, [0, 1, 2]);
+
+        // acking client 1's spawn releases its id
+        let replicable_id = EntityId::new(100);
+        harness.update(&[GameEvent::NetworkClientSpawnAck {
+            spawn_id: 1,
+            entity_id: replicable_id,
+        }]);
+
+        let acks = harness.received(1, |packet| match packet {
+            PacketRef::ClientSpawnAck(ack) => Some((ack.client_id(), ack.server_id())),
+            _ => None,
+        });
+        assert!(acks == [(EntityId::new(1), replicable_id)]);
+
+        let spawns = harness.received(0, |packet| match packet {
+            PacketRef::Spawn(spawn) => Some(spawn.entity_id()),
+            _ => None,
+        });
+        assert!(spawns == [replicable_id]);
+
+        // the lowest released id is reused before new ids are assigned
+        let mut spawn_ids = Vec::new();
+        spawn_ids.extend(spawn(&mut harness, 1, 2));
+        spawn_ids.extend(spawn(&mut harness, 0, 3));
+        spawn_ids.extend(flush(&mut harness));
+        assert_eq!(spawn_ids, [1, 3]);
+    }
+
+    #[test]
+    fn client_connects_to_server_on_ephemeral_port() {
+        let mut server = Server::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        assert_ne!(server.local_addr().port(), 0);
+
+        let mut client = Client::new(server.local_addr()).unwrap();
+
+        let thread_count = NonZeroUsize::new(1).unwrap();
+        let mut update_buffer = UpdateBuffer::new(thread_count);
+        update_buffer.assign_thread_event_buffer(0);
+
+        // the server is connected once it replies to the client's heartbeats
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.connected_clients.is_empty() || client.stats.packets_received == 0 {
+            assert!(Instant::now() < deadline, "client did not connect");
+
+            client.update(update_buffer.borrow().network());
+            server.update(update_buffer.borrow().network());
+            thread::sleep(POLL_INTERVAL);
+        }
     }
 }
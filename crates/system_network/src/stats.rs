@@ -0,0 +1,16 @@
+/// Counters for received network traffic, reset whenever the network role changes
+///
+/// Stale sequenced packets are not counted, as laminar discards them before delivery without
+/// reporting it.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct NetworkStats {
+    pub packets_received: u64,
+    /// packets dropped due to an unknown packet type or an unexpected length
+    pub packets_malformed: u64,
+    /// well-formed packets dropped because they are not handled by the current network role
+    pub packets_unexpected: u64,
+    /// well-formed packets dropped because the sender has not completed the handshake
+    pub packets_unconnected: u64,
+    /// connections reported as timed out by the socket
+    pub timeouts: u64,
+}
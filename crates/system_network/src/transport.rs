@@ -0,0 +1,216 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, sleep},
+    time::Instant,
+};
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use laminar::{ErrorKind, Packet, Socket, SocketEvent};
+
+use crate::POLL_INTERVAL;
+
+/// Sends packets to, and receives events from, remote hosts
+pub trait Transport: Send {
+    fn send(&self, packet: Packet);
+
+    fn try_recv(&self) -> Option<SocketEvent>;
+
+    /// Address packets are received on
+    fn local_addr(&self) -> SocketAddr;
+}
+
+/// UDP transport, polled on a background thread
+pub struct SocketTransport {
+    local_addr: SocketAddr,
+    socket_thread_join: Arc<AtomicBool>,
+    sender: Sender<Packet>,
+    receiver: Receiver<SocketEvent>,
+}
+
+impl SocketTransport {
+    pub fn bind(addr: SocketAddr) -> Result<Self, ErrorKind> {
+        Self::new(Socket::bind(addr)?)
+    }
+
+    /// Binds to an available port
+    pub fn bind_any() -> Result<Self, ErrorKind> {
+        Self::new(Socket::bind_any()?)
+    }
+
+    fn new(mut socket: Socket) -> Result<Self, ErrorKind> {
+        let local_addr = socket.local_addr()?;
+        let sender = socket.get_packet_sender();
+        let receiver = socket.get_event_receiver();
+
+        let socket_thread_join = Arc::new(AtomicBool::new(false));
+
+        let quit = socket_thread_join.clone();
+        thread::spawn(move || {
+            while !quit.load(Ordering::Relaxed) {
+                socket.manual_poll(Instant::now());
+                sleep(POLL_INTERVAL);
+            }
+        });
+
+        Ok(Self {
+            local_addr,
+            socket_thread_join,
+            sender,
+            receiver,
+        })
+    }
+}
+
+impl Drop for SocketTransport {
+    fn drop(&mut self) {
+        self.socket_thread_join.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Transport for SocketTransport {
+    fn send(&self, packet: Packet) {
+        self.sender.send(packet).unwrap();
+    }
+
+    fn try_recv(&self) -> Option<SocketEvent> {
+        self.receiver.try_recv().ok()
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+/// In-process transport connected to a single peer, which delivers every packet immediately
+/// and in order. Packets addressed to any other host are dropped.
+pub struct LoopbackTransport {
+    addr: SocketAddr,
+    peer_addr: SocketAddr,
+    peer_sender: Sender<SocketEvent>,
+    receiver: Receiver<SocketEvent>,
+}
+
+impl LoopbackTransport {
+    /// Creates two connected endpoints with the given addresses. Each endpoint first receives a
+    /// connect event from the other, as a socket would once packets have been exchanged.
+    pub fn pair(a_addr: SocketAddr, b_addr: SocketAddr) -> (Self, Self) {
+        let (a_sender, a_receiver) = unbounded();
+        let (b_sender, b_receiver) = unbounded();
+
+        a_sender.send(SocketEvent::Connect(b_addr)).unwrap();
+        b_sender.send(SocketEvent::Connect(a_addr)).unwrap();
+
+        let a = Self {
+            addr: a_addr,
+            peer_addr: b_addr,
+            peer_sender: b_sender,
+            receiver: a_receiver,
+        };
+
+        let b = Self {
+            addr: b_addr,
+            peer_addr: a_addr,
+            peer_sender: a_sender,
+            receiver: b_receiver,
+        };
+
+        (a, b)
+    }
+}
+
+impl Drop for LoopbackTransport {
+    fn drop(&mut self) {
+        // the peer may have been dropped first
+        let _ = self.peer_sender.send(SocketEvent::Disconnect(self.addr));
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn send(&self, packet: Packet) {
+        if packet.addr() != self.peer_addr {
+            return;
+        }
+
+        // delivery and ordering guarantees are already met, so received packets are unreliable
+        let packet = Packet::unreliable(self.addr, packet.payload().to_vec());
+        let _ = self.peer_sender.send(SocketEvent::Packet(packet));
+    }
+
+    fn try_recv(&self) -> Option<SocketEvent> {
+        self.receiver.try_recv().ok()
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use event::{EventManager, GameEvent, SystemGameEvent};
+    use game_entity::EntityId;
+    use update_buffer::UpdateBuffer;
+
+    use super::*;
+    use crate::{client::ClientFrameData, server::ServerFrameData, Client, Server};
+
+    #[test]
+    fn server_spawn_replicates_to_client() {
+        let client_addr = "127.0.0.1:1".parse().unwrap();
+        let server_addr = "127.0.0.1:2".parse().unwrap();
+        let (client_transport, server_transport) =
+            LoopbackTransport::pair(client_addr, server_addr);
+
+        let mut server = Server::with_transport(Box::new(server_transport), server_addr);
+        let mut client = Client::with_transport(Box::new(client_transport), server_addr);
+        let mut server_frame_data = ServerFrameData::default();
+        let mut client_frame_data = ClientFrameData::default();
+
+        let thread_count = NonZeroUsize::new(1).unwrap();
+
+        let mut event_manager = EventManager::new(thread_count);
+        event_manager.assign_thread_event_buffer(0);
+
+        let mut update_buffer = UpdateBuffer::new(thread_count);
+        update_buffer.assign_thread_event_buffer(0);
+
+        // connect
+        server.update(update_buffer.borrow().network());
+        client.update(update_buffer.borrow().network());
+
+        let entity_id = EntityId::new(3);
+        event_manager
+            .sync_delegate()
+            .push_game_event(GameEvent::Spawn {
+                entity_id,
+                replicate: true,
+            });
+
+        server_frame_data.update(&event_manager.async_delegate());
+        server.swap(&mut server_frame_data);
+        server.update(update_buffer.borrow().network());
+
+        client.update(update_buffer.borrow().network());
+        client.swap(&mut client_frame_data);
+
+        event_manager.swap();
+        client_frame_data.update(&event_manager.async_delegate());
+
+        let mut event_delegate = event_manager.sync_delegate();
+        let (_, system_game_events) = event_delegate.system_game_events_mut();
+        let spawned: Vec<_> = system_game_events
+            .filter_map(|event| match event {
+                SystemGameEvent::NetworkSpawn(entity_id) => Some(*entity_id),
+                _ => None,
+            })
+            .collect();
+
+        assert!(spawned == [entity_id]);
+    }
+}
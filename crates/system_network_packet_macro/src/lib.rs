@@ -1,7 +1,7 @@
 extern crate proc_macro;
 use proc_macro::TokenStream;
 use quote::{format_ident, quote};
-use syn::{parse_macro_input, Data, DeriveInput};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Type};
 
 #[proc_macro_derive(NetworkPacketTypes)]
 pub fn derive_network_packet_enum(input: TokenStream) -> TokenStream {
@@ -26,13 +26,16 @@ pub fn derive_network_packet_enum(input: TokenStream) -> TokenStream {
             #(#variant_idents(#variant_ref_idents<'a>),) *
         }
 
-        impl<'a> From<&'a [u8]> for PacketRef<'a> {
-            fn from(data: &'a [u8]) -> Self {
-                match data[0] {
+        impl<'a> TryFrom<&'a [u8]> for PacketRef<'a> {
+            type Error = PacketError;
+
+            fn try_from(data: &'a [u8]) -> Result<Self, Self::Error> {
+                let packet_type = *data.first().ok_or(PacketError::Empty)?;
+                match packet_type {
                     #(a if a == #ident::#variant_idents_2 as u8 => {
-                        Self::#variant_idents_2(#variant_ref_idents_2(data[1..].try_into().unwrap()))
+                        #variant_ref_idents_2::try_from(&data[1..]).map(Self::#variant_idents_2)
                     },) *
-                    _ => unreachable!(),
+                    _ => Err(PacketError::UnknownType(packet_type)),
                 }
             }
         }
@@ -40,6 +43,24 @@ pub fn derive_network_packet_enum(input: TokenStream) -> TokenStream {
     .into()
 }
 
+/// Type of a trailing variable-length field, serialized as a u16 length followed by the bytes
+enum TrailingField {
+    Bytes,
+    String,
+}
+
+impl TrailingField {
+    fn from_type(ty: &Type) -> Option<Self> {
+        match quote!(#ty).to_string().as_str() {
+            "Vec < u8 >" => Some(Self::Bytes),
+            "String" => Some(Self::String),
+            _ => None,
+        }
+    }
+}
+
+/// Fields must implement PacketField, except for the last field which may also be a `Vec<u8>` or
+/// `String` of at most `u16::MAX` bytes
 #[proc_macro_derive(NetworkPacket)]
 pub fn derive_network_packet(input: TokenStream) -> TokenStream {
     let DeriveInput { ident, data, .. } = parse_macro_input!(input);
@@ -49,14 +70,39 @@ pub fn derive_network_packet(input: TokenStream) -> TokenStream {
         _ => panic!("NetworkPacket may only be used on structs"),
     };
 
-    let field_idents = data.fields.iter().map(|field| field.ident.clone().unwrap());
-    let field_types = data.fields.iter().map(|field| field.ty.clone());
-    let num_fields = data.fields.iter().count();
+    let mut fields: Vec<_> = data.fields.iter().collect();
+
+    let trailing = match fields.last() {
+        Some(field) => TrailingField::from_type(&field.ty).map(|kind| (field.ident.clone(), kind)),
+        None => None,
+    };
+    if trailing.is_some() {
+        fields.pop();
+    }
+
+    if let Some(field) = fields
+        .iter()
+        .find(|field| TrailingField::from_type(&field.ty).is_some())
+    {
+        return Error::new_spanned(
+            &field.ty,
+            "variable-length NetworkPacket fields are only allowed as the last field",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let field_idents = fields.iter().map(|field| field.ident.clone().unwrap());
+    let field_types = fields.iter().map(|field| field.ty.clone());
+    let num_fields = fields.len();
 
     let field_idents_2 = field_idents.clone();
     let field_types_2 = field_types.clone();
     let field_types_3 = field_types.clone();
     let field_types_4 = field_types.clone();
+    let field_types_5 = field_types.clone();
+    let field_idents_3 = field_idents.clone();
+    let field_indices_2 = field_types.clone().enumerate().map(|(i, _)| i);
 
     let field_indices = field_types.clone().enumerate().map(|(i, _)| i);
 
@@ -64,37 +110,154 @@ pub fn derive_network_packet(input: TokenStream) -> TokenStream {
     let packet_size_ident = format_ident!("{}_PACKET_SIZE", ident);
     let ref_ident = format_ident!("{}Ref", ident);
 
-    quote!(
-        #[allow(non_upper_case_globals)]
-        const #packet_size_ident: usize = #(std::mem::size_of::<#field_types_3>() +)* 1;
+    let serialize_fixed_fields = quote!(
+        let mut i = 1;
+        #(
+            let size = <#field_types as PacketField>::SIZE;
+            PacketField::serialize_into(&self.#field_idents, &mut data[i..i + size]);
+            i += size;
+        ) *
+    );
 
-        #[allow(non_upper_case_globals)]
-        const #field_sizes_ident: [usize; #num_fields] = [#(std::mem::size_of::<#field_types_4>(),)*];
+    let fixed_size = quote!(#(<#field_types_3 as PacketField>::SIZE +)* 1);
+
+    // `body` is the packet data following the packet type, at least as long as the fixed fields
+    let validate_fixed_fields = quote!(
+        #(
+            let offset: usize = (0..#field_indices_2).map(|i| #field_sizes_ident[i]).sum();
+            let size = <#field_types_5 as PacketField>::SIZE;
+            if !<#field_types_5 as PacketField>::is_valid(&body[offset..offset + size]) {
+                return Err(PacketError::InvalidField(stringify!(#field_idents_3)));
+            }
+        ) *
+    );
+
+    let packet = match trailing {
+        None => quote!(
+            #[allow(non_upper_case_globals)]
+            const #packet_size_ident: usize = #fixed_size;
+
+            impl #ident {
+                pub fn serialize(&self) -> [u8; #packet_size_ident] {
+                    let mut data = [0; #packet_size_ident];
+                    data[0] = PacketType::#ident as u8;
+                    #serialize_fixed_fields
+                    data
+                }
+            }
 
-        impl #ident {
-            pub fn serialize(&self) -> [u8; #packet_size_ident ] {
-                let mut data = [0; #packet_size_ident];
-                data[0] = PacketType::#ident as u8;
+            pub struct #ref_ident<'a>(&'a [u8; #packet_size_ident - 1]);
 
-                let mut i = 1;
-                #(
-                    let size = std::mem::size_of::<#field_types>();
-                    data[i..i + size].copy_from_slice(&self.#field_idents.to_le_bytes());
-                    i += size;
-                ) *
+            impl<'a> TryFrom<&'a [u8]> for #ref_ident<'a> {
+                type Error = PacketError;
 
-                data
+                /// `body` is the packet data following the packet type
+                fn try_from(body: &'a [u8]) -> Result<Self, Self::Error> {
+                    let body: &'a [u8; #packet_size_ident - 1] = match body.try_into() {
+                        Ok(body) => body,
+                        Err(_) => {
+                            return Err(PacketError::InvalidLength {
+                                expected: #packet_size_ident,
+                                actual: body.len() + 1,
+                            })
+                        }
+                    };
+
+                    #validate_fixed_fields
+
+                    Ok(Self(body))
+                }
             }
+        ),
+        Some((trailing_ident, trailing_kind)) => {
+            let (trailing_type, validate, trailing_value) = match trailing_kind {
+                TrailingField::Bytes => (quote!(&[u8]), quote!(), quote!(trailing)),
+                TrailingField::String => (
+                    quote!(&str),
+                    quote!(
+                        std::str::from_utf8(&body[#packet_size_ident - 1..])
+                            .map_err(|_| PacketError::InvalidUtf8)?;
+                    ),
+                    // SAFETY: validated as UTF-8 when the ref was created
+                    quote!(unsafe { std::str::from_utf8_unchecked(trailing) }),
+                ),
+            };
+
+            quote!(
+                /// Minimum packet size, with an empty trailing field
+                #[allow(non_upper_case_globals)]
+                const #packet_size_ident: usize = #fixed_size + 2;
+
+                impl #ident {
+                    /// Panics if the trailing field exceeds `u16::MAX` bytes
+                    pub fn serialize(&self) -> Vec<u8> {
+                        let trailing = AsRef::<[u8]>::as_ref(&self.#trailing_ident);
+                        let len = u16::try_from(trailing.len())
+                            .expect("trailing packet field exceeds u16::MAX bytes");
+
+                        let mut data = vec![0; #packet_size_ident + trailing.len()];
+                        data[0] = PacketType::#ident as u8;
+                        #serialize_fixed_fields
+                        data[i..i + 2].copy_from_slice(&len.to_le_bytes());
+                        data[i + 2..].copy_from_slice(trailing);
+                        data
+                    }
+                }
+
+                pub struct #ref_ident<'a>(&'a [u8]);
+
+                impl<'a> TryFrom<&'a [u8]> for #ref_ident<'a> {
+                    type Error = PacketError;
+
+                    /// `body` is the packet data following the packet type
+                    fn try_from(body: &'a [u8]) -> Result<Self, Self::Error> {
+                        let len_offset = #packet_size_ident - 3;
+                        let len = match body.get(len_offset..len_offset + 2) {
+                            Some(len) => u16::from_le_bytes(len.try_into().unwrap()) as usize,
+                            None => {
+                                return Err(PacketError::InvalidLength {
+                                    expected: #packet_size_ident,
+                                    actual: body.len() + 1,
+                                })
+                            }
+                        };
+
+                        if body.len() + 1 != #packet_size_ident + len {
+                            return Err(PacketError::InvalidLength {
+                                expected: #packet_size_ident + len,
+                                actual: body.len() + 1,
+                            });
+                        }
+
+                        #validate_fixed_fields
+                        #validate
+
+                        Ok(Self(body))
+                    }
+                }
+
+                impl<'a> #ref_ident<'a> {
+                    pub fn #trailing_ident(&self) -> #trailing_type {
+                        let trailing: &'a [u8] = &self.0[#packet_size_ident - 1..];
+                        #trailing_value
+                    }
+                }
+            )
         }
+    };
 
-        pub struct #ref_ident<'a>(&'a [u8; #packet_size_ident - 1]);
+    quote!(
+        #packet
+
+        #[allow(non_upper_case_globals)]
+        const #field_sizes_ident: [usize; #num_fields] = [#(<#field_types_4 as PacketField>::SIZE,)*];
 
         impl #ref_ident<'_> {
             #(
                 pub fn #field_idents_2(&self) -> #field_types_2 {
                     let offset = (0..#field_indices).map(|i| #field_sizes_ident[i]).sum();
-                    let size = std::mem::size_of::<#field_types_2>();
-                    #field_types_2::from_le_bytes(self.0[offset..offset + size].try_into().unwrap())
+                    let size = <#field_types_2 as PacketField>::SIZE;
+                    <#field_types_2 as PacketField>::deserialize(&self.0[offset..offset + size])
                 }
             ) *
         }
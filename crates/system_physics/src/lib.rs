@@ -1,21 +1,171 @@
-use system_interfaces::physics::Data as SharedData;
+use std::sync::Arc;
+
+use event::{AsyncEventDelegate, FrameEvent, GameEvent};
+use frame_buffer::AsyncFrameBufferDelegate;
+use game_entity::{EntityId, EntityMap};
+use game_resources::{Mesh, Resource};
+use nalgebra_glm::Vec3;
+use system_interfaces::physics::{Collider, Data as SharedData, DataSingle};
+
+/// Radius of the collider given to each spawned static mesh until its mesh loads
+const DEFAULT_COLLIDER_RADIUS: f32 = 0.5;
 
 pub fn shared_data() -> SharedData {
     Default::default()
 }
 
 pub struct FrameData {
-    _shared_data: SharedData,
+    shared_data: SharedData,
+    mesh_colliders: MeshColliders,
 }
 
 impl FrameData {
     pub fn new(shared_data: SharedData) -> Self {
         Self {
-            _shared_data: shared_data,
+            shared_data,
+            mesh_colliders: MeshColliders::default(),
         }
     }
 
-    pub async fn update(&mut self) {}
+    pub async fn update(
+        &mut self,
+        event_delegate: &AsyncEventDelegate<'_>,
+        frame_buffer: &AsyncFrameBufferDelegate<'_>,
+    ) {
+        let mut data = self.shared_data.write_single().await;
+
+        for game_event in event_delegate.game_events() {
+            match game_event {
+                GameEvent::Spawn { entity_id, .. } => {
+                    // static meshes spawn at the origin
+                    let collider = Collider::sphere(Vec3::zeros(), DEFAULT_COLLIDER_RADIUS);
+                    data.colliders.insert(*entity_id, collider);
+                }
+                GameEvent::Despawn(entity_id) => {
+                    data.colliders.try_remove(*entity_id);
+                    self.mesh_colliders.remove(*entity_id);
+                }
+                GameEvent::UpdateEntityId { old_id, new_id } => {
+                    if let Some(collider) = data.colliders.try_remove(*old_id) {
+                        data.colliders.insert(*new_id, collider);
+                    }
+                    self.mesh_colliders.update_entity_id(*old_id, *new_id);
+                }
+                GameEvent::StaticMeshLocation(entity_id, location) => {
+                    self.mesh_colliders
+                        .set_location(&mut data, *entity_id, location);
+                }
+                GameEvent::SetVisible(entity_id, visible) => {
+                    if let Some(collider) = data.colliders.get_mut(*entity_id) {
+                        collider.visible = *visible;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for frame_event in event_delegate.frame_events() {
+            if let FrameEvent::Location(entity_id, location) = frame_event {
+                self.mesh_colliders
+                    .set_location(&mut data, *entity_id, location);
+            }
+        }
+
+        for static_mesh in frame_buffer.reader().spawned_static_meshes() {
+            if data.colliders.get(static_mesh.entity_id).is_some() {
+                let resource = static_mesh.resource.clone();
+                self.mesh_colliders.insert(static_mesh.entity_id, resource);
+            }
+        }
+
+        self.mesh_colliders.update(&mut data);
+
+        data.colliders.shrink_if_sparse();
+        self.mesh_colliders.shrink_if_sparse();
+    }
+}
+
+/// Colliders sized from the bounds of their static meshes
+#[derive(Default)]
+struct MeshColliders {
+    /// Static meshes with a default collider until their mesh loads
+    pending: EntityMap<Arc<Resource>>,
+    /// Offset of each collider's center from its entity's location, for meshes which aren't
+    /// centered on their origin
+    center_offsets: EntityMap<Vec3>,
+}
+
+impl MeshColliders {
+    fn insert(&mut self, entity_id: EntityId, resource: Arc<Resource>) {
+        self.pending.insert(entity_id, resource);
+    }
+
+    fn remove(&mut self, entity_id: EntityId) {
+        self.pending.try_remove(entity_id);
+        self.center_offsets.try_remove(entity_id);
+    }
+
+    fn update_entity_id(&mut self, old_id: EntityId, new_id: EntityId) {
+        if let Some(resource) = self.pending.try_remove(old_id) {
+            self.pending.insert(new_id, resource);
+        }
+        if let Some(offset) = self.center_offsets.try_remove(old_id) {
+            self.center_offsets.insert(new_id, offset);
+        }
+    }
+
+    fn shrink_if_sparse(&mut self) {
+        self.pending.shrink_if_sparse();
+        self.center_offsets.shrink_if_sparse();
+    }
+
+    fn set_location(&self, data: &mut DataSingle, entity_id: EntityId, location: &Vec3) {
+        if let Some(collider) = data.colliders.get_mut(entity_id) {
+            let offset = self.center_offsets.get(entity_id).copied();
+            collider.center = location + offset.unwrap_or_else(Vec3::zeros);
+        }
+    }
+
+    /// Replaces default colliders with boxes bounding their meshes, once the meshes have loaded
+    fn update(&mut self, data: &mut DataSingle) {
+        let mut loaded = Vec::new();
+
+        for (entity_id, resource) in &self.pending {
+            match resource.try_mesh() {
+                Some(Ok(mesh)) => loaded.push((*entity_id, Some(mesh))),
+                // the default collider is kept for meshes which fail to load
+                Some(Err(_)) => loaded.push((*entity_id, None)),
+                None => {}
+            }
+        }
+
+        for (entity_id, mesh) in loaded {
+            self.pending.remove(entity_id);
+
+            let (offset, collider) = match mesh.as_deref().and_then(mesh_collider) {
+                Some(mesh_collider) => mesh_collider,
+                None => continue,
+            };
+
+            if let Some(old_collider) = data.colliders.get_mut(entity_id) {
+                let location = old_collider.center;
+                *old_collider = Collider {
+                    center: location + offset,
+                    visible: old_collider.visible,
+                    ..collider
+                };
+
+                self.center_offsets.insert(entity_id, offset);
+            }
+        }
+    }
+}
+
+/// A box bounding the mesh, along with the offset of its center from the mesh origin
+fn mesh_collider(mesh: &Mesh) -> Option<(Vec3, Collider)> {
+    let (min, max) = mesh.bounds()?;
+    let offset = (min + max) * 0.5;
+    Some((offset, Collider::aabb(Vec3::zeros(), (max - min) * 0.5)))
 }
 
 pub struct FixedData {
@@ -27,3 +177,157 @@ impl FixedData {
 
     pub async fn update(&mut self) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, future::Future, num::NonZeroUsize, process};
+
+    use event::EventManager;
+    use frame_buffer::{FrameBufferManager, SpawnedStaticMesh};
+    use game_resources::ResourceManager;
+    use nalgebra_glm::vec3;
+    use system_interfaces::physics::Shape;
+    use task_executor::{async_task::yield_now, ExecutorOptions, TaskExecutor};
+
+    use super::*;
+
+    const TRIANGLE_OBJ: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1\n";
+
+    /// Writes a triangle mesh to a file unique to the test, returning its path
+    fn triangle_mesh_path(test_name: &str) -> String {
+        let path = env::temp_dir().join(format!("{test_name}-{}.obj", process::id()));
+        fs::write(&path, TRIANGLE_OBJ).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn execute_blocking(future: impl Future<Output = ()> + Send) {
+        let options = ExecutorOptions {
+            pin_threads: false,
+            ..Default::default()
+        };
+
+        let mut executor =
+            TaskExecutor::with_options(options, NonZeroUsize::new(1).unwrap(), &|_| {});
+
+        let mut future = Box::pin(future);
+        executor.execute_blocking(future.as_mut()).unwrap();
+    }
+
+    fn managers() -> (EventManager, FrameBufferManager) {
+        let thread_count = NonZeroUsize::new(1).unwrap();
+        (
+            EventManager::new(thread_count),
+            FrameBufferManager::new(thread_count),
+        )
+    }
+
+    async fn collider(shared_data: &SharedData, entity_id: EntityId) -> Option<Collider> {
+        shared_data
+            .read_single()
+            .await
+            .colliders
+            .get(entity_id)
+            .copied()
+    }
+
+    #[test]
+    fn loaded_mesh_gives_box_collider_at_location() {
+        let path = triangle_mesh_path("loaded_mesh_gives_box_collider_at_location");
+        let resource = ResourceManager::default().resource(path.clone());
+        let entity_id = EntityId::new(1);
+
+        execute_blocking(async {
+            let (mut event_manager, mut frame_buffer_manager) = managers();
+            event_manager.assign_thread_event_buffer(0);
+            frame_buffer_manager.assign_thread_frame_buffer(0);
+
+            let shared_data = shared_data();
+            let mut frame_data = FrameData::new(shared_data.clone());
+
+            let mut event_delegate = event_manager.sync_delegate();
+            event_delegate.push_game_event(GameEvent::Spawn {
+                entity_id,
+                replicate: false,
+            });
+            event_delegate.push_game_event(GameEvent::StaticMeshLocation(
+                entity_id,
+                vec3(2.0, 0.0, 0.0),
+            ));
+            frame_buffer_manager
+                .sync_delegate()
+                .spawn_static_mesh(SpawnedStaticMesh {
+                    entity_id,
+                    resource: resource.clone(),
+                });
+
+            // the default collider is used until the mesh loads
+            while resource.loaded_mesh().is_none() {
+                frame_data
+                    .update(
+                        &event_manager.async_delegate(),
+                        &frame_buffer_manager.async_delegate(),
+                    )
+                    .await;
+                event_manager.swap();
+                frame_buffer_manager.swap();
+                yield_now().await;
+            }
+
+            frame_data
+                .update(
+                    &event_manager.async_delegate(),
+                    &frame_buffer_manager.async_delegate(),
+                )
+                .await;
+
+            let collider = collider(&shared_data, entity_id).await.unwrap();
+            assert_eq!(collider.center, vec3(2.5, 0.5, 0.0));
+            match collider.shape {
+                Shape::Box { half_extents } => assert_eq!(half_extents, vec3(0.5, 0.5, 0.0)),
+                Shape::Sphere { .. } => panic!("expected a box collider"),
+            }
+        });
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn replicated_location_moves_collider() {
+        let entity_id = EntityId::new(1);
+
+        execute_blocking(async {
+            let (mut event_manager, mut frame_buffer_manager) = managers();
+            event_manager.assign_thread_event_buffer(0);
+            frame_buffer_manager.assign_thread_frame_buffer(0);
+
+            let shared_data = shared_data();
+            let mut frame_data = FrameData::new(shared_data.clone());
+
+            event_manager
+                .sync_delegate()
+                .push_game_event(GameEvent::Spawn {
+                    entity_id,
+                    replicate: false,
+                });
+
+            let event_delegate = event_manager.async_delegate();
+            frame_data
+                .update(&event_delegate, &frame_buffer_manager.async_delegate())
+                .await;
+            event_delegate.push_frame_event(FrameEvent::Location(entity_id, vec3(0.0, 0.0, 3.0)));
+
+            // frame events are read in the following frame
+            event_manager.swap();
+            frame_buffer_manager.swap();
+            frame_data
+                .update(
+                    &event_manager.async_delegate(),
+                    &frame_buffer_manager.async_delegate(),
+                )
+                .await;
+
+            let collider = collider(&shared_data, entity_id).await.unwrap();
+            assert_eq!(collider.center, vec3(0.0, 0.0, 3.0));
+        });
+    }
+}
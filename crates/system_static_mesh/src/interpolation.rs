@@ -0,0 +1,149 @@
+use std::{collections::VecDeque, time::Instant};
+
+use nalgebra_glm::{lerp, Vec3};
+
+/// Samples older than the rendered time are discarded, so this only bounds bursts of updates
+const MAX_SAMPLES: usize = 8;
+
+/// Recent remote locations of an entity, timestamped on arrival
+#[derive(Default)]
+pub struct LocationHistory {
+    samples: VecDeque<(Instant, Vec3)>,
+    /// The latest sample has been rendered, so the location will not change until the next push
+    settled: bool,
+}
+
+impl LocationHistory {
+    /// Adds a sample which arrived at `time`, while the location at `render_time` is rendered.
+    /// Samples older than the latest sample are stale and ignored. Samples received in the same
+    /// fixed update share a time and are pushed in arrival order, so a sample at the time of the
+    /// latest sample replaces it.
+    pub fn push(&mut self, time: Instant, location: Vec3, render_time: Instant) {
+        let latest = match self.samples.back_mut() {
+            Some((latest_time, _)) if time < *latest_time => return,
+            Some((latest_time, latest_location)) if time == *latest_time => {
+                *latest_location = location;
+                self.settled = false;
+                return;
+            }
+            Some(latest) => Some(*latest),
+            None => None,
+        };
+
+        // after a pause in updates, blend from the rendered location instead of from when the
+        // previous sample arrived
+        if let Some((latest_time, latest_location)) = latest {
+            if latest_time < render_time && render_time < time {
+                self.samples.clear();
+                self.samples.push_back((render_time, latest_location));
+            }
+        }
+
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back((time, location));
+        self.settled = false;
+    }
+
+    /// Location at the given time, blending between the surrounding samples. Times outside of
+    /// the history are clamped to the first or latest sample.
+    pub fn sample(&mut self, time: Instant) -> Option<Vec3> {
+        // samples are only needed until the following sample is reached
+        while matches!(self.samples.get(1), Some((next_time, _)) if *next_time <= time) {
+            self.samples.pop_front();
+        }
+
+        let (from_time, from) = *self.samples.front()?;
+
+        match self.samples.get(1) {
+            Some((to_time, to)) if time > from_time => {
+                let t = (time - from_time).as_secs_f32() / (*to_time - from_time).as_secs_f32();
+                Some(lerp(&from, to, t))
+            }
+            Some(_) => Some(from),
+            None => {
+                self.settled = true;
+                Some(from)
+            }
+        }
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.settled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use nalgebra_glm::vec3;
+
+    use super::*;
+
+    #[test]
+    fn blends_between_samples() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        let mut history = LocationHistory::default();
+        history.push(at(0), vec3(0.0, 0.0, 0.0), start);
+        history.push(at(100), vec3(4.0, 0.0, 0.0), start);
+
+        assert_eq!(history.sample(at(0)), Some(vec3(0.0, 0.0, 0.0)));
+        assert_eq!(history.sample(at(25)), Some(vec3(1.0, 0.0, 0.0)));
+        assert!(!history.is_settled());
+
+        assert_eq!(history.sample(at(200)), Some(vec3(4.0, 0.0, 0.0)));
+        assert!(history.is_settled());
+    }
+
+    #[test]
+    fn ignores_stale_samples() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        let mut history = LocationHistory::default();
+        history.push(at(100), vec3(1.0, 0.0, 0.0), start);
+        history.push(at(50), vec3(2.0, 0.0, 0.0), start);
+
+        assert_eq!(history.sample(at(200)), Some(vec3(1.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn same_update_samples_keep_the_latest() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        let mut history = LocationHistory::default();
+        history.push(at(0), vec3(0.0, 0.0, 0.0), start);
+        assert_eq!(history.sample(at(100)), Some(vec3(0.0, 0.0, 0.0)));
+        assert!(history.is_settled());
+
+        // two packets for the entity arrive in the same fixed update
+        history.push(at(100), vec3(1.0, 0.0, 0.0), at(50));
+        history.push(at(100), vec3(3.0, 0.0, 0.0), at(50));
+        assert!(!history.is_settled());
+
+        assert_eq!(history.sample(at(200)), Some(vec3(3.0, 0.0, 0.0)));
+        assert!(history.is_settled());
+    }
+
+    #[test]
+    fn blends_from_rendered_location_after_pause() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        let mut history = LocationHistory::default();
+        history.push(at(0), vec3(0.0, 0.0, 0.0), start);
+        assert_eq!(history.sample(at(500)), Some(vec3(0.0, 0.0, 0.0)));
+
+        // the next sample arrives long after the previous one, while 500ms is rendered
+        history.push(at(1000), vec3(10.0, 0.0, 0.0), at(500));
+
+        assert_eq!(history.sample(at(500)), Some(vec3(0.0, 0.0, 0.0)));
+        assert_eq!(history.sample(at(750)), Some(vec3(5.0, 0.0, 0.0)));
+    }
+}
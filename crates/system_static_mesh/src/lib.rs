@@ -1,23 +1,38 @@
-use event::{AsyncEventDelegate, GameEvent};
+use std::time::{Duration, Instant};
+
+use event::{AsyncEventDelegate, FrameEvent, GameEvent};
 use frame_buffer::AsyncFrameBufferDelegate;
 use game_data::system_swap_data::SystemSwapData;
-use game_entity::EntityMap;
+use game_entity::{EntityId, EntityMap};
 use nalgebra_glm::Vec3;
 use system_interfaces::static_mesh::Data as SharedData;
 use update_buffer::StaticMeshUpdateBufferRef;
 
+use self::interpolation::LocationHistory;
+
+mod interpolation;
+
+/// Remote updates arrive at the network poll interval, so rendering two intervals behind usually
+/// leaves a later sample to blend towards
+const DEFAULT_INTERPOLATION_DELAY: Duration = Duration::from_millis(100);
+
 pub fn shared_data() -> SharedData {
     Default::default()
 }
 
 #[derive(Default)]
 struct SwapData {
+    /// Locally modified locations, sent to the fixed update
     modified_entities: EntityMap<Vec3>,
+    /// Remotely modified locations and when they arrived, sent to the frame update
+    remote_locations: Vec<(EntityId, Instant, Vec3)>,
 }
 
 pub struct FrameData {
     shared_data: SharedData,
     swap_data: SystemSwapData<SwapData>,
+    remote_histories: EntityMap<LocationHistory>,
+    interpolation_delay: Duration,
 }
 
 impl FrameData {
@@ -25,9 +40,17 @@ impl FrameData {
         Self {
             shared_data,
             swap_data: Default::default(),
+            remote_histories: EntityMap::new(),
+            interpolation_delay: DEFAULT_INTERPOLATION_DELAY,
         }
     }
 
+    /// How far behind the latest remote update remote locations are rendered. Longer delays
+    /// smooth over late or dropped updates, while zero renders updates as they arrive.
+    pub fn set_interpolation_delay(&mut self, delay: Duration) {
+        self.interpolation_delay = delay;
+    }
+
     pub async fn update(
         &mut self,
         event_delegate: &AsyncEventDelegate<'_>,
@@ -39,41 +62,81 @@ impl FrameData {
 
         let frame_buffer_writer = frame_buffer.writer();
 
+        let now = Instant::now();
+        let render_time = now.checked_sub(self.interpolation_delay).unwrap_or(now);
+
         if let Some(swap_data) = self.swap_data.swapped() {
-            for (entity_id, modified_location) in &swap_data.modified_entities {
-                if let Some(location) = data.locations.get_mut(*entity_id) {
-                    *location = *modified_location;
-                    frame_buffer_writer.push_location(*entity_id, *modified_location);
+            for (entity_id, time, remote_location) in swap_data.remote_locations.drain(..) {
+                if let Some(location) = data.locations.get_mut(entity_id) {
+                    *location = remote_location;
+
+                    if self.remote_histories.get(entity_id).is_none() {
+                        self.remote_histories.insert(entity_id, Default::default());
+                    }
+
+                    self.remote_histories[entity_id].push(time, remote_location, render_time);
+                    event_delegate
+                        .push_frame_event(FrameEvent::Location(entity_id, remote_location));
                 }
             }
+        }
 
-            swap_data.modified_entities.clear();
+        for (entity_id, history) in &mut self.remote_histories {
+            if history.is_settled() {
+                continue;
+            }
+
+            if let Some(location) = history.sample(render_time) {
+                frame_buffer_writer.push_location(*entity_id, location);
+            }
         }
 
         for game_event in event_delegate.game_events() {
             match game_event {
                 GameEvent::Spawn { entity_id, .. } => {
                     data.locations.insert(*entity_id, Vec3::zeros());
+                    event_delegate.push_frame_event(FrameEvent::Spawned(*entity_id));
                 }
-                GameEvent::Despawn(entity_id) => {
-                    data.locations.remove(*entity_id);
+                // guests are despawned with the same event, but have no static mesh
+                GameEvent::Despawn(entity_id)
+                    if data.locations.try_remove(*entity_id).is_some() =>
+                {
+                    remove_history(&mut self.remote_histories, *entity_id);
+                    event_delegate.push_frame_event(FrameEvent::Despawned(*entity_id));
                 }
                 GameEvent::UpdateEntityId { old_id, new_id } => {
-                    let location = data.locations.remove(*old_id);
-                    data.locations.insert(*new_id, location);
+                    // the entity may not have been spawned in this system, e.g. if the id was
+                    // reassigned remotely before the spawn was processed
+                    if let Some(location) = data.locations.try_remove(*old_id) {
+                        data.locations.insert(*new_id, location);
+                    }
+                    remove_history(&mut self.remote_histories, *old_id);
                 }
                 GameEvent::StaticMeshLocation(entity_id, location) => {
-                    data.locations[*entity_id] = *location;
-                    self.swap_data
-                        .modified_entities
-                        .insert(*entity_id, *location);
+                    // the entity may have been despawned remotely earlier in the frame
+                    if let Some(data_location) = data.locations.get_mut(*entity_id) {
+                        // local changes take effect immediately
+                        remove_history(&mut self.remote_histories, *entity_id);
+
+                        *data_location = *location;
+                        self.swap_data
+                            .modified_entities
+                            .insert(*entity_id, *location);
+                    }
                 }
                 _ => {}
             }
         }
+
+        data.locations.shrink_if_sparse();
+        self.remote_histories.shrink_if_sparse();
     }
 }
 
+fn remove_history(remote_histories: &mut EntityMap<LocationHistory>, entity_id: EntityId) {
+    remote_histories.try_remove(entity_id);
+}
+
 #[derive(Default)]
 pub struct FixedData {
     swap_data: SystemSwapData<SwapData>,
@@ -96,10 +159,141 @@ impl FixedData {
 
         // update system from other changes
 
-        self.swap_data.modified_entities.extend(
+        let now = Instant::now();
+        self.swap_data.remote_locations.extend(
             update_buffer
                 .locations()
-                .map(|(entity_id, location)| (entity_id, *location)),
+                .map(|(entity_id, location)| (entity_id, now, *location)),
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use event::EventManager;
+    use frame_buffer::FrameBufferManager;
+    use task_executor::{ExecutorOptions, TaskExecutor};
+
+    use super::*;
+
+    struct Harness {
+        executor: TaskExecutor,
+        event_manager: EventManager,
+        frame_buffer_manager: FrameBufferManager,
+        frame_data: FrameData,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let options = ExecutorOptions {
+                pin_threads: false,
+                ..Default::default()
+            };
+
+            let thread_count = NonZeroUsize::new(1).unwrap();
+
+            Self {
+                executor: TaskExecutor::with_options(options, thread_count, &|_| {}),
+                event_manager: EventManager::new(thread_count),
+                frame_buffer_manager: FrameBufferManager::new(thread_count),
+                frame_data: FrameData::new(shared_data()),
+            }
+        }
+
+        /// Updates a frame, returning the frame events which systems see in the next frame
+        fn frame(&mut self, game_events: &[GameEvent]) -> Vec<FrameEvent> {
+            let mut event_delegate = self.event_manager.sync_delegate();
+            for game_event in game_events {
+                event_delegate.push_game_event(*game_event);
+            }
+
+            {
+                let event_manager = &mut self.event_manager;
+                let frame_buffer_manager = &mut self.frame_buffer_manager;
+                let frame_data = &mut self.frame_data;
+
+                let mut update = Box::pin(async move {
+                    event_manager.assign_thread_event_buffer(0);
+                    frame_buffer_manager.assign_thread_frame_buffer(0);
+
+                    frame_data
+                        .update(
+                            &event_manager.async_delegate(),
+                            &frame_buffer_manager.async_delegate(),
+                        )
+                        .await;
+                });
+
+                self.executor.execute_blocking(update.as_mut()).unwrap();
+            }
+
+            self.event_manager.swap();
+            self.frame_buffer_manager.swap();
+
+            self.event_manager
+                .async_delegate()
+                .frame_events()
+                .copied()
+                .collect()
+        }
+    }
+
+    fn spawn(entity_id: u32) -> GameEvent {
+        GameEvent::Spawn {
+            entity_id: EntityId::new(entity_id),
+            replicate: false,
+        }
+    }
+
+    #[test]
+    fn spawn_is_seen_as_frame_event_next_frame() {
+        let mut harness = Harness::new();
+
+        let frame_events = harness.frame(&[spawn(1)]);
+        assert_eq!(frame_events.len(), 1);
+        assert!(matches!(
+            frame_events[0],
+            FrameEvent::Spawned(entity_id) if entity_id.get() == 1
+        ));
+
+        assert!(harness.frame(&[]).is_empty());
+    }
+
+    #[test]
+    fn unknown_entities_are_ignored() {
+        let mut harness = Harness::new();
+        let location = Vec3::new(1.0, 0.0, 2.0);
+
+        harness.frame(&[
+            GameEvent::UpdateEntityId {
+                old_id: EntityId::new(5),
+                new_id: EntityId::new(6),
+            },
+            GameEvent::StaticMeshLocation(EntityId::new(7), location),
+        ]);
+
+        let data = harness.frame_data.shared_data.try_read_single().unwrap();
+        assert!(data.locations.get(EntityId::new(6)).is_none());
+        assert!(data.locations.get(EntityId::new(7)).is_none());
+        assert!(harness.frame_data.swap_data.modified_entities.is_empty());
+    }
+
+    #[test]
+    fn updated_entity_id_keeps_location() {
+        let mut harness = Harness::new();
+        let (old_id, new_id) = (EntityId::new(1), EntityId::new(2));
+        let location = Vec3::new(1.0, 0.0, 2.0);
+
+        harness.frame(&[
+            spawn(1),
+            GameEvent::StaticMeshLocation(old_id, location),
+            GameEvent::UpdateEntityId { old_id, new_id },
+        ]);
+
+        let data = harness.frame_data.shared_data.try_read_single().unwrap();
+        assert!(data.locations.get(old_id).is_none());
+        assert_eq!(data.locations.get(new_id), Some(&location));
+    }
+}
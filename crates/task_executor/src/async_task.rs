@@ -1,23 +1,39 @@
 use std::{
     cell::UnsafeCell,
     future::Future,
-    mem::{self, MaybeUninit},
+    mem::{self, ManuallyDrop, MaybeUninit},
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
     pin::Pin,
-    sync::{atomic::Ordering, Arc},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    thread,
+    time::{Duration, Instant},
 };
 
-use crate::{AtomicUsize, ChannelMessage, Task, TASK_SENDER};
+use crate::{
+    current_task,
+    queue::{in_flight_limit, push_task, push_task_at},
+    AtomicUsize, Task,
+};
 
+/// Dropping the handle before the task finishes detaches the task, which then runs to completion
+/// without a way to retrieve its result
 pub struct AsyncTaskHandle<T> {
-    _future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
-    join_handle: Pin<Box<AtomicUsize>>,
-    _task: Pin<Box<Task>>,
-    result: Arc<TaskResultWrapper<T>>,
+    // the executor references these until the task finishes, so they are leaked if it hasn't
+    future: ManuallyDrop<Pin<Box<dyn Future<Output = ()> + Send + 'static>>>,
+    join_handle: ManuallyDrop<Pin<Box<AtomicUsize>>>,
+    task: ManuallyDrop<Pin<Box<Task>>>,
+    result: Arc<TaskResultWrapper<thread::Result<T>>>,
 }
 
 #[derive(Debug)]
-struct TaskResultWrapper<T> {
+pub(crate) struct TaskResultWrapper<T> {
     inner: UnsafeCell<MaybeUninit<T>>,
+    completed: AtomicBool,
 }
 
 unsafe impl<T> Sync for TaskResultWrapper<T> where T: Send {}
@@ -26,24 +42,148 @@ impl<T> Default for TaskResultWrapper<T> {
     fn default() -> Self {
         Self {
             inner: UnsafeCell::new(MaybeUninit::uninit()),
+            completed: AtomicBool::new(false),
         }
     }
 }
 
+impl<T> TaskResultWrapper<T> {
+    /// SAFETY: must be called at most once, by the task producing the result
+    pub(crate) unsafe fn set(&self, value: T) {
+        self.inner.get().as_mut().unwrap_unchecked().write(value);
+        self.completed.store(true, Ordering::Release);
+    }
+
+    /// Takes the result if it has been set and not yet taken
+    pub(crate) fn take(&self) -> Option<T> {
+        self.completed
+            .compare_exchange(true, false, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| unsafe {
+                self.inner
+                    .get()
+                    .as_ref()
+                    .unwrap_unchecked()
+                    .assume_init_read()
+            })
+    }
+}
+
 impl<T> AsyncTaskHandle<T> {
-    pub fn result(self) -> Result<T, Self> {
-        if self.join_handle.load(Ordering::Acquire) & 1 == 1 {
-            match Arc::try_unwrap(self.result) {
-                Ok(result) => unsafe { Ok(result.inner.into_inner().assume_init()) },
-                Err(_) => panic!(),
-            }
+    pub fn is_finished(&self) -> bool {
+        self.join_handle.load(Ordering::Acquire) & 1 == 1
+    }
+
+    /// Takes the result if the task has finished. Returns None on later calls once taken. The
+    /// result is the panic payload if the task panicked.
+    pub fn try_take_result(&mut self) -> Option<thread::Result<T>> {
+        if self.is_finished() {
+            self.result.take()
         } else {
-            Err(self)
+            None
+        }
+    }
+
+    /// Returns the handle if the task has not finished, or its result was already taken
+    pub fn result(mut self) -> Result<thread::Result<T>, Self> {
+        match self.try_take_result() {
+            Some(result) => Ok(result),
+            None => Err(self),
+        }
+    }
+}
+
+impl<T> Drop for AsyncTaskHandle<T> {
+    fn drop(&mut self) {
+        if self.is_finished() {
+            // SAFETY: the executor no longer references the task, and these are never used again
+            unsafe {
+                ManuallyDrop::drop(&mut self.task);
+                ManuallyDrop::drop(&mut self.future);
+                ManuallyDrop::drop(&mut self.join_handle);
+            }
         }
     }
 }
 
+/// Counts the async tasks started on an executor, limiting those accepted by `try_execute_async`
+#[derive(Default)]
+pub(crate) struct InFlightLimit {
+    /// Tasks which have been started and not yet completed
+    count: AtomicUsize,
+    /// 0 if unlimited
+    max: AtomicUsize,
+}
+
+impl InFlightLimit {
+    pub(crate) fn new(max: Option<NonZeroUsize>) -> Self {
+        let limit = Self::default();
+        limit.set_max(max);
+        limit
+    }
+
+    pub(crate) fn set_max(&self, max: Option<NonZeroUsize>) {
+        self.max
+            .store(max.map_or(0, NonZeroUsize::get), Ordering::Relaxed);
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+/// Decrements the in-flight count when the task completes
+struct InFlightGuard(Arc<InFlightLimit>);
+
+impl InFlightGuard {
+    fn new(limit: Arc<InFlightLimit>) -> Self {
+        limit.count.fetch_add(1, Ordering::Relaxed);
+        Self(limit)
+    }
+
+    /// Returns None if the maximum in-flight count would be exceeded
+    fn try_new(limit: Arc<InFlightLimit>) -> Option<Self> {
+        let max = limit.max.load(Ordering::Relaxed);
+
+        limit
+            .count
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                (max == 0 || count < max).then(|| count + 1)
+            })
+            .ok()
+            .map(|_| Self(limit))
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Starts execution of the task, regardless of the maximum in-flight count
 pub fn execute_async<F, T>(future: F) -> AsyncTaskHandle<T>
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    start(future, InFlightGuard::new(in_flight_limit()))
+}
+
+/// Starts execution of the task, unless the executor's maximum in-flight count has been reached,
+/// in which case the future is returned so that the caller may retry later.
+pub fn try_execute_async<F, T>(future: F) -> Result<AsyncTaskHandle<T>, F>
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    match InFlightGuard::try_new(in_flight_limit()) {
+        Some(guard) => Ok(start(future, guard)),
+        None => Err(future),
+    }
+}
+
+fn start<F, T>(future: F, guard: InFlightGuard) -> AsyncTaskHandle<T>
 where
     F: Future<Output = T> + Send + 'static,
     T: Send + 'static,
@@ -52,15 +192,9 @@ where
     let mut future = {
         let result = result.clone();
         Box::pin(async move {
-            let awaited = future.await;
-            unsafe {
-                result
-                    .inner
-                    .get()
-                    .as_mut()
-                    .unwrap_unchecked()
-                    .write(awaited);
-            }
+            let awaited = CatchUnwind(future).await;
+            unsafe { result.set(awaited) };
+            drop(guard);
         })
     };
 
@@ -79,18 +213,213 @@ where
     let task_ref: Pin<&'static Task> = unsafe { mem::transmute(task_ref) };
 
     // begin execution of task
-    TASK_SENDER.with(|sender| {
-        // must be called from an executor thread
-        debug_assert!(!sender.get().is_null());
-
-        let sender = unsafe { sender.get().as_ref().unwrap_unchecked() };
-        sender.send(ChannelMessage::Task(task_ref)).unwrap();
-    });
+    push_task(task_ref);
 
     AsyncTaskHandle {
-        _future: future,
-        join_handle,
-        _task: task,
+        future: ManuallyDrop::new(future),
+        join_handle: ManuallyDrop::new(join_handle),
+        task: ManuallyDrop::new(task),
         result,
     }
 }
+
+/// Completes with the output of the inner future, or the payload if polling it panicked
+struct CatchUnwind<F>(F);
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = thread::Result<F::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: the inner future is pinned along with the wrapper, and never moved out of it
+        let future = unsafe { self.map_unchecked_mut(|this| &mut this.0) };
+
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(poll) => poll.map(Ok),
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+/// Returns Pending once, requeueing the task so that other tasks may run before it resumes
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    /// Must be polled from an executor task. Panics otherwise.
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.yielded {
+            Poll::Ready(())
+        } else {
+            self.yielded = true;
+            push_task(current_task(cx));
+            Poll::Pending
+        }
+    }
+}
+
+/// Completes once the duration has elapsed, without blocking an executor thread
+pub fn delay(duration: Duration) -> Timer {
+    Timer::at(Instant::now() + duration)
+}
+
+pub struct Timer {
+    deadline: Instant,
+    registered: bool,
+}
+
+impl Timer {
+    /// Completes once the deadline has passed
+    pub fn at(deadline: Instant) -> Self {
+        Self {
+            deadline,
+            registered: false,
+        }
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl Future for Timer {
+    type Output = ();
+
+    /// Must be polled from an executor task, which is requeued once the deadline passes. Panics
+    /// otherwise.
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if Instant::now() >= self.deadline {
+            Poll::Ready(())
+        } else {
+            if !self.registered {
+                self.registered = true;
+                push_task_at(self.deadline, current_task(cx));
+            }
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{executor, poll_foreign};
+
+    use super::*;
+
+    #[test]
+    fn yielded_task_is_polled_again() {
+        let mut executor = executor(1, &|_| {});
+
+        let polls = AtomicUsize::new(0);
+        let mut task = Box::pin(async {
+            for _ in 0..3 {
+                polls.fetch_add(1, Ordering::Relaxed);
+                yield_now().await;
+            }
+        });
+
+        assert!(executor.execute_blocking(task.as_mut()).is_ok());
+        assert_eq!(polls.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn delayed_task_waits_for_deadline() {
+        let mut executor = executor(1, &|_| {});
+        let duration = Duration::from_millis(50);
+
+        let mut task = Box::pin(async {
+            let task_handle = execute_async(delay(duration));
+            let deadline = Instant::now() + duration;
+
+            while !task_handle.is_finished() {
+                assert!(Instant::now() < deadline + duration);
+                yield_now().await;
+            }
+
+            assert!(Instant::now() >= deadline);
+        });
+
+        assert!(executor.execute_blocking(task.as_mut()).is_ok());
+    }
+
+    #[test]
+    fn panicked_task_returns_payload() {
+        let mut executor = executor(1, &|_| {});
+
+        let mut task = Box::pin(async {
+            let mut task_handle = execute_async(async { panic!("load failed") });
+
+            let result = loop {
+                match task_handle.try_take_result() {
+                    Some(result) => break result,
+                    None => yield_now().await,
+                }
+            };
+
+            let payload = result.err().unwrap();
+            assert_eq!(payload.downcast_ref::<&str>(), Some(&"load failed"));
+            assert!(task_handle.try_take_result().is_none());
+        });
+
+        assert!(executor.execute_blocking(task.as_mut()).is_ok());
+    }
+
+    #[test]
+    fn task_past_cap_is_deferred_until_another_completes() {
+        let mut executor = executor(1, &|_| {});
+        executor.set_max_in_flight(NonZeroUsize::new(1));
+
+        let mut task = Box::pin(async {
+            let release = Arc::new(AtomicBool::new(false));
+            let first_handle = {
+                let release = release.clone();
+                try_execute_async(async move {
+                    while !release.load(Ordering::Acquire) {
+                        yield_now().await;
+                    }
+                })
+            }
+            .ok()
+            .unwrap();
+
+            let deferred = match try_execute_async(async { 2 }) {
+                Ok(_) => panic!("task past the cap was started"),
+                Err(deferred) => deferred,
+            };
+
+            release.store(true, Ordering::Release);
+            while !first_handle.is_finished() {
+                yield_now().await;
+            }
+
+            let mut task_handle = try_execute_async(deferred).ok().unwrap();
+            loop {
+                match task_handle.try_take_result() {
+                    Some(result) => break assert_eq!(result.unwrap(), 2),
+                    None => yield_now().await,
+                }
+            }
+        });
+
+        assert!(executor.execute_blocking(task.as_mut()).is_ok());
+        assert_eq!(executor.in_flight_count(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be polled from an executor task")]
+    fn yield_rejects_foreign_executor() {
+        let _ = poll_foreign(Box::pin(yield_now()).as_mut());
+    }
+
+    #[test]
+    #[should_panic(expected = "must be polled from an executor task")]
+    fn delay_rejects_foreign_executor() {
+        let _ = poll_foreign(Box::pin(delay(Duration::from_secs(1))).as_mut());
+    }
+}
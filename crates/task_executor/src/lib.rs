@@ -3,17 +3,19 @@
 #![feature(waker_getters)]
 
 use std::{
-    cell::{Cell, UnsafeCell},
+    any::Any,
+    cell::UnsafeCell,
+    error::Error,
+    fmt,
     future::Future,
     marker::PhantomPinned,
     mem,
     num::NonZeroUsize,
-    panic,
+    panic::{self, AssertUnwindSafe},
     pin::Pin,
     process, ptr,
     sync::{
         atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
-        mpsc::{self, Sender},
         Arc, Condvar, Mutex,
     },
     task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
@@ -22,7 +24,13 @@ use std::{
 
 use core_affinity::{get_core_ids, CoreId};
 
+use crate::{
+    async_task::TaskResultWrapper,
+    queue::{push_task, ChannelMessage, LocalQueue, SharedQueues},
+};
+
 pub mod async_task;
+mod queue;
 pub mod task;
 
 /// Stack-pins a value and extends the reference lifetime to 'static.
@@ -57,35 +65,107 @@ macro_rules! pin_array_unsafe {
 
 pub(crate) use pin_array_unsafe;
 
-enum ChannelMessage {
-    Task(Pin<&'static Task>),
-    Join,
-}
-
-thread_local! {
-    static TASK_SENDER: Cell<*const Sender<ChannelMessage>> = Cell::new(ptr::null())
-}
-
 #[derive(Default)]
 struct BlockingTaskInfo {
     task: AtomicPtr<Task>,
     cvar: Condvar,
     completed: Mutex<bool>,
+    /// The first panic caught on an executor thread since the last blocking task returned
+    panic: Mutex<Option<TaskPanic>>,
+}
+
+impl BlockingTaskInfo {
+    /// Wakes the blocking caller if the completed task is the one it is waiting on
+    fn task_completed(&self, task: *const Task) {
+        if task == self.task.load(Ordering::Acquire) {
+            *self.completed.lock().unwrap() = true;
+            self.cvar.notify_one();
+        }
+    }
+}
+
+/// A panic caught in an executor task, returned from `TaskExecutor::execute_blocking`
+#[derive(Debug)]
+pub struct TaskPanic {
+    message: String,
 }
 
+impl TaskPanic {
+    fn new(payload: Box<dyn Any + Send>) -> Self {
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "unknown panic payload".to_string()
+        };
+
+        Self { message }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for TaskPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "executor task panicked: {}", self.message)
+    }
+}
+
+impl Error for TaskPanic {}
+
 pub struct TaskExecutor {
-    task_sender: Sender<ChannelMessage>,
+    queues: Arc<SharedQueues>,
     blocking_task_info: Arc<BlockingTaskInfo>,
     thread_join_handles: Vec<JoinHandle<()>>,
 }
 
+#[derive(Clone, Copy)]
+pub struct ExecutorOptions<'a> {
+    /// Executor threads are named "{prefix}-{thread_index}"
+    pub thread_name_prefix: &'a str,
+    /// Pin each executor thread to a core. Some profilers behave badly with pinned threads.
+    pub pin_threads: bool,
+    /// Exit the process as soon as any thread panics, rather than returning the panic from
+    /// `execute_blocking`. Skips all cleanup, so is best reserved for release builds.
+    pub exit_on_panic: bool,
+    /// Maximum number of async tasks `try_execute_async` lets run at once, or None if unlimited
+    pub max_in_flight: Option<NonZeroUsize>,
+    /// Cores to pin threads to, in thread order. Returns None if core affinity is unavailable.
+    pub core_ids: fn() -> Option<Vec<CoreId>>,
+}
+
+impl Default for ExecutorOptions<'_> {
+    fn default() -> Self {
+        Self {
+            thread_name_prefix: "worker",
+            pin_threads: true,
+            exit_on_panic: false,
+            max_in_flight: None,
+            core_ids: get_core_ids,
+        }
+    }
+}
+
 impl TaskExecutor {
     pub fn new(thread_count: NonZeroUsize, register_thread: &(dyn Fn(usize) + Sync)) -> Self {
-        let default_hook = panic::take_hook();
-        panic::set_hook(Box::new(move |err| {
-            default_hook(err);
-            process::exit(1);
-        }));
+        Self::with_options(Default::default(), thread_count, register_thread)
+    }
+
+    pub fn with_options(
+        options: ExecutorOptions,
+        thread_count: NonZeroUsize,
+        register_thread: &(dyn Fn(usize) + Sync),
+    ) -> Self {
+        if options.exit_on_panic {
+            let default_hook = panic::take_hook();
+            panic::set_hook(Box::new(move |err| {
+                default_hook(err);
+                process::exit(1);
+            }));
+        }
 
         // SAFETY: we do not return until all the threads have run the registration callback
         let register_thread =
@@ -100,32 +180,42 @@ impl TaskExecutor {
         let thread_init = Arc::new(ThreadInfo {
             init_count: Mutex::new(0),
             cvar: Condvar::new(),
-            core_ids: get_core_ids().unwrap(),
+            core_ids: if options.pin_threads {
+                (options.core_ids)().unwrap_or_default()
+            } else {
+                Vec::new()
+            },
         });
 
-        if thread_count.get() > thread_init.core_ids.len() {
+        if !options.pin_threads {
+            log::info!("executor thread pinning disabled");
+        } else if thread_init.core_ids.is_empty() {
+            log::warn!("core affinity unavailable, executor threads will not be pinned");
+        } else if thread_count.get() > thread_init.core_ids.len() {
             log::warn!(
                 "thread count ({}) > available thread core ids ({})",
-                thread_init.core_ids.len(),
                 thread_count.get(),
+                thread_init.core_ids.len(),
             );
         }
 
-        let (task_sender, task_receiver) = mpsc::channel();
-        let task_receiver = Arc::new(Mutex::new(task_receiver));
+        let (queues, workers) = SharedQueues::new(thread_count.get(), options.max_in_flight);
+        let queues = Arc::new(queues);
 
         let blocking_task_info = Arc::new(BlockingTaskInfo::default());
 
         let mut thread_join_handles = Vec::with_capacity(thread_count.get());
 
-        for thread_index in 0..thread_count.get() {
+        for (thread_index, worker) in workers.into_iter().enumerate() {
             let thread_init = thread_init.clone();
 
-            let task_sender = task_sender.clone();
-            let task_receiver = task_receiver.clone();
+            let queues = queues.clone();
             let blocking_task_info = blocking_task_info.clone();
 
-            thread_join_handles.push(thread::spawn(move || {
+            let thread_builder = thread::Builder::new()
+                .name(format!("{}-{thread_index}", options.thread_name_prefix));
+
+            let join_handle = thread_builder.spawn(move || {
                 if let Some(id) = thread_init.core_ids.get(thread_index) {
                     core_affinity::set_for_current(*id);
                 }
@@ -135,11 +225,11 @@ impl TaskExecutor {
                 thread_init.cvar.notify_one();
                 drop(thread_init);
 
-                TASK_SENDER.with(|sender| sender.set(&task_sender));
+                let local_queue = LocalQueue::new(worker, &queues);
+                local_queue.register();
 
                 loop {
-                    let task = task_receiver.lock().unwrap().recv().unwrap();
-                    match task {
+                    match local_queue.next_message() {
                         ChannelMessage::Task(task) => {
                             let ptr = &*task as *const _;
 
@@ -149,18 +239,19 @@ impl TaskExecutor {
                             let mut context = Context::from_waker(&waker);
 
                             match task.poll_future(&mut context) {
-                                TaskStatus::Ready => {
-                                    if ptr == blocking_task_info.task.load(Ordering::Acquire) {
-                                        *blocking_task_info.completed.lock().unwrap() = true;
-                                        blocking_task_info.cvar.notify_one();
-                                    }
+                                TaskStatus::Ready => blocking_task_info.task_completed(ptr),
+                                TaskStatus::Panicked(payload) => {
+                                    blocking_task_info
+                                        .panic
+                                        .lock()
+                                        .unwrap()
+                                        .get_or_insert_with(|| TaskPanic::new(payload));
+
+                                    blocking_task_info.task_completed(ptr);
                                 }
                                 TaskStatus::UnableToPoll => {
-                                    // reinstert task to queue
-                                    TASK_SENDER.with(|sender| unsafe {
-                                        let sender = sender.get().as_ref().unwrap_unchecked();
-                                        sender.send(ChannelMessage::Task(task)).unwrap();
-                                    });
+                                    // reinsert task to queue
+                                    push_task(task);
                                 }
                                 TaskStatus::Pending => {}
                             }
@@ -168,7 +259,9 @@ impl TaskExecutor {
                         ChannelMessage::Join => break,
                     }
                 }
-            }));
+            });
+
+            thread_join_handles.push(join_handle.expect("failed to spawn executor thread"));
         }
 
         let _init_guard = thread_init
@@ -179,17 +272,33 @@ impl TaskExecutor {
             .unwrap();
 
         Self {
-            task_sender,
+            queues,
             blocking_task_info,
             thread_join_handles,
         }
     }
 
+    /// Limits the number of async tasks `try_execute_async` lets run at once. Tasks already in
+    /// flight are unaffected.
+    pub fn set_max_in_flight(&self, max_in_flight: Option<NonZeroUsize>) {
+        self.queues.in_flight.set_max(max_in_flight);
+    }
+
+    /// Number of async tasks started on the executor which have not yet completed
+    pub fn in_flight_count(&self) -> usize {
+        self.queues.in_flight.count()
+    }
+
     pub fn available_parallelism() -> NonZeroUsize {
         thread::available_parallelism().expect("unable to determine available parallelism")
     }
 
-    pub fn execute_blocking(&mut self, future: Pin<&mut (dyn Future<Output = ()> + Send)>) {
+    /// Runs the future to completion on the executor threads. Returns the first panic caught in
+    /// any task since the last call, in which case the future may not have run to completion.
+    pub fn execute_blocking(
+        &mut self,
+        future: Pin<&mut (dyn Future<Output = ()> + Send)>,
+    ) -> Result<(), TaskPanic> {
         let join_handle = AtomicUsize::default();
 
         // SAFETY: we block until the future completes.
@@ -208,7 +317,7 @@ impl TaskExecutor {
             .task
             .store(&*task as *const Task as *mut _, Ordering::Release);
 
-        self.task_sender.send(ChannelMessage::Task(task)).unwrap();
+        self.queues.push(ChannelMessage::Task(task));
 
         let mut task_guard = self
             .blocking_task_info
@@ -220,16 +329,51 @@ impl TaskExecutor {
             .unwrap();
 
         *task_guard = false;
+
+        match self.blocking_task_info.panic.lock().unwrap().take() {
+            Some(panic) => Err(panic),
+            None => Ok(()),
+        }
     }
 
+    /// Starts execution of the task on the executor threads. Dropping the returned handle blocks
+    /// until the task completes, so it should be awaited or polled until it completes first.
     pub fn execute_fixed<F, T>(&mut self, task: F) -> FixedTaskHandle<T>
     where
         F: Future<Output = Box<T>> + Send + 'static,
+        T: Send + 'static,
     {
-        // TODO: start task processing
+        let result = Arc::new(TaskResultWrapper::default());
+        let mut future = {
+            let result = result.clone();
+            Box::pin(async move {
+                let awaited = task.await;
+                unsafe { result.set(awaited) };
+            })
+        };
+
+        let join_handle = Box::pin(AtomicUsize::default());
+
+        let join_handle_ref: Pin<&AtomicUsize> = join_handle.as_ref();
+        let join_handle_ref: Pin<&'static AtomicUsize> = unsafe { mem::transmute(join_handle_ref) };
+
+        // SAFETY: the handle owns the future and task, which must outlive execution
+        let task = unsafe {
+            let future: Pin<&mut dyn Future<Output = ()>> = future.as_mut();
+            let future: Pin<&'static mut (dyn Future<Output = ()> + Send)> = mem::transmute(future);
+            Box::pin(Task::new(future, join_handle_ref))
+        };
+
+        let task_ref: Pin<&Task> = task.as_ref();
+        let task_ref: Pin<&'static Task> = unsafe { mem::transmute(task_ref) };
+
+        self.queues.push(ChannelMessage::Task(task_ref));
 
         FixedTaskHandle {
-            future: Box::pin(task),
+            _future: future,
+            join_handle,
+            _task: task,
+            result,
         }
     }
 }
@@ -237,7 +381,7 @@ impl TaskExecutor {
 impl Drop for TaskExecutor {
     fn drop(&mut self) {
         for _ in &self.thread_join_handles {
-            self.task_sender.send(ChannelMessage::Join).unwrap();
+            self.queues.push(ChannelMessage::Join);
         }
 
         for thread in self.thread_join_handles.drain(..) {
@@ -246,21 +390,72 @@ impl Drop for TaskExecutor {
     }
 }
 
-const VTABLE: RawWakerVTable = RawWakerVTable::new(task_clone, |_| {}, |_| {}, |_| {});
+/// Static rather than const, so that executor wakers can be identified by their vtable address
+static VTABLE: RawWakerVTable = RawWakerVTable::new(task_clone, |_| {}, |_| {}, |_| {});
 
 fn task_clone(task: *const ()) -> RawWaker {
     RawWaker::new(task, &VTABLE)
 }
 
+/// The executor task being polled with this context. Panics if the context does not belong to
+/// an executor thread, as the waker would not point to a task.
+pub(crate) fn current_task(cx: &Context) -> Pin<&'static Task> {
+    assert!(
+        ptr::eq(cx.waker().as_raw().vtable(), &VTABLE),
+        "must be polled from an executor task"
+    );
+
+    let task = cx.waker().as_raw().data() as *const Task;
+    unsafe { Pin::new_unchecked(&*task) }
+}
+
 pub struct FixedTaskHandle<T> {
-    future: Pin<Box<dyn Future<Output = Box<T>> + Send + 'static>>,
+    _future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
+    join_handle: Pin<Box<AtomicUsize>>,
+    _task: Pin<Box<Task>>,
+    result: Arc<TaskResultWrapper<Box<T>>>,
+}
+
+impl<T> FixedTaskHandle<T> {
+    /// Whether the executor has finished with the task. The result is set before this, while the
+    /// executor is still polling the task.
+    pub fn is_finished(&self) -> bool {
+        self.join_handle.load(Ordering::Acquire) & 1 == 1
+    }
+
+    /// Returns the result if the task has already completed, without blocking
+    pub fn try_result(&mut self) -> Option<Box<T>> {
+        if self.is_finished() {
+            self.result.take()
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Drop for FixedTaskHandle<T> {
+    /// The task borrows the future and join handle owned here, so they must outlive it
+    fn drop(&mut self) {
+        while !self.is_finished() {
+            thread::yield_now();
+        }
+    }
 }
 
 impl<T> Future for FixedTaskHandle<T> {
     type Output = Box<T>;
 
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.future.as_mut().poll(cx)
+    /// Must be polled from an executor task, which is woken when the fixed task completes. Panics
+    /// otherwise.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut join_handle = JoinHandleTask {
+            join_handle: self.join_handle.as_ref(),
+        };
+
+        match Pin::new(&mut join_handle).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(self.result.take().expect("fixed task panicked")),
+            Poll::Pending => Poll::Pending,
+        }
     }
 }
 
@@ -268,6 +463,7 @@ enum TaskStatus {
     Ready,
     Pending,
     UnableToPoll,
+    Panicked(Box<dyn Any + Send>),
 }
 
 // We require at least an alignment of 2 so that the lower bit of the pointer may act as a flag.
@@ -304,25 +500,37 @@ impl Task {
             .is_ok()
         {
             let future = unsafe { self.future.get().as_mut().unwrap_unchecked() };
-            if future.as_mut().poll(context).is_ready() {
-                let pending_task = self.join_handle.fetch_or(1, Ordering::SeqCst) as *const Task;
-                if let Some(pending_task) = unsafe { pending_task.as_ref() } {
-                    TASK_SENDER.with(|sender| unsafe {
-                        let sender = sender.get().as_ref().unwrap_unchecked();
-                        let task = mem::transmute(pending_task);
-                        sender.send(ChannelMessage::Task(task)).unwrap();
-                    });
-                }
+            let poll = panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(context)));
 
-                TaskStatus::Ready
-            } else {
-                self.executing.store(false, Ordering::Release);
-                TaskStatus::Pending
+            match poll {
+                Ok(Poll::Ready(())) => {
+                    self.complete();
+                    TaskStatus::Ready
+                }
+                Ok(Poll::Pending) => {
+                    self.executing.store(false, Ordering::Release);
+                    TaskStatus::Pending
+                }
+                Err(payload) => {
+                    // the future is left marked as executing so that it is never polled again,
+                    // while the awaiting task is woken so the panic reaches the blocking caller
+                    self.complete();
+                    TaskStatus::Panicked(payload)
+                }
             }
         } else {
             TaskStatus::UnableToPoll
         }
     }
+
+    /// Marks the join handle done and wakes the task awaiting it, if any
+    fn complete(&self) {
+        let pending_task = self.join_handle.fetch_or(1, Ordering::SeqCst) as *const Task;
+        if let Some(pending_task) = unsafe { pending_task.as_ref() } {
+            // SAFETY: tasks are pinned for as long as the executor references them
+            push_task(unsafe { Pin::new_unchecked(pending_task) });
+        }
+    }
 }
 
 struct JoinHandleTask<'a> {
@@ -333,7 +541,7 @@ impl<'a> Future for JoinHandleTask<'a> {
     type Output = ();
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let task = cx.waker().as_raw().data() as usize;
+        let task = &*current_task(cx) as *const Task as usize;
         if self.join_handle.fetch_or(task, Ordering::SeqCst) & 1 == 1 {
             Poll::Ready(())
         } else {
@@ -341,3 +549,144 @@ impl<'a> Future for JoinHandleTask<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unpinned, so that tests running in parallel don't contend for the same cores
+    pub(crate) fn executor(
+        thread_count: usize,
+        register_thread: &(dyn Fn(usize) + Sync),
+    ) -> TaskExecutor {
+        let options = ExecutorOptions {
+            pin_threads: false,
+            ..Default::default()
+        };
+
+        TaskExecutor::with_options(
+            options,
+            NonZeroUsize::new(thread_count).unwrap(),
+            register_thread,
+        )
+    }
+
+    /// Polls the future once with a waker that doesn't belong to the executor, as a foreign
+    /// executor such as `futures::executor::block_on` would
+    pub(crate) fn poll_foreign<F: Future + ?Sized>(future: Pin<&mut F>) -> Poll<F::Output> {
+        static FOREIGN_VTABLE: RawWakerVTable = RawWakerVTable::new(
+            |data| RawWaker::new(data, &FOREIGN_VTABLE),
+            |_| {},
+            |_| {},
+            |_| {},
+        );
+
+        let waker = unsafe { Waker::from_raw(RawWaker::new(ptr::null(), &FOREIGN_VTABLE)) };
+        future.poll(&mut Context::from_waker(&waker))
+    }
+
+    #[test]
+    fn unpinned_executor_runs_tasks() {
+        let registered = AtomicUsize::new(0);
+        let mut executor = executor(2, &|_| {
+            registered.fetch_add(1, Ordering::Relaxed);
+        });
+        assert_eq!(registered.load(Ordering::Relaxed), 2);
+
+        let ran = AtomicBool::new(false);
+        let mut task = Box::pin(async {
+            ran.store(true, Ordering::Relaxed);
+        });
+
+        assert!(executor.execute_blocking(task.as_mut()).is_ok());
+        assert!(ran.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn executor_runs_tasks_without_core_affinity() {
+        let options = ExecutorOptions {
+            pin_threads: true,
+            core_ids: || None,
+            ..Default::default()
+        };
+
+        let mut executor =
+            TaskExecutor::with_options(options, NonZeroUsize::new(2).unwrap(), &|_| {});
+
+        let ran = AtomicBool::new(false);
+        let mut task = Box::pin(async {
+            ran.store(true, Ordering::Relaxed);
+        });
+
+        assert!(executor.execute_blocking(task.as_mut()).is_ok());
+        assert!(ran.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn threads_are_named_with_prefix_and_index() {
+        let options = ExecutorOptions {
+            thread_name_prefix: "test-pool",
+            pin_threads: false,
+            ..Default::default()
+        };
+
+        let thread_names = Mutex::new(Vec::new());
+        let _executor =
+            TaskExecutor::with_options(options, NonZeroUsize::new(3).unwrap(), &|thread_index| {
+                let thread_name = thread::current().name().unwrap().to_owned();
+                thread_names
+                    .lock()
+                    .unwrap()
+                    .push((thread_index, thread_name));
+            });
+
+        let mut thread_names = thread_names.into_inner().unwrap();
+        thread_names.sort();
+        assert_eq!(
+            thread_names,
+            [
+                (0, "test-pool-0".to_owned()),
+                (1, "test-pool-1".to_owned()),
+                (2, "test-pool-2".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn fixed_task_completes_without_polling() {
+        let mut executor = executor(1, &|_| {});
+        let mut task_handle = executor.execute_fixed(async { Box::new(42) });
+
+        while !task_handle.is_finished() {
+            thread::yield_now();
+        }
+
+        assert_eq!(task_handle.try_result().as_deref(), Some(&42));
+        assert_eq!(task_handle.try_result(), None);
+    }
+
+    #[test]
+    fn panic_returns_err() {
+        let mut executor = executor(1, &|_| {});
+
+        let mut task = Box::pin(async {
+            panic!("task failed");
+        });
+
+        let panic = executor.execute_blocking(task.as_mut()).unwrap_err();
+        assert_eq!(panic.message(), "task failed");
+
+        // the panic is only returned once, and the executor keeps running tasks
+        let mut task = Box::pin(async {});
+        assert!(executor.execute_blocking(task.as_mut()).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "must be polled from an executor task")]
+    fn fixed_task_handle_rejects_foreign_executor() {
+        let mut executor = executor(1, &|_| {});
+        let mut task_handle = executor.execute_fixed(async { Box::new(42) });
+
+        let _ = poll_foreign(Pin::new(&mut task_handle));
+    }
+}
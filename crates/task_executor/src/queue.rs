@@ -0,0 +1,275 @@
+use std::{
+    cell::Cell,
+    iter,
+    num::NonZeroUsize,
+    pin::Pin,
+    ptr,
+    sync::{Arc, Condvar, Mutex},
+    time::Instant,
+};
+
+use crossbeam_deque::{Injector, Steal, Stealer, Worker};
+
+use crate::{async_task::InFlightLimit, Task};
+
+pub(crate) enum ChannelMessage {
+    Task(Pin<&'static Task>),
+    Join,
+}
+
+/// A task waiting to be requeued once its deadline passes
+struct PendingTimer {
+    deadline: Instant,
+    task: Pin<&'static Task>,
+}
+
+thread_local! {
+    static LOCAL_QUEUE: Cell<*const LocalQueue<'static>> = Cell::new(ptr::null())
+}
+
+/// Queues shared by all executor threads. Messages from outside the executor go to the
+/// injector, while each thread pushes to its own queue, which idle threads steal from.
+pub(crate) struct SharedQueues {
+    injector: Injector<ChannelMessage>,
+    stealers: Vec<Stealer<ChannelMessage>>,
+    /// Incremented on every push, so that a thread about to sleep can tell if it missed one
+    push_count: Mutex<usize>,
+    cvar: Condvar,
+    timers: Mutex<Vec<PendingTimer>>,
+    pub(crate) in_flight: Arc<InFlightLimit>,
+}
+
+impl SharedQueues {
+    /// Creates the shared queues along with one local queue per thread
+    pub(crate) fn new(
+        thread_count: usize,
+        max_in_flight: Option<NonZeroUsize>,
+    ) -> (Self, Vec<Worker<ChannelMessage>>) {
+        let workers: Vec<_> = (0..thread_count).map(|_| Worker::new_fifo()).collect();
+
+        let shared_queues = Self {
+            injector: Injector::new(),
+            stealers: workers.iter().map(Worker::stealer).collect(),
+            push_count: Mutex::new(0),
+            cvar: Condvar::new(),
+            timers: Mutex::new(Vec::new()),
+            in_flight: Arc::new(InFlightLimit::new(max_in_flight)),
+        };
+
+        (shared_queues, workers)
+    }
+
+    /// Pushes a message from outside the executor threads
+    pub(crate) fn push(&self, message: ChannelMessage) {
+        self.injector.push(message);
+        self.notify();
+    }
+
+    fn notify(&self) {
+        *self.push_count.lock().unwrap() += 1;
+        self.cvar.notify_one();
+    }
+
+    fn push_timer(&self, timer: PendingTimer) {
+        self.timers.lock().unwrap().push(timer);
+
+        // wake a sleeping thread so that it waits on the new deadline
+        self.notify();
+    }
+
+    /// Removes and returns the tasks of expired timers. Returns none if another thread is
+    /// already taking them.
+    fn take_expired_timers(&self) -> Vec<Pin<&'static Task>> {
+        let mut timers = match self.timers.try_lock() {
+            Ok(timers) => timers,
+            Err(_) => return Vec::new(),
+        };
+
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        let mut i = 0;
+        while i < timers.len() {
+            if timers[i].deadline <= now {
+                expired.push(timers.swap_remove(i).task);
+            } else {
+                i += 1;
+            }
+        }
+
+        expired
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.timers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|timer| timer.deadline)
+            .min()
+    }
+}
+
+pub(crate) struct LocalQueue<'a> {
+    worker: Worker<ChannelMessage>,
+    shared: &'a SharedQueues,
+}
+
+impl<'a> LocalQueue<'a> {
+    pub(crate) fn new(worker: Worker<ChannelMessage>, shared: &'a SharedQueues) -> Self {
+        Self { worker, shared }
+    }
+
+    /// Makes this the queue `push_task` pushes to on the current thread. The queue must outlive
+    /// all executor work on the thread.
+    pub(crate) fn register(&self) {
+        LOCAL_QUEUE.with(|queue| queue.set(self as *const Self as *const _));
+    }
+
+    /// Blocks until a message is available, taking from the local queue first, then the
+    /// injector, then other threads. Idle threads wake to requeue expired timers.
+    pub(crate) fn next_message(&self) -> ChannelMessage {
+        loop {
+            let push_count = *self.shared.push_count.lock().unwrap();
+
+            // expired timers join the local queue, as a busy thread never reaches the injector
+            for task in self.shared.take_expired_timers() {
+                self.push(ChannelMessage::Task(task));
+            }
+
+            if let Some(message) = self.find_message() {
+                return message;
+            }
+
+            // timers pushed after this point also increment the push count
+            let next_deadline = self.shared.next_deadline();
+
+            let guard = self.shared.push_count.lock().unwrap();
+            let condition = |count: &mut usize| *count == push_count;
+
+            match next_deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    let _guard = self
+                        .shared
+                        .cvar
+                        .wait_timeout_while(guard, timeout, condition)
+                        .unwrap();
+                }
+                None => {
+                    let _guard = self.shared.cvar.wait_while(guard, condition).unwrap();
+                }
+            }
+        }
+    }
+
+    fn find_message(&self) -> Option<ChannelMessage> {
+        self.worker.pop().or_else(|| {
+            iter::repeat_with(|| {
+                self.shared
+                    .injector
+                    .steal_batch_and_pop(&self.worker)
+                    .or_else(|| self.shared.stealers.iter().map(Stealer::steal).collect())
+            })
+            .find(|steal| !steal.is_retry())
+            .and_then(Steal::success)
+        })
+    }
+
+    fn push(&self, message: ChannelMessage) {
+        self.worker.push(message);
+        self.shared.notify();
+    }
+}
+
+const NOT_EXECUTOR_THREAD: &str = "tasks may only be started from an executor thread";
+
+/// Queues a task on the current executor thread, where it may be stolen by idle threads
+pub(crate) fn push_task(task: Pin<&'static Task>) {
+    LOCAL_QUEUE.with(|queue| {
+        // SAFETY: registered queues outlive all executor work on their thread
+        let queue = unsafe { queue.get().as_ref().expect(NOT_EXECUTOR_THREAD) };
+        queue.push(ChannelMessage::Task(task));
+    });
+}
+
+/// Queues a task on the shared injector once the deadline has passed
+pub(crate) fn push_task_at(deadline: Instant, task: Pin<&'static Task>) {
+    LOCAL_QUEUE.with(|queue| {
+        // SAFETY: registered queues outlive all executor work on their thread
+        let queue = unsafe { queue.get().as_ref().expect(NOT_EXECUTOR_THREAD) };
+        queue.shared.push_timer(PendingTimer { deadline, task });
+    });
+}
+
+/// The in-flight limit of the executor the current thread belongs to
+pub(crate) fn in_flight_limit() -> Arc<InFlightLimit> {
+    LOCAL_QUEUE.with(|queue| {
+        // SAFETY: registered queues outlive all executor work on their thread
+        let queue = unsafe { queue.get().as_ref().expect(NOT_EXECUTOR_THREAD) };
+        queue.shared.in_flight.clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+    };
+
+    use crate::{
+        async_task::{execute_async, yield_now},
+        tests::executor,
+        TaskExecutor,
+    };
+
+    use super::*;
+
+    const TASK_COUNT: usize = 5000;
+
+    #[test]
+    fn many_tiny_tasks_all_complete() {
+        let thread_count = TaskExecutor::available_parallelism().get();
+        let mut executor = executor(thread_count, &|_| {});
+
+        let completed = Arc::new(AtomicUsize::new(0));
+        let thread_names = Arc::new(Mutex::new(HashSet::new()));
+
+        let mut task = Box::pin(async {
+            let task_handles: Vec<_> = (0..TASK_COUNT)
+                .map(|i| {
+                    let completed = completed.clone();
+                    let thread_names = thread_names.clone();
+                    execute_async(async move {
+                        // some tasks are requeued, so that they may be stolen part way through
+                        if i % 2 == 0 {
+                            yield_now().await;
+                        }
+
+                        let thread_name = thread::current().name().map(str::to_owned);
+                        thread_names.lock().unwrap().insert(thread_name);
+                        completed.fetch_add(1, Ordering::Relaxed);
+                    })
+                })
+                .collect();
+
+            for task_handle in task_handles {
+                while !task_handle.is_finished() {
+                    yield_now().await;
+                }
+            }
+        });
+
+        assert!(executor.execute_blocking(task.as_mut()).is_ok());
+        assert_eq!(completed.load(Ordering::Relaxed), TASK_COUNT);
+        assert_eq!(executor.in_flight_count(), 0);
+
+        // every task ran on one of the executor's threads
+        let thread_names = thread_names.lock().unwrap();
+        assert!(thread_names.len() <= thread_count);
+        assert!(thread_names
+            .iter()
+            .all(|name| name.as_deref().unwrap().starts_with("worker-")));
+    }
+}
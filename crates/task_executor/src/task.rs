@@ -6,7 +6,7 @@ use std::{
     sync::atomic::AtomicUsize,
 };
 
-use crate::{pin_array_unsafe, ChannelMessage, JoinHandleTask, Task, TASK_SENDER};
+use crate::{pin_array_unsafe, queue::push_task, JoinHandleTask, Task};
 
 pub async fn parallel<const N: usize>(futures: [Pin<&mut (dyn Future<Output = ()> + Send)>; N]) {
     let join_handles = [0; N].map(|_| AtomicUsize::default());
@@ -27,12 +27,9 @@ pub async fn parallel<const N: usize>(futures: [Pin<&mut (dyn Future<Output = ()
     // SAFETY: we block until the future completes.
     pin_array_unsafe!(tasks, N, Task);
 
-    TASK_SENDER.with(|sender| {
-        let sender = unsafe { sender.get().as_ref().unwrap_unchecked() };
-        for task in tasks {
-            sender.send(ChannelMessage::Task(task)).unwrap();
-        }
-    });
+    for task in tasks {
+        push_task(task);
+    }
 
     for join_handle in join_handles {
         JoinHandleTask { join_handle }.await;
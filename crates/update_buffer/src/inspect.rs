@@ -0,0 +1,124 @@
+use game_entity::EntityId;
+use nalgebra_glm::Vec3;
+
+use crate::{
+    Data, EntityData, GuestGoals, NetworkGuestGoals, NetworkLocations, StaticMeshLocations,
+    UpdateChannel,
+};
+
+/// Which half of the double buffer to inspect
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BufferSide {
+    /// Updates pushed during the previous fixed update, which systems are currently reading
+    Read,
+    /// Updates pushed during the current fixed update
+    Write,
+}
+
+/// Read-only access to the raw contents of each channel, named after the system which reads
+/// the channel. Contents are concatenated across all threads, in thread index order.
+pub struct UpdateBufferInspector<'a> {
+    update_buffers: &'a [[Data; 2]],
+    swap_index: bool,
+}
+
+impl<'a> UpdateBufferInspector<'a> {
+    pub(crate) fn new(update_buffers: &'a [[Data; 2]], swap_index: bool) -> Self {
+        Self {
+            update_buffers,
+            swap_index,
+        }
+    }
+
+    fn channel<C>(&self, side: BufferSide) -> Vec<C::Item>
+    where
+        C: UpdateChannel,
+        C::Item: Copy,
+    {
+        self.collect(side, C::buffer)
+    }
+
+    pub fn guest_goals(&self, side: BufferSide) -> Vec<(EntityId, Vec3)> {
+        self.channel::<GuestGoals>(side)
+    }
+
+    pub fn network_guest_goals(&self, side: BufferSide) -> Vec<(EntityId, Vec3)> {
+        self.channel::<NetworkGuestGoals>(side)
+    }
+
+    pub fn network_locations(&self, side: BufferSide) -> Vec<(EntityId, Vec3)> {
+        self.collect_entity_data(side, NetworkLocations::buffer)
+    }
+
+    pub fn static_mesh_locations(&self, side: BufferSide) -> Vec<(EntityId, Vec3)> {
+        self.collect_entity_data(side, StaticMeshLocations::buffer)
+    }
+
+    fn index(&self, side: BufferSide) -> usize {
+        match side {
+            BufferSide::Read => !self.swap_index as usize,
+            BufferSide::Write => self.swap_index as usize,
+        }
+    }
+
+    fn collect<T: Copy>(&self, side: BufferSide, channel: impl Fn(&Data) -> &Vec<T>) -> Vec<T> {
+        let index = self.index(side);
+        self.update_buffers
+            .iter()
+            .flat_map(|buffers| channel(&buffers[index]))
+            .copied()
+            .collect()
+    }
+
+    fn collect_entity_data(
+        &self,
+        side: BufferSide,
+        channel: impl Fn(&Data) -> &Vec<EntityData<Vec3>>,
+    ) -> Vec<(EntityId, Vec3)> {
+        self.collect(side, channel)
+            .into_iter()
+            .map(|entity_data| (entity_data.entity_id, entity_data.data))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use nalgebra_glm::vec3;
+
+    use crate::UpdateBuffer;
+
+    use super::*;
+
+    #[test]
+    fn inspects_each_side_in_thread_order() {
+        let mut update_buffer = UpdateBuffer::new(NonZeroUsize::new(2).unwrap());
+        let (a, b) = (EntityId::new(1), EntityId::new(2));
+        let location = vec3(1.0, 0.0, 2.0);
+
+        // thread 1 pushes first, but is inspected after thread 0
+        update_buffer.assign_thread_event_buffer(1);
+        update_buffer.borrow().network().push_location(b, location);
+        update_buffer.assign_thread_event_buffer(0);
+        update_buffer.borrow().network().push_location(a, location);
+        update_buffer.borrow().guest().push_goal(a, location);
+
+        let inspector = update_buffer.inspect();
+        let locations = inspector.static_mesh_locations(BufferSide::Write);
+        assert!(locations == [(a, location), (b, location)]);
+        assert_eq!(inspector.network_guest_goals(BufferSide::Write).len(), 1);
+        assert!(inspector.static_mesh_locations(BufferSide::Read).is_empty());
+
+        update_buffer.swap_buffers();
+
+        let inspector = update_buffer.inspect();
+        assert_eq!(inspector.static_mesh_locations(BufferSide::Read).len(), 2);
+        assert!(inspector
+            .static_mesh_locations(BufferSide::Write)
+            .is_empty());
+        assert!(inspector.guest_goals(BufferSide::Read).is_empty());
+        assert!(inspector.network_locations(BufferSide::Read).is_empty());
+    }
+}
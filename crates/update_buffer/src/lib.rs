@@ -1,13 +1,13 @@
-use std::{cell::Cell, num::NonZeroUsize, ptr::null_mut};
+use std::{any::Any, cell::Cell, marker::PhantomData, num::NonZeroUsize, ptr::null_mut};
 
 use game_entity::EntityId;
 use nalgebra_glm::Vec3;
 
-#[derive(Clone, Copy)]
-pub enum System {
-    Network,
-    StaticMesh,
-}
+#[cfg(feature = "inspect")]
+pub use inspect::{BufferSide, UpdateBufferInspector};
+
+#[cfg(feature = "inspect")]
+mod inspect;
 
 thread_local! {
     static UPDATE_BUFFER: Cell<*mut [Data; 2]> = Cell::new(null_mut())
@@ -20,64 +20,115 @@ pub struct EntityData<T> {
 }
 
 impl<T> EntityData<T> {
-    fn new(entity_id: EntityId, data: T) -> Self {
+    pub fn new(entity_id: EntityId, data: T) -> Self {
         Self { entity_id, data }
     }
 }
 
-#[derive(Default)]
-struct Data {
-    guest: Guest,
-    network: Network,
-    static_mesh: StaticMesh,
-}
+/// A typed buffer of updates, pushed by any system and read by a single system. Channels of the
+/// systems in this crate are declared with `update_channels!`, and exposed through the per-system
+/// refs. Other systems register their channels with `UpdateBuffer::register_channel`.
+pub(crate) trait UpdateChannel {
+    type Item: 'static;
 
-impl Data {
-    fn clear(&mut self) {
-        self.guest.clear();
-        self.network.clear();
-        self.static_mesh.clear();
-    }
+    fn buffer(data: &Data) -> &Vec<Self::Item>;
+
+    fn buffer_mut(data: &mut Data) -> &mut Vec<Self::Item>;
 }
 
-/// Updates that the guest system will read.
-#[derive(Default)]
-struct Guest {
-    goals: Vec<(EntityId, Vec3)>,
+/// Declares the channels stored in each buffer. Adding a channel only requires a line here.
+macro_rules! update_channels {
+    ($($(#[$meta:meta])* $channel:ident($field:ident: $item:ty),)*) => {
+        /// One half of a thread's double buffer, holding every channel
+        #[derive(Default)]
+        pub(crate) struct Data {
+            $($field: Vec<$item>,)*
+            /// Indexed by `ChannelId`
+            registered: Vec<Box<dyn RegisteredChannel>>,
+        }
+
+        impl Data {
+            fn clear(&mut self) {
+                $(self.$field.clear();)*
+
+                for channel in &mut self.registered {
+                    channel.clear();
+                }
+            }
+        }
+
+        $(
+            $(#[$meta])*
+            pub(crate) struct $channel;
+
+            impl UpdateChannel for $channel {
+                type Item = $item;
+
+                fn buffer(data: &Data) -> &Vec<Self::Item> {
+                    &data.$field
+                }
+
+                fn buffer_mut(data: &mut Data) -> &mut Vec<Self::Item> {
+                    &mut data.$field
+                }
+            }
+        )*
+    };
 }
 
-impl Guest {
-    fn clear(&mut self) {
-        self.goals.clear();
-    }
+update_channels! {
+    /// Goals for the guest system, e.g. replicated from the server
+    GuestGoals(guest_goals: (EntityId, Vec3)),
+    /// Guest goals for the network system to replicate
+    NetworkGuestGoals(network_guest_goals: (EntityId, Vec3)),
+    /// Locations for the network system to replicate
+    NetworkLocations(network_locations: EntityData<Vec3>),
+    /// Locations for the static mesh system, e.g. replicated from a remote host
+    StaticMeshLocations(static_mesh_locations: EntityData<Vec3>),
 }
 
-/// Updates that the network system will read.
-#[derive(Default)]
-struct Network {
-    guest_goals: Vec<(EntityId, Vec3)>,
-    locations: Vec<EntityData<Vec3>>,
+/// Type-erased buffer of a channel registered with `UpdateBuffer::register_channel`
+trait RegisteredChannel: Send + Sync {
+    fn clear(&mut self);
+
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
 }
 
-impl Network {
+impl<T: Send + Sync + 'static> RegisteredChannel for Vec<T> {
     fn clear(&mut self) {
-        self.guest_goals.clear();
-        self.locations.clear();
+        Vec::clear(self);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
     }
 }
 
-/// Updates that the static mesh system will read.
-#[derive(Default)]
-struct StaticMesh {
-    locations: Vec<EntityData<Vec3>>,
+const FOREIGN_CHANNEL: &str = "channel was registered with a different update buffer";
+
+/// Identifies a channel of `T` registered with `UpdateBuffer::register_channel`. Only valid for
+/// the update buffer which registered it.
+pub struct ChannelId<T> {
+    index: usize,
+    marker: PhantomData<fn() -> T>,
 }
 
-impl StaticMesh {
-    fn clear(&mut self) {
-        self.locations.clear();
+impl<T> Clone for ChannelId<T> {
+    fn clone(&self) -> Self {
+        *self
     }
 }
 
+impl<T> Copy for ChannelId<T> {}
+
+/// Per-system inboxes of updates made during the fixed update. Each system reads its own inbox,
+/// and pushes updates into the inboxes of the systems which read them.
 pub struct UpdateBuffer {
     update_buffers: Vec<[Data; 2]>,
     swap_index: bool,
@@ -100,6 +151,20 @@ impl UpdateBuffer {
         UPDATE_BUFFER.with(|queue| queue.set(self.update_buffers[thread_index].as_ptr() as _));
     }
 
+    /// Adds a channel to every buffer, for a system whose updates aren't declared in this crate
+    pub fn register_channel<T: Send + Sync + 'static>(&mut self) -> ChannelId<T> {
+        let index = self.update_buffers[0][0].registered.len();
+
+        for data in self.update_buffers.iter_mut().flatten() {
+            data.registered.push(Box::new(Vec::<T>::new()));
+        }
+
+        ChannelId {
+            index,
+            marker: PhantomData,
+        }
+    }
+
     pub fn borrow(&mut self) -> UpdateBufferRef {
         UpdateBufferRef {
             update_buffers: &self.update_buffers,
@@ -107,6 +172,11 @@ impl UpdateBuffer {
         }
     }
 
+    #[cfg(feature = "inspect")]
+    pub fn inspect(&self) -> UpdateBufferInspector {
+        UpdateBufferInspector::new(&self.update_buffers, self.swap_index)
+    }
+
     pub fn swap_buffers(&mut self) {
         self.swap_index = !self.swap_index;
 
@@ -123,157 +193,261 @@ pub struct UpdateBufferRef<'a> {
     swap_index: bool,
 }
 
-impl UpdateBufferRef<'_> {
-    pub fn guest(&self) -> GuestUpdateBufferRef {
-        GuestUpdateBufferRef {
-            update_buffers: self.update_buffers,
-            swap_index: self.swap_index,
-        }
+impl<'a> UpdateBufferRef<'a> {
+    pub fn guest(&self) -> GuestUpdateBufferRef<'a> {
+        GuestUpdateBufferRef(*self)
     }
 
-    pub fn network(&self) -> NetworkUpdateBufferRef {
-        NetworkUpdateBufferRef {
-            update_buffers: self.update_buffers,
-            swap_index: self.swap_index,
-        }
+    pub fn network(&self) -> NetworkUpdateBufferRef<'a> {
+        NetworkUpdateBufferRef(*self)
     }
 
-    pub fn static_mesh(&self) -> StaticMeshUpdateBufferRef {
-        StaticMeshUpdateBufferRef {
-            update_buffers: self.update_buffers,
-            swap_index: self.swap_index,
-        }
+    pub fn static_mesh(&self) -> StaticMeshUpdateBufferRef<'a> {
+        StaticMeshUpdateBufferRef(*self)
     }
-}
 
-#[derive(Clone, Copy)]
-pub struct GuestUpdateBufferRef<'a> {
-    update_buffers: &'a Vec<[Data; 2]>,
-    swap_index: bool,
-}
-
-impl<'a> GuestUpdateBufferRef<'a> {
+    /// Updates pushed to the channel during the previous fixed update, across all threads
     #[inline]
-    pub fn goals(&self) -> impl Iterator<Item = &(EntityId, Vec3)> {
-        let index = self.read_index();
+    pub(crate) fn read<C: UpdateChannel>(&self) -> impl Iterator<Item = &'a C::Item> {
+        let index = !self.swap_index as usize;
         self.update_buffers
             .iter()
-            .flat_map(move |buffers| &buffers[index].guest.goals)
+            .flat_map(move |buffers| C::buffer(&buffers[index]))
     }
 
+    /// Pushes to the current thread's buffer, which is read after the next swap
     #[inline]
-    pub fn push_goal(&self, entity_id: EntityId, goal: Vec3) {
-        let index = self.write_index();
+    pub(crate) fn push<C: UpdateChannel>(&self, item: C::Item) {
+        let index = self.swap_index as usize;
 
         UPDATE_BUFFER.with(|buffer| unsafe {
             let buffer = &mut buffer.get().as_mut().unwrap_unchecked()[index];
 
-            buffer.network.guest_goals.push((entity_id, goal));
+            C::buffer_mut(buffer).push(item);
         });
     }
 
-    fn read_index(&self) -> usize {
-        !self.swap_index as usize
+    /// Updates pushed to the registered channel during the previous fixed update, across all
+    /// threads
+    #[inline]
+    pub fn read_channel<T: 'static>(&self, channel: ChannelId<T>) -> impl Iterator<Item = &'a T> {
+        let index = !self.swap_index as usize;
+        self.update_buffers.iter().flat_map(move |buffers| {
+            buffers[index]
+                .registered
+                .get(channel.index)
+                .and_then(|buffer| buffer.as_any().downcast_ref::<Vec<T>>())
+                .expect(FOREIGN_CHANNEL)
+        })
     }
 
-    fn write_index(&self) -> usize {
-        self.swap_index as usize
+    /// Pushes to the registered channel in the current thread's buffer, which is read after the
+    /// next swap
+    #[inline]
+    pub fn push_channel<T: 'static>(&self, channel: ChannelId<T>, item: T) {
+        let index = self.swap_index as usize;
+
+        UPDATE_BUFFER.with(|buffer| unsafe {
+            let buffer = &mut buffer.get().as_mut().unwrap_unchecked()[index];
+
+            buffer
+                .registered
+                .get_mut(channel.index)
+                .and_then(|buffer| buffer.as_any_mut().downcast_mut::<Vec<T>>())
+                .expect(FOREIGN_CHANNEL)
+                .push(item);
+        });
     }
 }
 
 #[derive(Clone, Copy)]
-pub struct NetworkUpdateBufferRef<'a> {
-    update_buffers: &'a Vec<[Data; 2]>,
-    swap_index: bool,
+pub struct GuestUpdateBufferRef<'a>(UpdateBufferRef<'a>);
+
+impl<'a> GuestUpdateBufferRef<'a> {
+    #[inline]
+    pub fn goals(&self) -> impl Iterator<Item = &'a (EntityId, Vec3)> {
+        self.0.read::<GuestGoals>()
+    }
+
+    /// Read by the network system
+    #[inline]
+    pub fn push_goal(&self, entity_id: EntityId, goal: Vec3) {
+        self.0.push::<NetworkGuestGoals>((entity_id, goal));
+    }
 }
 
+#[derive(Clone, Copy)]
+pub struct NetworkUpdateBufferRef<'a>(UpdateBufferRef<'a>);
+
 impl<'a> NetworkUpdateBufferRef<'a> {
     #[inline]
-    pub fn guest_goals(&self) -> impl Iterator<Item = &(EntityId, Vec3)> {
-        let index = self.read_index();
-        self.update_buffers
-            .iter()
-            .flat_map(move |buffers| &buffers[index].network.guest_goals)
+    pub fn guest_goals(&self) -> impl Iterator<Item = &'a (EntityId, Vec3)> {
+        self.0.read::<NetworkGuestGoals>()
     }
 
+    /// Read by the guest system
     #[inline]
     pub fn push_guest_goal(&self, entity_id: EntityId, goal: Vec3) {
-        let index = self.write_index();
-
-        UPDATE_BUFFER.with(|buffer| unsafe {
-            let buffer = &mut buffer.get().as_mut().unwrap_unchecked()[index];
-
-            buffer.guest.goals.push((entity_id, goal));
-        });
+        self.0.push::<GuestGoals>((entity_id, goal));
     }
 
     #[inline]
-    pub fn locations(&self) -> impl Iterator<Item = (EntityId, &Vec3)> {
-        let index = self.read_index();
-        self.update_buffers
-            .iter()
-            .flat_map(move |buffers| &buffers[index].network.locations)
+    pub fn locations(&self) -> impl Iterator<Item = (EntityId, &'a Vec3)> {
+        self.0
+            .read::<NetworkLocations>()
             .map(|entity_data| (entity_data.entity_id, &entity_data.data))
     }
 
+    /// Read by the static mesh system
     #[inline]
     pub fn push_location(&self, entity_id: EntityId, location: Vec3) {
-        let index = self.write_index();
-
-        UPDATE_BUFFER.with(|buffer| unsafe {
-            let buffer = &mut buffer.get().as_mut().unwrap_unchecked()[index];
-
-            buffer
-                .static_mesh
-                .locations
-                .push(EntityData::new(entity_id, location))
-        });
-    }
-
-    fn read_index(&self) -> usize {
-        !self.swap_index as usize
-    }
-
-    fn write_index(&self) -> usize {
-        self.swap_index as usize
+        self.0
+            .push::<StaticMeshLocations>(EntityData::new(entity_id, location));
     }
 }
 
 #[derive(Clone, Copy)]
-pub struct StaticMeshUpdateBufferRef<'a> {
-    update_buffers: &'a Vec<[Data; 2]>,
-    swap_index: bool,
-}
+pub struct StaticMeshUpdateBufferRef<'a>(UpdateBufferRef<'a>);
 
 impl<'a> StaticMeshUpdateBufferRef<'a> {
     #[inline]
-    pub fn locations(&self) -> impl Iterator<Item = (EntityId, &Vec3)> {
-        let index = self.read_index();
-        self.update_buffers
-            .iter()
-            .flat_map(move |buffers| &buffers[index].static_mesh.locations)
+    pub fn locations(&self) -> impl Iterator<Item = (EntityId, &'a Vec3)> {
+        self.0
+            .read::<StaticMeshLocations>()
             .map(|entity_data| (entity_data.entity_id, &entity_data.data))
     }
 
+    /// Read by the network system
     #[inline]
     pub fn push_location(&self, entity_id: EntityId, location: Vec3) {
-        let index = self.write_index();
+        self.0
+            .push::<NetworkLocations>(EntityData::new(entity_id, location));
+    }
+}
 
-        UPDATE_BUFFER.with(|buffer| unsafe {
-            let buffer = &mut buffer.get().as_mut().unwrap_unchecked()[index];
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::vec3;
 
-            buffer
-                .network
-                .locations
-                .push(EntityData::new(entity_id, location))
-        });
+    use super::*;
+
+    #[test]
+    fn updates_are_read_after_swap() {
+        let mut update_buffer = UpdateBuffer::new(NonZeroUsize::new(1).unwrap());
+        update_buffer.assign_thread_event_buffer(0);
+
+        let entity_id = EntityId::new(1);
+        let goal = vec3(1.0, 0.0, 2.0);
+
+        update_buffer.borrow().guest().push_goal(entity_id, goal);
+        update_buffer
+            .borrow()
+            .static_mesh()
+            .push_location(entity_id, goal);
+        assert_eq!(update_buffer.borrow().network().guest_goals().count(), 0);
+
+        update_buffer.swap_buffers();
+
+        let update_buffer_ref = update_buffer.borrow();
+        let guest_goals: Vec<_> = update_buffer_ref.network().guest_goals().collect();
+        assert!(guest_goals == [&(entity_id, goal)]);
+
+        let locations: Vec<_> = update_buffer_ref.network().locations().collect();
+        assert!(locations == [(entity_id, &goal)]);
+
+        // each channel is only read by its own system
+        assert_eq!(update_buffer_ref.guest().goals().count(), 0);
+        assert_eq!(update_buffer_ref.static_mesh().locations().count(), 0);
+
+        update_buffer.swap_buffers();
+        assert_eq!(update_buffer.borrow().network().guest_goals().count(), 0);
+    }
+
+    #[test]
+    fn locations_pass_between_network_and_static_mesh() {
+        let mut update_buffer = UpdateBuffer::new(NonZeroUsize::new(1).unwrap());
+        update_buffer.assign_thread_event_buffer(0);
+
+        let (remote_id, local_id) = (EntityId::new(1), EntityId::new(2));
+        let (remote, local) = (vec3(1.0, 0.0, 2.0), vec3(3.0, 0.0, 4.0));
+
+        update_buffer
+            .borrow()
+            .network()
+            .push_location(remote_id, remote);
+        update_buffer
+            .borrow()
+            .static_mesh()
+            .push_location(local_id, local);
+
+        update_buffer.swap_buffers();
+
+        let update_buffer_ref = update_buffer.borrow();
+
+        let static_mesh_locations: Vec<_> = update_buffer_ref.static_mesh().locations().collect();
+        assert!(static_mesh_locations == [(remote_id, &remote)]);
+
+        let network_locations: Vec<_> = update_buffer_ref.network().locations().collect();
+        assert!(network_locations == [(local_id, &local)]);
+    }
+
+    #[test]
+    fn guest_goals_are_read_in_next_swap() {
+        let mut update_buffer = UpdateBuffer::new(NonZeroUsize::new(1).unwrap());
+        update_buffer.assign_thread_event_buffer(0);
+
+        let entity_id = EntityId::new(1);
+        let goal = vec3(1.0, 0.0, 2.0);
+
+        update_buffer
+            .borrow()
+            .network()
+            .push_guest_goal(entity_id, goal);
+        assert_eq!(update_buffer.borrow().guest().goals().count(), 0);
+
+        update_buffer.swap_buffers();
+
+        let goals: Vec<_> = update_buffer.borrow().guest().goals().collect();
+        assert!(goals == [&(entity_id, goal)]);
+
+        // read for one swap only
+        update_buffer.swap_buffers();
+        assert_eq!(update_buffer.borrow().guest().goals().count(), 0);
     }
 
-    fn read_index(&self) -> usize {
-        !self.swap_index as usize
+    #[test]
+    fn registered_channel_round_trips_through_swap() {
+        let mut update_buffer = UpdateBuffer::new(NonZeroUsize::new(1).unwrap());
+        update_buffer.assign_thread_event_buffer(0);
+
+        let scores = update_buffer.register_channel::<(EntityId, u32)>();
+        let names = update_buffer.register_channel::<&'static str>();
+
+        let entity_id = EntityId::new(1);
+        update_buffer.borrow().push_channel(scores, (entity_id, 10));
+        update_buffer.borrow().push_channel(names, "coaster");
+        assert_eq!(update_buffer.borrow().read_channel(scores).count(), 0);
+
+        update_buffer.swap_buffers();
+
+        let update_buffer_ref = update_buffer.borrow();
+        let pushed_scores: Vec<_> = update_buffer_ref.read_channel(scores).collect();
+        assert!(pushed_scores == [&(entity_id, 10)]);
+
+        let pushed_names: Vec<_> = update_buffer_ref.read_channel(names).collect();
+        assert_eq!(pushed_names, [&"coaster"]);
+
+        update_buffer.swap_buffers();
+        assert_eq!(update_buffer.borrow().read_channel(scores).count(), 0);
     }
 
-    fn write_index(&self) -> usize {
-        self.swap_index as usize
+    #[test]
+    #[should_panic(expected = "channel was registered with a different update buffer")]
+    fn foreign_channel_is_rejected() {
+        let mut other_buffer = UpdateBuffer::new(NonZeroUsize::new(1).unwrap());
+        let channel = other_buffer.register_channel::<u32>();
+
+        let mut update_buffer = UpdateBuffer::new(NonZeroUsize::new(1).unwrap());
+        let _ = update_buffer.borrow().read_channel(channel).count();
     }
 }
@@ -76,8 +76,54 @@ impl GpuAllocator {
                 .dealloc(EruptMemoryDevice::wrap(&self.device), buffer.block)
         }
     }
+
+    pub fn alloc_image(
+        &mut self,
+        image_create_info: &vk::ImageCreateInfo,
+        usage: UsageFlags,
+    ) -> GpuImage {
+        let image = unsafe { self.device.create_image(image_create_info, None).unwrap() };
+
+        let memory_requirements = unsafe { self.device.get_image_memory_requirements(image) };
+
+        let request = Request {
+            size: memory_requirements.size,
+            align_mask: memory_requirements.alignment - 1,
+            usage,
+            memory_types: memory_requirements.memory_type_bits,
+        };
+
+        let block = unsafe {
+            self.allocator
+                .alloc(EruptMemoryDevice::wrap(&self.device), request)
+                .unwrap()
+        };
+
+        unsafe {
+            self.device
+                .bind_image_memory(image, *block.memory(), block.offset())
+                .unwrap();
+        }
+
+        GpuImage { image, block }
+    }
+
+    pub fn dealloc_image(&mut self, image: GpuImage) {
+        unsafe {
+            self.device.destroy_image(image.image, None);
+            self.allocator
+                .dealloc(EruptMemoryDevice::wrap(&self.device), image.block)
+        }
+    }
 }
 
+pub struct GpuImage {
+    block: MemoryBlock<vk::DeviceMemory>,
+    pub image: vk::Image,
+}
+
+unsafe impl Send for GpuImage {}
+
 pub struct GpuBuffer {
     block: MemoryBlock<vk::DeviceMemory>,
     pub buffer: vk::Buffer,
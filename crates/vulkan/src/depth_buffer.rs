@@ -0,0 +1,77 @@
+use anyhow::Result;
+use erupt::vk;
+use gpu_alloc::UsageFlags;
+
+use crate::{
+    allocator::{GpuAllocator, GpuImage},
+    VulkanInfo,
+};
+
+pub const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+/// Depth attachment matching the swapchain extent. Both frames in flight share the image, which
+/// is cleared at the start of every frame.
+pub struct DepthBuffer {
+    image: GpuImage,
+    pub image_view: vk::ImageView,
+}
+
+impl DepthBuffer {
+    pub fn new(
+        vulkan: &VulkanInfo,
+        allocator: &mut GpuAllocator,
+        extent: vk::Extent2D,
+    ) -> Result<Self> {
+        let image_create_info = vk::ImageCreateInfoBuilder::new()
+            .image_type(vk::ImageType::_2D)
+            .format(DEPTH_FORMAT)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlagBits::_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+        let image = allocator.alloc_image(&image_create_info, UsageFlags::FAST_DEVICE_ACCESS);
+
+        let image_view_create_info = vk::ImageViewCreateInfoBuilder::new()
+            .image(image.image)
+            .view_type(vk::ImageViewType::_2D)
+            .format(DEPTH_FORMAT)
+            .subresource_range(subresource_range());
+
+        let image_view = unsafe {
+            vulkan
+                .device
+                .create_image_view(&image_view_create_info, None)
+                .result()?
+        };
+
+        Ok(Self { image, image_view })
+    }
+
+    pub fn image(&self) -> vk::Image {
+        self.image.image
+    }
+
+    pub unsafe fn destroy(self, vulkan: &VulkanInfo, allocator: &mut GpuAllocator) {
+        vulkan.device.destroy_image_view(self.image_view, None);
+        allocator.dealloc_image(self.image);
+    }
+}
+
+pub fn subresource_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::DEPTH,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    }
+}
@@ -10,6 +10,8 @@ pub struct Device {
     loader: Arc<DeviceLoader>,
     pub physical_device: vk::PhysicalDevice,
     pub queues: Queues,
+    /// Whether polygons may be drawn as lines, for wireframe rendering
+    pub supports_wireframe: bool,
 }
 
 impl Device {
@@ -48,11 +50,24 @@ impl Device {
         let mut synchronization2_features =
             vk::PhysicalDeviceSynchronization2FeaturesBuilder::new().synchronization2(true);
 
+        // transfer completion
+        let mut timeline_semaphore_features =
+            vk::PhysicalDeviceTimelineSemaphoreFeaturesBuilder::new().timeline_semaphore(true);
+
+        // wireframe rendering, where supported
+        let supported_features = unsafe { instance.get_physical_device_features(physical_device) };
+        let supports_wireframe = supported_features.fill_mode_non_solid != 0;
+
+        let features =
+            vk::PhysicalDeviceFeaturesBuilder::new().fill_mode_non_solid(supports_wireframe);
+
         let create_info = vk::DeviceCreateInfoBuilder::new()
             .queue_create_infos(&queue_create_infos)
+            .enabled_features(&features)
             .enabled_extension_names(&required_device_extensions)
             .extend_from(&mut dynamic_rendering_features)
-            .extend_from(&mut synchronization2_features);
+            .extend_from(&mut synchronization2_features)
+            .extend_from(&mut timeline_semaphore_features);
 
         let device_loader = unsafe { DeviceLoader::new(instance, physical_device, &create_info)? };
         let device_loader = Arc::new(device_loader);
@@ -85,6 +100,7 @@ impl Device {
             loader: device_loader,
             physical_device,
             queues,
+            supports_wireframe,
         })
     }
 }
@@ -16,6 +16,7 @@ pub struct CurrentFrameInfo {
     pub command_buffer: vk::CommandBuffer,
     pub acquire_semaphore: vk::Semaphore,
     pub instance_descriptor_set: vk::DescriptorSet,
+    pub instance_capacity: usize,
     instance_data_alignment: vk::DeviceSize,
 }
 
@@ -49,6 +50,7 @@ pub struct Frame {
     acquire_semaphore: vk::Semaphore,
     present_semaphore: vk::Semaphore,
     instance_data_alignment: vk::DeviceSize,
+    instance_capacity: usize,
     instance_buffer: GpuBuffer,
     instance_descriptor_set: vk::DescriptorSet,
 }
@@ -56,7 +58,11 @@ pub struct Frame {
 unsafe impl Send for Frame {}
 
 impl Frame {
-    pub fn new(vulkan: &VulkanInfo, allocator: &mut GpuAllocator) -> Result<Self> {
+    pub fn new(
+        vulkan: &VulkanInfo,
+        allocator: &mut GpuAllocator,
+        instance_capacity: usize,
+    ) -> Result<Self> {
         // descriptor pool
         let descriptor_pool_sizes = descriptor_pool_sizes();
 
@@ -151,12 +157,11 @@ impl Frame {
                 .min_uniform_buffer_offset_alignment
         };
 
-        let instance_data_alignment =
-            (size_of::<InstanceData>() as vk::DeviceSize + min_ubo_alignment - 1)
-                & !(min_ubo_alignment - 1);
+        let (instance_data_alignment, instance_buffer_size) =
+            instance_buffer_layout(min_ubo_alignment, instance_capacity);
 
         let uniform_buffer_create_info = vk::BufferCreateInfoBuilder::new()
-            .size(instance_data_alignment * 32)
+            .size(instance_buffer_size)
             .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
@@ -191,6 +196,7 @@ impl Frame {
             acquire_semaphore,
             present_semaphore,
             instance_data_alignment,
+            instance_capacity,
             instance_buffer,
             instance_descriptor_set,
         })
@@ -207,6 +213,22 @@ impl Frame {
     }
 }
 
+/// Returns the stride of instance data within the instance buffer, which must be a multiple of the
+/// device's minimum uniform buffer offset alignment, and the size of the buffer
+fn instance_buffer_layout(
+    min_ubo_alignment: vk::DeviceSize,
+    instance_capacity: usize,
+) -> (vk::DeviceSize, vk::DeviceSize) {
+    let instance_data_alignment = (size_of::<InstanceData>() as vk::DeviceSize + min_ubo_alignment
+        - 1)
+        & !(min_ubo_alignment - 1);
+
+    (
+        instance_data_alignment,
+        instance_data_alignment * instance_capacity as vk::DeviceSize,
+    )
+}
+
 impl Frame {
     pub fn begin(&self) -> Result<CurrentFrameInfo> {
         let command_buffer_begin_info = vk::CommandBufferBeginInfoBuilder::new()
@@ -216,7 +238,6 @@ impl Frame {
             self.device
                 .wait_for_fences(&[self.command_fence], false, u64::MAX)
                 .result()?;
-            self.device.reset_fences(&[self.command_fence]).result()?;
             self.device
                 .reset_command_pool(self.command_pool, vk::CommandPoolResetFlags::empty())
                 .result()?;
@@ -229,11 +250,19 @@ impl Frame {
             command_buffer: self.command_buffer,
             acquire_semaphore: self.acquire_semaphore,
             instance_descriptor_set: self.instance_descriptor_set,
+            instance_capacity: self.instance_capacity,
             instance_data_alignment: self.instance_data_alignment,
         })
     }
 
+    /// Maximum number of instances which may be updated and drawn
+    pub fn instance_capacity(&self) -> usize {
+        self.instance_capacity
+    }
+
     pub fn update_instance(&mut self, instance_index: usize, transform: &InstanceData) {
+        assert!(instance_index < self.instance_capacity);
+
         unsafe {
             self.instance_buffer.write(
                 &self.device,
@@ -243,27 +272,71 @@ impl Frame {
         };
     }
 
-    pub fn end_and_submit(&self, _current_frame_info: CurrentFrameInfo) -> Result<vk::Semaphore> {
-        let wait_semaphores = [self.acquire_semaphore];
-        let command_buffers = [self.command_buffer];
-        let signal_semaphores = [self.present_semaphore];
-
-        let submits_info = [vk::SubmitInfoBuilder::new()
-            .wait_semaphores(&wait_semaphores)
-            .wait_dst_stage_mask(&[vk::PipelineStageFlags::TOP_OF_PIPE])
-            .command_buffers(&command_buffers)
-            .signal_semaphores(&signal_semaphores)];
+    /// Submits the frame once the swapchain image is acquired and `transfer_wait` is reached.
+    /// The command fence is signaled when the frame completes, guarding reuse of the command
+    /// buffer, the semaphores, and the instance buffer.
+    pub fn end_and_submit(
+        &self,
+        _current_frame_info: CurrentFrameInfo,
+        transfer_wait: vk::SemaphoreSubmitInfoBuilder,
+    ) -> Result<vk::Semaphore> {
+        let wait_semaphore_infos = [
+            vk::SemaphoreSubmitInfoBuilder::new()
+                .semaphore(self.acquire_semaphore)
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS),
+            transfer_wait,
+        ];
+
+        let command_buffer_infos =
+            [vk::CommandBufferSubmitInfoBuilder::new().command_buffer(self.command_buffer)];
+
+        let signal_semaphore_infos = [vk::SemaphoreSubmitInfoBuilder::new()
+            .semaphore(self.present_semaphore)
+            .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)];
+
+        let submits_info = [vk::SubmitInfo2Builder::new()
+            .wait_semaphore_infos(&wait_semaphore_infos)
+            .command_buffer_infos(&command_buffer_infos)
+            .signal_semaphore_infos(&signal_semaphore_infos)];
 
         unsafe {
             self.device
                 .end_command_buffer(self.command_buffer)
                 .result()?;
 
+            // reset only once the submit is certain, so that a frame abandoned after begin,
+            // e.g. due to an out of date swapchain, doesn't leave the fence unsignaled
+            self.device.reset_fences(&[self.command_fence]).result()?;
+
             self.device
-                .queue_submit(self.graphics_queue, &submits_info, self.command_fence)
+                .queue_submit2(self.graphics_queue, &submits_info, self.command_fence)
                 .result()?;
         }
 
         Ok(self.present_semaphore)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_buffer_is_sized_by_capacity() {
+        let (stride, size) = instance_buffer_layout(256, 32);
+        let (larger_stride, larger_size) = instance_buffer_layout(256, 128);
+
+        assert_eq!(stride, larger_stride);
+        assert_eq!(size, 32 * stride);
+        assert_eq!(larger_size, 128 * stride);
+    }
+
+    #[test]
+    fn instance_data_is_aligned() {
+        for min_ubo_alignment in [1, 16, 64, 256] {
+            let (stride, _) = instance_buffer_layout(min_ubo_alignment, 1);
+            assert_eq!(stride % min_ubo_alignment, 0);
+            assert!(stride >= size_of::<InstanceData>() as vk::DeviceSize);
+        }
+    }
+}
@@ -1,32 +1,37 @@
 #![cfg(not(target_vendor = "apple"))]
 
-use std::{mem, slice};
+use std::{mem, sync::Arc};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use erupt::{vk, EntryLoader};
-use frame_buffer::FrameBufferReader;
-use nalgebra_glm::{look_at_lh, perspective_lh_zo, Mat4};
+use frame_buffer::{FrameBufferReader, RenderStyle};
+use game_resources::{pack_mesh, MeshLayout, Resource};
+use nalgebra_glm::Mat4;
 use pipeline::SceneData;
-use scene::Scene;
+use scene::{drawn_static_meshes, GpuMesh, Scene};
 use winit::{dpi::PhysicalSize, window::Window};
 
 use crate::{
     allocator::GpuAllocator,
+    depth_buffer::DepthBuffer,
     descriptor_set_layouts::{DescriptorSetLayouts, InstanceData},
     device::Device,
     frame::Frame,
     instance::Instance,
     pipeline::Pipeline,
+    pipeline_cache::PipelineCache,
     swapchain::Swapchain,
     transfer::Transfer,
 };
 
 mod allocator;
+mod depth_buffer;
 mod descriptor_set_layouts;
 mod device;
 mod frame;
 mod instance;
 mod pipeline;
+mod pipeline_cache;
 mod scene;
 mod static_mesh;
 mod swapchain;
@@ -55,13 +60,31 @@ pub struct Vulkan {
     current_frame_index: bool,
     allocator: GpuAllocator,
     pipeline: Pipeline,
+    /// None if the device doesn't support wireframe rendering
+    wireframe_pipeline: Option<Pipeline>,
+    ghost_pipeline: Pipeline,
+    wireframe: bool,
+    pipeline_cache: PipelineCache,
+    depth_buffer: DepthBuffer,
     swapchain: Swapchain,
+    /// Set when the swapchain no longer matches the surface, and recreated before the next frame
+    swapchain_out_of_date: bool,
+    /// A zero-sized surface has no valid swapchain, so nothing is rendered while minimized
+    minimized: bool,
     vulkan_info: VulkanInfo,
     aspect: f32,
 }
 
+/// Number of static mesh instances which may be drawn, unless set otherwise
+pub const DEFAULT_INSTANCE_CAPACITY: usize = 32;
+
 impl Vulkan {
     pub fn new(window: &Window) -> Result<Self> {
+        Self::with_instance_capacity(window, DEFAULT_INSTANCE_CAPACITY)
+    }
+
+    /// Sizes per-instance GPU buffers for up to `instance_capacity` static meshes
+    pub fn with_instance_capacity(window: &Window, instance_capacity: usize) -> Result<Self> {
         let entry = EntryLoader::new()?;
         let instance = Instance::new(&entry, window)?;
         let device = Device::new(&instance)?;
@@ -76,21 +99,56 @@ impl Vulkan {
 
         let swapchain = Swapchain::new(&vulkan_info)?;
 
-        let pipeline = Pipeline::new(&vulkan_info, &swapchain, "default")?;
+        let pipeline_cache = PipelineCache::new(&vulkan_info)?;
+
+        let pipeline = Pipeline::new(
+            &vulkan_info,
+            &swapchain,
+            &pipeline_cache,
+            "default",
+            vk::PolygonMode::FILL,
+            false,
+        )?;
+
+        let wireframe_pipeline = if vulkan_info.device.supports_wireframe {
+            Some(Pipeline::new(
+                &vulkan_info,
+                &swapchain,
+                &pipeline_cache,
+                "default",
+                vk::PolygonMode::LINE,
+                false,
+            )?)
+        } else {
+            log::warn!("wireframe rendering is not supported by this device");
+            None
+        };
+
+        let ghost_pipeline = Pipeline::new(
+            &vulkan_info,
+            &swapchain,
+            &pipeline_cache,
+            "default",
+            vk::PolygonMode::FILL,
+            true,
+        )?;
 
         let mut allocator = GpuAllocator::new(&vulkan_info)?;
 
+        let depth_buffer =
+            DepthBuffer::new(&vulkan_info, &mut allocator, swapchain.surface_extent)?;
+
         let transfer = Transfer::new(&vulkan_info)?;
 
         let frames = [
-            Frame::new(&vulkan_info, &mut allocator)?,
-            Frame::new(&vulkan_info, &mut allocator)?,
+            Frame::new(&vulkan_info, &mut allocator, instance_capacity)?,
+            Frame::new(&vulkan_info, &mut allocator, instance_capacity)?,
         ];
 
         let size = window.inner_size();
         let aspect = size.width as f32 / size.height as f32;
 
-        let scene = Scene::new(&mut allocator);
+        let scene = Scene::new(&mut allocator, instance_capacity);
 
         Ok(Self {
             scene,
@@ -99,18 +157,32 @@ impl Vulkan {
             current_frame_index: false,
             allocator,
             pipeline,
+            wireframe_pipeline,
+            ghost_pipeline,
+            wireframe: false,
+            pipeline_cache,
+            depth_buffer,
             swapchain,
+            swapchain_out_of_date: false,
+            minimized: false,
             vulkan_info,
             aspect,
         })
     }
 
     pub fn destroy(mut self) {
+        if let Err(err) = self.pipeline_cache.save() {
+            log::warn!("failed to save pipeline cache: {err}");
+        }
+
         unsafe {
             self.vulkan_info.device.device_wait_idle().unwrap();
 
             self.scene.destroy(&mut self.allocator);
 
+            self.depth_buffer
+                .destroy(&self.vulkan_info, &mut self.allocator);
+
             for frame in self.frames {
                 frame.destroy(&mut self.allocator);
             }
@@ -120,39 +192,91 @@ impl Vulkan {
 
 impl Vulkan {
     pub fn window_resized(&mut self, size: PhysicalSize<u32>) {
-        self.aspect = size.width as f32 / size.height as f32;
+        self.minimized = size.width == 0 || size.height == 0;
+        if !self.minimized {
+            self.aspect = size.width as f32 / size.height as f32;
+        }
+
+        self.swapchain_out_of_date = true;
+    }
+
+    /// Fails if the device doesn't support wireframe rendering
+    pub fn set_wireframe(&mut self, wireframe: bool) -> Result<()> {
+        if wireframe && self.wireframe_pipeline.is_none() {
+            bail!("wireframe rendering is not supported by this device");
+        }
+
+        self.wireframe = wireframe;
+        Ok(())
     }
 
     pub async fn frame(&mut self, frame_buffer: &FrameBufferReader<'_>) {
         self.update_scene(frame_buffer);
 
+        if self.minimized {
+            return;
+        }
+
+        if self.swapchain_out_of_date {
+            self.recreate_swapchain().unwrap();
+        }
+
+        // waits until the GPU has finished the previous use of this frame's resources. The
+        // other frame may still be in flight.
         let frame_info = self.frames[self.current_frame_index as usize]
             .begin()
             .unwrap();
 
-        let swapchain_image_index = self
+        self.update_instances();
+
+        let swapchain_image_index = match self
             .swapchain
             .acquire_next_image(frame_info.acquire_semaphore)
-            .unwrap();
+            .unwrap()
+        {
+            Some(swapchain_image_index) => swapchain_image_index,
+            None => {
+                // nothing was submitted, so the frame may simply be begun again next time
+                self.swapchain_out_of_date = true;
+                return;
+            }
+        };
 
         let swapchain_image = self.swapchain.images[swapchain_image_index as usize];
         let swapchain_image_view = self.swapchain.image_views[swapchain_image_index as usize];
 
-        // transition swapchain image to color attachment
-
-        let image_memory_barriers = [vk::ImageMemoryBarrier2Builder::new()
-            .src_stage_mask(vk::PipelineStageFlags2::TOP_OF_PIPE)
-            .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
-            .dst_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
-            .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-            .image(swapchain_image)
-            .subresource_range(vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                layer_count: 1,
-            })];
+        // transition swapchain image to color attachment, and depth buffer to depth attachment
+        // once the previous frame's depth tests are complete
+
+        let image_memory_barriers = [
+            vk::ImageMemoryBarrier2Builder::new()
+                .src_stage_mask(vk::PipelineStageFlags2::TOP_OF_PIPE)
+                .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                .dst_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+                .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .image(swapchain_image)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                }),
+            vk::ImageMemoryBarrier2Builder::new()
+                .src_stage_mask(vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS)
+                .src_access_mask(vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_stage_mask(
+                    vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                        | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+                )
+                .dst_access_mask(
+                    vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                        | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .image(self.depth_buffer.image())
+                .subresource_range(depth_buffer::subresource_range()),
+        ];
 
         let dependency_info =
             vk::DependencyInfoBuilder::new().image_memory_barriers(&image_memory_barriers);
@@ -176,37 +300,62 @@ impl Vulkan {
                 },
             })];
 
+        let depth_attachment = vk::RenderingAttachmentInfoBuilder::new()
+            .image_view(self.depth_buffer.image_view)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .clear_value(vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            });
+
         let rendering_info = vk::RenderingInfoBuilder::new()
             .render_area(vk::Rect2D {
                 offset: vk::Offset2D { x: 0, y: 0 },
                 extent: self.swapchain.surface_extent,
             })
             .layer_count(1)
-            .color_attachments(&color_attachments);
+            .color_attachments(&color_attachments)
+            .depth_attachment(&depth_attachment);
 
         unsafe {
             self.vulkan_info
                 .device
                 .cmd_begin_rendering(frame_info.command_buffer, &rendering_info);
-        }
 
-        // render static mesh instances
+            let extent = self.swapchain.surface_extent;
+
+            let viewport = vk::ViewportBuilder::new()
+                .width(extent.width as f32)
+                .height(extent.height as f32)
+                .min_depth(0.0)
+                .max_depth(1.0);
+
+            let scissor = vk::Rect2DBuilder::new()
+                .offset(vk::Offset2D { x: 0, y: 0 })
+                .extent(extent);
+
+            self.vulkan_info
+                .device
+                .cmd_set_viewport(frame_info.command_buffer, 0, &[viewport]);
 
-        self.pipeline.bind(frame_info.command_buffer);
+            self.vulkan_info
+                .device
+                .cmd_set_scissor(frame_info.command_buffer, 0, &[scissor]);
+        }
+
+        // render static mesh instances, translucent meshes last so they blend over the scene
 
         let scene_data = {
             let camera_info = frame_buffer.camera_info();
 
-            let mut proj_matrix = perspective_lh_zo(
-                self.aspect,
-                camera_info.fov,
-                camera_info.near_plane,
-                camera_info.far_plane,
-            );
+            let mut proj_matrix = camera_info.proj_matrix(self.aspect);
             proj_matrix[5] *= -1.0;
 
-            let view_matrix =
-                look_at_lh(&camera_info.location, &camera_info.focus, &camera_info.up);
+            let view_matrix = camera_info.view_matrix();
 
             SceneData {
                 proj_matrix,
@@ -214,42 +363,66 @@ impl Vulkan {
             }
         };
 
-        unsafe {
-            self.vulkan_info.device.cmd_push_constants(
-                frame_info.command_buffer,
-                self.pipeline.layout(),
-                vk::ShaderStageFlags::VERTEX,
-                0,
-                mem::size_of::<SceneData>() as u32,
-                &scene_data as *const _ as *const _,
-            )
-        }
+        let opaque_pipeline = match &self.wireframe_pipeline {
+            Some(wireframe_pipeline) if self.wireframe => wireframe_pipeline,
+            _ => &self.pipeline,
+        };
 
-        for (i, static_mesh) in self.scene.static_meshes.values().enumerate() {
-            frame_info.bind_instance_descriptor_set(
-                &self.vulkan_info.device,
-                i,
-                self.pipeline.layout(),
-            );
+        for (pipeline, translucent) in [(opaque_pipeline, false), (&self.ghost_pipeline, true)] {
+            pipeline.bind(frame_info.command_buffer);
 
             unsafe {
-                self.vulkan_info.device.cmd_bind_index_buffer(
+                self.vulkan_info.device.cmd_push_constants(
                     frame_info.command_buffer,
-                    static_mesh.vertex_buffer.buffer,
+                    pipeline.layout(),
+                    vk::ShaderStageFlags::VERTEX,
                     0,
-                    vk::IndexType::UINT16,
-                );
+                    mem::size_of::<SceneData>() as u32,
+                    &scene_data as *const _ as *const _,
+                )
+            }
 
-                self.vulkan_info.device.cmd_bind_vertex_buffers(
-                    frame_info.command_buffer,
-                    0,
-                    &[static_mesh.vertex_buffer.buffer],
-                    &[static_mesh.vertex_offset],
+            for (i, static_mesh) in drawn_static_meshes(
+                &self.scene.static_meshes,
+                frame_info.instance_capacity,
+                translucent,
+            ) {
+                frame_info.bind_instance_descriptor_set(
+                    &self.vulkan_info.device,
+                    i,
+                    pipeline.layout(),
                 );
 
-                self.vulkan_info
-                    .device
-                    .cmd_draw_indexed(frame_info.command_buffer, 3, 1, 0, 0, 0);
+                unsafe {
+                    if let Some(blend_color) = static_mesh.style.blend_color() {
+                        self.vulkan_info
+                            .device
+                            .cmd_set_blend_constants(frame_info.command_buffer, blend_color);
+                    }
+
+                    self.vulkan_info.device.cmd_bind_index_buffer(
+                        frame_info.command_buffer,
+                        static_mesh.vertex_buffer,
+                        0,
+                        vk::IndexType::UINT16,
+                    );
+
+                    self.vulkan_info.device.cmd_bind_vertex_buffers(
+                        frame_info.command_buffer,
+                        0,
+                        &[static_mesh.vertex_buffer],
+                        &[static_mesh.vertex_offset],
+                    );
+
+                    self.vulkan_info.device.cmd_draw_indexed(
+                        frame_info.command_buffer,
+                        static_mesh.index_count,
+                        1,
+                        0,
+                        0,
+                        0,
+                    );
+                }
             }
         }
 
@@ -286,90 +459,161 @@ impl Vulkan {
         }
 
         let present_semaphore = self.frames[self.current_frame_index as usize]
-            .end_and_submit(frame_info)
+            .end_and_submit(frame_info, self.transfer.wait_info())
             .unwrap();
 
-        self.swapchain
+        if self
+            .swapchain
             .present(present_semaphore, swapchain_image_index)
-            .unwrap();
+            .unwrap()
+        {
+            self.swapchain_out_of_date = true;
+        }
 
         self.current_frame_index = !self.current_frame_index;
     }
 
+    /// Rebuilds the swapchain and depth buffer at the current surface extent
+    fn recreate_swapchain(&mut self) -> Result<()> {
+        unsafe {
+            self.vulkan_info.device.device_wait_idle().result()?;
+        }
+
+        self.swapchain.recreate(&self.vulkan_info)?;
+
+        let depth_buffer = DepthBuffer::new(
+            &self.vulkan_info,
+            &mut self.allocator,
+            self.swapchain.surface_extent,
+        )?;
+
+        let old_depth_buffer = mem::replace(&mut self.depth_buffer, depth_buffer);
+
+        unsafe {
+            old_depth_buffer.destroy(&self.vulkan_info, &mut self.allocator);
+        }
+
+        self.swapchain_out_of_date = false;
+
+        Ok(())
+    }
+
+    /// Returns the vertex buffer and layout of the resource's mesh, transferring it to the GPU on
+    /// first use
+    fn upload_mesh(
+        &mut self,
+        resource: Option<&Arc<Resource>>,
+    ) -> Option<(vk::Buffer, MeshLayout)> {
+        let transfer = &mut self.transfer;
+        let allocator = &mut self.allocator;
+
+        let mesh = self.scene.meshes.get_or_upload(resource, |mesh| {
+            // align vertices to 4 bytes
+            let (data, layout) = pack_mesh(mesh, 4)?;
+
+            let buffer = transfer.transfer_buffer(
+                &data,
+                vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER,
+                allocator,
+            );
+
+            Ok(GpuMesh { buffer, layout })
+        })?;
+
+        Some((mesh.buffer.buffer, mesh.layout))
+    }
+
     fn update_scene(&mut self, frame_buffer: &FrameBufferReader<'_>) {
         // despawn
 
-        for buffer in self.scene.delete_queue.drain(..) {
-            self.allocator.dealloc(buffer);
-        }
+        // entities may have no mesh if their upload was rejected
 
         for entity_id in frame_buffer.despawned() {
-            let static_mesh = self.scene.static_meshes.remove(*entity_id);
-            self.scene.delete_queue.push(static_mesh.vertex_buffer);
+            self.scene.static_meshes.try_remove(*entity_id);
         }
 
         for (old_id, new_id) in frame_buffer.updated_entity_ids() {
-            let static_mesh = self.scene.static_meshes.remove(*old_id);
-            self.scene.static_meshes.insert(*new_id, static_mesh);
+            if let Some(static_mesh) = self.scene.static_meshes.try_remove(*old_id) {
+                self.scene.static_meshes.insert(*new_id, static_mesh);
+            }
         }
 
         // spawn
 
         self.transfer.begin_transfers(&mut self.allocator).unwrap();
 
-        for entity_id in frame_buffer
+        let spawned_static_meshes = frame_buffer
             .spawned_static_meshes()
-            .map(|static_mesh| &static_mesh.entity_id)
-            .chain(frame_buffer.spawned_guests())
-        {
-            const INDICES: [u16; 3] = [0, 1, 2];
-            const VERTEX_DATA: [f32; 9] = [0.0, 0.0, 1.0, -1.0, 0.0, -1.0, 1.0, 0.0, -1.0];
-
-            const VERTEX_OFFSET: usize = 8;
-            const SIZE: usize = VERTEX_OFFSET + mem::size_of::<[f32; 9]>();
-
-            let mut data = [0; SIZE];
-
-            unsafe {
-                let data = data.as_mut_ptr();
-                let indices = slice::from_raw_parts_mut(data as *mut u16, INDICES.len());
-                indices.copy_from_slice(&INDICES);
-
-                let data = data.add(VERTEX_OFFSET);
-                let vertices = slice::from_raw_parts_mut(data as *mut f32, VERTEX_DATA.len());
-                vertices.copy_from_slice(&VERTEX_DATA);
-            }
+            .map(|static_mesh| (static_mesh.entity_id, Some(&static_mesh.resource)));
 
-            let vertex_buffer = self.transfer.transfer_buffer(
-                &data,
-                vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER,
-                &mut self.allocator,
-            );
+        let spawned_guests = frame_buffer
+            .spawned_guests()
+            .map(|guest| (guest.entity_id, guest.resource.as_ref()));
 
-            // create vertex buffer and transfer
+        for (entity_id, resource) in spawned_static_meshes.chain(spawned_guests) {
+            let (vertex_buffer, layout) = match self.upload_mesh(resource) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
 
             self.scene.static_meshes.insert(
-                *entity_id,
+                entity_id,
                 scene::StaticMesh {
                     vertex_buffer,
-                    vertex_offset: VERTEX_OFFSET as vk::DeviceSize,
+                    vertex_offset: layout.vertex_offset as vk::DeviceSize,
+                    index_count: layout.index_count,
                     transform: Mat4::identity(),
+                    visible: true,
+                    style: RenderStyle::Solid,
                 },
             );
+
+            let instance_capacity = self.frames[0].instance_capacity();
+            if self.scene.static_meshes.len() > instance_capacity {
+                log::warn!(
+                    "static mesh count exceeds instance capacity ({instance_capacity}), \
+                    entity {entity_id} will not be drawn"
+                );
+            }
         }
 
         self.transfer.submit_transfers().unwrap();
 
+        for (entity_id, visible) in frame_buffer.visibility() {
+            if let Some(static_mesh) = self.scene.static_meshes.get_mut(*entity_id) {
+                static_mesh.visible = *visible;
+            }
+        }
+
+        for (entity_id, style) in frame_buffer.render_styles() {
+            if let Some(static_mesh) = self.scene.static_meshes.get_mut(*entity_id) {
+                static_mesh.style = *style;
+            }
+        }
+
         // update instances
 
-        for (entity_id, location) in frame_buffer.locations() {
-            let transform = nalgebra_glm::translate(&Mat4::identity(), location);
-            self.scene.static_meshes[entity_id].transform = transform;
+        for (entity_id, transform) in frame_buffer.transforms() {
+            if let Some(static_mesh) = self.scene.static_meshes.get_mut(entity_id) {
+                static_mesh.transform = transform.matrix();
+            }
         }
+    }
 
+    /// Writes instance data for the current frame. The frame must have begun, which guarantees
+    /// the GPU is no longer reading its instance buffer.
+    fn update_instances(&mut self) {
         let frame = &mut self.frames[self.current_frame_index as usize];
 
-        for (i, static_mesh) in self.scene.static_meshes.values().enumerate() {
+        let instance_capacity = frame.instance_capacity();
+
+        for (i, static_mesh) in self
+            .scene
+            .static_meshes
+            .values()
+            .enumerate()
+            .take(instance_capacity)
+        {
             frame.update_instance(
                 i,
                 &InstanceData {
@@ -377,9 +621,5 @@ impl Vulkan {
                 },
             );
         }
-
-        unsafe {
-            self.vulkan_info.device.device_wait_idle().unwrap();
-        }
     }
 }
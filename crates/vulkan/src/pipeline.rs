@@ -5,7 +5,10 @@ use erupt::{utils::decode_spv, vk, DeviceLoader, ExtendableFrom};
 use memoffset::offset_of;
 use nalgebra_glm::Mat4;
 
-use crate::{cstr, static_mesh::Vertex, swapchain::Swapchain, VulkanInfo};
+use crate::{
+    cstr, depth_buffer::DEPTH_FORMAT, pipeline_cache::PipelineCache, static_mesh::Vertex,
+    swapchain::Swapchain, VulkanInfo,
+};
 
 #[repr(C)]
 pub struct SceneData {
@@ -20,7 +23,14 @@ pub struct Pipeline {
 }
 
 impl Pipeline {
-    pub fn new(vulkan: &VulkanInfo, swapchain: &Swapchain, shader_name: &str) -> Result<Self> {
+    pub fn new(
+        vulkan: &VulkanInfo,
+        swapchain: &Swapchain,
+        cache: &PipelineCache,
+        shader_name: &str,
+        polygon_mode: vk::PolygonMode,
+        translucent: bool,
+    ) -> Result<Self> {
         let shader_entry = cstr!("main");
         let shader = Shader::new(vulkan, shader_name, unsafe { CStr::from_ptr(shader_entry) })?;
 
@@ -42,22 +52,13 @@ impl Pipeline {
         let input_assembly_create_info = vk::PipelineInputAssemblyStateCreateInfoBuilder::new()
             .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
 
-        let viewports = [vk::ViewportBuilder::new()
-            .width(swapchain.surface_extent.width as f32)
-            .height(swapchain.surface_extent.height as f32)
-            .min_depth(0.0)
-            .max_depth(1.0)];
-
-        let scissors = [vk::Rect2DBuilder::new()
-            .offset(vk::Offset2D { x: 0, y: 0 })
-            .extent(swapchain.surface_extent)];
-
+        // viewport and scissor are dynamic, so pipelines survive swapchain recreation
         let viewport_create_info = vk::PipelineViewportStateCreateInfoBuilder::new()
-            .viewports(&viewports)
-            .scissors(&scissors);
+            .viewport_count(1)
+            .scissor_count(1);
 
         let rasterization_create_info = vk::PipelineRasterizationStateCreateInfoBuilder::new()
-            .polygon_mode(vk::PolygonMode::FILL)
+            .polygon_mode(polygon_mode)
             .cull_mode(vk::CullModeFlags::BACK)
             .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
             .line_width(1.0);
@@ -67,21 +68,43 @@ impl Pipeline {
 
         let depth_stencil_create_info = vk::PipelineDepthStencilStateCreateInfoBuilder::new()
             .depth_test_enable(true)
-            .depth_write_enable(true)
+            .depth_write_enable(!translucent)
             .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
             .depth_bounds_test_enable(false)
             .stencil_test_enable(false);
 
+        // translucent pipelines blend the dynamic blend constant over the scene, which tints
+        // meshes without requiring a separate shader
         let color_blend_attachments = [vk::PipelineColorBlendAttachmentStateBuilder::new()
-            .blend_enable(false)
+            .blend_enable(translucent)
+            .src_color_blend_factor(vk::BlendFactor::CONSTANT_COLOR)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_CONSTANT_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+            .alpha_blend_op(vk::BlendOp::ADD)
             .color_write_mask(vk::ColorComponentFlags::all())];
 
         let color_blend_create_info = vk::PipelineColorBlendStateCreateInfoBuilder::new()
             .attachments(&color_blend_attachments);
 
+        let dynamic_states: &[vk::DynamicState] = if translucent {
+            &[
+                vk::DynamicState::VIEWPORT,
+                vk::DynamicState::SCISSOR,
+                vk::DynamicState::BLEND_CONSTANTS,
+            ]
+        } else {
+            &[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]
+        };
+
+        let dynamic_state_create_info =
+            vk::PipelineDynamicStateCreateInfoBuilder::new().dynamic_states(dynamic_states);
+
         let color_attachment_formats = [swapchain.surface_format.format];
         let mut pipeline_rendering_create_info = vk::PipelineRenderingCreateInfoBuilder::new()
-            .color_attachment_formats(&color_attachment_formats);
+            .color_attachment_formats(&color_attachment_formats)
+            .depth_attachment_format(DEPTH_FORMAT);
 
         let pipeline_layout = pipeline_layout(vulkan)?;
 
@@ -94,13 +117,14 @@ impl Pipeline {
             .multisample_state(&multisample_create_info)
             .depth_stencil_state(&depth_stencil_create_info)
             .color_blend_state(&color_blend_create_info)
+            .dynamic_state(&dynamic_state_create_info)
             .layout(pipeline_layout)
             .extend_from(&mut pipeline_rendering_create_info)];
 
         let pipeline = unsafe {
             vulkan
                 .device
-                .create_graphics_pipelines(vk::PipelineCache::null(), &pipeline_create_info, None)
+                .create_graphics_pipelines(cache.handle(), &pipeline_create_info, None)
                 .map_err(|_| Error::msg("create_graphics_pipelines"))?[0]
         };
 
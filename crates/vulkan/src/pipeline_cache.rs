@@ -0,0 +1,270 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use erupt::{vk, DeviceLoader};
+
+use crate::VulkanInfo;
+
+/// Relative to the user's cache directory
+const PIPELINE_CACHE_PATH: &str = "theme-park-sim/pipeline_cache.bin";
+
+pub struct PipelineCache {
+    device: Arc<DeviceLoader>,
+    cache: vk::PipelineCache,
+    file: CacheFile,
+}
+
+impl PipelineCache {
+    /// Loads the cache from the user's cache directory
+    pub fn new(vulkan: &VulkanInfo) -> Result<Self> {
+        Self::with_path(vulkan, &user_cache_dir().join(PIPELINE_CACHE_PATH))
+    }
+
+    /// Loads the cache from `path` if present. A missing, unreadable or foreign file results in
+    /// an empty cache, as does a file the driver rejects.
+    pub fn with_path(vulkan: &VulkanInfo, path: &Path) -> Result<Self> {
+        let file = CacheFile {
+            path: path.to_owned(),
+        };
+        let initial_data = file.read(&CacheHeader::new(vulkan));
+
+        let cache = match unsafe { create_pipeline_cache(vulkan, &initial_data) } {
+            Ok(cache) => cache,
+            Err(err) if !initial_data.is_empty() => {
+                log::warn!("discarding pipeline cache {}: {err}", path.display());
+                unsafe { create_pipeline_cache(vulkan, &[])? }
+            }
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            device: vulkan.device.clone_loader(),
+            cache,
+            file,
+        })
+    }
+
+    pub fn handle(&self) -> vk::PipelineCache {
+        self.cache
+    }
+
+    /// Writes the cache to the path it was loaded from
+    pub fn save(&self) -> Result<()> {
+        // query the size, then fill the data
+        let mut size = 0;
+        unsafe {
+            self.device
+                .get_pipeline_cache_data(self.cache, &mut size, std::ptr::null_mut())
+                .result()?;
+        }
+
+        let mut data = vec![0u8; size];
+        unsafe {
+            self.device
+                .get_pipeline_cache_data(self.cache, &mut size, data.as_mut_ptr() as _)
+                .result()?;
+        }
+        data.truncate(size);
+
+        self.file.write(&data)
+    }
+}
+
+impl Drop for PipelineCache {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline_cache(self.cache, None);
+        }
+    }
+}
+
+/// Identifies the device a cache was created by, as stored at the start of the cache data
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct CacheHeader {
+    vendor_id: u32,
+    device_id: u32,
+    uuid: [u8; vk::UUID_SIZE as usize],
+}
+
+impl CacheHeader {
+    /// Size of a `VK_PIPELINE_CACHE_HEADER_VERSION_ONE` header
+    const SIZE: usize = 16 + vk::UUID_SIZE as usize;
+
+    fn new(vulkan: &VulkanInfo) -> Self {
+        let properties = unsafe {
+            vulkan
+                .instance
+                .get_physical_device_properties(vulkan.device.physical_device)
+        };
+
+        Self {
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            uuid: properties.pipeline_cache_uuid,
+        }
+    }
+
+    /// Returns None if the data doesn't begin with a version one header
+    fn parse(data: &[u8]) -> Option<Self> {
+        let word = |i: usize| u32::from_le_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+
+        if data.len() < Self::SIZE
+            || (word(0) as usize) < Self::SIZE
+            || word(1) != vk::PipelineCacheHeaderVersion::ONE.0 as u32
+        {
+            return None;
+        }
+
+        Some(Self {
+            vendor_id: word(2),
+            device_id: word(3),
+            uuid: data[16..Self::SIZE].try_into().unwrap(),
+        })
+    }
+}
+
+/// The on-disk copy of a pipeline cache
+struct CacheFile {
+    path: PathBuf,
+}
+
+impl CacheFile {
+    /// Returns the cache data if it was created by the device with the given header, or no data
+    /// if the file is missing, unreadable, or from another device. Some drivers crash on data from
+    /// other devices rather than rejecting it, so it is never passed on.
+    fn read(&self, header: &CacheHeader) -> Vec<u8> {
+        let data = fs::read(&self.path).unwrap_or_default();
+
+        if data.is_empty() || CacheHeader::parse(&data).as_ref() == Some(header) {
+            data
+        } else {
+            log::warn!("discarding pipeline cache {}", self.path.display());
+            Vec::new()
+        }
+    }
+
+    fn write(&self, data: &[u8]) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // write then rename, so that an interrupted save can't leave a truncated cache behind
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, data)
+            .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("failed to write {}", self.path.display()))?;
+
+        Ok(())
+    }
+}
+
+/// The platform's per-user cache directory, or the temporary directory if it can't be found
+fn user_cache_dir() -> PathBuf {
+    #[cfg(windows)]
+    let dir = env::var_os("LOCALAPPDATA").map(PathBuf::from);
+
+    #[cfg(not(windows))]
+    let dir = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")));
+
+    dir.unwrap_or_else(env::temp_dir)
+}
+
+unsafe fn create_pipeline_cache(
+    vulkan: &VulkanInfo,
+    initial_data: &[u8],
+) -> Result<vk::PipelineCache> {
+    let create_info = vk::PipelineCacheCreateInfoBuilder::new()
+        .initial_data_size(initial_data.len())
+        .initial_data(initial_data.as_ptr() as _);
+    let cache = vulkan
+        .device
+        .create_pipeline_cache(&create_info, None)
+        .result()?;
+
+    Ok(cache)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    const HEADER: CacheHeader = CacheHeader {
+        vendor_id: 0x10de,
+        device_id: 0x2204,
+        uuid: [7; vk::UUID_SIZE as usize],
+    };
+
+    /// Cache data as a driver would write it: a version one header followed by the payload
+    fn cache_data(header: &CacheHeader, payload: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend((CacheHeader::SIZE as u32).to_le_bytes());
+        data.extend((vk::PipelineCacheHeaderVersion::ONE.0 as u32).to_le_bytes());
+        data.extend(header.vendor_id.to_le_bytes());
+        data.extend(header.device_id.to_le_bytes());
+        data.extend(header.uuid);
+        data.extend(payload);
+        data
+    }
+
+    /// A cache file in a directory unique to the test
+    fn cache_file(test_name: &str) -> CacheFile {
+        let dir = env::temp_dir().join(format!("{test_name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        CacheFile {
+            path: dir.join("pipeline_cache.bin"),
+        }
+    }
+
+    #[test]
+    fn saved_cache_is_reloaded() {
+        let file = cache_file("saved_cache_is_reloaded");
+        let data = cache_data(&HEADER, &[1, 2, 3]);
+
+        file.write(&data).unwrap();
+
+        assert_eq!(file.read(&HEADER), data);
+        assert!(!file.path.with_extension("tmp").exists());
+    }
+
+    #[test]
+    fn missing_cache_is_empty() {
+        let file = cache_file("missing_cache_is_empty");
+        assert!(file.read(&HEADER).is_empty());
+    }
+
+    #[test]
+    fn corrupt_cache_is_discarded() {
+        let file = cache_file("corrupt_cache_is_discarded");
+
+        file.write(b"not a pipeline cache").unwrap();
+        assert!(file.read(&HEADER).is_empty());
+
+        let mut truncated = cache_data(&HEADER, &[]);
+        truncated.pop();
+        file.write(&truncated).unwrap();
+        assert!(file.read(&HEADER).is_empty());
+    }
+
+    #[test]
+    fn cache_from_another_device_is_discarded() {
+        let file = cache_file("cache_from_another_device_is_discarded");
+        let other_device = CacheHeader {
+            device_id: 0x2206,
+            ..HEADER
+        };
+
+        file.write(&cache_data(&other_device, &[1, 2, 3])).unwrap();
+
+        assert!(file.read(&HEADER).is_empty());
+    }
+}
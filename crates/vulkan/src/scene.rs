@@ -1,5 +1,7 @@
 use erupt::vk;
+use frame_buffer::RenderStyle;
 use game_entity::EntityMap;
+use game_resources::{MeshCache, MeshLayout};
 use gpu_alloc::UsageFlags;
 use nalgebra_glm::Mat4;
 
@@ -7,20 +9,44 @@ use crate::allocator::{GpuAllocator, GpuBuffer};
 
 pub struct Scene {
     pub static_meshes: EntityMap<StaticMesh>,
-    pub delete_queue: Vec<GpuBuffer>,
+    pub meshes: MeshCache<GpuMesh>,
     pub guests_buffer: GpuBuffer,
 }
 
+pub struct GpuMesh {
+    pub buffer: GpuBuffer,
+    pub layout: MeshLayout,
+}
+
 pub struct StaticMesh {
-    pub vertex_buffer: GpuBuffer,
+    /// Combined index and vertex buffer, owned by `Scene::meshes`
+    pub vertex_buffer: vk::Buffer,
     pub vertex_offset: vk::DeviceSize,
+    pub index_count: u32,
     pub transform: Mat4,
+    pub visible: bool,
+    pub style: RenderStyle,
+}
+
+/// Static meshes drawn in the translucent or opaque pass, with their instance indices. Hidden
+/// meshes keep their buffers but are skipped, as are meshes beyond the instance capacity.
+pub fn drawn_static_meshes(
+    static_meshes: &EntityMap<StaticMesh>,
+    instance_capacity: usize,
+    translucent: bool,
+) -> impl Iterator<Item = (usize, &StaticMesh)> {
+    static_meshes
+        .values()
+        .enumerate()
+        .take(instance_capacity)
+        .filter(|(_, static_mesh)| static_mesh.visible)
+        .filter(move |(_, static_mesh)| static_mesh.style.blend_color().is_some() == translucent)
 }
 
 impl Scene {
-    pub fn new(allocator: &mut GpuAllocator) -> Self {
+    pub fn new(allocator: &mut GpuAllocator, instance_capacity: usize) -> Self {
         let buffer_info = vk::BufferCreateInfoBuilder::new()
-            .size(8 * 32) // TEMP
+            .size(8 * instance_capacity as vk::DeviceSize) // TEMP
             .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
@@ -28,16 +54,76 @@ impl Scene {
 
         Self {
             static_meshes: EntityMap::new(),
-            delete_queue: Vec::new(),
+            meshes: MeshCache::default(),
             guests_buffer,
         }
     }
 
     pub unsafe fn destroy(self, allocator: &mut GpuAllocator) {
-        for (_, static_mesh) in self.static_meshes {
-            allocator.dealloc(static_mesh.vertex_buffer);
+        for mesh in self.meshes.into_meshes() {
+            allocator.dealloc(mesh.buffer);
         }
 
         allocator.dealloc(self.guests_buffer);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use game_entity::EntityId;
+
+    use super::*;
+
+    fn static_mesh(style: RenderStyle) -> StaticMesh {
+        StaticMesh {
+            vertex_buffer: vk::Buffer::null(),
+            vertex_offset: 0,
+            index_count: 3,
+            transform: Mat4::identity(),
+            visible: true,
+            style,
+        }
+    }
+
+    fn draw_count(static_meshes: &EntityMap<StaticMesh>) -> usize {
+        [false, true]
+            .into_iter()
+            .map(|translucent| drawn_static_meshes(static_meshes, 32, translucent).count())
+            .sum()
+    }
+
+    #[test]
+    fn hidden_meshes_are_not_drawn_but_kept() {
+        let mut static_meshes = EntityMap::new();
+        for id in 1..=3 {
+            static_meshes.insert(EntityId::new(id), static_mesh(RenderStyle::Solid));
+        }
+        assert_eq!(draw_count(&static_meshes), 3);
+
+        static_meshes.get_mut(EntityId::new(2)).unwrap().visible = false;
+        assert_eq!(draw_count(&static_meshes), 2);
+        assert_eq!(static_meshes.len(), 3);
+
+        static_meshes.get_mut(EntityId::new(2)).unwrap().visible = true;
+        assert_eq!(draw_count(&static_meshes), 3);
+        assert_eq!(static_meshes.len(), 3);
+    }
+
+    #[test]
+    fn meshes_are_drawn_in_their_style_pass_within_capacity() {
+        let mut static_meshes = EntityMap::new();
+        static_meshes.insert(EntityId::new(1), static_mesh(RenderStyle::Solid));
+        static_meshes.insert(EntityId::new(2), static_mesh(RenderStyle::Ghost));
+        static_meshes.insert(EntityId::new(3), static_mesh(RenderStyle::Solid));
+
+        let opaque: Vec<_> = drawn_static_meshes(&static_meshes, 2, false)
+            .map(|(i, _)| i)
+            .collect();
+        let translucent: Vec<_> = drawn_static_meshes(&static_meshes, 2, true)
+            .map(|(i, _)| i)
+            .collect();
+
+        assert_eq!(opaque, [0]);
+        assert_eq!(translucent, [1]);
+    }
+}
@@ -18,6 +18,18 @@ pub struct Swapchain {
 
 impl Swapchain {
     pub fn new(vulkan: &VulkanInfo) -> Result<Self> {
+        Self::with_old_swapchain(vulkan, vk::SwapchainKHR::null())
+    }
+
+    /// Replaces the swapchain with one matching the current surface extent. The device must be
+    /// idle.
+    pub fn recreate(&mut self, vulkan: &VulkanInfo) -> Result<()> {
+        // dropping the old swapchain destroys it, now that it has been retired
+        *self = Self::with_old_swapchain(vulkan, self.swapchain)?;
+        Ok(())
+    }
+
+    fn with_old_swapchain(vulkan: &VulkanInfo, old_swapchain: vk::SwapchainKHR) -> Result<Self> {
         if vulkan.device.queues.graphics.family_index != vulkan.device.queues.present.family_index {
             return Err(Error::msg(
                 "separate graphics and present queue families is unsupported",
@@ -81,7 +93,8 @@ impl Swapchain {
             .pre_transform(surface_capabilities.current_transform)
             .composite_alpha(vk::CompositeAlphaFlagBitsKHR::OPAQUE_KHR)
             .present_mode(vk::PresentModeKHR::FIFO_KHR)
-            .clipped(true);
+            .clipped(true)
+            .old_swapchain(old_swapchain);
 
         let swapchain = unsafe {
             vulkan
@@ -151,20 +164,25 @@ impl Drop for Swapchain {
 }
 
 impl Swapchain {
-    pub fn acquire_next_image(&self, acquire_semaphore: vk::Semaphore) -> Result<u32> {
-        unsafe {
-            self.device
-                .acquire_next_image_khr(
-                    self.swapchain,
-                    u64::MAX,
-                    acquire_semaphore,
-                    vk::Fence::null(),
-                )
-                .map_err(Error::from)
+    /// Returns None if the swapchain is out of date and must be recreated
+    pub fn acquire_next_image(&self, acquire_semaphore: vk::Semaphore) -> Result<Option<u32>> {
+        let result = unsafe {
+            self.device.acquire_next_image_khr(
+                self.swapchain,
+                u64::MAX,
+                acquire_semaphore,
+                vk::Fence::null(),
+            )
+        };
+
+        match result.raw {
+            vk::Result::ERROR_OUT_OF_DATE_KHR => Ok(None),
+            _ => Ok(Some(result.result()?)),
         }
     }
 
-    pub fn present(&self, wait_semaphore: vk::Semaphore, image_index: u32) -> Result<()> {
+    /// Returns true if the swapchain is out of date or suboptimal and should be recreated
+    pub fn present(&self, wait_semaphore: vk::Semaphore, image_index: u32) -> Result<bool> {
         let wait_semaphores = [wait_semaphore];
         let swapchains = [self.swapchain];
         let image_indices = [image_index];
@@ -173,12 +191,17 @@ impl Swapchain {
             .swapchains(&swapchains)
             .image_indices(&image_indices);
 
-        unsafe {
+        let result = unsafe {
             self.device
                 .queue_present_khr(self.present_queue, &present_info)
-                .result()?;
-        }
+        };
 
-        Ok(())
+        match result.raw {
+            vk::Result::ERROR_OUT_OF_DATE_KHR | vk::Result::SUBOPTIMAL_KHR => Ok(true),
+            _ => {
+                result.result()?;
+                Ok(false)
+            }
+        }
     }
 }
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use erupt::{vk, DeviceLoader};
+use erupt::{vk, DeviceLoader, ExtendableFrom};
 use gpu_alloc::UsageFlags;
 
 use crate::{
@@ -15,6 +15,11 @@ pub struct Transfer {
     command_buffer: vk::CommandBuffer,
     transfer_queue: vk::Queue,
     fence: vk::Fence,
+    /// Timeline semaphore reaching `signal_value` once the last submitted transfers complete
+    semaphore: vk::Semaphore,
+    signal_value: u64,
+    /// Whether any transfers were recorded since `begin_transfers`
+    recorded: bool,
     transient_buffers: Vec<GpuBuffer>,
 }
 
@@ -53,12 +58,29 @@ impl Transfer {
                 .result()?
         };
 
+        let mut semaphore_type_create_info = vk::SemaphoreTypeCreateInfoBuilder::new()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+
+        let semaphore_create_info =
+            vk::SemaphoreCreateInfoBuilder::new().extend_from(&mut semaphore_type_create_info);
+
+        let semaphore = unsafe {
+            vulkan
+                .device
+                .create_semaphore(&semaphore_create_info, None)
+                .result()?
+        };
+
         Ok(Self {
             device: vulkan.device.clone_loader(),
             command_pool,
             command_buffer,
             transfer_queue: vulkan.device.queues.transfer.queue,
             fence,
+            semaphore,
+            signal_value: 0,
+            recorded: false,
             transient_buffers: Vec::new(),
         })
     }
@@ -68,6 +90,7 @@ impl Drop for Transfer {
     fn drop(&mut self) {
         unsafe {
             self.device.destroy_fence(self.fence, None);
+            self.device.destroy_semaphore(self.semaphore, None);
             self.device
                 .free_command_buffers(self.command_pool, &[self.command_buffer]);
             self.device.destroy_command_pool(self.command_pool, None);
@@ -83,10 +106,6 @@ impl Transfer {
                 .result()?;
         }
 
-        unsafe {
-            self.device.reset_fences(&[self.fence]).result()?;
-        }
-
         for buffer in self.transient_buffers.drain(..) {
             allocator.dealloc(buffer);
         }
@@ -149,9 +168,13 @@ impl Transfer {
 
         self.transient_buffers.push(transfer_buffer);
 
+        self.recorded = true;
+
         dst_buffer
     }
 
+    /// Submits the recorded transfers, if any. Work using the transferred buffers must wait on
+    /// `wait_info`.
     pub fn submit_transfers(&mut self) -> Result<()> {
         unsafe {
             self.device
@@ -159,15 +182,46 @@ impl Transfer {
                 .result()?;
         }
 
-        let command_buffers = [self.command_buffer];
-        let submit_info = vk::SubmitInfoBuilder::new().command_buffers(&command_buffers);
+        // the fence stays signaled, and the command buffer is reset by the next begin_transfers
+        if !self.recorded {
+            return Ok(());
+        }
+
+        self.recorded = false;
+        self.signal_value += 1;
+
+        let command_buffer_infos =
+            [vk::CommandBufferSubmitInfoBuilder::new().command_buffer(self.command_buffer)];
+
+        let signal_semaphore_infos = [vk::SemaphoreSubmitInfoBuilder::new()
+            .semaphore(self.semaphore)
+            .value(self.signal_value)
+            .stage_mask(vk::PipelineStageFlags2::ALL_TRANSFER)];
+
+        let submit_info = vk::SubmitInfo2Builder::new()
+            .command_buffer_infos(&command_buffer_infos)
+            .signal_semaphore_infos(&signal_semaphore_infos);
 
         unsafe {
+            self.device.reset_fences(&[self.fence]).result()?;
+
             self.device
-                .queue_submit(self.transfer_queue, &[submit_info], self.fence)
+                .queue_submit2(self.transfer_queue, &[submit_info], self.fence)
                 .result()?;
         }
 
         Ok(())
     }
+
+    /// Semaphore wait covering every transfer submitted so far, to be used by the next submit
+    /// which reads transferred buffers. Waiting on an already reached value doesn't block.
+    pub fn wait_info(&self) -> vk::SemaphoreSubmitInfoBuilder<'static> {
+        vk::SemaphoreSubmitInfoBuilder::new()
+            .semaphore(self.semaphore)
+            .value(self.signal_value)
+            .stage_mask(
+                vk::PipelineStageFlags2::INDEX_INPUT
+                    | vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT,
+            )
+    }
 }